@@ -29,7 +29,9 @@ use smos_sddf::driver_setup::sDDFClient;
 use smos_sddf::driver_setup::sddf_driver_pre_init;
 use smos_sddf::driver_setup::VirtRegistration;
 use smos_sddf::irq_channel::IrqChannel;
-use smos_sddf::blk_config::{BlkConnectionResource, BlkDriverConfig, SDDF_BLK_MAGIC};
+use smos_sddf::blk_config::{
+    BlkConnectionResource, BlkDriverConfig, BLK_QUEUE_CAPACITY, BLK_QUEUE_SIZE, SDDF_BLK_MAGIC,
+};
 use smos_sddf::notification_channel::{BidirectionalChannel, NotificationChannel, PPCForbidden};
 use smos_sddf::queue::{ActiveQueue, FreeQueue, SerialQueue, Queue};
 use smos_sddf::sddf_bindings::{init, sddf_event_loop, sddf_set_channel};
@@ -51,8 +53,6 @@ const VIRT_DATA: *const u8 = 0xD000000 as *const u8;
 const REQ_BUF: *const u8 = 0xD200000 as *const u8;
 const VIRTIO_BUF: *const u8 = 0xD201000 as *const u8;
 
-const QUEUE_SIZE: usize = 4096;
-
 const BLK_REGS_PADDR: usize = 0xa003a00 as usize;
 const BLK_OFFSET: usize = 
     BLK_REGS_PADDR - ROUND_DOWN(BLK_REGS_PADDR as usize, sel4_sys::seL4_PageBits as usize);
@@ -128,7 +128,7 @@ fn handle_queue_register (
     client: &mut sDDFBlkDriverClient,
     args: &sDDFQueueRegister,
 ) -> Result<SMOSReply, InvocationError> {
-    if args.size != QUEUE_SIZE {
+    if args.size != BLK_QUEUE_SIZE {
         return Err(InvocationError::InvalidArguments);
     }
 
@@ -304,7 +304,7 @@ fn main(rs_conn: RootServerConnection, mut cspace: SMOSUserCSpace) -> sel4::Resu
                     vaddr: virts[0].unwrap().resp_queue.unwrap().vaddr,
                     size: virts[0].unwrap().resp_queue.unwrap().size,
                 },
-                num_buffers: 1024,
+                num_buffers: BLK_QUEUE_CAPACITY,
                 id: virts[0].unwrap().channel.unwrap().from_bit.unwrap(),
             },
         };
@@ -6,10 +6,13 @@ use core::cell::RefCell;
 use elf::ElfBytes;
 use include_bytes_aligned::include_bytes_aligned;
 use smos_common::error::*;
+use smos_common::init::SMOS_CORE_SERVER_ENV;
 use smos_common::local_handle::{
     ConnRegistrationHandle, ConnectionHandle, HandleOrHandleCap, LocalHandle, ObjectHandle,
     ViewHandle, WindowHandle, WindowRegistrationHandle,
 };
+use smos_common::obj_attributes::ObjAttributes;
+use smos_common::sched::SchedParams;
 use smos_common::syscall::{ObjectServerInterface, ReplyWrapper, RootServerInterface};
 use smos_common::{
     connection::{ObjectServerConnection, RootServerConnection},
@@ -27,7 +30,9 @@ use smos_server::handle_capability::{HandleCapability, HandleCapabilityTable};
 use smos_server::reply::*;
 use smos_server::syscalls::*;
 extern crate alloc;
+use alloc::format;
 use alloc::rc::Rc;
+use alloc::string::String;
 use alloc::vec::Vec;
 use offset_allocator::{Allocation, Allocator};
 use smos_server::event::{smos_serv_cleanup, smos_serv_decode_invocation, smos_serv_replyrecv};
@@ -37,7 +42,6 @@ use smos_server::ntfn_buffer::*;
 // to prevent information leakage. Maybe if another page aligned 'guard page' is added? Kinda
 // relying on the behaviour of the compiler
 
-const NUM_FILES: usize = 11;
 const INIT_ELF_CONTENTS: &[u8] = include_bytes_aligned!(4096, env!("INIT_ELF"));
 const ETH_DRIVER_ELF_CONTENTS: &[u8] = include_bytes_aligned!(4096, env!("ETH_DRIVER_ELF"));
 const ETH_VIRT_RX_ELF_CONTENTS: &[u8] = include_bytes_aligned!(4096, env!("ETH_VIRT_RX_ELF"));
@@ -50,22 +54,36 @@ const SERIAL_VIRT_RX_ELF_CONTENTS: &[u8] = include_bytes_aligned!(4096, env!("SE
 const SERIAL_VIRT_TX_ELF_CONTENTS: &[u8] = include_bytes_aligned!(4096, env!("SERIAL_VIRT_TX_ELF"));
 
 const BLK_DRIVER_ELF_CONTENTS: &[u8] = include_bytes_aligned!(4096, env!("BLK_DRIVER_ELF"));
+const SWAP_SERVER_ELF_CONTENTS: &[u8] = include_bytes_aligned!(4096, env!("SWAP_SERVER_ELF"));
 
-#[derive(Debug, Copy, Clone)]
 struct File {
-    name: &'static str,
+    name: String,
     data: &'static [u8],
+    /* Files created by clients are kept in memory from the root server */
+    backing: Option<FileBacking>,
 }
 
-static mut FILES: [Option<File>; NUM_FILES] = [None; NUM_FILES];
+struct FileBacking {
+    obj_hndl: HandleOrHandleCap<ObjectHandle>,
+    win_hndl: HandleOrHandleCap<WindowHandle>,
+    view_hndl: LocalHandle<ViewHandle>,
+    region: Allocation<u32>,
+}
+
+static mut FILES: Vec<Rc<File>> = Vec::new();
 
 const NTFN_BUFFER: *mut u8 = 0xB0000 as *mut u8;
 const SHARED_BUFFER_BASE: *mut u8 = 0xC0000 as *mut u8;
 
+/* The contents of files created by clients are mapped in from here */
+const FILE_DATA_BASE: *mut u8 = 0x20000000 as *mut u8;
+const FILE_DATA_PAGES: u32 = 0x4000;
+const MAX_CREATED_FILES: u32 = 16;
+
 const MAX_HANDLES: usize = 16;
 
 struct Object {
-    file: &'static File,
+    file: Rc<File>,
     associated_views: Vec<Rc<RefCell<ViewData>>>,
 }
 
@@ -175,36 +193,206 @@ fn handle_obj_stat(
     })
 }
 
-fn handle_obj_open(
+fn find_file(name: &str) -> Option<Rc<File>> {
+    unsafe {
+        return FILES.iter().find(|x| x.name == name).cloned();
+    }
+}
+
+/* Sets up the memory of a new file, which is mapped into the file server so that pages of it can be
+handed out on faults the same way as those of the boot files */
+fn create_file_backing(
+    rs_conn: &RootServerConnection,
+    cspace: &mut SMOSUserCSpace,
+    file_allocator: &mut Allocator,
+    size: usize,
+) -> Result<FileBacking, InvocationError> {
+    let region = file_allocator
+        .allocate((size / 4096) as u32)
+        .ok_or(InvocationError::InsufficientResources)?;
+    let address = FILE_DATA_BASE as usize + (region.offset as usize * 4096);
+
+    let obj_hndl = rs_conn
+        .obj_create(
+            None,
+            size,
+            sel4::CapRights::all(),
+            ObjAttributes::DEFAULT,
+            None,
+        )
+        .map_err(|e| {
+            file_allocator.free(region);
+            e
+        })?;
+
+    let win_hndl = rs_conn.window_create(address, size, None).map_err(|e| {
+        rs_conn
+            .obj_destroy(obj_hndl.clone(), cspace)
+            .expect("Failed to destroy object");
+        file_allocator.free(region);
+        e
+    })?;
+
+    let view_hndl = rs_conn
+        .view(&win_hndl, &obj_hndl, 0, 0, size, sel4::CapRights::all())
+        .map_err(|e| {
+            rs_conn
+                .window_destroy(win_hndl.clone(), cspace)
+                .expect("Failed to destroy window");
+            rs_conn
+                .obj_destroy(obj_hndl.clone(), cspace)
+                .expect("Failed to destroy object");
+            file_allocator.free(region);
+            e
+        })?;
+
+    return Ok(FileBacking {
+        obj_hndl: obj_hndl,
+        win_hndl: win_hndl,
+        view_hndl: view_hndl,
+        region: region,
+    });
+}
+
+fn destroy_file_backing(
+    rs_conn: &RootServerConnection,
+    cspace: &mut SMOSUserCSpace,
+    file_allocator: &mut Allocator,
+    backing: &FileBacking,
+) {
+    rs_conn
+        .unview(backing.view_hndl.clone())
+        .expect("Failed to unview");
+    rs_conn
+        .window_destroy(backing.win_hndl.clone(), cspace)
+        .expect("Failed to destroy window");
+    rs_conn
+        .obj_destroy(backing.obj_hndl.clone(), cspace)
+        .expect("Failed to destroy object");
+    file_allocator.free(backing.region);
+}
+
+fn handle_obj_create(
+    rs_conn: &RootServerConnection,
+    cspace: &mut SMOSUserCSpace,
+    file_allocator: &mut Allocator,
     client: &mut Client,
     handle_cap_table: &mut HandleCapabilityTable<BFSResource>,
-    args: &ObjOpen,
+    args: &ObjCreate,
 ) -> Result<SMOSReply, InvocationError> {
-    let mut matched_file = None;
+    /* Every file needs a unique name so that it can be opened again */
+    let name = args.name.ok_or(InvocationError::InvalidArguments)?;
+    if find_file(name).is_some() {
+        return Err(InvocationError::InvalidArguments);
+    }
 
-    unsafe {
-        for file in FILES.iter() {
-            if file.is_none() {
-                continue;
-            }
+    /* Files are handed out a page at a time */
+    if args.size == 0 || args.size % 4096 != 0 {
+        return Err(InvocationError::InvalidArguments);
+    }
 
-            let file_unwrapped = file.as_ref().unwrap();
-            if file_unwrapped.name == args.name {
-                matched_file = Some(file_unwrapped)
+    let backing = create_file_backing(rs_conn, cspace, file_allocator, args.size)?;
+
+    let (idx, handle_ref, cptr) =
+        match generic_allocate_handle(client, handle_cap_table, args.return_cap) {
+            Ok(x) => x,
+            Err(e) => {
+                destroy_file_backing(rs_conn, cspace, file_allocator, &backing);
+                return Err(e);
             }
-        }
+        };
+
+    // Safety: The view covers the whole file, and it stays around until the file is destroyed
+    let data = unsafe {
+        core::slice::from_raw_parts(
+            FILE_DATA_BASE.wrapping_add(backing.region.offset as usize * 4096),
+            args.size,
+        )
+    };
+
+    let file = Rc::new(File {
+        name: String::from(name),
+        data: data,
+        backing: Some(backing),
+    });
+    unsafe {
+        FILES.push(file.clone());
     }
 
-    /* Couldn't find the file */
-    if matched_file.is_none() {
+    let object = Rc::new(RefCell::new(Object {
+        file: file,
+        associated_views: Vec::new(),
+    }));
+
+    *handle_ref = Some(ServerHandle::<BFSResource>::new(BFSResource::Object(
+        object,
+    )));
+
+    let ret = if args.return_cap {
+        HandleOrHandleCap::<ObjectHandle>::new_handle_cap(cptr.unwrap())
+    } else {
+        HandleOrHandleCap::<ObjectHandle>::new_handle(idx)
+    };
+
+    Ok(SMOSReply::ObjCreate { hndl: ret })
+}
+
+fn handle_obj_destroy(
+    rs_conn: &RootServerConnection,
+    cspace: &mut SMOSUserCSpace,
+    file_allocator: &mut Allocator,
+    client: &mut Client,
+    handle_cap_table: &mut HandleCapabilityTable<BFSResource>,
+    args: &ObjDestroy,
+) -> Result<SMOSReply, InvocationError> {
+    let obj_ref = generic_get_handle(client, handle_cap_table, args.hndl, 0)?;
+
+    let object = match obj_ref.as_ref().unwrap().inner() {
+        BFSResource::Object(obj) => Ok(obj.clone()),
+        _ => Err(generic_invalid_handle_error(args.hndl, 0)),
+    }?;
+
+    /* The boot files can't be destroyed */
+    let file = object.borrow().file.clone();
+    if file.backing.is_none() {
         return Err(InvocationError::InvalidArguments);
     }
 
+    /* The memory of the file can't go away while anyone could still fault on it. The file table,
+    this object and the reference above are the only ones allowed to refer to the file. */
+    if !object.borrow().associated_views.is_empty() || Rc::strong_count(&file) != 3 {
+        return Err(InvocationError::InvalidArguments);
+    }
+
+    generic_cleanup_handle(client, handle_cap_table, args.hndl, 0)
+        .expect("Failed to clean up handle");
+
+    unsafe {
+        FILES.retain(|x| !Rc::ptr_eq(x, &file));
+    }
+    destroy_file_backing(
+        rs_conn,
+        cspace,
+        file_allocator,
+        file.backing.as_ref().unwrap(),
+    );
+
+    return Ok(SMOSReply::ObjDestroy);
+}
+
+fn handle_obj_open(
+    client: &mut Client,
+    handle_cap_table: &mut HandleCapabilityTable<BFSResource>,
+    args: &ObjOpen,
+) -> Result<SMOSReply, InvocationError> {
+    /* Couldn't find the file */
+    let matched_file = find_file(args.name).ok_or(InvocationError::InvalidArguments)?;
+
     let (idx, handle_ref, cptr) =
         generic_allocate_handle(client, handle_cap_table, args.return_cap)?;
 
     let object = Rc::new(RefCell::new(Object {
-        file: matched_file.unwrap(),
+        file: matched_file,
         associated_views: Vec::new(),
     }));
 
@@ -590,6 +778,9 @@ fn syscall_loop<T: ServerConnection>(
     /* Used to allocate regions of the virtual address space for the windows used for mapping
     data buffers with clients  */
     let mut window_allocator = Allocator::with_max_allocs(MAX_CLIENTS as u32, MAX_CLIENTS as u32);
+    /* Used to allocate regions of the virtual address space for the contents of created files,
+    in pages */
+    let mut file_allocator = Allocator::with_max_allocs(FILE_DATA_PAGES, MAX_CREATED_FILES);
 
     loop {
         let (msg, badge) = smos_serv_replyrecv(&listen_conn, &reply, reply_msg_info);
@@ -674,12 +865,28 @@ fn syscall_loop<T: ServerConnection>(
                             client_unwrapped,
                             &mut cspace,
                         ),
+                        SMOS_Invocation::ObjCreate(t) => handle_obj_create(
+                            &rs_conn,
+                            &mut cspace,
+                            &mut file_allocator,
+                            client_unwrapped,
+                            &mut handle_cap_table,
+                            &t,
+                        ),
                         SMOS_Invocation::ObjOpen(t) => {
                             handle_obj_open(client_unwrapped, &mut handle_cap_table, &t)
                         }
                         SMOS_Invocation::ObjClose(t) => {
                             handle_obj_close(client_unwrapped, &mut handle_cap_table, &t)
                         }
+                        SMOS_Invocation::ObjDestroy(t) => handle_obj_destroy(
+                            &rs_conn,
+                            &mut cspace,
+                            &mut file_allocator,
+                            client_unwrapped,
+                            &mut handle_cap_table,
+                            &t,
+                        ),
                         SMOS_Invocation::ObjStat(t) => {
                             handle_obj_stat(client_unwrapped, &mut handle_cap_table, &t)
                         }
@@ -704,51 +911,29 @@ fn syscall_loop<T: ServerConnection>(
 }
 
 fn init_file_table() {
-    unsafe {
-        FILES[0] = Some(File {
-            name: "init",
-            data: INIT_ELF_CONTENTS,
-        });
-        FILES[1] = Some(File {
-            name: "eth_driver",
-            data: ETH_DRIVER_ELF_CONTENTS,
-        });
-        FILES[2] = Some(File {
-            name: "eth_virt_rx",
-            data: ETH_VIRT_RX_ELF_CONTENTS,
-        });
-        FILES[3] = Some(File {
-            name: "eth_virt_tx",
-            data: ETH_VIRT_TX_ELF_CONTENTS,
-        });
-        FILES[4] = Some(File {
-            name: "eth_copier",
-            data: ETH_COPIER_ELF_CONTENTS,
-        });
-        FILES[5] = Some(File {
-            name: "echo_server",
-            data: ECHO_SERVER_ELF_CONTENTS,
-        });
-        FILES[6] = Some(File {
-            name: "timer",
-            data: TIMER_ELF_CONTENTS,
-        });
-        FILES[7] = Some(File {
-            name: "serial_driver",
-            data: SERIAL_DRIVER_ELF_CONTENTS,
-        });
-        FILES[8] = Some(File {
-            name: "serial_virt_rx",
-            data: SERIAL_VIRT_RX_ELF_CONTENTS,
-        });
-        FILES[9] = Some(File {
-            name: "serial_virt_tx",
-            data: SERIAL_VIRT_TX_ELF_CONTENTS,
-        });
-        FILES[10] = Some(File {
-            name: "blk_driver",
-            data: BLK_DRIVER_ELF_CONTENTS,
-        });
+    let boot_files: [(&str, &'static [u8]); 11] = [
+        ("init", INIT_ELF_CONTENTS),
+        ("eth_driver", ETH_DRIVER_ELF_CONTENTS),
+        ("eth_virt_rx", ETH_VIRT_RX_ELF_CONTENTS),
+        ("eth_virt_tx", ETH_VIRT_TX_ELF_CONTENTS),
+        ("eth_copier", ETH_COPIER_ELF_CONTENTS),
+        ("echo_server", ECHO_SERVER_ELF_CONTENTS),
+        ("timer", TIMER_ELF_CONTENTS),
+        ("serial_driver", SERIAL_DRIVER_ELF_CONTENTS),
+        ("serial_virt_rx", SERIAL_VIRT_RX_ELF_CONTENTS),
+        ("serial_virt_tx", SERIAL_VIRT_TX_ELF_CONTENTS),
+        ("blk_driver", BLK_DRIVER_ELF_CONTENTS),
+        ("swap_server", SWAP_SERVER_ELF_CONTENTS),
+    ];
+
+    for (name, data) in boot_files {
+        unsafe {
+            FILES.push(Rc::new(File {
+                name: String::from(name),
+                data: data,
+                backing: None,
+            }));
+        }
     }
 }
 
@@ -793,9 +978,27 @@ fn main(rs_conn: RootServerConnection, mut cspace: SMOSUserCSpace) -> sel4::Resu
 
     sel4::debug_println!("Boot file server published...");
 
+    /* Make the boot files available to anyone spawning a process */
+    rs_conn
+        .mount("/boot", "BOOT_FS")
+        .expect("Could not mount boot fs");
+
+    /* Core files of anything that crashes are written here */
+    let core_server_env = format!("{}=BOOT_FS", SMOS_CORE_SERVER_ENV);
+
     /* Start the other relavant processes */
     rs_conn
-        .process_spawn("init", "BOOT_FS", 250, None)
+        .process_spawn(
+            "init",
+            /* Init starts the drivers, which run at a higher priority than init itself */
+            &SchedParams {
+                mcp: 254,
+                ..SchedParams::new(250)
+            },
+            None,
+            Some(&[core_server_env.as_str()]),
+            None,
+        )
         .expect("Failed to spawn init");
 
     let reply_cptr = cspace.alloc_slot().expect("Could not get a slot");
@@ -4,82 +4,261 @@
 #![allow(internal_features)]
 #![feature(lang_items)]
 
-use smos_common::connection::RootServerConnection;
-use smos_common::syscall::RootServerInterface;
+use smos_common::connection::{ObjectServerConnection, RootServerConnection};
+use smos_common::local_handle::{LocalHandle, ProcessHandle};
+use smos_common::obj_attributes::ObjAttributes;
+use smos_common::sched::SchedParams;
+use smos_common::syscall::{NonRootServerInterface, RootServerInterface};
 use smos_cspace::SMOSUserCSpace;
+use smos_runtime::coredump;
 use smos_runtime::{smos_declare_main, Never};
 extern crate alloc;
+use alloc::format;
+use alloc::vec::Vec;
+
+const SHARED_BUFFER_REGION: usize = 0x30000000;
+/* Core files are copied through the 2 * coredump::CHUNK_SIZE bytes from here */
+const CORE_SCRATCH_REGION: usize = 0x30010000;
+
+/* Scheduling parameters of the components started by init, with budgets and periods in
+microseconds. Together they take up less than the whole core that init was given. */
+const DRIVER_SCHED: SchedParams = SchedParams {
+    budget: 100,
+    period: 1000,
+    ..SchedParams::new(254)
+};
+const VIRT_SCHED: SchedParams = SchedParams {
+    budget: 50,
+    period: 1000,
+    ..SchedParams::new(253)
+};
+const COPIER_SCHED: SchedParams = SchedParams {
+    budget: 50,
+    period: 1000,
+    ..SchedParams::new(252)
+};
+/* Applications run for longer at a time, but less often */
+const APP_SCHED: SchedParams = SchedParams {
+    budget: 2000,
+    period: 10000,
+    ..SchedParams::new(251)
+};
+
+/* Connects to the object server that core files are written to, if one was configured */
+fn open_core_server(
+    rs_conn: &RootServerConnection,
+    cspace: &mut SMOSUserCSpace,
+) -> Option<ObjectServerConnection> {
+    let server_name = coredump::server()?;
+
+    /* Set up shared buffer with the server */
+    let shared_buf_obj_slot = cspace
+        .alloc_slot()
+        .expect("Failed to allocate slot for shared buffer");
+    let shared_buf_obj = rs_conn
+        .obj_create(
+            None,
+            4096,
+            sel4::CapRights::all(),
+            ObjAttributes::DEFAULT,
+            Some(cspace.to_absolute_cptr(shared_buf_obj_slot)),
+        )
+        .expect("Failed to create shared buffer object");
+    let shared_buffer_win_hndl = rs_conn
+        .window_create(SHARED_BUFFER_REGION, 4096, None)
+        .expect("Failed to create window for shared buffer");
+    rs_conn
+        .view(
+            &shared_buffer_win_hndl,
+            &shared_buf_obj,
+            0,
+            0,
+            4096,
+            sel4::CapRights::all(),
+        )
+        .expect("Failed to map shared buffer object");
+
+    let fs_ep_slot = cspace
+        .alloc_slot()
+        .expect("Failed to allocate slot for core server connection");
+    let mut fs_conn = match rs_conn
+        .conn_create::<ObjectServerConnection>(&cspace.to_absolute_cptr(fs_ep_slot), server_name)
+    {
+        Ok(conn) => conn,
+        Err(e) => {
+            sel4::debug_println!("Could not connect to core server {}: {:?}", server_name, e);
+            cspace.free_slot(fs_ep_slot);
+            return None;
+        }
+    };
+
+    fs_conn
+        .conn_open(Some((
+            shared_buf_obj,
+            (SHARED_BUFFER_REGION as *mut u8, 4096),
+        )))
+        .expect("Failed to open connection to core server");
+
+    return Some(fs_conn);
+}
+
+/* Saves the core file of a child that was killed by a fault and reaps it. Returns false if the
+child hasn't faulted. */
+fn reap_faulted_child(
+    rs_conn: &RootServerConnection,
+    fs_conn: &ObjectServerConnection,
+    cspace: &mut SMOSUserCSpace,
+    name: &str,
+    child: LocalHandle<ProcessHandle>,
+) -> bool {
+    /* A child only has a core file once it has been killed by a fault */
+    let (core_hndl, size) = match rs_conn.process_core_dump(&child) {
+        Ok(x) => x,
+        Err(_) => return false,
+    };
+
+    let file_name = format!("{}.core", name);
+    match coredump::write(
+        rs_conn,
+        fs_conn,
+        cspace,
+        core_hndl,
+        size,
+        &file_name,
+        CORE_SCRATCH_REGION,
+    ) {
+        Ok(()) => {
+            sel4::debug_println!("{} crashed, its core file was saved to {}", name, file_name)
+        }
+        Err(e) => sel4::debug_println!("{} crashed, failed to save its core file: {:?}", name, e),
+    }
+
+    match rs_conn.process_wait(child) {
+        Ok(status) => sel4::debug_println!("{} exited with {:?}", name, status),
+        Err(e) => sel4::debug_println!("Failed to reap {}: {:?}", name, e),
+    }
+
+    return true;
+}
 
 #[smos_declare_main]
-fn main(rs_conn: RootServerConnection, _cspace: SMOSUserCSpace) -> sel4::Result<Never> {
+fn main(rs_conn: RootServerConnection, mut cspace: SMOSUserCSpace) -> sel4::Result<Never> {
     sel4::debug_println!("Hello world! I am init ^_^! I will now initialize the system...");
 
+    /* The children whose core files are saved if they crash */
+    let mut children: Vec<(&str, LocalHandle<ProcessHandle>)> = Vec::new();
+
     /* Start the ethernet driver */
-    rs_conn
-        .process_spawn("eth_driver", "BOOT_FS", 254, Some(&["eth0"]))
+    let child = rs_conn
+        .process_spawn("eth_driver", &DRIVER_SCHED, Some(&["eth0"]), None, None)
         .expect("Failed to start eth_driver");
+    children.push(("eth_driver", child));
 
     /* Start the serial driver */
-    rs_conn
-        .process_spawn("serial_driver", "BOOT_FS", 254, Some(&["serial0"]))
+    let child = rs_conn
+        .process_spawn(
+            "serial_driver",
+            &DRIVER_SCHED,
+            Some(&["serial0"]),
+            None,
+            None,
+        )
         .expect("Failed to start serial_driver");
+    children.push(("serial_driver", child));
 
     /* Start the blk driver */
-    rs_conn
-        .process_spawn("blk_driver", "BOOT_FS", 254, Some(&["blk0"]))
+    let child = rs_conn
+        .process_spawn("blk_driver", &DRIVER_SCHED, Some(&["blk0"]), None, None)
         .expect("Failed to start blk_driver");
+    children.push(("blk_driver", child));
+
+    /* Start the swap server on top of the blk driver. Only processes with the highest mcp can
+    provide the swap area. */
+    let child = rs_conn
+        .process_spawn(
+            "swap_server",
+            &SchedParams {
+                mcp: 254,
+                ..DRIVER_SCHED
+            },
+            Some(&["swap0", "blk0"]),
+            None,
+            None,
+        )
+        .expect("Failed to start swap_server");
+    children.push(("swap_server", child));
 
     /* eth components */
     /* Start the eth virt rx */
-    rs_conn
-        .process_spawn("eth_virt_rx", "BOOT_FS", 253, Some(&["rx_eth0", "eth0"]))
+    let child = rs_conn
+        .process_spawn(
+            "eth_virt_rx",
+            &VIRT_SCHED,
+            Some(&["rx_eth0", "eth0"]),
+            None,
+            None,
+        )
         .expect("Failed to start eth_virt_rx");
+    children.push(("eth_virt_rx", child));
 
     /* Start the eth virt tx */
-    rs_conn
-        .process_spawn("eth_virt_tx", "BOOT_FS", 253, Some(&["tx_eth0", "eth0"]))
+    let child = rs_conn
+        .process_spawn(
+            "eth_virt_tx",
+            &VIRT_SCHED,
+            Some(&["tx_eth0", "eth0"]),
+            None,
+            None,
+        )
         .expect("Failed to start eth_virt_tx");
+    children.push(("eth_virt_tx", child));
 
     /* Start the copier */
-    rs_conn
+    let child = rs_conn
         .process_spawn(
             "eth_copier",
-            "BOOT_FS",
-            252,
+            &COPIER_SCHED,
             Some(&["cli0_copy_eth0", "rx_eth0"]),
+            None,
+            None,
         )
         .expect("Failed to start eth_copier");
+    children.push(("eth_copier", child));
 
     /* Serial components */
-    rs_conn
+    let child = rs_conn
         .process_spawn(
             "serial_virt_rx",
-            "BOOT_FS",
-            253,
+            &VIRT_SCHED,
             Some(&["rx_serial0", "serial0"]),
+            None,
+            None,
         )
         .expect("Failed to start eth_virt_rx");
+    children.push(("serial_virt_rx", child));
 
-    rs_conn
+    let child = rs_conn
         .process_spawn(
             "serial_virt_tx",
-            "BOOT_FS",
-            253,
+            &VIRT_SCHED,
             Some(&["tx_serial0", "serial0"]),
+            None,
+            None,
         )
         .expect("Failed to start eth_virt_rx");
+    children.push(("serial_virt_tx", child));
 
     /* Start the timer driver */
-    rs_conn
-        .process_spawn("timer", "BOOT_FS", 254, Some(&["timer0"]))
+    let child = rs_conn
+        .process_spawn("timer", &DRIVER_SCHED, Some(&["timer0"]), None, None)
         .expect("Failed to star timer_driver");
+    children.push(("timer", child));
 
     /* Start the client */
-    rs_conn
+    let child = rs_conn
         .process_spawn(
             "echo_server",
-            "BOOT_FS",
-            251,
+            &APP_SCHED,
             Some(&[
                 "cli0_copy_eth0",
                 "tx_eth0",
@@ -87,15 +266,27 @@ fn main(rs_conn: RootServerConnection, _cspace: SMOSUserCSpace) -> sel4::Result<
                 "tx_serial0",
                 "timer0",
             ]),
+            None,
+            None,
         )
         .expect("Failed to start echo_server");
+    children.push(("echo_server", child));
+
+    let fs_conn = open_core_server(&rs_conn, &mut cspace);
 
-    loop {}
+    // @alwin: This should block instead of polling once a process can wait on any of its children
+    loop {
+        if let Some(fs_conn) = fs_conn.as_ref() {
+            children.retain(|(name, child)| {
+                !reap_faulted_child(&rs_conn, fs_conn, &mut cspace, name, *child)
+            });
+        }
+    }
 
-    // rs_conn.process_spawn("eth_virt_tx", "BOOT_FS", 253, Some(&["tx_eth0", "eth0"]));
+    // rs_conn.process_spawn("eth_virt_tx", &SchedParams::new(253), Some(&["tx_eth0", "eth0"]), None, None);
 
     // /* Start a user application */
-    // rs_conn.process_spawn("test_app", "BOOT_FS", 252, None);
+    // rs_conn.process_spawn("test_app", &SchedParams::new(252), None, None, None);
 
     // let root_server_ep = sel4::CPtr::from_bits(1).cast::<sel4::cap_type::Endpoint>();
 
@@ -119,7 +310,7 @@ fn main(rs_conn: RootServerConnection, _cspace: SMOSUserCSpace) -> sel4::Result<
     // server look the same, so I think they are deleted by the capability revocation. Need to do
     // something so they look different
 
-    // rs_conn.process_exit();
+    // rs_conn.process_exit(0);
 
     // loop {}
     // unreachable!()
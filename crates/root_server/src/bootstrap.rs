@@ -3,6 +3,9 @@ use crate::cspace::{
     CNODE_SIZE_BITS, CNODE_SLOTS, CNODE_SLOT_BITS, NODE_INDEX, TOP_LVL_INDEX, WATERMARK_SLOTS,
 };
 use crate::dma::{DMAPool, DMA_RESERVATION_NUM_PAGES, DMA_RESERVATION_SIZE_BITS};
+use crate::large_page::{
+    LargePagePool, LARGE_PAGE_RESERVATION_NUM_PAGES, LARGE_PAGE_RESERVATION_SIZE_BITS,
+};
 use crate::page::{BYTES_TO_4K_PAGES, BYTES_TO_SIZE_BITS, PAGE_SIZE_4K};
 use crate::ut::{UTRegion, UTTable, UT};
 use crate::util::{ALIGN_DOWN, ALIGN_UP};
@@ -118,7 +121,9 @@ struct BootstrapCSpace {
 
 // pub fn smos_bootstrap(bi: &sel4::BootInfo) -> CSpace{
 #[allow(unused_assignments)]
-pub fn smos_bootstrap(bi: &sel4::BootInfo) -> Result<(CSpace, UTTable, DMAPool), sel4::Error> {
+pub fn smos_bootstrap(
+    bi: &sel4::BootInfo,
+) -> Result<(CSpace, UTTable, DMAPool, LargePagePool), sel4::Error> {
     let mut bootinfo_avail_bytes: [usize; sel4_cfg_usize!(MAX_NUM_BOOTINFO_UNTYPED_CAPS)] =
         [0; sel4_cfg_usize!(MAX_NUM_BOOTINFO_UNTYPED_CAPS)];
 
@@ -166,6 +171,9 @@ pub fn smos_bootstrap(bi: &sel4::BootInfo) -> Result<(CSpace, UTTable, DMAPool),
     /* NUM_PAGES cptrs for dma */
     n_slots += DMA_RESERVATION_NUM_PAGES;
 
+    /* and the untyped that large pages are made from, along with one for each large page */
+    n_slots += LARGE_PAGE_RESERVATION_NUM_PAGES + 1;
+
     /* now work out the number of slots required to retype the untyped memory provided by
      * boot info into 4K untyped objects. We aren't going to initialise these objects yet,
      * but before we have bootstrapped the frame table we cannot allocate memory from it --
@@ -173,8 +181,9 @@ pub fn smos_bootstrap(bi: &sel4::BootInfo) -> Result<(CSpace, UTTable, DMAPool),
      * requirements, up until the frame table is created*/
     n_slots += calculate_ut_caps(bi, sel4_sys::seL4_PageBits, &mut bootinfo_avail_bytes);
 
-    /* subtract what we don't need for dma */
+    /* subtract what we don't need for dma and large pages */
     n_slots -= BIT((DMA_RESERVATION_SIZE_BITS - sel4_sys::seL4_PageBits) as usize);
+    n_slots -= BIT((LARGE_PAGE_RESERVATION_SIZE_BITS - sel4_sys::seL4_PageBits) as usize);
 
     /* now work out how many 2nd level nodes are required - with a buffer */
     let mut n_cnodes = n_slots / CNODE_SLOTS(CNODE_SIZE_BITS) + 2;
@@ -402,6 +411,41 @@ pub fn smos_bootstrap(bi: &sel4::BootInfo) -> Result<(CSpace, UTTable, DMAPool),
         first_free_slot += 1;
     }
 
+    /* and 32MB that large pages can be made from. This is split into untypeds the size of a
+    large page, so that each one can be reused once the large page made from it is deleted. */
+    let (large_page_ut, _) = steal_untyped(
+        bi,
+        LARGE_PAGE_RESERVATION_SIZE_BITS.try_into().unwrap(),
+        &mut bootinfo_avail_bytes,
+    )
+    .ok_or(sel4::Error::NotEnoughMemory)?;
+
+    cspace.untyped_retype(
+        &large_page_ut,
+        sel4::ObjectBlueprint::Untyped {
+            size_bits: LARGE_PAGE_RESERVATION_SIZE_BITS.try_into().unwrap(),
+        },
+        first_free_slot,
+    )?;
+    let large_page_reservation =
+        CPtr::from_bits(first_free_slot.try_into().unwrap()).cast::<sel4::cap_type::Untyped>();
+    first_free_slot += 1;
+
+    let mut large_page_untypeds: [sel4::cap::Untyped; LARGE_PAGE_RESERVATION_NUM_PAGES] =
+        [CPtr::from_bits(0).cast(); LARGE_PAGE_RESERVATION_NUM_PAGES];
+    for i in 0..LARGE_PAGE_RESERVATION_NUM_PAGES {
+        cspace.untyped_retype(
+            &large_page_reservation,
+            sel4::ObjectBlueprint::Untyped {
+                size_bits: sel4_sys::seL4_LargePageBits.try_into().unwrap(),
+            },
+            first_free_slot,
+        )?;
+        large_page_untypeds[i] =
+            CPtr::from_bits(first_free_slot.try_into().unwrap()).cast::<sel4::cap_type::Untyped>();
+        first_free_slot += 1;
+    }
+
     let mut ut_table = UTTable::new(UT_TABLE, memory, bootstrap_data.next_free_vaddr);
 
     for (i, untyped) in bi.untyped_list().iter().enumerate() {
@@ -551,5 +595,10 @@ pub fn smos_bootstrap(bi: &sel4::BootInfo) -> Result<(CSpace, UTTable, DMAPool),
         cspace.watermark[i] = cspace.alloc_slot()?;
     }
 
-    return Ok((cspace, ut_table, dma));
+    return Ok((
+        cspace,
+        ut_table,
+        dma,
+        LargePagePool::new(large_page_untypeds),
+    ));
 }
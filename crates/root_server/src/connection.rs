@@ -6,6 +6,7 @@ use crate::irq::UserNotificationDispatch;
 use crate::object::AnonymousMemoryObject;
 use crate::page::PAGE_SIZE_4K;
 use crate::proc::{procs_get, ProcessType, UserProcess};
+use crate::swap::swap_server_gone;
 use crate::ut::{UTTable, UTWrapper};
 use crate::util::{alloc_retype, dealloc_retyped};
 use crate::view::View;
@@ -33,10 +34,9 @@ use smos_server::syscalls::{
 
 #[derive(Debug, Clone)]
 pub struct Server {
-    pid: usize,
+    pub pid: usize,
     pub unbadged_ep: (sel4::cap::Endpoint, UTWrapper),
     pub ntfn_dispatch: UserNotificationDispatch,
-    #[allow(dead_code)] // @alwin: Not used for now
     ntfn_buffer_view: Rc<RefCell<View>>,
     pub ntfn_buffer_addr: *mut u8, //@alwin: I am unsure if this is the best way to appraoch this, but having something with a lifetime in here is extremely painful
     pub connections: Vec<Rc<RefCell<Connection>>>,
@@ -54,7 +54,7 @@ pub struct Connection {
 
 static mut SERVERS: BTreeMap<String, Rc<RefCell<Server>>> = BTreeMap::new();
 
-fn find_server_with_name(name: &str) -> Option<Rc<RefCell<Server>>> {
+pub fn find_server_with_name(name: &str) -> Option<Rc<RefCell<Server>>> {
     unsafe { Some(SERVERS.get(name)?.clone()) }
 }
 
@@ -104,13 +104,17 @@ pub fn handle_conn_deregister(
         _ => Err(InvocationError::InvalidHandle { which_arg: 0 }),
     }?;
 
-    conn.borrow_mut().registered = false;
+    handle_conn_deregister_internal(conn);
     p.cleanup_handle(args.hndl.idx)
         .expect("Failed to clean up handle");
 
     return Ok(SMOSReply::ConnDeregister);
 }
 
+pub fn handle_conn_deregister_internal(conn: Rc<RefCell<Connection>>) {
+    conn.borrow_mut().registered = false;
+}
+
 pub fn handle_conn_create(
     cspace: &mut CSpace,
     p: &mut UserProcess,
@@ -184,6 +188,15 @@ pub fn handle_conn_destroy(
         _ => Err(InvocationError::InvalidHandle { which_arg: 0 }),
     }?;
 
+    handle_conn_destroy_internal(cspace, conn);
+
+    p.cleanup_handle(args.hndl.idx)
+        .expect("Failed to clean up handle");
+
+    return Ok(SMOSReply::ConnDestroy);
+}
+
+pub fn handle_conn_destroy_internal(cspace: &mut CSpace, conn: Rc<RefCell<Connection>>) {
     cspace
         .root_cnode()
         .absolute_cptr(conn.borrow().badged_ep)
@@ -209,10 +222,21 @@ pub fn handle_conn_destroy(
             .signal();
     }
 
-    p.cleanup_handle(args.hndl.idx)
-        .expect("Failed to clean up handle");
-
-    return Ok(SMOSReply::ConnDestroy);
+    cspace
+        .delete_cap(conn.borrow().badged_ep)
+        .expect("Failed to delete badged endpoint");
+    cspace.free_cap(conn.borrow().badged_ep);
+
+    /* Remove the connection from the server's list of connections */
+    let server = conn.borrow().server.clone();
+    let pos = server
+        .borrow()
+        .connections
+        .iter()
+        .position(|x| Rc::ptr_eq(x, &conn));
+    if let Some(x) = pos {
+        server.borrow_mut().connections.swap_remove(x);
+    }
 }
 
 pub fn handle_server_handle_cap_create(
@@ -457,3 +481,45 @@ pub fn handle_conn_publish(
         ep: ep.0,
     });
 }
+
+pub fn handle_conn_unpublish_internal(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_table: &mut FrameTable,
+    server: Rc<RefCell<Server>>,
+) {
+    unsafe { SERVERS.retain(|_, sv| !Rc::ptr_eq(sv, &server)) };
+
+    /* Stop swapping to the server before its notification goes away */
+    swap_server_gone(cspace, frame_table, &server);
+
+    /* Clients may still hold handles to their connections. Mark them as unregistered so tearing
+    them down later does not try to notify a server that no longer exists. */
+    for conn in &server.borrow().connections {
+        conn.borrow_mut().registered = false;
+    }
+    server.borrow_mut().connections.clear();
+
+    /* Revoking the unbadged endpoint also deletes all the badged copies handed out to clients */
+    let ep = server.borrow().unbadged_ep;
+    cspace
+        .root_cnode()
+        .absolute_cptr(ep.0)
+        .revoke()
+        .expect("Failed to revoke server endpoint");
+    dealloc_retyped(cspace, ut_table, ep);
+
+    server.borrow_mut().ntfn_dispatch.destroy(cspace, ut_table);
+
+    /* Free the frame backing the notification buffer */
+    let view = server.borrow().ntfn_buffer_view.clone();
+    view.borrow()
+        .bound_object
+        .as_ref()
+        .unwrap()
+        .borrow_mut()
+        .cleanup_frame_table(cspace, frame_table);
+    view.borrow_mut().cleanup_cap_table(cspace, false);
+    view.borrow().bound_window.borrow_mut().bound_view = None;
+    server.borrow_mut().ntfn_buffer_addr = core::ptr::null_mut();
+}
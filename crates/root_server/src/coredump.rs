@@ -0,0 +1,426 @@
+use crate::cspace::{CSpace, CSpaceTrait};
+use crate::frame_table::FrameTable;
+use crate::large_page::LargeFrameRef;
+use crate::mapping::map_large_frame;
+use crate::object::AnonymousMemoryObject;
+use crate::page::{PAGE_SIZE_2M, PAGE_SIZE_4K};
+use crate::proc::UserProcess;
+use crate::swap::swap_area;
+use crate::thread::{idx_to_tid, ThreadType};
+use crate::ut::UTTable;
+use crate::view::View;
+use crate::vmem_layout;
+use alloc::vec::Vec;
+use byteorder::{ByteOrder, LittleEndian};
+use smos_common::obj_attributes::ObjAttributes;
+use smos_common::util::ROUND_UP;
+
+/* Sizes of the structures that make up an ELF core file */
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+const NOTE_HEADER_SIZE: usize = 12;
+const NOTE_NAME: &[u8] = b"CORE\0\0\0\0";
+const NOTE_NAME_SIZE: u32 = 5;
+
+/* Layout of struct elf_prstatus on aarch64 */
+const NT_PRSTATUS: u32 = 1;
+const PRSTATUS_SIZE: usize = 392;
+const PRSTATUS_CURSIG: usize = 12;
+const PRSTATUS_PID: usize = 32;
+const PRSTATUS_PPID: usize = 36;
+const PRSTATUS_REGS: usize = 112;
+const NOTE_SIZE: usize = NOTE_HEADER_SIZE + NOTE_NAME.len() + PRSTATUS_SIZE;
+
+/* pc, sp, spsr and x0-x30 */
+const USER_CONTEXT_REGS: u64 = 34;
+
+const SIGILL: u16 = 4;
+const SIGABRT: u16 = 6;
+const SIGSEGV: u16 = 11;
+
+/* The signal a POSIX system would have delivered for a fault, which is what debuggers expect to
+find in a core file */
+pub fn fault_signal(fault: &sel4::Fault) -> u16 {
+    match fault {
+        sel4::Fault::VmFault(_) => SIGSEGV,
+        sel4::Fault::UnknownSyscall(_) | sel4::Fault::UserException(_) => SIGILL,
+        _ => SIGABRT,
+    }
+}
+
+/* Copies data into an object at offset, allocating any frames that aren't there yet */
+fn write_object_bytes(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_table: &mut FrameTable,
+    object: &mut AnonymousMemoryObject,
+    offset: usize,
+    data: &[u8],
+) -> Result<(), sel4::Error> {
+    let mut pos: usize = 0;
+
+    while pos < data.len() {
+        let curr_offset = offset + pos;
+        let page_offset = curr_offset - curr_offset % PAGE_SIZE_4K;
+
+        let frame_ref = match object.lookup_frame(page_offset) {
+            Some(frame) => frame.frame_ref,
+            None => {
+                let frame_ref = frame_table
+                    .alloc_frame(cspace, ut_table)
+                    .ok_or(sel4::Error::NotEnoughMemory)?;
+                frame_table.frame_data(frame_ref).fill(0);
+                object
+                    .insert_frame_at(
+                        page_offset,
+                        (frame_table.frame_from_ref(frame_ref).get_cap(), frame_ref),
+                    )
+                    .expect("Failed to insert frame into object");
+                frame_ref
+            }
+        };
+
+        let frame_offset = curr_offset % PAGE_SIZE_4K;
+        let len = usize::min(PAGE_SIZE_4K - frame_offset, data.len() - pos);
+        frame_table.frame_data(frame_ref)[frame_offset..frame_offset + len]
+            .copy_from_slice(&data[pos..pos + len]);
+
+        pos += len;
+    }
+
+    return Ok(());
+}
+
+/* Builds an NT_PRSTATUS note describing the register state of a thread */
+fn prstatus_note(
+    tcb: sel4::cap::Tcb,
+    id: u32,
+    parent: u32,
+    signal: u16,
+) -> Result<[u8; NOTE_SIZE], sel4::Error> {
+    let ctx = tcb.tcb_read_registers(true, USER_CONTEXT_REGS)?;
+
+    let mut note = [0; NOTE_SIZE];
+    LittleEndian::write_u32(&mut note[0..4], NOTE_NAME_SIZE);
+    LittleEndian::write_u32(&mut note[4..8], PRSTATUS_SIZE as u32);
+    LittleEndian::write_u32(&mut note[8..12], NT_PRSTATUS);
+    note[NOTE_HEADER_SIZE..NOTE_HEADER_SIZE + NOTE_NAME.len()].copy_from_slice(NOTE_NAME);
+
+    let prstatus = &mut note[NOTE_HEADER_SIZE + NOTE_NAME.len()..];
+    LittleEndian::write_u16(&mut prstatus[PRSTATUS_CURSIG..], signal);
+    LittleEndian::write_u32(&mut prstatus[PRSTATUS_PID..], id);
+    LittleEndian::write_u32(&mut prstatus[PRSTATUS_PPID..], parent);
+
+    /* The registers are stored as x0-x30, sp, pc and pstate */
+    let regs = &mut prstatus[PRSTATUS_REGS..];
+    for i in 0..31 {
+        LittleEndian::write_u64(&mut regs[i * 8..], *ctx.gpr(i as u64));
+    }
+    LittleEndian::write_u64(&mut regs[31 * 8..], *ctx.sp());
+    LittleEndian::write_u64(&mut regs[32 * 8..], *ctx.pc());
+    LittleEndian::write_u64(&mut regs[33 * 8..], ctx.inner().spsr);
+
+    return Ok(note);
+}
+
+fn write_phdr(
+    phdr: &mut [u8],
+    p_type: u32,
+    flags: u32,
+    offset: usize,
+    vaddr: usize,
+    filesz: usize,
+    memsz: usize,
+    align: usize,
+) {
+    LittleEndian::write_u32(&mut phdr[0..4], p_type);
+    LittleEndian::write_u32(&mut phdr[4..8], flags);
+    LittleEndian::write_u64(&mut phdr[8..16], offset as u64);
+    LittleEndian::write_u64(&mut phdr[16..24], vaddr as u64);
+    LittleEndian::write_u64(&mut phdr[24..32], 0);
+    LittleEndian::write_u64(&mut phdr[32..40], filesz as u64);
+    LittleEndian::write_u64(&mut phdr[40..48], memsz as u64);
+    LittleEndian::write_u64(&mut phdr[48..56], align as u64);
+}
+
+/* Copies the page at obj_offset into the core file at core_offset. Pages that were never touched
+or only hold zeroes are left out, as they read as zeroes anyway. */
+fn write_object_page(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_table: &mut FrameTable,
+    object: &AnonymousMemoryObject,
+    obj_offset: usize,
+    core: &mut AnonymousMemoryObject,
+    core_offset: usize,
+    scratch: &mut Option<(LargeFrameRef, sel4::cap::UnspecifiedPage)>,
+) -> Result<(), sel4::Error> {
+    let mut page = [0; PAGE_SIZE_4K];
+
+    if let Some(frame) = object.lookup_frame(obj_offset) {
+        page.copy_from_slice(frame_table.frame_data(frame.frame_ref));
+    } else if let Some(block) = object.lookup_swapped(obj_offset) {
+        /* Pages in swap are left there, the core file refers to the same block */
+        core.insert_swapped_at(core_offset, block)?;
+        swap_area().unwrap().share_block(block);
+        return Ok(());
+    } else if let Some(frame) = object.lookup_large_frame(obj_offset) {
+        if !scratch.as_ref().is_some_and(|(x, _)| *x == frame.frame_ref) {
+            unmap_scratch(cspace, scratch);
+            *scratch = Some((frame.frame_ref, map_scratch(cspace, ut_table, frame.cap)?));
+        }
+
+        // Safety: The large frame was mapped at SCRATCH above
+        page.copy_from_slice(unsafe {
+            core::slice::from_raw_parts(
+                (vmem_layout::SCRATCH + obj_offset % PAGE_SIZE_2M) as *const u8,
+                PAGE_SIZE_4K,
+            )
+        });
+    } else {
+        return Ok(());
+    }
+
+    if page.iter().all(|x| *x == 0) {
+        return Ok(());
+    }
+
+    return write_object_bytes(cspace, ut_table, frame_table, core, core_offset, &page);
+}
+
+/* Large frames aren't mapped into the root server, so a copy of the cap is mapped at SCRATCH
+while the frame is being dumped */
+fn map_scratch(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    cap: sel4::cap::LargePage,
+) -> Result<sel4::cap::UnspecifiedPage, sel4::Error> {
+    let copy = cspace.alloc_cap::<sel4::cap_type::UnspecifiedPage>()?;
+    cspace
+        .root_cnode()
+        .absolute_cptr(copy)
+        .copy(
+            &cspace.root_cnode().absolute_cptr(cap),
+            sel4::CapRights::read_only(),
+        )
+        .map_err(|e| {
+            cspace.free_cap(copy);
+            e
+        })?;
+
+    map_large_frame(
+        cspace,
+        ut_table,
+        copy,
+        sel4::init_thread::slot::VSPACE.cap(),
+        vmem_layout::SCRATCH,
+        sel4::CapRights::read_only(),
+        sel4::VmAttributes::DEFAULT,
+        None,
+    )
+    .map_err(|e| {
+        cspace.delete_cap(copy).expect("Failed to delete cap");
+        cspace.free_cap(copy);
+        e
+    })?;
+
+    return Ok(copy);
+}
+
+fn unmap_scratch(
+    cspace: &mut CSpace,
+    scratch: &mut Option<(LargeFrameRef, sel4::cap::UnspecifiedPage)>,
+) {
+    /* Deleting the copy of the cap unmaps it */
+    if let Some((_, cap)) = scratch.take() {
+        cspace.delete_cap(cap).expect("Failed to delete cap");
+        cspace.free_cap(cap);
+    }
+}
+
+/* Copies what can be seen through a view that covers size bytes of its window into the core file
+at data_offset */
+fn write_view_contents(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_table: &mut FrameTable,
+    view: &View,
+    object: &AnonymousMemoryObject,
+    size: usize,
+    core: &mut AnonymousMemoryObject,
+    data_offset: usize,
+) -> Result<(), sel4::Error> {
+    let mut scratch = None;
+    let mut ret = Ok(());
+
+    for offset in (view.win_offset..size).step_by(PAGE_SIZE_4K) {
+        ret = write_object_page(
+            cspace,
+            ut_table,
+            frame_table,
+            object,
+            view.obj_offset + offset - view.win_offset,
+            core,
+            data_offset + offset,
+            &mut scratch,
+        );
+        if ret.is_err() {
+            break;
+        }
+    }
+
+    unmap_scratch(cspace, &mut scratch);
+    return ret;
+}
+
+fn write_core_dump(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_table: &mut FrameTable,
+    p: &UserProcess,
+    signal: u16,
+    core: &mut AnonymousMemoryObject,
+) -> Result<usize, sel4::Error> {
+    let parent = p.parent.unwrap_or(0) as u32;
+
+    /* One note for each thread. Debuggers need each thread to have a distinct id, so the tid of
+    other threads is packed in above the pid. */
+    let mut notes = Vec::new();
+    notes.push(prstatus_note(p.tcb.0, p.pid as u32, parent, signal)?);
+    for (i, thread) in p.threads.iter().enumerate() {
+        if let Some(ThreadType::ActiveThread(x)) = thread {
+            let id = (idx_to_tid(i) << 16 | p.pid) as u32;
+            notes.push(prstatus_note(x.tcb.0, id, parent, signal)?);
+        }
+    }
+
+    let phnum = 1 + p.windows.len();
+    let notes_offset = EHDR_SIZE + phnum * PHDR_SIZE;
+    let notes_size = notes.len() * NOTE_SIZE;
+
+    let mut headers = Vec::new();
+    headers.resize(notes_offset, 0);
+
+    /* ELF header */
+    headers[0..4].copy_from_slice(&elf::abi::ELFMAGIC);
+    headers[elf::abi::EI_CLASS] = elf::abi::ELFCLASS64;
+    headers[elf::abi::EI_DATA] = elf::abi::ELFDATA2LSB;
+    headers[elf::abi::EI_VERSION] = elf::abi::EV_CURRENT;
+    LittleEndian::write_u16(&mut headers[16..18], elf::abi::ET_CORE);
+    LittleEndian::write_u16(&mut headers[18..20], elf::abi::EM_AARCH64);
+    LittleEndian::write_u32(&mut headers[20..24], elf::abi::EV_CURRENT as u32);
+    LittleEndian::write_u64(&mut headers[32..40], EHDR_SIZE as u64);
+    LittleEndian::write_u16(&mut headers[52..54], EHDR_SIZE as u16);
+    LittleEndian::write_u16(&mut headers[54..56], PHDR_SIZE as u16);
+    LittleEndian::write_u16(&mut headers[56..58], phnum as u16);
+
+    write_phdr(
+        &mut headers[EHDR_SIZE..EHDR_SIZE + PHDR_SIZE],
+        elf::abi::PT_NOTE,
+        0,
+        notes_offset,
+        0,
+        notes_size,
+        0,
+        4,
+    );
+
+    /* The contents of each window start on a page boundary after the notes */
+    let mut data_offset = ROUND_UP(
+        notes_offset + notes_size,
+        sel4_sys::seL4_PageBits.try_into().unwrap(),
+    );
+
+    for (i, window) in p.windows.iter().enumerate() {
+        let window = window.borrow();
+        let mut flags = 0;
+        let mut filesz = 0;
+
+        /* Only memory that the root server backs with frames from the frame table is dumped.
+        @alwin: Views of objects provided by other servers could be included by mapping in the
+        caps of the view */
+        if let Some(view) = window.bound_view.as_ref() {
+            let view = view.borrow();
+            if view.rights.read() {
+                flags |= elf::abi::PF_R;
+            }
+            if view.rights.write() {
+                flags |= elf::abi::PF_W;
+            }
+
+            let object = view.bound_object.as_ref().filter(|x| {
+                x.borrow().dma_allocation.is_none()
+                    && !x.borrow().attributes.has(ObjAttributes::DEVICE)
+            });
+
+            if let Some(object) = object {
+                filesz = window.size;
+                write_view_contents(
+                    cspace,
+                    ut_table,
+                    frame_table,
+                    &view,
+                    &object.borrow(),
+                    window.size,
+                    core,
+                    data_offset,
+                )?;
+            }
+        }
+
+        let phdr_offset = EHDR_SIZE + (i + 1) * PHDR_SIZE;
+        write_phdr(
+            &mut headers[phdr_offset..phdr_offset + PHDR_SIZE],
+            elf::abi::PT_LOAD,
+            flags,
+            data_offset,
+            window.start,
+            filesz,
+            window.size,
+            PAGE_SIZE_4K,
+        );
+        data_offset += filesz;
+    }
+
+    write_object_bytes(cspace, ut_table, frame_table, core, 0, &headers)?;
+    for (i, note) in notes.iter().enumerate() {
+        write_object_bytes(
+            cspace,
+            ut_table,
+            frame_table,
+            core,
+            notes_offset + i * NOTE_SIZE,
+            note,
+        )?;
+    }
+
+    return Ok(data_offset);
+}
+
+/* Creates an ELF core file describing the threads and memory of a process that is about to be
+destroyed. Returns the object holding the core file along with the size of the file.
+@alwin: This can use a lot of memory for processes with large windows */
+pub fn create_core_dump(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_table: &mut FrameTable,
+    p: &UserProcess,
+    signal: u16,
+) -> Result<(AnonymousMemoryObject, usize), sel4::Error> {
+    let mut core = AnonymousMemoryObject::new(
+        0,
+        sel4::CapRightsBuilder::none().read(true).build(),
+        ObjAttributes::DEFAULT,
+    );
+
+    match write_core_dump(cspace, ut_table, frame_table, p, signal, &mut core) {
+        Ok(size) => {
+            core.size = ROUND_UP(size, sel4_sys::seL4_PageBits.try_into().unwrap());
+            return Ok((core, size));
+        }
+        Err(e) => {
+            core.cleanup_frame_table(cspace, frame_table);
+            return Err(e);
+        }
+    }
+}
@@ -337,6 +337,21 @@ impl UserCSpace {
         return Ok(new_cspace);
     }
 
+    /* Deletes every cap in the cspace apart from the ones in keep. Like destroy, this has to go
+    through every slot as we don't know what the user does with the cspace. */
+    pub fn clear(&mut self, keep: &[usize]) {
+        for i in 1..BIT(CNODE_SIZE_BITS) {
+            if keep.contains(&i) {
+                continue;
+            }
+
+            self.delete(i).expect("Failed to delete cap");
+            if i < CNODE_SLOTS(self.top_lvl_size_bits) {
+                bf_clr_bit(&mut self.top_bf, i);
+            }
+        }
+    }
+
     pub fn destroy(&mut self, bootstrap: &mut CSpace, ut_table: &mut UTTable) {
         /* We have to go through and delete everything because we don't know what the user
         does with the cspace */
@@ -57,6 +57,7 @@ impl DMAPool {
                 sel4::CapRights::all(),
                 sel4::VmAttributes::DEFAULT,
                 None,
+                None,
             )?;
         }
 
@@ -81,6 +82,11 @@ impl DMAPool {
         return self.pstart + (alloc.offset as usize) * PAGE_SIZE_4K;
     }
 
+    /* The address that an allocation is mapped at in the root server */
+    pub fn allocation_vaddr(&self, alloc: &Allocation) -> usize {
+        return self.phys_to_virt(self.allocation_paddr(alloc));
+    }
+
     pub fn allocate_contig_pages(
         &mut self,
         n_pages: u32,
@@ -101,4 +107,9 @@ impl DMAPool {
 
         return Ok((alloc, vec));
     }
+
+    /* Gives an allocation that nothing refers to anymore back to the pool */
+    pub fn free_contig_pages(&mut self, alloc: Allocation) {
+        self.allocation_table.as_mut().unwrap().free(alloc);
+    }
 }
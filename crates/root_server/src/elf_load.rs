@@ -1,16 +1,18 @@
 use crate::cspace::{CSpace, CSpaceTrait};
 use crate::frame_table::FrameTable;
-use crate::mapping::map_frame;
+use crate::mapping::{map_frame, PageTableRegistry};
 use crate::object::{AnonymousMemoryObject, MAX_OBJ_SIZE};
 use crate::page::PAGE_SIZE_4K;
 use crate::ut::UTTable;
 use crate::view::View;
+use crate::vmem_layout::PROCESS_PIE_BASE;
 use crate::window::Window;
 use alloc::rc::Rc;
 use alloc::vec::Vec;
 use core::cell::RefCell;
 use smos_common::obj_attributes::ObjAttributes;
-use smos_common::util::ROUND_DOWN;
+use smos_common::reloc::relative_relocations;
+use smos_common::util::{ROUND_DOWN, ROUND_UP};
 
 fn rights_from_elf_flags(flags: u32) -> sel4::CapRights {
     let mut builder = sel4::CapRightsBuilder::none();
@@ -28,133 +30,121 @@ fn rights_from_elf_flags(flags: u32) -> sel4::CapRights {
     return builder.build();
 }
 
-fn overlapping_window(
-    windows: &Vec<Rc<RefCell<Window>>>,
+/* A page-aligned part of the address space that one or more segments are loaded into */
+struct LoadRegion {
     start: usize,
-    size: usize,
-) -> Option<Rc<RefCell<Window>>> {
-    for window in windows {
-        if (start >= window.borrow().start && start < window.borrow().start + window.borrow().size)
-            || (start + size >= window.borrow().start
-                && start + size < window.borrow().start + window.borrow().size)
-        {
-            return Some(window.clone());
+    end: usize,
+    flags: u32,
+}
+
+/* Windows are page-aligned, so segments that share a page have to be loaded into the same window.
+Merge them into a single region which has the combined rights of all of them. The segments must be
+sorted by their virtual address. */
+fn load_regions(segments: &Vec<elf::segment::ProgramHeader>) -> Vec<LoadRegion> {
+    let mut regions: Vec<LoadRegion> = Vec::new();
+
+    for segment in segments {
+        let start = ROUND_DOWN(
+            segment.p_vaddr.try_into().unwrap(),
+            sel4_sys::seL4_PageBits.try_into().unwrap(),
+        );
+        let end = ROUND_UP(
+            (segment.p_vaddr + segment.p_memsz).try_into().unwrap(),
+            sel4_sys::seL4_PageBits.try_into().unwrap(),
+        );
+
+        match regions.last_mut() {
+            Some(region) if start < region.end => {
+                log_rs!(
+                    "Merging segment at 0x{:x} with the segment before it",
+                    segment.p_vaddr
+                );
+                region.end = usize::max(region.end, end);
+                region.flags |= segment.p_flags;
+            }
+            _ => regions.push(LoadRegion {
+                start: start,
+                end: end,
+                flags: segment.p_flags,
+            }),
         }
     }
 
-    return None;
+    return regions;
 }
 
-fn handle_overlapping_segment(
-    window: Rc<RefCell<Window>>,
-    segment: &elf::segment::ProgramHeader,
-    _data: &[u8],
+/* Frees the frames backing windows that a program or its stack was loaded into, along with the
+slots of the caps that were mapped into the loadee. Page tables are left for the owner of the page
+table registry to free. */
+pub fn free_loaded_windows(
+    cspace: &mut CSpace,
+    frame_table: &mut FrameTable,
+    windows: &[Rc<RefCell<Window>>],
 ) {
-    log_rs!("Dealing with an overlapping region");
-    // @alwin: We should make sure no segments actually overlap in terms of their precise virtual addresses.
-    // This is a bit annoying because, windows are all page-aligned. For us to check that the elf segments
-    // themselves don't overlap, we will need to keep some extra book-keeping and have more segments.
-
-    // Check that the rights of this segment are the same as the existing window that overlaps with it
-    if rights_from_elf_flags(segment.p_flags)
-        != window.borrow().bound_view.as_ref().unwrap().borrow().rights
-    {
-        todo!();
-    }
+    for window in windows {
+        let window = window.borrow();
+        let view = window.bound_view.as_ref().unwrap();
+
+        /* Clean up the memory object by freeing the frames in it */
+        view.borrow()
+            .bound_object
+            .as_ref()
+            .unwrap()
+            .borrow_mut()
+            .cleanup_frame_table(cspace, frame_table);
 
-    /* If the segment doesn't have any data, we don't need to do anything. */
-    if segment.p_filesz == 0 {
-        return;
+        /* The above should delete these caps, just need to free the slots */
+        view.borrow_mut().cleanup_cap_table(cspace, false);
     }
-
-    /* If the segment does contain data, we need to copy it into the right part of the frame,
-    making sure we don't overwrite anything that has been written for the other segment */
-    // @alwin: Cross this bridge if/when we get there :p
-    todo!();
 }
 
-fn load_segment_into_vspace(
+/* Creates a window backed by zeroed frames covering [start, start + size). The window is added to
+windows before it is filled in, so it is there to be freed even if this fails part way. */
+fn load_region_into_vspace(
     cspace: &mut CSpace,
     ut_table: &mut UTTable,
     frame_table: &mut FrameTable,
     windows: &mut Vec<Rc<RefCell<Window>>>,
     vspace: sel4::cap::VSpace,
-    segment: &elf::segment::ProgramHeader,
-    data: &[u8],
+    page_tables: &mut PageTableRegistry,
+    start: usize,
+    size: usize,
+    rights: sel4::CapRights,
 ) -> Result<(), sel4::Error> {
-    let mut pos: usize = 0;
-    let mut curr_vaddr: usize = segment.p_vaddr.try_into().unwrap();
-    let mut curr_offset: usize = 0;
-    let total_size = segment.p_memsz
-        + (segment.p_vaddr % PAGE_SIZE_4K as u64)
-        + (PAGE_SIZE_4K as u64 - ((segment.p_vaddr + segment.p_memsz) % PAGE_SIZE_4K as u64));
-
-    log_rs!(
-        "Loading segment of type {} => 0x{:x} - 0x{:x}",
-        segment.p_type,
-        segment.p_vaddr,
-        segment.p_vaddr + segment.p_memsz
-    );
-
-    match overlapping_window(
-        windows,
-        segment.p_vaddr.try_into().unwrap(),
-        segment.p_memsz.try_into().unwrap(),
-    ) {
-        Some(win) => {
-            handle_overlapping_segment(win, segment, data);
-            return Ok(());
-        }
-        None => {}
-    };
-
-    if total_size as usize > MAX_OBJ_SIZE {
-        err_rs!("@alwin: Deal with the case where segment size is to large");
-        todo!();
-    }
-
-    /* Create a window corresponding to this segment */
+    /* Create a window corresponding to this region */
     let window = Rc::new(RefCell::new(Window {
-        start: ROUND_DOWN(
-            segment.p_vaddr.try_into().unwrap(),
-            sel4_sys::seL4_PageBits.try_into().unwrap(),
-        ),
-        size: total_size.try_into().unwrap(),
+        start: start,
+        size: size,
         bound_view: None,
     }));
 
-    /* Create a memory object corresponding to this segment */
+    /* Create a memory object corresponding to this region */
     let object = Rc::new(RefCell::new(AnonymousMemoryObject::new(
-        total_size.try_into().unwrap(),
-        rights_from_elf_flags(segment.p_flags),
+        size,
+        rights.clone(),
         ObjAttributes::DEFAULT,
     )));
 
-    /* Create a view corresponding to this segment */
+    /* Create a view corresponding to this region */
     let view = Rc::new(RefCell::new(View::new(
         window.clone(),
         Some(object.clone()),
         None,
-        rights_from_elf_flags(segment.p_flags),
+        rights.clone(),
         0,
         0,
     )));
 
     window.borrow_mut().bound_view = Some(view.clone());
     object.borrow_mut().associated_views.push(view.clone());
+    windows.push(window);
 
-    /* This counter keeps track of the index into the memory object */
-    let mut i: usize = 0;
-
-    while pos < segment.p_memsz.try_into().unwrap() {
-        let loadee_vaddr = ROUND_DOWN(
-            curr_vaddr.try_into().unwrap(),
-            sel4_sys::seL4_PageBits.try_into().unwrap(),
-        );
-
+    for i in 0..(size / PAGE_SIZE_4K) {
         let frame_ref = frame_table
             .alloc_frame(cspace, ut_table)
             .ok_or(sel4::Error::NotEnoughMemory)?;
+        frame_table.frame_data(frame_ref).fill(0);
+
         let orig_frame_cap = frame_table.frame_from_ref(frame_ref).get_cap();
         object
             .borrow_mut()
@@ -167,9 +157,7 @@ fn load_segment_into_vspace(
             .root_cnode()
             .absolute_cptr(loadee_frame)
             .copy(
-                &cspace
-                    .root_cnode()
-                    .absolute_cptr(frame_table.frame_from_ref(frame_ref).get_cap()),
+                &cspace.root_cnode().absolute_cptr(orig_frame_cap),
                 sel4::CapRightsBuilder::all().build(),
             )
             .expect("Failed to copy frame capability into loadee frame cslot");
@@ -177,92 +165,192 @@ fn load_segment_into_vspace(
             .insert_cap_at(i * PAGE_SIZE_4K, loadee_frame.cast())
             .expect("Failed to insert into view");
 
-        match map_frame(
+        map_frame(
             cspace,
             ut_table,
             loadee_frame,
             vspace,
-            loadee_vaddr,
-            view.borrow().rights.clone(),
+            start + i * PAGE_SIZE_4K,
+            rights.clone(),
             sel4::VmAttributes::DEFAULT,
             None,
-        ) {
-            Ok(_) => {}
-            Err(e) => match e {
-                // @alwin: This won't get triggered as you would expect because overmapping does
-                // not return an error on aarch64.
-                sel4::Error::DeleteFirst => {
-                    cspace
-                        .delete(loadee_frame.bits().try_into().unwrap())
-                        .unwrap();
-                    cspace.free_slot(loadee_frame.bits().try_into().unwrap());
-                    frame_table.free_frame(frame_ref);
-                }
-                _ => return Err(e),
-            },
-        }
+            Some(&mut *page_tables),
+        )?;
+    }
 
-        /* Copy over the data */
-        let frame_data = frame_table.frame_data(frame_ref);
-        let mut frame_offset: usize = 0;
-
-        let leading_zeroes: usize = curr_vaddr as usize % PAGE_SIZE_4K;
-        frame_data[frame_offset..leading_zeroes].fill(0);
-        frame_offset += leading_zeroes;
-
-        let segment_bytes = PAGE_SIZE_4K - leading_zeroes;
-        if pos < segment.p_filesz.try_into().unwrap() {
-            /* Copy data from the ELF into the region */
-            let file_bytes = usize::min(segment_bytes, segment.p_filesz as usize - pos);
-            frame_data[frame_offset..frame_offset + file_bytes]
-                .copy_from_slice(&data[curr_offset..curr_offset + file_bytes]);
-            frame_offset += file_bytes;
-
-            /* Fill in the rest of the frame with zeroes */
-            frame_data[frame_offset..PAGE_SIZE_4K].fill(0);
-        } else {
-            frame_data.fill(0);
-        }
+    Ok(())
+}
 
-        pos += segment_bytes;
-        curr_vaddr += segment_bytes;
-        curr_offset += segment_bytes;
-        i += 1
-    }
+/* Copies data into the frames backing the loaded windows, starting at vaddr */
+fn write_loaded_bytes(
+    frame_table: &mut FrameTable,
+    windows: &Vec<Rc<RefCell<Window>>>,
+    vaddr: usize,
+    data: &[u8],
+) -> Result<(), sel4::Error> {
+    let mut pos: usize = 0;
 
-    /* Add the window to the vector */
-    windows.push(window);
+    while pos < data.len() {
+        let curr_vaddr = vaddr + pos;
+        let window = windows
+            .iter()
+            .find(|x| {
+                curr_vaddr >= x.borrow().start && curr_vaddr < x.borrow().start + x.borrow().size
+            })
+            .ok_or(sel4::Error::InvalidArgument)?;
+        let offset = curr_vaddr - window.borrow().start;
+
+        let frame_ref = window
+            .borrow()
+            .bound_view
+            .as_ref()
+            .unwrap()
+            .borrow()
+            .bound_object
+            .as_ref()
+            .unwrap()
+            .borrow()
+            .lookup_frame(ROUND_DOWN(
+                offset,
+                sel4_sys::seL4_PageBits.try_into().unwrap(),
+            ))
+            .ok_or(sel4::Error::InvalidArgument)?
+            .frame_ref;
+
+        /* Copy as much as fits into the rest of this frame */
+        let frame_offset = offset % PAGE_SIZE_4K;
+        let len = usize::min(PAGE_SIZE_4K - frame_offset, data.len() - pos);
+        frame_table.frame_data(frame_ref)[frame_offset..frame_offset + len]
+            .copy_from_slice(&data[pos..pos + len]);
+
+        pos += len;
+    }
 
-    Ok(())
+    return Ok(());
 }
 
+/* Loads an ELF file into a virtual address space and returns the windows it was loaded into along
+with its entry point. Position-independent executables are relocated to PROCESS_PIE_BASE. If this
+fails, the windows are freed, but the page tables that were added to page_tables are not. */
 pub fn load_elf(
     cspace: &mut CSpace,
     ut_table: &mut UTTable,
     frame_table: &mut FrameTable,
     vspace: sel4::cap::VSpace,
+    page_tables: &mut PageTableRegistry,
     elf: &elf::ElfBytes<elf::endian::AnyEndian>,
-) -> Result<Vec<Rc<RefCell<Window>>>, sel4::Error> {
+) -> Result<(Vec<Rc<RefCell<Window>>>, u64), sel4::Error> {
     let mut windows = Vec::<Rc<RefCell<Window>>>::new();
 
+    return match load_elf_into_windows(
+        cspace,
+        ut_table,
+        frame_table,
+        vspace,
+        page_tables,
+        elf,
+        &mut windows,
+    ) {
+        Ok(entry_point) => Ok((windows, entry_point)),
+        Err(e) => {
+            free_loaded_windows(cspace, frame_table, &windows);
+            Err(e)
+        }
+    };
+}
+
+fn load_elf_into_windows(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_table: &mut FrameTable,
+    vspace: sel4::cap::VSpace,
+    page_tables: &mut PageTableRegistry,
+    elf: &elf::ElfBytes<elf::endian::AnyEndian>,
+    windows: &mut Vec<Rc<RefCell<Window>>>,
+) -> Result<u64, sel4::Error> {
+    // @alwin: The base should be randomised once we have a source of entropy
+    let bias: u64 = match elf.ehdr.e_type {
+        elf::abi::ET_EXEC => 0,
+        elf::abi::ET_DYN => PROCESS_PIE_BASE as u64,
+        _ => return Err(sel4::Error::InvalidArgument),
+    };
+
+    let mut segments = Vec::new();
     for segment in elf.segments().ok_or(sel4::Error::InvalidArgument)?.iter() {
-        if segment.p_type != elf::abi::PT_LOAD && segment.p_type != elf::abi::PT_TLS {
+        /* The initial TLS image lives inside a PT_LOAD segment, so there is nothing to load for
+        PT_TLS segments */
+        if segment.p_type != elf::abi::PT_LOAD || segment.p_memsz == 0 {
             continue;
         }
 
+        if segment.p_filesz > segment.p_memsz {
+            err_rs!(
+                "Segment at 0x{:x} has more data than memory",
+                segment.p_vaddr
+            );
+            return Err(sel4::Error::InvalidArgument);
+        }
+
+        let mut segment = segment;
+        segment.p_vaddr += bias;
+        segments.push(segment);
+    }
+    segments.sort_by_key(|x| x.p_vaddr);
+
+    for region in load_regions(&segments) {
+        log_rs!(
+            "Loading region => 0x{:x} - 0x{:x}",
+            region.start,
+            region.end
+        );
+
+        /* Regions that are larger than a single object can be are split across several windows */
+        let mut start = region.start;
+        while start < region.end {
+            let size = usize::min(region.end - start, MAX_OBJ_SIZE);
+            load_region_into_vspace(
+                cspace,
+                ut_table,
+                frame_table,
+                windows,
+                vspace,
+                page_tables,
+                start,
+                size,
+                rights_from_elf_flags(region.flags),
+            )?;
+            start += size;
+        }
+    }
+
+    /* Copy the contents of each segment into the frames backing it. Anything past the data in
+    the file is left zeroed. */
+    for segment in &segments {
         let data = elf
-            .segment_data(&segment)
-            .expect("Could not get segment data");
-        load_segment_into_vspace(
-            cspace,
-            ut_table,
+            .segment_data(segment)
+            .or(Err(sel4::Error::InvalidArgument))?;
+        write_loaded_bytes(
             frame_table,
-            &mut windows,
-            vspace,
-            &segment,
-            data,
+            windows,
+            segment.p_vaddr.try_into().unwrap(),
+            &data[..(segment.p_filesz as usize)],
         )?;
     }
 
-    Ok(windows)
+    if elf.ehdr.e_type == elf::abi::ET_DYN {
+        let relocations = relative_relocations(elf).map_err(|e| {
+            err_rs!("Failed to relocate ELF file: {:?}", e);
+            sel4::Error::InvalidArgument
+        })?;
+        for (offset, addend) in relocations {
+            write_loaded_bytes(
+                frame_table,
+                windows,
+                (bias + offset).try_into().unwrap(),
+                &(bias + addend).to_le_bytes(),
+            )?;
+        }
+    }
+
+    Ok(elf.ehdr.e_entry + bias)
 }
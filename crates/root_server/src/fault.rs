@@ -1,11 +1,17 @@
+use crate::coredump::{create_core_dump, fault_signal};
 use crate::cspace::CSpace;
 use crate::frame_table::FrameTable;
-use crate::proc::{procs_get_mut, ProcessType, UserProcess};
+use crate::handle::RootServerResource;
+use crate::proc::{handle_process_exit, procs_get, ProcessType, UserProcess};
+use crate::thread::thread_tcb;
 use crate::ut::UTTable;
+use crate::util::dealloc_retyped;
 use crate::vm::handle_vm_fault;
 use crate::RSReplyWrapper;
 use sel4::Fault;
-use smos_server::reply::handle_fault_reply;
+use smos_common::returns::{ExitStatus, FaultKind};
+use smos_server::handle_capability::HandleCapabilityTable;
+use smos_server::reply::{handle_fault_reply, FaultReply};
 
 // fn handle_vm_fault(fault_info: sel4::VmFault, msg : sel4::MessageInfo, pid: usize) {
 //     log_rs!("Handling VM fault");
@@ -16,24 +22,131 @@ use smos_server::reply::handle_fault_reply;
 // fsr: {:x}", fault_info.ip(), fault_info.addr(), fault_info.fsr());
 // }
 
+fn fault_kind(fault: &sel4::Fault) -> FaultKind {
+    return match fault {
+        sel4::Fault::VmFault(_) => FaultKind::VmFault,
+        sel4::Fault::CapFault(_) => FaultKind::CapFault,
+        sel4::Fault::UnknownSyscall(_) => FaultKind::UnknownSyscall,
+        sel4::Fault::UserException(_) => FaultKind::UserException,
+        sel4::Fault::Timeout(_) => FaultKind::Timeout,
+        _ => FaultKind::Other,
+    };
+}
+
 pub fn handle_fault(
     cspace: &mut CSpace,
     frame_table: &mut FrameTable,
     ut_table: &mut UTTable,
+    handle_cap_table: &mut HandleCapabilityTable<RootServerResource>,
     reply: RSReplyWrapper,
     msg: sel4::MessageInfo,
     pid: usize,
+    tid: usize,
 ) -> Option<sel4::MessageInfo> {
-    let proc_type: &mut ProcessType = &mut procs_get_mut(pid)
-        .as_mut()
-        .expect("Was called with an invalid badge")
-        .borrow_mut();
+    let fault = sel4::with_ipc_buffer(|buf| Fault::new(buf, &msg));
+    return resolve_fault(
+        cspace,
+        frame_table,
+        ut_table,
+        handle_cap_table,
+        reply,
+        fault,
+        pid,
+        tid,
+        true,
+    );
+}
+
+/* Handles a VM fault that was parked while the swap area was busy again. The faulting thread is
+replied to directly, since the reply object isn't the one the syscall loop is using. */
+pub fn retry_vm_fault(
+    cspace: &mut CSpace,
+    frame_table: &mut FrameTable,
+    ut_table: &mut UTTable,
+    handle_cap_table: &mut HandleCapabilityTable<RootServerResource>,
+    reply: RSReplyWrapper,
+    fault: sel4::VmFault,
+    pid: usize,
+    tid: usize,
+) {
+    let msginfo = resolve_fault(
+        cspace,
+        frame_table,
+        ut_table,
+        handle_cap_table,
+        reply,
+        sel4::Fault::VmFault(fault),
+        pid,
+        tid,
+        false,
+    );
+
+    if let Some(msginfo) = msginfo {
+        reply.0.send(msginfo);
+        dealloc_retyped(cspace, ut_table, reply);
+    }
+}
+
+fn resolve_fault(
+    cspace: &mut CSpace,
+    frame_table: &mut FrameTable,
+    ut_table: &mut UTTable,
+    handle_cap_table: &mut HandleCapabilityTable<RootServerResource>,
+    reply: RSReplyWrapper,
+    fault: sel4::Fault,
+    pid: usize,
+    tid: usize,
+    first_attempt: bool,
+) -> Option<sel4::MessageInfo> {
+    /* A thread of a process that has already been destroyed is never resumed, so the fault is
+    dropped */
+    let proc_rc = match procs_get(pid).as_ref() {
+        Some(x) => x.clone(),
+        None => {
+            warn_rs!("Fault from a process that no longer exists");
+            dealloc_retyped(cspace, ut_table, reply);
+            return None;
+        }
+    };
+    let proc_type: &mut ProcessType = &mut proc_rc.borrow_mut();
     let mut p: &mut UserProcess = match proc_type {
         ProcessType::ActiveProcess(x) => x,
-        ProcessType::ZombieProcess(_) => panic!("Zombie process faulted"),
+        ProcessType::ZombieProcess(..) => {
+            warn_rs!("Fault from a zombie process");
+            dealloc_retyped(cspace, ut_table, reply);
+            return None;
+        }
+    };
+    let tcb = match thread_tcb(p, tid) {
+        Some(x) => x,
+        None => {
+            warn_rs!("Fault from a thread that no longer exists");
+            dealloc_retyped(cspace, ut_table, reply);
+            return None;
+        }
     };
 
-    let fault = sel4::with_ipc_buffer(|buf| Fault::new(buf, &msg));
+    /* A process with a fault handler only faults into the root server when the handler forwarded
+    the fault, so the next fault of the thread goes to the handler again */
+    if first_attempt && p.delegate_thread_faults(cspace, tcb, tid).is_err() {
+        warn_rs!(
+            "Failed to give the faults of process {} back to its fault handler",
+            pid
+        );
+    }
+
+    let signal = fault_signal(&fault);
+
+    /* How the process is reported to have exited if the fault can't be resolved */
+    let status = ExitStatus::Faulted {
+        kind: fault_kind(&fault),
+        addr: match &fault {
+            sel4::Fault::VmFault(f) => f.addr() as usize,
+            _ => tcb
+                .tcb_read_registers(false, 1)
+                .map_or(0, |ctx| *ctx.pc() as usize),
+        },
+    };
 
     let ret = match fault {
         sel4::Fault::NullFault(_)
@@ -44,10 +157,46 @@ pub fn handle_fault(
         | sel4::Fault::VCpuFault(_)
         | sel4::Fault::Timeout(_)
         | sel4::Fault::VPpiEvent(_) => {
-            panic!("Don't know how to handle this kind of fault {:?}!", fault)
+            let pc = status.addr();
+            warn_rs!(
+                "Process {} faulted at pc: 0x{:x} with an unhandled fault {:?}",
+                pid,
+                pc,
+                fault
+            );
+            FaultReply::VMFault { resume: false }
+        }
+        sel4::Fault::VmFault(f) => {
+            handle_vm_fault(cspace, frame_table, ut_table, reply, &mut p, tid, f)?
+        }
+    };
+
+    /* A fault that can't be resolved terminates the process rather than leaving it blocked on
+    its fault endpoint forever */
+    if let FaultReply::VMFault { resume: false } = ret {
+        /* The parent can collect a core file of the process once it has been destroyed, even if
+        it was waiting on the process and has already reaped it. Nobody can collect core files of
+        processes owned by the root server, so they aren't created. */
+        let parent = p.parent.and_then(|x| procs_get(x).clone());
+        if let Some(parent) = parent {
+            match create_core_dump(cspace, ut_table, frame_table, p, signal) {
+                Ok((core_dump, size)) => match &mut *parent.borrow_mut() {
+                    ProcessType::ActiveProcess(x) => {
+                        x.add_core_dump(cspace, frame_table, proc_rc.clone(), core_dump, size)
+                    }
+                    ProcessType::ZombieProcess(..) => panic!("Parent of an active process exited"),
+                },
+                Err(e) => warn_rs!("Failed to create core dump of process {}: {:?}", pid, e),
+            }
         }
-        sel4::Fault::VmFault(f) => handle_vm_fault(cspace, frame_table, ut_table, reply, &mut p, f),
-    }?;
+
+        handle_process_exit(cspace, ut_table, frame_table, handle_cap_table, p, status);
+        *proc_type = ProcessType::ZombieProcess(p.zombie(status));
+
+        /* The faulting thread is gone, so the reply object will never be used */
+        dealloc_retyped(cspace, ut_table, reply);
+        return None;
+    }
 
     sel4::with_ipc_buffer_mut(|ipc_buf| handle_fault_reply(ipc_buf, ret))
 }
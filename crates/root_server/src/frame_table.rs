@@ -1,9 +1,14 @@
 use crate::cspace::{CSpace, CSpaceTrait};
+use crate::large_page::{LargeFrameRef, LargePagePool};
 use crate::mapping::map_frame;
+use crate::object::AnonymousMemoryObject;
 use crate::page::PAGE_SIZE_4K;
 use crate::ut::UTTable;
 use crate::vmem_layout;
+use alloc::collections::btree_map::BTreeMap;
+use alloc::rc::{Rc, Weak};
 use alloc::vec::Vec;
+use core::cell::RefCell;
 use core::mem::size_of;
 use sel4::CPtr;
 use smos_common::util::BIT;
@@ -31,6 +36,10 @@ pub struct Frame {
     prev: Option<FrameRef>,
     next: Option<FrameRef>,
     list_id: ListID,
+    /* The number of objects that the frame is shared between */
+    refcount: u32,
+    /* Whether the frame has been accessed since the pager last looked at it */
+    referenced: bool,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -71,6 +80,22 @@ impl FrameWrapper {
         unsafe { (*self.frame).list_id = list_id };
     }
 
+    fn get_refcount(self: &Self) -> u32 {
+        return unsafe { (*self.frame).refcount };
+    }
+
+    fn set_refcount(self: &Self, refcount: u32) {
+        unsafe { (*self.frame).refcount = refcount };
+    }
+
+    fn get_referenced(self: &Self) -> bool {
+        return unsafe { (*self.frame).referenced };
+    }
+
+    fn set_referenced(self: &Self, referenced: bool) {
+        unsafe { (*self.frame).referenced = referenced };
+    }
+
     fn inner(self: &Self) -> *const Frame {
         return self.frame;
     }
@@ -81,6 +106,8 @@ enum ListID {
     NoList,
     FreeList,
     AllocatedList,
+    /* Frames of anonymous memory objects that can be paged out */
+    PageableList,
 }
 
 #[derive(Copy, Clone)]
@@ -99,7 +126,12 @@ pub struct FrameTable {
     byte_length: usize,
     free: FrameList,
     allocated: FrameList,
+    pageable: FrameList,
+    /* The object and offset that each pageable frame belongs to */
+    pageable_owners: BTreeMap<FrameRef, (Weak<RefCell<AnonymousMemoryObject>>, usize)>,
     vspace: sel4::cap::VSpace,
+    /* Large pages aren't mapped into the root server, so they don't have any frame data */
+    large_pages: LargePagePool,
 }
 
 impl FrameTable {
@@ -130,6 +162,7 @@ impl FrameTable {
         let mut list = match list_id {
             ListID::FreeList => self.free,
             ListID::AllocatedList => self.allocated,
+            ListID::PageableList => self.pageable,
             _ => panic!("Invalid list type"),
         };
 
@@ -156,6 +189,7 @@ impl FrameTable {
         match list_id {
             ListID::FreeList => self.free = list,
             ListID::AllocatedList => self.allocated = list,
+            ListID::PageableList => self.pageable = list,
             _ => panic!("Invalid list type"),
         };
 
@@ -170,6 +204,7 @@ impl FrameTable {
         let mut list = match list_id {
             ListID::FreeList => self.free,
             ListID::AllocatedList => self.allocated,
+            ListID::PageableList => self.pageable,
             _ => panic!("Invalid list type"),
         };
 
@@ -192,6 +227,7 @@ impl FrameTable {
         match list_id {
             ListID::FreeList => self.free = list,
             ListID::AllocatedList => self.allocated = list,
+            ListID::PageableList => self.pageable = list,
             _ => panic!("Invalid list type"),
         };
     }
@@ -204,6 +240,7 @@ impl FrameTable {
         let mut list = match list_id {
             ListID::FreeList => self.free,
             ListID::AllocatedList => self.allocated,
+            ListID::PageableList => self.pageable,
             _ => panic!("Invalid list type"),
         };
 
@@ -222,6 +259,7 @@ impl FrameTable {
                 match list_id {
                     ListID::FreeList => self.free = list,
                     ListID::AllocatedList => self.allocated = list,
+                    ListID::PageableList => self.pageable = list,
                     _ => panic!("Invalid list type"),
                 };
             }
@@ -229,7 +267,7 @@ impl FrameTable {
         }
     }
 
-    pub fn init(vspace: sel4::cap::VSpace) -> Self {
+    pub fn init(vspace: sel4::cap::VSpace, large_pages: LargePagePool) -> Self {
         return FrameTable {
             frames: vmem_layout::FRAME_TABLE as *mut Frame,
             frame_data: vmem_layout::FRAME_DATA as *mut FrameData,
@@ -245,10 +283,18 @@ impl FrameTable {
                 last: None,
                 length: 0,
             },
+            pageable: FrameList {
+                list_id: ListID::PageableList,
+                first: None,
+                last: None,
+                length: 0,
+            },
+            pageable_owners: BTreeMap::new(),
             capacity: 0,
             used: 0,
             byte_length: 0,
             vspace: vspace,
+            large_pages: large_pages,
         };
     }
 
@@ -264,10 +310,80 @@ impl FrameTable {
             frame = Some(self.alloc_fresh_frame(cspace, ut_table).ok()?);
         }
 
+        frame.unwrap().set_refcount(1);
         self.push_back(ListID::AllocatedList, frame.unwrap());
         return Some(self.ref_from_frame(&frame.unwrap()));
     }
 
+    /* Adds another reference to a frame, which then needs to be freed once more before it is
+    actually returned to the free list */
+    pub fn share_frame(self: &mut Self, frame_ref: FrameRef) {
+        /* Shared frames belong to more than one object, so they can't be paged out */
+        self.pin_frame(frame_ref);
+
+        let frame = self.frame_from_ref(frame_ref);
+        assert!(frame.get_list_id() == ListID::AllocatedList);
+        frame.set_refcount(frame.get_refcount() + 1);
+    }
+
+    /* Makes a frame that belongs to the object at offset a candidate for being paged out. The
+    frame starts off as referenced, as it is about to be accessed. */
+    pub fn make_pageable(
+        self: &mut Self,
+        frame_ref: FrameRef,
+        object: &Rc<RefCell<AnonymousMemoryObject>>,
+        offset: usize,
+    ) {
+        let frame = self.frame_from_ref(frame_ref);
+        frame.set_referenced(true);
+
+        if frame.get_list_id() == ListID::PageableList {
+            return;
+        }
+
+        self.remove_frame(ListID::AllocatedList, &frame);
+        self.push_back(ListID::PageableList, frame);
+        self.pageable_owners
+            .insert(frame_ref, (Rc::downgrade(object), offset));
+    }
+
+    /* Stops a frame from being paged out */
+    pub fn pin_frame(self: &mut Self, frame_ref: FrameRef) {
+        let frame = self.frame_from_ref(frame_ref);
+        if frame.get_list_id() != ListID::PageableList {
+            return;
+        }
+
+        self.remove_frame(ListID::PageableList, &frame);
+        self.push_back(ListID::AllocatedList, frame);
+        self.pageable_owners.remove(&frame_ref);
+    }
+
+    pub fn num_pageable(self: &Self) -> usize {
+        return self.pageable.length;
+    }
+
+    pub fn is_referenced(self: &Self, frame_ref: FrameRef) -> bool {
+        return self.frame_from_ref(frame_ref).get_referenced();
+    }
+
+    pub fn set_referenced(self: &mut Self, frame_ref: FrameRef, referenced: bool) {
+        self.frame_from_ref(frame_ref).set_referenced(referenced);
+    }
+
+    /* Advances the clock hand, which is the front of the pageable list, by moving the frame under
+    it to the back. Returns the frame along with the object and offset it belongs to. */
+    pub fn clock_advance(
+        self: &mut Self,
+    ) -> Option<(FrameRef, Weak<RefCell<AnonymousMemoryObject>>, usize)> {
+        let frame = self.pop_front(ListID::PageableList)?;
+        self.push_back(ListID::PageableList, frame);
+
+        let frame_ref = self.ref_from_frame(&frame);
+        let (object, offset) = self.pageable_owners.get(&frame_ref).unwrap().clone();
+        return Some((frame_ref, object, offset));
+    }
+
     // @alwin: This whole function is a disgusting hack
     pub fn alloc_device_mem(
         self: &mut Self,
@@ -330,6 +446,7 @@ impl FrameTable {
         let mut list = match list_id {
             ListID::FreeList => self.free,
             ListID::AllocatedList => self.allocated,
+            ListID::PageableList => self.pageable,
             _ => panic!("Invalid list type"),
         };
 
@@ -361,6 +478,7 @@ impl FrameTable {
         match list_id {
             ListID::FreeList => self.free = list,
             ListID::AllocatedList => self.allocated = list,
+            ListID::PageableList => self.pageable = list,
             _ => panic!("Invalid list type"),
         };
     }
@@ -368,10 +486,33 @@ impl FrameTable {
     pub fn free_frame(self: &mut Self, frame_ref: FrameRef) {
         let frame = self.frame_from_ref(frame_ref);
 
-        self.remove_frame(ListID::AllocatedList, &frame);
+        assert!(frame.get_refcount() > 0);
+        frame.set_refcount(frame.get_refcount() - 1);
+        if frame.get_refcount() != 0 {
+            return;
+        }
+
+        self.remove_frame(frame.get_list_id(), &frame);
+        self.pageable_owners.remove(&frame_ref);
         self.push_front(ListID::FreeList, frame);
     }
 
+    pub fn alloc_large_frame(
+        self: &mut Self,
+        cspace: &mut CSpace,
+    ) -> Option<(sel4::cap::LargePage, LargeFrameRef)> {
+        return self.large_pages.alloc(cspace).ok();
+    }
+
+    pub fn free_large_frame(
+        self: &mut Self,
+        cspace: &mut CSpace,
+        cap: sel4::cap::LargePage,
+        frame_ref: LargeFrameRef,
+    ) {
+        self.large_pages.free(cspace, cap, frame_ref);
+    }
+
     fn alloc_frame_at(
         self: &mut Self,
         cspace: &mut CSpace,
@@ -411,6 +552,7 @@ impl FrameTable {
             sel4::CapRightsBuilder::all().build(),
             sel4::VmAttributes::DEFAULT | sel4::VmAttributes::EXECUTE_NEVER,
             None,
+            None,
         )
         .map_err(|e| {
             // @alwin: I don't like this error handling;
@@ -463,6 +605,8 @@ impl FrameTable {
                 frame.set_list_id(ListID::NoList);
                 frame.set_prev(None);
                 frame.set_next(None);
+                frame.set_refcount(0);
+                frame.set_referenced(false);
                 Ok(frame)
             }
             Err(e) => {
@@ -29,6 +29,7 @@ pub fn initialise_heap(cspace: &mut CSpace, ut_table: &mut UTTable) -> Result<()
             sel4::CapRightsBuilder::all().build(),
             sel4::VmAttributes::DEFAULT,
             None,
+            None,
         )?;
         vaddr += PAGE_SIZE_4K;
     }
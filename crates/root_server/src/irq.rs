@@ -1,4 +1,5 @@
 use crate::{
+    connection::Server,
     cspace::{CSpace, CSpaceTrait},
     handle::RootServerResource,
     proc::UserProcess,
@@ -20,13 +21,15 @@ use smos_server::{
 pub struct IRQRegistration {
     irq_number: usize,
     badge_bit: u8,
+    server: Rc<RefCell<Server>>,
 }
 
 impl IRQRegistration {
-    pub fn new(irq_num: usize, badge_bit: u8) -> Self {
+    pub fn new(irq_num: usize, badge_bit: u8, server: Rc<RefCell<Server>>) -> Self {
         return Self {
             irq_number: irq_num,
             badge_bit: badge_bit,
+            server: server,
         };
     }
 }
@@ -224,6 +227,7 @@ impl UserNotificationDispatch {
                 cspace.free_cap(ntfn.unwrap());
             }
         }
+        self.badged_ntfns.fill(None);
 
         for irq_hndler in self.irq_handler_caps {
             if irq_hndler.is_some() {
@@ -233,6 +237,14 @@ impl UserNotificationDispatch {
                 cspace.free_cap(irq_hndler.unwrap());
             }
         }
+        self.irq_handler_caps.fill(None);
+
+        /* Get rid of any copies of the badged notifications that were handed out to other processes */
+        cspace
+            .root_cnode()
+            .absolute_cptr(self.ntfn.0)
+            .revoke()
+            .expect("Failed to revoke notification");
 
         dealloc_retyped(cspace, ut_table, self.ntfn);
     }
@@ -340,6 +352,26 @@ impl UserNotificationDispatch {
         return Ok((ident_bit.try_into().unwrap(), handler));
     }
 
+    pub fn irq_deregister(&mut self, cspace: &mut CSpace, bit: u8) {
+        let bit: usize = bit.into();
+
+        /* These will already be gone if the dispatch was destroyed first */
+        if let Some(handler) = self.irq_handler_caps[bit].take() {
+            cspace
+                .delete_cap(handler)
+                .expect("Failed to delete IRQ handler cap");
+            cspace.free_cap(handler);
+        }
+
+        if let Some(ntfn) = self.badged_ntfns[bit].take() {
+            cspace
+                .delete_cap(ntfn)
+                .expect("Failed to delete badged notification");
+            cspace.free_cap(ntfn);
+            self.free_ntfn_bit(bit);
+        }
+    }
+
     fn alloc_ntfn_bit(self: &mut Self) -> Result<usize, sel4::Error> {
         let bit = bf_first_free(&self.allocated_bits).map_err(|_| sel4::Error::NotEnoughMemory)?;
         bf_set_bit(&mut self.allocated_bits, bit);
@@ -373,7 +405,11 @@ pub fn handle_irq_register(
 
     let (idx, handle_ref) = p.allocate_handle()?;
 
-    let irq_reg = Rc::new(RefCell::new(IRQRegistration::new(args.irq_num, badge_bit)));
+    let irq_reg = Rc::new(RefCell::new(IRQRegistration::new(
+        args.irq_num,
+        badge_bit,
+        server.clone(),
+    )));
 
     *handle_ref = Some(ServerHandle::new(RootServerResource::IRQRegistration(
         irq_reg,
@@ -386,6 +422,16 @@ pub fn handle_irq_register(
     });
 }
 
+pub fn handle_irq_deregister_internal(cspace: &mut CSpace, irq_reg: Rc<RefCell<IRQRegistration>>) {
+    let badge_bit = irq_reg.borrow().badge_bit;
+    irq_reg
+        .borrow()
+        .server
+        .borrow_mut()
+        .ntfn_dispatch
+        .irq_deregister(cspace, badge_bit);
+}
+
 #[allow(dead_code)] // @alwin: Remove once implemented
 pub fn handle_irq_deregister() {
     todo!();
@@ -0,0 +1,73 @@
+use crate::cspace::{CSpace, CSpaceTrait};
+use smos_common::util::BIT;
+
+/* 32MB is reserved for large pages */
+pub const LARGE_PAGE_RESERVATION_SIZE_BITS: u32 = sel4_sys::seL4_LargePageBits + 4;
+pub const LARGE_PAGE_RESERVATION_NUM_PAGES: usize =
+    BIT((LARGE_PAGE_RESERVATION_SIZE_BITS - sel4_sys::seL4_LargePageBits) as usize);
+
+pub type LargeFrameRef = usize;
+
+/* Hands out large pages from untypeds that were set aside during bootstrap. The untyped table
+only deals with 4K untypeds, which can't be combined into anything bigger. */
+#[derive(Debug, Copy, Clone)]
+pub struct LargePagePool {
+    untypeds: [sel4::cap::Untyped; LARGE_PAGE_RESERVATION_NUM_PAGES],
+    in_use: [bool; LARGE_PAGE_RESERVATION_NUM_PAGES],
+}
+
+impl LargePagePool {
+    pub fn new(untypeds: [sel4::cap::Untyped; LARGE_PAGE_RESERVATION_NUM_PAGES]) -> Self {
+        return LargePagePool {
+            untypeds: untypeds,
+            in_use: [false; LARGE_PAGE_RESERVATION_NUM_PAGES],
+        };
+    }
+
+    /* Retypes one of the free untypeds into a large page. The kernel zeroes the memory as part of
+    the retype. */
+    pub fn alloc(
+        &mut self,
+        cspace: &mut CSpace,
+    ) -> Result<(sel4::cap::LargePage, LargeFrameRef), sel4::Error> {
+        let idx = self
+            .in_use
+            .iter()
+            .position(|x| !*x)
+            .ok_or(sel4::Error::NotEnoughMemory)?;
+
+        let slot = cspace.alloc_slot()?;
+        cspace
+            .untyped_retype(
+                &self.untypeds[idx],
+                sel4::ObjectBlueprint::Arch(sel4::ObjectBlueprintArch::LargePage),
+                slot,
+            )
+            .map_err(|e| {
+                cspace.free_slot(slot);
+                e
+            })?;
+
+        self.in_use[idx] = true;
+
+        return Ok((
+            sel4::CPtr::from_bits(slot.try_into().unwrap()).cast::<sel4::cap_type::LargePage>(),
+            idx,
+        ));
+    }
+
+    /* Deleting the page resets the untyped it came from, so it can be retyped again. Any copies
+    of the cap need to have been revoked by the caller. */
+    pub fn free(
+        &mut self,
+        cspace: &mut CSpace,
+        cap: sel4::cap::LargePage,
+        frame_ref: LargeFrameRef,
+    ) {
+        assert!(self.in_use[frame_ref]);
+
+        cspace.delete_cap(cap).expect("Failed to delete large page");
+        cspace.free_cap(cap);
+        self.in_use[frame_ref] = false;
+    }
+}
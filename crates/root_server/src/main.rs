@@ -12,6 +12,7 @@ mod bootstrap;
 mod cspace;
 mod debug;
 mod dma;
+mod large_page;
 mod limits;
 mod mapping;
 mod page;
@@ -22,17 +23,22 @@ mod util;
 mod printing;
 mod clock;
 mod connection;
+mod coredump;
 mod elf_load;
 mod fault;
 mod frame_table;
 mod handle;
 mod heap;
 mod irq;
+mod mount;
 mod object;
+mod pager;
 mod proc;
 mod stack;
+mod swap;
 mod syscall;
 mod tests;
+mod thread;
 mod view;
 mod vm;
 #[rustfmt::skip]
@@ -54,14 +60,18 @@ use crate::mapping::map_frame;
 use crate::page::PAGE_SIZE_4K;
 use crate::printing::print_init;
 use crate::proc::start_process;
+use crate::swap::{handle_swap_notification, swap_init, SWAP_NTFN_BADGE};
 use crate::syscall::handle_syscall;
 use crate::tests::run_tests;
 use crate::uart::uart_init;
 use crate::ut::{UTTable, UTWrapper};
 use crate::util::alloc_retype;
+use alloc::vec::Vec;
 use dma::DMAPool;
+use large_page::LargePagePool;
 use sel4::BootInfo;
 use sel4_root_task::{declare_root_task, Never};
+use smos_common::sched::SchedParams;
 use smos_server::event::*;
 use smos_server::handle_capability::HandleCapabilityTable;
 // use crate::connection::publish_boot_fs;
@@ -139,14 +149,20 @@ fn syscall_loop(
 
         reply_msg_info = match decode_entry_type(badge.try_into().unwrap()) {
             EntryType::Notification(badge) => {
-                irq_dispatch.handle_irq(badge.into_inner());
+                let badge = badge.into_inner();
+                if badge & SWAP_NTFN_BADGE != 0 {
+                    handle_swap_notification(cspace, frame_table, ut_table, handle_cap_table);
+                }
+                irq_dispatch.handle_irq(badge);
                 None
             }
-            EntryType::Invocation(pid) => {
+            EntryType::Invocation(ident) => {
                 /* We recieved a syscall from something in the system*/
+                let (pid, tid) = split_thread_ident(ident);
                 handle_syscall(
                     msg,
                     pid,
+                    tid,
                     cspace,
                     frame_table,
                     ut_table,
@@ -158,15 +174,22 @@ fn syscall_loop(
                     reply,
                 )
             }
-            EntryType::Fault(pid) => {
+            EntryType::Fault(ident) => {
                 /* We must have recieved a message from a fault handler endpoint */
-                handle_fault(cspace, frame_table, ut_table, reply, msg, pid)
-                /* @alwin: what to actually do when this returns None. This means that the faulting
-                thread won't be resumed, so what we should we do? First of all, we can't use the
-                same reply object, so we should destroy it and allocate a new one (otherwise
-                we get the reply object has an unexecuted reply warning thing, but this might
-                actually just be benign). We should probably also clean up the process instead of
-                just leaving it laying around */
+                /* If this returns None, the fault was either forwarded to a pager, which holds
+                on to the reply object, or the process was terminated, in which case the reply
+                object was destroyed. Either way, a new one is allocated below. */
+                let (pid, tid) = split_thread_ident(ident);
+                handle_fault(
+                    cspace,
+                    frame_table,
+                    ut_table,
+                    handle_cap_table,
+                    reply,
+                    msg,
+                    pid,
+                    tid,
+                )
             }
         };
 
@@ -191,6 +214,7 @@ extern "C" fn main_continued(
     cspace_ptr: *mut CSpace,
     ut_table_ptr: *mut UTTable,
     dma_pool: *mut DMAPool,
+    large_page_pool: *mut LargePagePool,
 ) -> ! {
     log_rs!("Switched to new stack...");
 
@@ -205,16 +229,18 @@ extern "C" fn main_continued(
     let cspace = unsafe { &mut *cspace_ptr };
     let ut_table = unsafe { &mut *ut_table_ptr };
     let dma_pool = unsafe { &mut *dma_pool };
+    let large_page_pool = unsafe { &mut *large_page_pool };
 
     let (ipc_ep, ntfn) = ipc_init(cspace, ut_table).expect("Failed to initialize IPC");
-    let mut frame_table = FrameTable::init(sel4::init_thread::slot::VSPACE.cap());
+    let mut frame_table = FrameTable::init(sel4::init_thread::slot::VSPACE.cap(), *large_page_pool);
 
     let mut irq_dispatch = IRQDispatch::new(
         sel4::init_thread::slot::IRQ_CONTROL.cap(),
         ntfn,
         NTFN_BIT,
-        IRQ_IDENT_BADGE_BITS,
+        IRQ_IDENT_BADGE_BITS & !SWAP_NTFN_BADGE,
     );
+    swap_init(ntfn);
 
     initialise_heap(cspace, ut_table).expect("Failed to initialize heap!");
 
@@ -236,13 +262,20 @@ extern "C" fn main_continued(
         cspace,
         ut_table,
         &mut frame_table,
+        &mut handle_cap_table,
         bootinfo.sched_control().index(0).cap(),
         "boot_file_server",
         ipc_ep,
         BFS_CONTENTS,
         None,
         None,
-        254,
+        Vec::new(),
+        &[],
+        &SchedParams {
+            mcp: 254,
+            ..SchedParams::new(254)
+        },
+        None,
     )
     .expect("Failed to start first process");
 
@@ -276,7 +309,7 @@ fn main(bootinfo: &sel4::BootInfoPtr) -> sel4::Result<Never> {
     sel4::init_thread::slot::TCB.cap().debug_name(b"SMOS:root");
 
     /* Set up CSpce and untyped tables */
-    let (mut cspace, mut ut_table, mut dma_pool) =
+    let (mut cspace, mut ut_table, mut dma_pool, mut large_page_pool) =
         smos_bootstrap(bootinfo).expect("Failed to bootstrap");
 
     /* Setup the uart driver and configure printing with it  */
@@ -308,6 +341,7 @@ fn main(bootinfo: &sel4::BootInfoPtr) -> sel4::Result<Never> {
             sel4::CapRightsBuilder::all().build(),
             sel4::VmAttributes::DEFAULT,
             None,
+            None,
         )
         .expect("Failed to map stack page");
         vaddr += PAGE_SIZE_4K;
@@ -322,6 +356,7 @@ fn main(bootinfo: &sel4::BootInfoPtr) -> sel4::Result<Never> {
         &mut cspace,
         &mut ut_table,
         &mut dma_pool,
+        &mut large_page_pool,
     );
 
     sel4::init_thread::slot::TCB.cap().tcb_suspend()?;
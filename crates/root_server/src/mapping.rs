@@ -1,9 +1,69 @@
 use sel4::CPtr;
 
 use crate::cspace::{CSpace, CSpaceTrait, MAPPING_SLOTS};
-use crate::page::PAGE_SIZE_4K;
-use crate::ut::UTTable;
-use smos_common::util::BIT;
+use crate::page::{PAGE_BITS_2M, PAGE_SIZE_4K};
+use crate::ut::{UTTable, UTWrapper};
+use crate::util::dealloc_retyped;
+use alloc::vec::Vec;
+use smos_common::util::{BIT, ROUND_DOWN};
+
+/* Each level of page table translates PT_INDEX_BITS more of the address than the one below it */
+const PT_INDEX_BITS: usize = 9;
+
+#[derive(Debug, Copy, Clone)]
+struct PageTable {
+    pt: sel4::cap::PT,
+    ut: UTWrapper,
+    /* The page table translates addresses in [vaddr, vaddr + BIT(size_bits)) */
+    vaddr: usize,
+    size_bits: usize,
+}
+
+/* Keeps track of the page tables that were allocated to map frames into a vspace, so that they
+can be given back when the mappings they hold go away */
+#[derive(Debug)]
+pub struct PageTableRegistry {
+    tables: Vec<PageTable>,
+}
+
+impl PageTableRegistry {
+    pub fn new() -> Self {
+        return PageTableRegistry { tables: Vec::new() };
+    }
+
+    /* Frees the page tables that only translate addresses in [start, end), except for ones that
+    translate any of the addresses in keep */
+    pub fn free_range(
+        &mut self,
+        cspace: &mut CSpace,
+        ut_table: &mut UTTable,
+        start: usize,
+        end: usize,
+        keep: &[usize],
+    ) {
+        /* Free the lower levels before the page tables they are mapped into */
+        self.tables.sort_by_key(|x| x.size_bits);
+        self.tables.retain(|x| {
+            let pt_end = x.vaddr + BIT(x.size_bits);
+            if x.vaddr < start
+                || pt_end > end
+                || keep.iter().any(|addr| *addr >= x.vaddr && *addr < pt_end)
+            {
+                return true;
+            }
+
+            dealloc_retyped(cspace, ut_table, (x.pt, x.ut));
+            return false;
+        });
+    }
+
+    pub fn free_all(&mut self, cspace: &mut CSpace, ut_table: &mut UTTable) {
+        self.tables.sort_by_key(|x| x.size_bits);
+        for x in self.tables.drain(..) {
+            dealloc_retyped(cspace, ut_table, (x.pt, x.ut));
+        }
+    }
+}
 
 fn retype_map_pt(
     cspace: &CSpace,
@@ -30,32 +90,125 @@ pub fn map_frame(
     rights: sel4::CapRights,
     attributes: sel4::VmAttributes,
     free_slots: Option<[usize; MAPPING_SLOTS]>,
+    page_tables: Option<&mut PageTableRegistry>,
+) -> Result<usize, sel4::Error> {
+    return map_page(
+        cspace,
+        ut_table,
+        frame_cap,
+        vspace,
+        vaddr,
+        rights,
+        attributes,
+        free_slots,
+        page_tables,
+        sel4_sys::seL4_PageBits.try_into().unwrap(),
+    );
+}
+
+/* Maps a large frame. The frame goes where the lowest level page table would otherwise be, so
+that one is never allocated. */
+pub fn map_large_frame(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_cap: sel4::cap::UnspecifiedPage,
+    vspace: sel4::cap::VSpace,
+    vaddr: usize,
+    rights: sel4::CapRights,
+    attributes: sel4::VmAttributes,
+    page_tables: Option<&mut PageTableRegistry>,
+) -> Result<(), sel4::Error> {
+    map_page(
+        cspace,
+        ut_table,
+        frame_cap,
+        vspace,
+        vaddr,
+        rights,
+        attributes,
+        None,
+        page_tables,
+        PAGE_BITS_2M,
+    )?;
+
+    return Ok(());
+}
+
+/* Allocates a page table and maps it in at the highest level that is missing for vaddr. The table
+goes into free_slot if one is given. Nothing is left allocated if this fails. */
+fn alloc_map_pt(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    vspace: sel4::cap::VSpace,
+    vaddr: usize,
+    free_slot: Option<usize>,
+) -> Result<(sel4::cap::PT, UTWrapper), sel4::Error> {
+    let (_, ut) = ut_table.alloc_4k_untyped()?;
+
+    let slot = match free_slot {
+        Some(slot) => slot,
+        None => match cspace.alloc_slot() {
+            Ok(slot) => slot,
+            Err(e) => {
+                ut_table.free(ut);
+                return Err(e);
+            }
+        },
+    };
+
+    let null_slot: usize = sel4_sys::seL4_RootCNodeCapSlots::seL4_CapNull
+        .try_into()
+        .unwrap();
+    let ret = if slot == null_slot {
+        Err(sel4::Error::InvalidCapability)
+    } else {
+        retype_map_pt(cspace, vspace, vaddr, ut.get_cap(), slot)
+    };
+
+    if let Err(e) = ret {
+        /* The retype may have worked even though mapping the table didn't */
+        if slot != null_slot {
+            cspace.delete(slot).expect("Failed to delete page table");
+            if free_slot.is_none() {
+                cspace.free_slot(slot);
+            }
+        }
+        ut_table.free(ut);
+        return Err(e);
+    }
+
+    return Ok((
+        sel4::CPtr::from_bits(slot.try_into().unwrap()).cast::<sel4::cap_type::PT>(),
+        ut,
+    ));
+}
+
+fn map_page(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_cap: sel4::cap::UnspecifiedPage,
+    vspace: sel4::cap::VSpace,
+    vaddr: usize,
+    rights: sel4::CapRights,
+    attributes: sel4::VmAttributes,
+    free_slots: Option<[usize; MAPPING_SLOTS]>,
+    page_tables: Option<&mut PageTableRegistry>,
+    page_bits: usize,
 ) -> Result<usize, sel4::Error> {
     let mut err: Result<(), sel4::Error> =
         frame_cap.frame_map(vspace, vaddr, rights.clone(), attributes);
     let mut i = 0;
     let mut used: usize = 0;
+    let mut allocated: Vec<(sel4::cap::PT, UTWrapper)> = Vec::new();
     while i < MAPPING_SLOTS && err.is_err_and(|err| err == sel4::Error::FailedLookup) {
-        let (_, ut) = ut_table.alloc_4k_untyped()?;
-
-        let slot = {
-            if let Some(free_slots_internal) = free_slots {
-                used |= BIT(i);
-                Ok(free_slots_internal[i])
-            } else {
-                cspace.alloc_slot()
-            }
-        }?;
-
-        if slot
-            == sel4_sys::seL4_RootCNodeCapSlots::seL4_CapNull
-                .try_into()
-                .unwrap()
-        {
-            return Err(sel4::Error::InvalidCapability);
-        }
-
-        err = retype_map_pt(cspace, vspace, vaddr, ut.get_cap(), slot);
+        err = alloc_map_pt(cspace, ut_table, vspace, vaddr, free_slots.map(|x| x[i])).map(
+            |(pt, ut)| {
+                allocated.push((pt, ut));
+                if free_slots.is_some() {
+                    used |= BIT(i);
+                }
+            },
+        );
 
         if err.is_ok() {
             err = frame_cap.frame_map(vspace, vaddr, rights.clone(), attributes);
@@ -64,10 +217,36 @@ pub fn map_frame(
         i += 1;
     }
 
-    if err.is_ok() {
-        return Ok(used);
+    /* The page tables that were allocated are no use without the page, so they are removed again,
+    lowest level first. Slots that came from free_slots are left for the caller to reuse. */
+    if let Err(e) = err {
+        for (pt, ut) in allocated.into_iter().rev() {
+            let slot: usize = pt.bits().try_into().unwrap();
+            cspace.delete(slot).expect("Failed to delete page table");
+            if free_slots.is_none() {
+                cspace.free_slot(slot);
+            }
+            ut_table.free(ut);
+        }
+        return Err(e);
     }
-    return Err(err.err().unwrap());
+
+    /* Each page table is mapped in at the highest level that was missing. The page went into the
+    last one, so the tables cover consecutive levels down to the page. */
+    if let Some(registry) = page_tables {
+        let count = allocated.len();
+        for (j, (pt, ut)) in allocated.into_iter().enumerate() {
+            let size_bits = page_bits + (count - j) * PT_INDEX_BITS;
+            registry.tables.push(PageTable {
+                pt: pt,
+                ut: ut,
+                vaddr: ROUND_DOWN(vaddr, size_bits),
+                size_bits: size_bits,
+            });
+        }
+    }
+
+    return Ok(used);
 }
 
 const DEVICE_START: usize = 0xB0000000;
@@ -104,6 +283,7 @@ pub fn map_device(
                 sel4::CapRightsBuilder::all().build(),
                 sel4::VmAttributes::NONE,
                 None,
+                None,
             )?;
             DEVICE_VIRT += PAGE_SIZE_4K;
         }
@@ -0,0 +1,104 @@
+use crate::connection::find_server_with_name;
+use crate::proc::UserProcess;
+use alloc::collections::btree_map::BTreeMap;
+use alloc::string::{String, ToString};
+use smos_common::error::InvocationError;
+use smos_server::reply::SMOSReply;
+use smos_server::syscalls::{Mount, Unmount};
+
+#[derive(Debug, Clone)]
+struct MountPoint {
+    server_name: String,
+    pid: usize,
+}
+
+/* Maps absolute paths to the name of the published server which is responsible for them */
+static mut MOUNTS: BTreeMap<String, MountPoint> = BTreeMap::new();
+
+/* Mount points are absolute paths without a trailing '/', except for the root */
+fn valid_mount_path(path: &str) -> bool {
+    if path == "/" {
+        return true;
+    }
+
+    return path.starts_with('/') && !path.ends_with('/') && !path.contains("//");
+}
+
+/* Finds the server responsible for an absolute path and the path of the file within that server.
+The longest mount point which is a prefix of the path wins. Mounts of servers that are no longer
+published are ignored. */
+pub fn resolve_path(path: &str) -> Option<(String, String)> {
+    if !path.starts_with('/') {
+        return None;
+    }
+
+    let mut best: Option<(&String, &MountPoint)> = None;
+    for (mount_path, mount) in unsafe { MOUNTS.iter() } {
+        let matches = mount_path == "/"
+            || path == mount_path
+            || path
+                .strip_prefix(mount_path.as_str())
+                .is_some_and(|x| x.starts_with('/'));
+
+        if matches && best.map_or(true, |(x, _)| mount_path.len() > x.len()) {
+            best = Some((mount_path, mount));
+        }
+    }
+
+    let (mount_path, mount) = best?;
+    if find_server_with_name(&mount.server_name).is_none() {
+        return None;
+    }
+
+    let file = path[mount_path.len()..].trim_start_matches('/');
+    if file.is_empty() {
+        return None;
+    }
+
+    return Some((mount.server_name.clone(), file.to_string()));
+}
+
+pub fn handle_mount(p: &mut UserProcess, args: &Mount) -> Result<SMOSReply, InvocationError> {
+    if !valid_mount_path(args.path) {
+        return Err(InvocationError::InvalidArguments);
+    }
+
+    /* Only published servers can be mounted, and only by the process that published them */
+    match find_server_with_name(args.server_name) {
+        Some(server) if server.borrow().pid == p.pid => {}
+        _ => return Err(InvocationError::InvalidArguments),
+    }
+
+    if unsafe { MOUNTS.contains_key(args.path) } {
+        return Err(InvocationError::InvalidArguments);
+    }
+
+    unsafe {
+        MOUNTS.insert(
+            args.path.to_string(),
+            MountPoint {
+                server_name: args.server_name.to_string(),
+                pid: p.pid,
+            },
+        )
+    };
+
+    return Ok(SMOSReply::Mount);
+}
+
+pub fn handle_unmount(p: &mut UserProcess, args: &Unmount) -> Result<SMOSReply, InvocationError> {
+    /* Only the process that created a mount point can remove it */
+    match unsafe { MOUNTS.get(args.path) } {
+        Some(mount) if mount.pid == p.pid => {}
+        _ => return Err(InvocationError::InvalidArguments),
+    }
+
+    unsafe { MOUNTS.remove(args.path) };
+
+    return Ok(SMOSReply::Unmount);
+}
+
+/* Removes the mount points that were created by a process */
+pub fn remove_process_mounts(pid: usize) {
+    unsafe { MOUNTS.retain(|_, mount| mount.pid != pid) };
+}
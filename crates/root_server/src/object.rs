@@ -1,9 +1,12 @@
-use crate::cspace::CSpace;
+use crate::cspace::{CSpace, CSpaceTrait};
 use crate::dma::DMAPool;
 use crate::frame_table::FrameRef;
 use crate::frame_table::FrameTable;
 use crate::handle::RootServerResource;
+use crate::large_page::LargeFrameRef;
+use crate::page::PAGE_SIZE_2M;
 use crate::proc::UserProcess;
+use crate::swap::{free_swap_block, swap_area, SwapArea};
 use crate::ut::UTTable;
 use crate::view::View;
 use crate::PAGE_SIZE_4K;
@@ -12,6 +15,7 @@ use alloc::vec;
 use alloc::vec::Vec;
 use core::cell::RefCell;
 use offset_allocator::Allocation;
+use smos_common::args::ObjCloneArgs;
 use smos_common::error::InvocationError;
 use smos_common::local_handle::{HandleOrHandleCap, ObjectHandle};
 use smos_common::obj_attributes::ObjAttributes;
@@ -23,7 +27,7 @@ use smos_server::handle::{
 };
 use smos_server::handle_capability::HandleCapabilityTable;
 use smos_server::reply::SMOSReply;
-use smos_server::syscalls::{ObjCreate, ObjDestroy, ObjStat};
+use smos_server::syscalls::{ObjClone, ObjCreate, ObjDestroy, ObjStat};
 
 /* Each level of the page table uses 9 bits, just like the underlying page table structure. Realistically,
 this is kind of unnecessary, because no objects of this size should ever need to be allocated, but
@@ -44,6 +48,23 @@ pub const MAX_OBJ_SIZE: usize = OBJ_LVL_MAX.pow(NUM_FRAME_TABLE_LEVELS) * PAGE_S
 pub struct ObjectFrame {
     pub cap: sel4::cap::SmallPage,
     pub frame_ref: FrameRef,
+    /* Set when the frame is shared with a clone of the object. The frame is only ever mapped
+    read-only and is copied on the first write. The cap is a copy that belongs to this object. */
+    pub cow: bool,
+    /* The number of pages that page_map has handed the frame to. The object can't take the frame
+    back from those views, so it isn't paged out until they have all been unmapped. */
+    pub pins: usize,
+    /* A block in the swap area that holds an up to date copy of the frame. The frame is only
+    mapped read-only while this is set, so that the copy is dropped on the first write. */
+    pub swap_block: Option<usize>,
+}
+
+/* Large frames are stored one level above the 4K frames, so each one takes the place of a table of
+4K frames */
+#[derive(Clone, Debug)]
+pub struct ObjectLargeFrame {
+    pub cap: sel4::cap::LargePage,
+    pub frame_ref: LargeFrameRef,
 }
 
 #[derive(Clone, Debug)]
@@ -54,7 +75,10 @@ pub struct ObjectFrameTable {
 #[derive(Clone, Debug)]
 pub enum ObjectFrameTableEntry {
     Frame(ObjectFrame),
+    LargeFrame(ObjectLargeFrame),
     FrameTable(ObjectFrameTable),
+    /* A 4K frame that has been paged out to the given block of the swap area */
+    Swapped(usize),
 }
 
 #[derive(Debug)]
@@ -96,6 +120,85 @@ impl AnonymousMemoryObject {
                 None => return None,
                 Some(x) => match x {
                     ObjectFrameTableEntry::Frame(ref y) => return Some(&y),
+                    ObjectFrameTableEntry::LargeFrame(_) | ObjectFrameTableEntry::Swapped(_) => {
+                        return None
+                    }
+                    ObjectFrameTableEntry::FrameTable(y) => &y.table,
+                },
+            };
+
+            curr_table_lvl += 1;
+            shift -= 9;
+        }
+
+        return None;
+    }
+
+    pub fn lookup_frame_mut<'a>(&'a mut self, offset: usize) -> Option<&'a mut ObjectFrame> {
+        let mut shift = 39;
+        let mut curr_table_lvl = 0;
+        let mut curr_table = &mut self.frames;
+        while curr_table_lvl < 4 {
+            let idx = (offset >> shift) & (BIT(9) - 1);
+
+            curr_table = match &mut curr_table[idx] {
+                None => return None,
+                Some(x) => match x {
+                    ObjectFrameTableEntry::Frame(ref mut y) => return Some(y),
+                    ObjectFrameTableEntry::LargeFrame(_) | ObjectFrameTableEntry::Swapped(_) => {
+                        return None
+                    }
+                    ObjectFrameTableEntry::FrameTable(y) => &mut y.table,
+                },
+            };
+
+            curr_table_lvl += 1;
+            shift -= 9;
+        }
+
+        return None;
+    }
+
+    pub fn lookup_large_frame<'a>(&'a self, offset: usize) -> Option<&'a ObjectLargeFrame> {
+        let mut shift = 39;
+        let mut curr_table_lvl = 0;
+        let mut curr_table = &self.frames;
+        while curr_table_lvl < 3 {
+            let idx = (offset >> shift) & (BIT(9) - 1);
+
+            curr_table = match &curr_table[idx] {
+                None => return None,
+                Some(x) => match x {
+                    ObjectFrameTableEntry::LargeFrame(ref y) => return Some(&y),
+                    ObjectFrameTableEntry::Frame(_) | ObjectFrameTableEntry::Swapped(_) => {
+                        return None
+                    }
+                    ObjectFrameTableEntry::FrameTable(y) => &y.table,
+                },
+            };
+
+            curr_table_lvl += 1;
+            shift -= 9;
+        }
+
+        return None;
+    }
+
+    /* Returns the block of the swap area that the page at offset has been paged out to */
+    pub fn lookup_swapped(&self, offset: usize) -> Option<usize> {
+        let mut shift = 39;
+        let mut curr_table_lvl = 0;
+        let mut curr_table = &self.frames;
+        while curr_table_lvl < 4 {
+            let idx = (offset >> shift) & (BIT(9) - 1);
+
+            curr_table = match &curr_table[idx] {
+                None => return None,
+                Some(x) => match x {
+                    ObjectFrameTableEntry::Swapped(block) => return Some(*block),
+                    ObjectFrameTableEntry::Frame(_) | ObjectFrameTableEntry::LargeFrame(_) => {
+                        return None
+                    }
                     ObjectFrameTableEntry::FrameTable(y) => &y.table,
                 },
             };
@@ -107,6 +210,82 @@ impl AnonymousMemoryObject {
         return None;
     }
 
+    /* Returns the bottom level entry for the 4K page at offset, if the tables above it exist */
+    fn entry_at_mut<'a>(
+        &'a mut self,
+        offset: usize,
+    ) -> Option<&'a mut Option<ObjectFrameTableEntry>> {
+        let mut shift = 39;
+        let mut curr_table_lvl = 0;
+        let mut curr_table = &mut self.frames;
+        while curr_table_lvl < 3 {
+            let idx = (offset >> shift) & (BIT(9) - 1);
+
+            curr_table = match &mut curr_table[idx] {
+                None => return None,
+                Some(x) => match x {
+                    ObjectFrameTableEntry::FrameTable(ref mut y) => &mut y.table,
+                    _ => return None,
+                },
+            };
+
+            curr_table_lvl += 1;
+            shift -= 9;
+        }
+
+        let idx = (offset >> shift) & (BIT(9) - 1);
+        return Some(&mut curr_table[idx]);
+    }
+
+    /* Whether the large page containing offset could be backed by a large frame. This is only the
+    case for objects created with LARGE_PAGES where the whole large page is inside the object. */
+    pub fn large_page_possible(&self, offset: usize) -> bool {
+        return self.attributes.has(ObjAttributes::LARGE_PAGES)
+            && offset - offset % PAGE_SIZE_2M + PAGE_SIZE_2M <= self.size;
+    }
+
+    pub fn insert_large_frame_at(
+        &mut self,
+        offset: usize,
+        frame: (sel4::cap::LargePage, LargeFrameRef),
+    ) -> Result<(), sel4::Error> {
+        let mut shift = 39;
+        let mut curr_table_lvl = 0;
+        let mut curr_table = &mut self.frames;
+        while curr_table_lvl < 2 {
+            let idx = (offset >> shift) & (BIT(9) - 1);
+
+            if curr_table[idx].is_none() {
+                curr_table[idx] = Some(ObjectFrameTableEntry::FrameTable(ObjectFrameTable {
+                    table: vec![None; OBJ_LVL_MAX],
+                }));
+            }
+
+            curr_table = match &mut curr_table[idx] {
+                None => return Err(sel4::Error::InvalidArgument),
+                Some(ref mut x) => match x {
+                    ObjectFrameTableEntry::FrameTable(ref mut y) => &mut y.table,
+                    _ => return Err(sel4::Error::DeleteFirst),
+                },
+            };
+
+            curr_table_lvl += 1;
+            shift -= 9;
+        }
+
+        /* Any 4K frames that are already in this part of the object get in the way */
+        let idx = (offset >> shift) & (BIT(9) - 1);
+        if curr_table[idx].is_some() {
+            return Err(sel4::Error::DeleteFirst);
+        }
+
+        curr_table[idx] = Some(ObjectFrameTableEntry::LargeFrame(ObjectLargeFrame {
+            cap: frame.0,
+            frame_ref: frame.1,
+        }));
+        return Ok(());
+    }
+
     pub fn insert_frame_at(
         &mut self,
         offset: usize,
@@ -127,7 +306,9 @@ impl AnonymousMemoryObject {
             curr_table = match &mut curr_table[idx] {
                 None => return Err(sel4::Error::InvalidArgument), // @alwin: What to actually return here?
                 Some(ref mut x) => match x {
-                    ObjectFrameTableEntry::Frame(_) => return Err(sel4::Error::DeleteFirst),
+                    ObjectFrameTableEntry::Frame(_)
+                    | ObjectFrameTableEntry::LargeFrame(_)
+                    | ObjectFrameTableEntry::Swapped(_) => return Err(sel4::Error::DeleteFirst),
                     ObjectFrameTableEntry::FrameTable(ref mut y) => &mut y.table,
                 },
             };
@@ -141,6 +322,9 @@ impl AnonymousMemoryObject {
             curr_table[idx] = Some(ObjectFrameTableEntry::Frame(ObjectFrame {
                 cap: frame.0,
                 frame_ref: frame.1,
+                cow: false,
+                pins: 0,
+                swap_block: None,
             }));
             return Ok(());
         }
@@ -148,27 +332,279 @@ impl AnonymousMemoryObject {
         match &curr_table[idx] {
             None => panic!("This should have already been handled above"),
             Some(x) => match x {
-                ObjectFrameTableEntry::Frame(_) => return Err(sel4::Error::DeleteFirst),
-                ObjectFrameTableEntry::FrameTable(_) => {
+                ObjectFrameTableEntry::Frame(_) | ObjectFrameTableEntry::Swapped(_) => {
+                    return Err(sel4::Error::DeleteFirst)
+                }
+                ObjectFrameTableEntry::LargeFrame(_) | ObjectFrameTableEntry::FrameTable(_) => {
                     panic!("Internal error: FrameTable on bottom level should not occur")
                 }
             },
         }
     }
 
+    /* Records that the page at offset has been paged out to the given block of the swap area */
+    pub fn insert_swapped_at(&mut self, offset: usize, block: usize) -> Result<(), sel4::Error> {
+        let mut shift = 39;
+        let mut curr_table_lvl = 0;
+        let mut curr_table = &mut self.frames;
+        while curr_table_lvl < 3 {
+            let idx = (offset >> shift) & (BIT(9) - 1);
+
+            if curr_table[idx].is_none() {
+                curr_table[idx] = Some(ObjectFrameTableEntry::FrameTable(ObjectFrameTable {
+                    table: vec![None; OBJ_LVL_MAX],
+                }));
+            }
+
+            curr_table = match &mut curr_table[idx] {
+                None => return Err(sel4::Error::InvalidArgument),
+                Some(ref mut x) => match x {
+                    ObjectFrameTableEntry::FrameTable(ref mut y) => &mut y.table,
+                    _ => return Err(sel4::Error::DeleteFirst),
+                },
+            };
+
+            curr_table_lvl += 1;
+            shift -= 9;
+        }
+
+        let idx = (offset >> shift) & (BIT(9) - 1);
+        if curr_table[idx].is_some() {
+            return Err(sel4::Error::DeleteFirst);
+        }
+
+        curr_table[idx] = Some(ObjectFrameTableEntry::Swapped(block));
+        return Ok(());
+    }
+
+    /* Removes the 4K frame at offset from the object and returns it. The caller is responsible for
+    freeing the frame and any caps to it. */
+    pub fn remove_frame_at(&mut self, offset: usize) -> Option<ObjectFrame> {
+        let mut shift = 39;
+        let mut curr_table_lvl = 0;
+        let mut curr_table = &mut self.frames;
+        while curr_table_lvl < 3 {
+            let idx = (offset >> shift) & (BIT(9) - 1);
+
+            curr_table = match &mut curr_table[idx] {
+                None => return None,
+                Some(x) => match x {
+                    ObjectFrameTableEntry::Frame(_)
+                    | ObjectFrameTableEntry::LargeFrame(_)
+                    | ObjectFrameTableEntry::Swapped(_) => return None,
+                    ObjectFrameTableEntry::FrameTable(ref mut y) => &mut y.table,
+                },
+            };
+
+            curr_table_lvl += 1;
+            shift -= 9;
+        }
+
+        let idx = (offset >> shift) & (BIT(9) - 1);
+        return match curr_table[idx].take() {
+            Some(ObjectFrameTableEntry::Frame(x)) => Some(x),
+            x => {
+                curr_table[idx] = x;
+                None
+            }
+        };
+    }
+
+    fn frame_offsets_inner(
+        vec: &Vec<Option<ObjectFrameTableEntry>>,
+        base: usize,
+        shift: usize,
+        offsets: &mut Vec<usize>,
+    ) {
+        for (i, node) in vec.iter().enumerate() {
+            match node {
+                None => continue,
+                Some(ObjectFrameTableEntry::FrameTable(ref y)) => {
+                    Self::frame_offsets_inner(&y.table, base + (i << shift), shift - 9, offsets)
+                }
+                Some(ObjectFrameTableEntry::Frame(_)) => offsets.push(base + (i << shift)),
+                Some(ObjectFrameTableEntry::LargeFrame(_))
+                | Some(ObjectFrameTableEntry::Swapped(_)) => continue,
+            }
+        }
+    }
+
+    /* Returns the offsets of all the 4K frames that are present in the object */
+    pub fn frame_offsets(&self) -> Vec<usize> {
+        let mut offsets = Vec::new();
+        Self::frame_offsets_inner(&self.frames, 0, 39, &mut offsets);
+        return offsets;
+    }
+
+    fn swapped_offsets_inner(
+        vec: &Vec<Option<ObjectFrameTableEntry>>,
+        base: usize,
+        shift: usize,
+        offsets: &mut Vec<usize>,
+    ) {
+        for (i, node) in vec.iter().enumerate() {
+            match node {
+                None => continue,
+                Some(ObjectFrameTableEntry::FrameTable(ref y)) => {
+                    Self::swapped_offsets_inner(&y.table, base + (i << shift), shift - 9, offsets)
+                }
+                Some(ObjectFrameTableEntry::Swapped(_)) => offsets.push(base + (i << shift)),
+                Some(ObjectFrameTableEntry::Frame(_))
+                | Some(ObjectFrameTableEntry::LargeFrame(_)) => continue,
+            }
+        }
+    }
+
+    /* Returns the offsets of all the pages of the object that have been paged out */
+    pub fn swapped_offsets(&self) -> Vec<usize> {
+        let mut offsets = Vec::new();
+        Self::swapped_offsets_inner(&self.frames, 0, 39, &mut offsets);
+        return offsets;
+    }
+
+    /* Removes the page at offset from every view of the object, so that the next access through
+    any of them faults */
+    pub fn unmap_from_views(&self, cspace: &mut CSpace, offset: usize) {
+        for view in &self.associated_views {
+            let mut view = view.borrow_mut();
+            if offset < view.obj_offset {
+                continue;
+            }
+
+            let win_offset = view.win_offset + (offset - view.obj_offset);
+            if win_offset >= view.bound_window.borrow().size {
+                continue;
+            }
+
+            if let Some(view_cap) = view.remove_cap_at(win_offset) {
+                /* Deleting the cap unmaps it from the process */
+                cspace
+                    .delete_cap(view_cap.cap)
+                    .expect("Failed to delete capability");
+                cspace.free_cap(view_cap.cap);
+            }
+        }
+    }
+
+    /* Gives the object its own copy of a frame that it shares with a clone of it. The page is
+    removed from all views of the object, which then fault the copy in. */
+    pub fn unshare_frame(
+        &mut self,
+        cspace: &mut CSpace,
+        ut_table: &mut UTTable,
+        frame_table: &mut FrameTable,
+        offset: usize,
+    ) -> Result<(), sel4::Error> {
+        assert!(self.lookup_frame(offset).is_some_and(|x| x.cow));
+
+        // @alwin: If this object holds the last reference to the frame, it could just take the
+        // frame over instead of copying it
+        let frame_ref = frame_table
+            .alloc_frame(cspace, ut_table)
+            .ok_or(sel4::Error::NotEnoughMemory)?;
+
+        self.unmap_from_views(cspace, offset);
+        let shared_frame = self.remove_frame_at(offset).unwrap();
+
+        frame_table
+            .frame_data(frame_ref)
+            .copy_from_slice(frame_table.frame_data(shared_frame.frame_ref));
+
+        cspace
+            .delete_cap(shared_frame.cap)
+            .expect("Failed to delete cap");
+        cspace.free_cap(shared_frame.cap);
+        frame_table.free_frame(shared_frame.frame_ref);
+
+        self.insert_frame_at(
+            offset,
+            (frame_table.frame_from_ref(frame_ref).get_cap(), frame_ref),
+        )
+        .expect("Failed to insert frame into object");
+
+        return Ok(());
+    }
+
+    /* Pages the frame at offset out to the swap area and frees it. Frames that already have an up
+    to date copy in swap don't need to be written out again. Otherwise the swap area can't be busy,
+    as the page is written out through its data page. */
+    pub fn swap_out(
+        &mut self,
+        cspace: &mut CSpace,
+        frame_table: &mut FrameTable,
+        swap: &mut SwapArea,
+        offset: usize,
+    ) -> Result<(), sel4::Error> {
+        let frame = self.lookup_frame(offset).unwrap().clone();
+        assert!(!frame.cow && frame.pins == 0);
+
+        /* Make sure the page can't be written to while it is being copied out */
+        self.unmap_from_views(cspace, offset);
+
+        let block = match frame.swap_block {
+            Some(block) => block,
+            None => {
+                let block = swap.alloc_block().ok_or(sel4::Error::NotEnoughMemory)?;
+                swap.start_write(block, frame_table.frame_data(frame.frame_ref));
+                block
+            }
+        };
+
+        *self.entry_at_mut(offset).unwrap() = Some(ObjectFrameTableEntry::Swapped(block));
+        frame_table.free_frame(frame.frame_ref);
+
+        return Ok(());
+    }
+
+    /* Puts the frame that block was read into back into the object at offset. The copy in swap is
+    kept around until the page is written to. */
+    pub fn swap_in(
+        &mut self,
+        frame_table: &mut FrameTable,
+        offset: usize,
+        block: usize,
+        frame_ref: FrameRef,
+    ) {
+        assert!(self.lookup_swapped(offset) == Some(block));
+
+        *self.entry_at_mut(offset).unwrap() = Some(ObjectFrameTableEntry::Frame(ObjectFrame {
+            cap: frame_table.frame_from_ref(frame_ref).get_cap(),
+            frame_ref: frame_ref,
+            cow: false,
+            pins: 0,
+            swap_block: Some(block),
+        }));
+    }
+
+    /* Drops the copy in swap of the frame at offset, as it is about to be written to. The page is
+    removed from all views so that it gets mapped writable the next time it is accessed. */
+    pub fn mark_dirty(&mut self, cspace: &mut CSpace, offset: usize) {
+        let frame = self.lookup_frame_mut(offset).unwrap();
+        if let Some(block) = frame.swap_block.take() {
+            free_swap_block(block);
+        }
+
+        self.unmap_from_views(cspace, offset);
+    }
+
     pub fn cleanup_obj_table_inner(
         vec: &Vec<Option<ObjectFrameTableEntry>>,
         cspace: &mut CSpace,
         frame_table: &mut FrameTable,
         revoke: bool,
+        free_frames: bool,
     ) {
         for node in vec {
             match node {
                 None => continue,
                 Some(x) => match x {
-                    ObjectFrameTableEntry::FrameTable(ref y) => {
-                        Self::cleanup_obj_table_inner(&y.table, cspace, frame_table, revoke)
-                    }
+                    ObjectFrameTableEntry::FrameTable(ref y) => Self::cleanup_obj_table_inner(
+                        &y.table,
+                        cspace,
+                        frame_table,
+                        revoke,
+                        free_frames,
+                    ),
                     ObjectFrameTableEntry::Frame(ref y) => {
                         if revoke {
                             cspace
@@ -177,7 +613,29 @@ impl AnonymousMemoryObject {
                                 .revoke()
                                 .expect("Failed to revoke cap");
                         }
-                        frame_table.free_frame(y.frame_ref);
+                        if y.cow {
+                            cspace.delete_cap(y.cap).expect("Failed to delete cap");
+                            cspace.free_cap(y.cap);
+                        }
+                        if let Some(block) = y.swap_block {
+                            free_swap_block(block);
+                        }
+                        if free_frames {
+                            frame_table.free_frame(y.frame_ref);
+                        }
+                    }
+                    ObjectFrameTableEntry::Swapped(block) => {
+                        free_swap_block(*block);
+                    }
+                    ObjectFrameTableEntry::LargeFrame(ref y) => {
+                        if revoke {
+                            cspace
+                                .root_cnode
+                                .absolute_cptr(y.cap)
+                                .revoke()
+                                .expect("Failed to revoke cap");
+                        }
+                        frame_table.free_large_frame(cspace, y.cap, y.frame_ref);
                     }
                 },
             }
@@ -185,12 +643,18 @@ impl AnonymousMemoryObject {
     }
 
     pub fn cleanup_frame_table(&mut self, cspace: &mut CSpace, frame_table: &mut FrameTable) {
+        /* Device and DMA memory doesn't come from the frame table, so it isn't given back to it
+        @alwin: DMA allocations are never given back to the pool either */
+        let free_frames =
+            !self.attributes.has(ObjAttributes::DEVICE) && self.dma_allocation.is_none();
+
         // @alwin: Double check the revoke condition
         Self::cleanup_obj_table_inner(
             &self.frames,
             cspace,
             frame_table,
             self.associated_views.len() != 0,
+            free_frames,
         )
     }
 }
@@ -220,6 +684,14 @@ pub fn handle_obj_create(
         return Err(InvocationError::InvalidArguments);
     }
 
+    /* Device and DMA memory is made up of 4K frames that already exist */
+    if args.attributes.has(ObjAttributes::LARGE_PAGES)
+        && (args.attributes.has(ObjAttributes::DEVICE)
+            || args.attributes.has(ObjAttributes::CONTIGUOUS))
+    {
+        return Err(InvocationError::InvalidArguments);
+    }
+
     let mem_obj = Rc::new(RefCell::new(AnonymousMemoryObject::new(
         args.size,
         args.rights.clone(),
@@ -263,18 +735,29 @@ pub fn handle_obj_create(
         } else if (args.attributes.has(ObjAttributes::CONTIGUOUS) && n_pages == 1)
             || args.attributes.has(ObjAttributes::EAGER)
         {
-            for i in 0..n_pages {
+            let mut offset = 0;
+            while offset < args.size {
+                /* Fall back to 4K frames if there are no large frames left */
+                if offset % PAGE_SIZE_2M == 0 && mem_obj.borrow().large_page_possible(offset) {
+                    if let Some(frame) = frame_table.alloc_large_frame(cspace) {
+                        mem_obj
+                            .borrow_mut()
+                            .insert_large_frame_at(offset, frame)
+                            .expect("Failed to insert large frame into object");
+                        offset += PAGE_SIZE_2M;
+                        continue;
+                    }
+                }
+
                 let frame = frame_table
                     .alloc_frame(cspace, ut_table)
                     .expect("@alwin: This should not be an exepct");
 
                 mem_obj
                     .borrow_mut()
-                    .insert_frame_at(
-                        i * PAGE_SIZE_4K,
-                        (frame_table.frame_from_ref(frame).get_cap(), frame),
-                    )
+                    .insert_frame_at(offset, (frame_table.frame_from_ref(frame).get_cap(), frame))
                     .expect("Failed to insert frame into object");
+                offset += PAGE_SIZE_4K;
             }
         }
     }
@@ -359,3 +842,170 @@ pub fn handle_obj_stat(
         },
     });
 }
+
+fn copy_frame_cap(
+    cspace: &mut CSpace,
+    cap: sel4::cap::SmallPage,
+) -> Result<sel4::cap::SmallPage, sel4::Error> {
+    let copy = cspace.alloc_cap::<sel4::cap_type::SmallPage>()?;
+    cspace
+        .root_cnode()
+        .absolute_cptr(copy)
+        .copy(
+            &cspace.root_cnode().absolute_cptr(cap),
+            sel4::CapRightsBuilder::all().build(),
+        )
+        .map_err(|e| {
+            cspace.free_cap(copy);
+            e
+        })?;
+
+    return Ok(copy);
+}
+
+/* Shares the frame at offset between object and clone. Once a frame is shared, it is only mapped
+read-only and is copied when either of the objects is written to. */
+fn share_frame_with_clone(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_table: &mut FrameTable,
+    object: &mut AnonymousMemoryObject,
+    clone: &mut AnonymousMemoryObject,
+    offset: usize,
+) -> Result<(), sel4::Error> {
+    let frame = object.lookup_frame(offset).unwrap().clone();
+
+    /* A frame that has been handed to another view with page_map stays writable through it, so it
+    can't be shared. The clone gets its own copy straight away instead. */
+    if frame.pins > 0 {
+        let frame_ref = frame_table
+            .alloc_frame(cspace, ut_table)
+            .ok_or(sel4::Error::NotEnoughMemory)?;
+        frame_table
+            .frame_data(frame_ref)
+            .copy_from_slice(frame_table.frame_data(frame.frame_ref));
+        clone
+            .insert_frame_at(
+                offset,
+                (frame_table.frame_from_ref(frame_ref).get_cap(), frame_ref),
+            )
+            .expect("Failed to insert frame into object");
+        return Ok(());
+    }
+
+    if !frame.cow {
+        /* Existing mappings of the frame may be writable. The cap held by the object is the one
+        owned by the frame table, so the object gets its own copy that can be deleted when the
+        frame is no longer shared. */
+        object.unmap_from_views(cspace, offset);
+        let cap = copy_frame_cap(cspace, frame.cap)?;
+        let object_frame = object.lookup_frame_mut(offset).unwrap();
+        object_frame.cap = cap;
+        object_frame.cow = true;
+
+        /* Shared frames are never paged out, so the copy in swap isn't needed anymore */
+        if let Some(block) = object_frame.swap_block.take() {
+            free_swap_block(block);
+        }
+    }
+
+    let cap = copy_frame_cap(cspace, frame.cap)?;
+    clone
+        .insert_frame_at(offset, (cap, frame.frame_ref))
+        .expect("Failed to insert frame into object");
+    clone.lookup_frame_mut(offset).unwrap().cow = true;
+    frame_table.share_frame(frame.frame_ref);
+
+    return Ok(());
+}
+
+pub fn handle_obj_clone(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_table: &mut FrameTable,
+    p: &mut UserProcess,
+    handle_cap_table: &mut HandleCapabilityTable<RootServerResource>,
+    args: &ObjClone,
+) -> Result<SMOSReply, InvocationError> {
+    /* Check that the passed in handle/cap is within bounds */
+    let handle_ref = generic_get_handle(
+        p,
+        handle_cap_table,
+        args.hndl,
+        ObjCloneArgs::Handle as usize,
+    )?;
+
+    /* Check that the handle refers to is an object */
+    let object = match handle_ref.as_ref().unwrap().inner() {
+        RootServerResource::Object(obj) => Ok(obj.clone()),
+        _ => Err(generic_invalid_handle_error(
+            args.hndl,
+            ObjCloneArgs::Handle as usize,
+        )),
+    }?;
+
+    /* Only memory that comes from the frame table can be shared, since the root server has to be
+    able to copy it. @alwin: Large frames could be supported once they can be mapped into the root
+    server */
+    if object.borrow().attributes.has(ObjAttributes::DEVICE)
+        || object.borrow().attributes.has(ObjAttributes::LARGE_PAGES)
+        || object.borrow().dma_allocation.is_some()
+    {
+        return Err(InvocationError::InvalidArguments);
+    }
+
+    let clone = Rc::new(RefCell::new(AnonymousMemoryObject::new(
+        object.borrow().size,
+        object.borrow().rights.clone(),
+        object.borrow().attributes,
+    )));
+
+    /* Frames that haven't been touched yet are left out, so they get allocated separately for
+    each object when they are first accessed */
+    for offset in object.borrow().frame_offsets() {
+        if share_frame_with_clone(
+            cspace,
+            ut_table,
+            frame_table,
+            &mut object.borrow_mut(),
+            &mut clone.borrow_mut(),
+            offset,
+        )
+        .is_err()
+        {
+            clone.borrow_mut().cleanup_frame_table(cspace, frame_table);
+            return Err(InvocationError::InsufficientResources);
+        }
+    }
+
+    /* Pages that are in swap are left there. The clone refers to the same block, which is read
+    in separately for each object. */
+    for offset in object.borrow().swapped_offsets() {
+        let block = object.borrow().lookup_swapped(offset).unwrap();
+        clone
+            .borrow_mut()
+            .insert_swapped_at(offset, block)
+            .expect("Failed to insert swapped page into object");
+        swap_area().unwrap().share_block(block);
+    }
+
+    let (idx, handle_ref, cptr) =
+        match generic_allocate_handle(p, handle_cap_table, args.return_cap) {
+            Ok(x) => x,
+            Err(e) => {
+                clone.borrow_mut().cleanup_frame_table(cspace, frame_table);
+                return Err(e);
+            }
+        };
+
+    *handle_ref = Some(ServerHandle::new(RootServerResource::Object(clone)));
+
+    let ret_value = if args.return_cap {
+        p.created_handle_caps.push(idx);
+        HandleOrHandleCap::<ObjectHandle>::new_handle_cap(cptr.unwrap())
+    } else {
+        HandleOrHandleCap::<ObjectHandle>::new_handle(idx)
+    };
+
+    return Ok(SMOSReply::ObjClone { hndl: ret_value });
+}
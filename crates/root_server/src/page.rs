@@ -24,3 +24,6 @@ pub const PAGE_MASK_4K: usize = PAGE_SIZE_4K - 1;
 pub const fn PAGE_ALIGN_4K(addr: usize) -> usize {
     addr & !PAGE_MASK_4K
 }
+
+pub const PAGE_BITS_2M: usize = 21;
+pub const PAGE_SIZE_2M: usize = SIZE_BITS_TO_BYTES(PAGE_BITS_2M);
@@ -0,0 +1,218 @@
+use crate::cspace::CSpace;
+use crate::frame_table::{FrameRef, FrameTable};
+use crate::object::AnonymousMemoryObject;
+use crate::swap::{swap_active, swap_area};
+use crate::ut::UTTable;
+use alloc::rc::Rc;
+use core::cell::RefCell;
+use smos_common::obj_attributes::ObjAttributes;
+
+/* Frames of anonymous memory objects are paged out to the swap area when the root server runs out
+of memory. Victims are chosen with the clock (second chance) algorithm over the pageable list of the
+frame table. There are no hardware reference bits to look at, so a frame is marked as referenced
+when it is faulted into a view, and clearing the mark removes it from all the views of its object so
+that the next access faults again.
+
+The swap server is never waited on. Only one transfer can be in flight at a time, and callers that
+need the swap area while it is busy are told to try again once the transfer has finished.
+
+@alwin: Pages are only written out when a frame is needed, it would be better to keep a pool of free
+frames around and write pages out ahead of time */
+
+#[derive(Debug)]
+pub enum PagerError {
+    /* The swap area is busy with another transfer */
+    Busy,
+    Failed(sel4::Error),
+}
+
+/* Only memory that comes from the frame table can be paged out */
+fn object_is_pageable(object: &AnonymousMemoryObject) -> bool {
+    return !object.attributes.has(ObjAttributes::DEVICE)
+        && !object.attributes.has(ObjAttributes::CONTIGUOUS)
+        && object.dma_allocation.is_none();
+}
+
+/* Makes the frame at offset into object a candidate for being paged out and marks it as
+referenced */
+pub fn track_frame(
+    frame_table: &mut FrameTable,
+    object: &Rc<RefCell<AnonymousMemoryObject>>,
+    offset: usize,
+) {
+    let frame_ref = {
+        let object_ref = object.borrow();
+        if !swap_active() || !object_is_pageable(&object_ref) {
+            return;
+        }
+
+        match object_ref.lookup_frame(offset) {
+            Some(frame) if !frame.cow && frame.pins == 0 => frame.frame_ref,
+            _ => return,
+        }
+    };
+
+    frame_table.make_pageable(frame_ref, object, offset);
+}
+
+/* Stops the frame at offset into object from being paged out until the pin is released with
+unpin_frame, bringing it in first if it isn't present. Large frames are never paged out, so they
+aren't pinned. */
+pub fn pin_frame(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_table: &mut FrameTable,
+    object: &Rc<RefCell<AnonymousMemoryObject>>,
+    offset: usize,
+) -> Result<(), PagerError> {
+    if object.borrow().lookup_large_frame(offset).is_some() {
+        return Ok(());
+    }
+
+    get_or_alloc_frame(cspace, ut_table, frame_table, object, offset)?;
+
+    let swap_block = {
+        let mut object_ref = object.borrow_mut();
+        let frame = object_ref.lookup_frame_mut(offset).unwrap();
+        frame.pins += 1;
+        frame_table.pin_frame(frame.frame_ref);
+        frame.swap_block
+    };
+
+    /* The frame is going to be written to through a mapping that the object doesn't know about */
+    if swap_block.is_some() {
+        object.borrow_mut().mark_dirty(cspace, offset);
+    }
+
+    return Ok(());
+}
+
+/* Releases a pin taken with pin_frame. Once nothing pins the frame, it can be paged out again. */
+pub fn unpin_frame(
+    frame_table: &mut FrameTable,
+    object: &Rc<RefCell<AnonymousMemoryObject>>,
+    offset: usize,
+) {
+    let unpinned = match object.borrow_mut().lookup_frame_mut(offset) {
+        Some(frame) if frame.pins > 0 => {
+            frame.pins -= 1;
+            frame.pins == 0
+        }
+        _ => false,
+    };
+
+    if unpinned {
+        track_frame(frame_table, object, offset);
+    }
+}
+
+/* Pages out a single frame, giving frames that have been referenced since the clock hand last
+passed them a second chance. The frame is freed straight away, the page is written out in the
+background. */
+fn evict_one(cspace: &mut CSpace, frame_table: &mut FrameTable) -> Result<(), PagerError> {
+    if !swap_active() {
+        return Err(PagerError::Failed(sel4::Error::NotEnoughMemory));
+    }
+
+    let swap = swap_area().unwrap();
+    if swap.is_busy() {
+        return Err(PagerError::Busy);
+    }
+
+    /* Every frame is looked at most twice, since the first pass clears all the referenced marks */
+    for _ in 0..2 * frame_table.num_pageable() {
+        let (frame_ref, object, offset) = match frame_table.clock_advance() {
+            Some(x) => x,
+            None => break,
+        };
+
+        let object = match object.upgrade() {
+            Some(object) => object,
+            None => continue,
+        };
+
+        /* Skip objects that are being worked on further up the call stack */
+        let mut object = match object.try_borrow_mut() {
+            Ok(object) => object,
+            Err(_) => continue,
+        };
+
+        if frame_table.is_referenced(frame_ref) {
+            frame_table.set_referenced(frame_ref, false);
+            object.unmap_from_views(cspace, offset);
+            continue;
+        }
+
+        return object
+            .swap_out(cspace, frame_table, swap, offset)
+            .map_err(PagerError::Failed);
+    }
+
+    return Err(PagerError::Failed(sel4::Error::NotEnoughMemory));
+}
+
+/* Allocates a frame from the frame table, paging out another frame if there are none left */
+pub fn alloc_frame(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_table: &mut FrameTable,
+) -> Result<FrameRef, PagerError> {
+    if let Some(frame_ref) = frame_table.alloc_frame(cspace, ut_table) {
+        return Ok(frame_ref);
+    }
+
+    evict_one(cspace, frame_table)?;
+
+    return frame_table
+        .alloc_frame(cspace, ut_table)
+        .ok_or(PagerError::Failed(sel4::Error::NotEnoughMemory));
+}
+
+/* Returns the frame at offset into object. If there isn't one, a new zeroed frame is allocated, or
+the page is read back in from the swap area. Reading a page in always returns Busy, and the frame
+is put into the object once the read has finished. */
+pub fn get_or_alloc_frame(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_table: &mut FrameTable,
+    object: &Rc<RefCell<AnonymousMemoryObject>>,
+    offset: usize,
+) -> Result<sel4::cap::SmallPage, PagerError> {
+    if let Some(frame) = object.borrow().lookup_frame(offset) {
+        return Ok(frame.cap);
+    }
+
+    let swapped = object.borrow().lookup_swapped(offset);
+
+    /* The page is lost if the swap server has gone away */
+    if swapped.is_some() && !swap_active() {
+        return Err(PagerError::Failed(sel4::Error::IllegalOperation));
+    }
+
+    if swapped.is_some() && swap_area().unwrap().is_busy() {
+        return Err(PagerError::Busy);
+    }
+
+    let frame_ref = alloc_frame(cspace, ut_table, frame_table)?;
+    let frame_cap = frame_table.frame_from_ref(frame_ref).get_cap();
+
+    if let Some(block) = swapped {
+        /* Making room may have started writing another page out */
+        let swap = swap_area().unwrap();
+        if swap.is_busy() {
+            frame_table.free_frame(frame_ref);
+            return Err(PagerError::Busy);
+        }
+
+        swap.start_read(object, offset, block, frame_ref);
+        return Err(PagerError::Busy);
+    }
+
+    frame_table.frame_data(frame_ref).fill(0);
+    object
+        .borrow_mut()
+        .insert_frame_at(offset, (frame_cap, frame_ref))
+        .expect("Failed to insert frame into object");
+
+    return Ok(frame_cap);
+}
@@ -1,101 +1,316 @@
+use crate::connection::{
+    handle_conn_deregister_internal, handle_conn_destroy_internal, handle_conn_unpublish_internal,
+};
 use crate::cspace::{CSpace, CSpaceTrait, UserCSpace};
-use crate::elf_load::load_elf;
+use crate::elf_load::{free_loaded_windows, load_elf};
 use crate::frame_table::{FrameRef, FrameTable};
 use crate::handle::RootServerResource;
-use crate::mapping::map_frame;
+use crate::irq::handle_irq_deregister_internal;
+use crate::mapping::{map_frame, PageTableRegistry};
+use crate::mount::{remove_process_mounts, resolve_path};
 use crate::object::{handle_obj_destroy_internal, AnonymousMemoryObject};
 use crate::page::PAGE_SIZE_4K;
+use crate::thread::{active_thread, destroy_thread_internal, idx_to_tid, Thread, ThreadType};
 use crate::ut::{UTTable, UTWrapper};
 use crate::util::{alloc_retype, dealloc_retyped};
 use crate::view::{handle_unview_internal, View};
-use crate::vmem_layout::{self, STACK_PAGES};
-use crate::window::handle_window_destroy_internal;
+use crate::vmem_layout::{self, PROCESS_STACK_PAGES};
 use crate::window::Window;
+use crate::window::{handle_window_deregister_internal, handle_window_destroy_internal};
 use crate::RSReplyWrapper;
+use alloc::format;
 use alloc::rc::Rc;
+use alloc::string::{String, ToString};
 use alloc::vec;
 use alloc::vec::Vec;
 use byteorder::{ByteOrder, LittleEndian};
 use core::cell::RefCell;
 use elf::ElfBytes;
 use smos_common::error::InvocationError;
+use smos_common::init::{
+    InitCNodeSlots, SMOS_CAP_ENV_PREFIX, SMOS_DEFAULT_PATH, SMOS_HANDLE_ENV_PREFIX, SMOS_PATH_ENV,
+};
 use smos_common::local_handle;
 use smos_common::obj_attributes::ObjAttributes;
+use smos_common::returns::{ExitStatus, ProcessStat, ProcessState};
+use smos_common::sched::SchedParams;
 use smos_common::string::copy_terminated_rust_string_to_buffer;
-use smos_common::util::ROUND_UP;
-use smos_server::event::{FAULT_EP_BITS, INVOCATION_EP_BITS};
+use smos_common::util::BIT;
+use smos_server::event::{thread_ident, FAULT_EP_BITS, INVOCATION_EP_BITS, TID_SHIFT};
 use smos_server::handle::{HandleAllocater, ServerHandle};
 use smos_server::handle_capability::HandleCapabilityTable;
 use smos_server::reply::{handle_reply, SMOSReply};
-use smos_server::syscalls::{LoadComplete, ProcessSpawn, ProcessWait};
+use smos_server::syscalls::{
+    LoadComplete, ProcessArgsAppend, ProcessCoreDump, ProcessDestroy, ProcessExec,
+    ProcessFaultForward, ProcessInfo, ProcessSetFaultHandler, ProcessSetSched, ProcessSpawn,
+    ProcessWait,
+};
 
 const LOADER_CONTENTS: &[u8] = include_bytes!(env!("LOADER_ELF"));
 
-// @alwin: This should probably be unbounded
-const MAX_PROCS: usize = 64;
 const MAX_HANDLES: usize = 256;
+/* The number of uncollected core files of children a process can have */
+const MAX_CORE_DUMPS: usize = 4;
+
+/* These mirror seL4_CoreSchedContextBytes, seL4_RefillSizeBytes and MIN_REFILLS from the kernel */
+const CORE_SCHED_CONTEXT_BYTES: usize = 10 * core::mem::size_of::<usize>() + 6 * 8;
+const REFILL_SIZE_BYTES: usize = 2 * 8;
+const MIN_REFILLS: usize = 2;
+
+/* Find the size of a scheduling context that can hold the requested number of extra refills */
+pub fn sched_context_size_bits(extra_refills: usize) -> Option<usize> {
+    let bytes = extra_refills
+        .checked_add(MIN_REFILLS)?
+        .checked_mul(REFILL_SIZE_BYTES)?
+        .checked_add(CORE_SCHED_CONTEXT_BYTES)?;
+
+    let size_bits = core::cmp::max(
+        bytes.checked_next_power_of_two()?.trailing_zeros() as usize,
+        sel4_sys::seL4_MinSchedContextBits.try_into().unwrap(),
+    );
+
+    // @alwin: This is an arbitrary limit, but a process shouldn't need more refills than fit in a page
+    if size_bits > sel4_sys::seL4_PageBits.try_into().unwrap() {
+        return None;
+    }
+
+    return Some(size_bits);
+}
 
 #[derive(Debug)]
 pub enum ProcessType {
     ActiveProcess(UserProcess),
-    ZombieProcess(usize), // @alwin: This should probably store the return code
-                          // @alwin: What happens to orphans? Does it become the responsibility of the root server
-                          // to adopt and do a periodic sweep to reap them?
+    /* A process that has exited but has not been waited on by its parent yet. Zombies whose
+    parent has exited are reaped straight away, so only a live parent can keep one around. */
+    ZombieProcess(Zombie),
+}
+
+/* What is kept of a process once it has exited so that it can still be looked up */
+#[derive(Debug)]
+pub struct Zombie {
+    pub pid: usize,
+    pub status: ExitStatus,
+    pub name: String,
+    /* The scheduling parameters the process had when it exited */
+    pub sched: SchedParams,
+}
+
+/* A pid is made up of an index into the process table and the generation of that slot. The
+generation is bumped whenever a slot is freed, so a stale pid (such as the badge of an endpoint cap
+that outlived its process) never refers to a process that later reuses the same slot. Pids are used
+as endpoint badges, so they need to fit in the bits that are not used by the badge type or the
+thread id. */
+const PID_INDEX_BITS: usize = 20;
+const PID_GEN_BITS: usize = TID_SHIFT - PID_INDEX_BITS;
+
+struct ProcSlot {
+    generation: usize,
+    proc: Option<Rc<RefCell<ProcessType>>>,
+}
+
+static mut PROCS: Vec<ProcSlot> = Vec::new();
+
+fn pid_index(pid: usize) -> usize {
+    return pid & (BIT(PID_INDEX_BITS) - 1);
+}
+
+fn pid_generation(pid: usize) -> usize {
+    return (pid >> PID_INDEX_BITS) & (BIT(PID_GEN_BITS) - 1);
 }
 
-const ARRAY_REPEAT_VALUE: Option<Rc<RefCell<ProcessType>>> = None;
-static mut PROCS: [Option<Rc<RefCell<ProcessType>>>; MAX_PROCS] = [ARRAY_REPEAT_VALUE; MAX_PROCS];
+fn make_pid(index: usize, generation: usize) -> usize {
+    return (generation << PID_INDEX_BITS) | index;
+}
 
-pub fn procs_get(i: usize) -> &'static Option<Rc<RefCell<ProcessType>>> {
+fn procs_slot(pid: usize) -> Option<&'static mut ProcSlot> {
     unsafe {
-        assert!(i < PROCS.len());
-        return &PROCS[i];
+        return PROCS
+            .get_mut(pid_index(pid))
+            .filter(|x| x.generation == pid_generation(pid));
     }
 }
 
-pub fn procs_get_mut(i: usize) -> &'static mut Option<Rc<RefCell<ProcessType>>> {
-    unsafe {
-        assert!(i < PROCS.len());
-        return &mut PROCS[i];
+pub fn procs_get(pid: usize) -> &'static Option<Rc<RefCell<ProcessType>>> {
+    return match procs_slot(pid) {
+        Some(x) => &x.proc,
+        /* The pid is stale or was never handed out */
+        None => &None,
+    };
+}
+
+pub fn procs_set(pid: usize, proc: Option<Rc<RefCell<ProcessType>>>) {
+    let slot = procs_slot(pid).expect("Invalid pid");
+
+    /* Freeing a slot invalidates every pid that refers to it */
+    if proc.is_none() && slot.proc.is_some() {
+        slot.generation = (slot.generation + 1) & (BIT(PID_GEN_BITS) - 1);
     }
+
+    slot.proc = proc;
 }
 
-pub fn procs_set(i: usize, proc: Option<Rc<RefCell<ProcessType>>>) {
+pub fn find_free_proc() -> Option<usize> {
     unsafe {
-        assert!(i < PROCS.len());
-        PROCS[i] = proc;
+        for (i, slot) in PROCS.iter().enumerate() {
+            if slot.proc.is_none() {
+                return Some(make_pid(i, slot.generation));
+            }
+        }
+
+        if PROCS.len() >= BIT(PID_INDEX_BITS) {
+            return None;
+        }
+
+        PROCS.push(ProcSlot {
+            generation: 0,
+            proc: None,
+        });
+        return Some(make_pid(PROCS.len() - 1, 0));
     }
 }
 
-pub fn find_free_proc() -> Option<usize> {
-    for i in 0..(unsafe { PROCS.len() }) {
-        if procs_get(i).is_none() {
-            return Some(i);
+/* The arguments of a process_spawn or process_exec that didn't fit into the shared buffer are sent
+ahead in parts, which are kept here until the thread that sent them makes the invocation */
+#[derive(Debug, Clone, Default)]
+pub struct StagedArgs {
+    tid: usize,
+    data: Vec<u8>,
+}
+
+impl StagedArgs {
+    /* Arguments can't be larger than the stack they are copied onto */
+    pub const MAX_SIZE: usize = PROCESS_STACK_PAGES * PAGE_SIZE_4K;
+
+    /* Parts that were sent by another thread are dropped, as they belong to an invocation that
+    thread never made */
+    pub fn append(&mut self, tid: usize, part: &[u8]) -> Result<(), InvocationError> {
+        if self.tid != tid {
+            self.data.clear();
+            self.tid = tid;
+        }
+
+        if self.data.len() + part.len() > Self::MAX_SIZE {
+            self.data.clear();
+            return Err(InvocationError::BufferTooLarge);
         }
+
+        self.data.extend_from_slice(part);
+        return Ok(());
     }
 
-    return None;
+    /* Returns the parts sent by tid followed by last, which is the contents of the shared buffer,
+    or None if tid didn't send anything ahead. Nothing is kept afterwards. */
+    pub fn take(&mut self, tid: usize, last: &[u8]) -> Option<Vec<u8>> {
+        let mut data = core::mem::take(&mut self.data);
+        if self.tid != tid || data.is_empty() {
+            return None;
+        }
+
+        data.extend_from_slice(last);
+        return Some(data);
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct UserProcess {
     pub tcb: (sel4::cap::Tcb, UTWrapper),
     pub pid: usize,
+    pub name: String,
     pub vspace: (sel4::cap::VSpace, UTWrapper),
     ipc_buffer: (sel4::cap::SmallPage, FrameRef), // @alwin: Maybe make this a window/object/view
     pub shared_buffer: (sel4::cap::SmallPage, FrameRef), // @alwin: Maybe make this a window/object/view
     sched_context: (sel4::cap::SchedContext, UTWrapper),
-    cspace: UserCSpace,
-    fault_ep: sel4::cap::Endpoint,
+    pub cspace: UserCSpace,
+    pub fault_ep: sel4::cap::Endpoint,
+    /* The endpoint of the process that took over the faults of this one, and the identifier that
+    its threads are badged with. None if the root server handles the faults. */
+    pub fault_handler: Option<(sel4::cap::Endpoint, usize)>,
     handle_table: [Option<ServerHandle<RootServerResource>>; MAX_HANDLES],
     pub created_handle_caps: Vec<usize>, // @alwin: this is a temporary hack, but doing it as Vec<HandleCapability> completely screws up the generic handle abstraction I have
     initial_windows: Vec<Rc<RefCell<Window>>>,
-    windows: Vec<Rc<RefCell<Window>>>,
+    pub windows: Vec<Rc<RefCell<Window>>>,
     pub views: Vec<Rc<RefCell<View>>>,
     pub waiter: Option<RSReplyWrapper>,
+    /* The pid of the process that spawned this one. None means that the process is owned by the
+    root server, either because it was started by the root server or because its parent exited. */
+    pub parent: Option<usize>,
+    pub children: Vec<Rc<RefCell<ProcessType>>>,
+    pub sched: SchedParams,
+    /* Threads other than the main one. The thread with tid i lives in slot i - 1. */
+    pub threads: Vec<Option<ThreadType>>,
+    /* KEY=VALUE strings that make up the environment the process was started with */
+    pub env: Vec<String>,
+    /* Core files of children that faulted which have not been collected yet, along with the size
+    of each file. They outlive the child being reaped, so a parent that was waiting on the child
+    when it faulted can still collect its core file. */
+    pub core_dumps: Vec<(
+        Rc<RefCell<ProcessType>>,
+        Rc<RefCell<AnonymousMemoryObject>>,
+        usize,
+    )>,
+    /* The page tables that were allocated to map things into the vspace of the process. This is
+    shared with views that have a fault pending on them, which need to map frames in later. */
+    pub page_tables: Rc<RefCell<PageTableRegistry>>,
+    pub staged_args: StagedArgs,
     // pub connections: Vec<Rc<Connection>> // @alwin: This stores outgoing conns. Do we need to store incoming conns too?
 }
 
+/* Cleans up whatever a handle of a process refers to */
+fn cleanup_handle_resource(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_table: &mut FrameTable,
+    resource: &RootServerResource,
+) {
+    match resource {
+        RootServerResource::Window(win) => {
+            handle_window_destroy_internal(cspace, win.clone(), false);
+        }
+        RootServerResource::Object(obj) => {
+            handle_obj_destroy_internal(cspace, frame_table, obj.clone(), true);
+        }
+        RootServerResource::ConnRegistration(conn) => {
+            handle_conn_deregister_internal(conn.clone());
+        }
+        RootServerResource::WindowRegistration(view) => {
+            handle_window_deregister_internal(cspace, ut_table, frame_table, view.clone());
+        }
+        RootServerResource::View(view) => {
+            handle_unview_internal(cspace, view.clone());
+        }
+        RootServerResource::Connection(conn) => {
+            handle_conn_destroy_internal(cspace, conn.clone());
+        }
+        RootServerResource::Server(server) => {
+            handle_conn_unpublish_internal(cspace, ut_table, frame_table, server.clone());
+        }
+        RootServerResource::Process(_) => {
+            /* Nothing to do here apart from dropping our reference to the process */
+        }
+        RootServerResource::Reply(reply) => {
+            dealloc_retyped(cspace, ut_table, *reply);
+        }
+        RootServerResource::HandleCap(cap) => {
+            /* Get rid of any copies of the handle cap that were given out by the server */
+            cspace
+                .root_cnode()
+                .absolute_cptr(*cap)
+                .revoke()
+                .expect("Failed to revoke handle cap");
+            cspace
+                .delete_cap(*cap)
+                .expect("Failed to delete handle cap");
+            cspace.free_cap(*cap);
+        }
+        RootServerResource::IRQRegistration(irq_reg) => {
+            handle_irq_deregister_internal(cspace, irq_reg.clone());
+        }
+        RootServerResource::ChannelAuthority(_) => {
+            /* Channel authorities only ever live in the handle cap table */
+        }
+    }
+}
+
 impl HandleAllocater<RootServerResource> for UserProcess {
     fn handle_table_size(&self) -> usize {
         return self.handle_table.len();
@@ -114,6 +329,7 @@ impl UserProcess {
     pub fn new(
         tcb: (sel4::cap::Tcb, UTWrapper),
         pid: usize,
+        name: &str,
         vspace: (sel4::cap::VSpace, UTWrapper),
         ipc_buffer: (sel4::cap::SmallPage, FrameRef),
         sched_context: (sel4::cap::SchedContext, UTWrapper),
@@ -121,16 +337,22 @@ impl UserProcess {
         fault_ep: sel4::cap::Endpoint,
         shared_buffer: (sel4::cap::SmallPage, FrameRef),
         initial_windows: Vec<Rc<RefCell<Window>>>,
+        page_tables: PageTableRegistry,
+        parent: Option<usize>,
+        sched: SchedParams,
+        env: Vec<String>,
     ) -> UserProcess {
         const HNDL_REPEAT_VALUE: Option<ServerHandle<RootServerResource>> = None;
         return UserProcess {
             tcb: tcb,
             pid: pid,
+            name: String::from(name),
             vspace: vspace,
             ipc_buffer: ipc_buffer,
             sched_context: sched_context,
             cspace: cspace,
             fault_ep: fault_ep,
+            fault_handler: None,
             handle_table: [HNDL_REPEAT_VALUE; 256],
             created_handle_caps: Vec::new(),
             shared_buffer: shared_buffer,
@@ -138,70 +360,295 @@ impl UserProcess {
             windows: Vec::new(),
             views: Vec::new(), /* connections: Vec::new() */
             waiter: None,
+            parent: parent,
+            children: Vec::new(),
+            sched: sched,
+            threads: Vec::new(),
+            env: env,
+            core_dumps: Vec::new(),
+            page_tables: Rc::new(RefCell::new(page_tables)),
+            staged_args: StagedArgs::default(),
         };
     }
 
-    pub fn destroy(
+    /* Sets the endpoint that faults of a thread of this process are delivered to. The kernel keeps
+    its own copy of the capability, so the caller is free to delete fault_ep afterwards. */
+    fn set_thread_fault_ep(
+        &self,
+        tcb: sel4::cap::Tcb,
+        fault_ep: sel4::cap::Endpoint,
+    ) -> Result<(), sel4::Error> {
+        return tcb.tcb_set_space(
+            fault_ep.cptr(),
+            self.cspace.root_cnode(),
+            sel4::CNodeCapData::new(0, 0),
+            self.vspace.0,
+        );
+    }
+
+    /* Sends the faults of the thread with the given tid to the fault handler of the process. Does
+    nothing if the root server is handling the faults of the process. */
+    pub fn delegate_thread_faults(
+        &self,
+        cspace: &mut CSpace,
+        tcb: sel4::cap::Tcb,
+        tid: usize,
+    ) -> Result<(), sel4::Error> {
+        let (handler, ident) = match self.fault_handler {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        /* Badge the endpoint so that the handler can tell which thread of which child faulted */
+        let fault_ep = cspace.alloc_cap::<sel4::cap_type::Endpoint>()?;
+        cspace
+            .root_cnode()
+            .absolute_cptr(fault_ep)
+            .mint(
+                &cspace.root_cnode().absolute_cptr(handler),
+                sel4::CapRightsBuilder::all().build(),
+                (thread_ident(ident, tid) | FAULT_EP_BITS)
+                    .try_into()
+                    .unwrap(),
+            )
+            .map_err(|e| {
+                cspace.free_cap(fault_ep);
+                e
+            })?;
+
+        let ret = self.set_thread_fault_ep(tcb, fault_ep);
+
+        cspace
+            .delete_cap(fault_ep)
+            .expect("Failed to delete fault endpoint");
+        cspace.free_cap(fault_ep);
+
+        return ret;
+    }
+
+    fn active_threads(&self) -> impl Iterator<Item = &Thread> {
+        return self.threads.iter().flatten().filter_map(|x| match x {
+            ThreadType::ActiveThread(thread) => Some(thread),
+            ThreadType::ZombieThread(_) => None,
+        });
+    }
+
+    /* Hands the faults of every thread of the process to the handler at handler_ep. The process
+    takes ownership of handler_ep, which is a copy in the cspace of the root server. */
+    fn set_fault_handler(
+        &mut self,
+        cspace: &mut CSpace,
+        handler_ep: sel4::cap::Endpoint,
+        ident: usize,
+    ) -> Result<(), sel4::Error> {
+        self.clear_fault_handler(cspace);
+        self.fault_handler = Some((handler_ep, ident));
+
+        let mut ret = self.delegate_thread_faults(cspace, self.tcb.0, 0);
+        for (i, thread) in self.threads.iter().enumerate() {
+            if let Some(ThreadType::ActiveThread(x)) = thread {
+                ret = ret.and_then(|_| self.delegate_thread_faults(cspace, x.tcb.0, idx_to_tid(i)));
+            }
+        }
+
+        if ret.is_err() {
+            self.clear_fault_handler(cspace);
+        }
+
+        return ret;
+    }
+
+    /* Makes the root server handle the next fault of the thread with the given tid, so that a
+    fault handler can pass on faults it can't resolve itself */
+    fn forward_thread_faults(&self, tid: usize) -> Result<(), InvocationError> {
+        if self.fault_handler.is_none() {
+            return Err(InvocationError::InvalidArguments);
+        }
+
+        let (tcb, fault_ep) = match tid {
+            0 => (self.tcb.0, self.fault_ep),
+            _ => match active_thread(self, tid) {
+                Some(x) => (x.tcb.0, x.fault_ep),
+                None => return Err(InvocationError::InvalidArguments),
+            },
+        };
+
+        return self
+            .set_thread_fault_ep(tcb, fault_ep)
+            .or(Err(InvocationError::InvalidArguments));
+    }
+
+    /* Makes the root server handle the faults of every thread of the process again */
+    fn clear_fault_handler(&mut self, cspace: &mut CSpace) {
+        let (handler, _) = match self.fault_handler.take() {
+            Some(x) => x,
+            None => return,
+        };
+
+        self.set_thread_fault_ep(self.tcb.0, self.fault_ep)
+            .expect("Failed to restore fault handler");
+        for thread in self.active_threads() {
+            self.set_thread_fault_ep(thread.tcb.0, thread.fault_ep)
+                .expect("Failed to restore fault handler");
+        }
+
+        cspace
+            .delete_cap(handler)
+            .expect("Failed to delete fault handler endpoint");
+        cspace.free_cap(handler);
+    }
+
+    /* Configures the scheduling contexts of all the threads of the process. The budget of the
+    process is split evenly between its threads, so that a process can't get more of the core than
+    it was granted by creating more threads. */
+    pub fn configure_sched(
+        &self,
+        sched_control: sel4::cap::SchedControl,
+        sched: &SchedParams,
+    ) -> Result<(), sel4::Error> {
+        let num_threads: u64 = (self.active_threads().count() + 1).try_into().unwrap();
+        let budget = sched.budget / num_threads;
+
+        let main_thread = core::iter::once((self.tcb.0, self.sched_context.0));
+        let threads = self.active_threads().map(|x| (x.tcb.0, x.sched_context.0));
+
+        for (tcb, sched_context) in main_thread.chain(threads) {
+            sched_control.sched_control_configure_flags(
+                sched_context,
+                budget,
+                sched.period,
+                sched.extra_refills.try_into().unwrap(),
+                0,
+                0,
+            )?;
+
+            tcb.tcb_set_priority(sel4::init_thread::slot::TCB.cap(), sched.prio.into())?;
+        }
+
+        return Ok(());
+    }
+
+    /* Stop the threads other than the main one */
+    fn destroy_threads(
+        &mut self,
+        cspace: &mut CSpace,
+        ut_table: &mut UTTable,
+        frame_table: &mut FrameTable,
+    ) {
+        for thread in core::mem::take(&mut self.threads).iter().flatten() {
+            if let ThreadType::ActiveThread(x) = thread {
+                if let Some(reply) = x.joiner {
+                    dealloc_retyped(cspace, ut_table, reply);
+                }
+                destroy_thread_internal(cspace, ut_table, frame_table, self, x);
+            }
+        }
+    }
+
+    /* Cleans up everything in the handle table apart from the handles in keep */
+    fn cleanup_handles(
         &mut self,
         cspace: &mut CSpace,
         ut_table: &mut UTTable,
         frame_table: &mut FrameTable,
-        _handle_cap_table: &mut HandleCapabilityTable<RootServerResource>,
+        keep: &[usize],
     ) {
-        /* Clean up the handle table */
-        for handle in &self.handle_table {
-            if handle.is_none() {
+        for (i, handle) in self.handle_table.iter_mut().enumerate() {
+            if keep.contains(&i) {
                 continue;
             }
 
-            match handle.as_ref().unwrap().inner() {
-                RootServerResource::Window(win) => {
+            if let Some(handle) = handle.take() {
+                cleanup_handle_resource(cspace, ut_table, frame_table, handle.inner());
+            }
+        }
+    }
+
+    /* Cleans up the handle caps that were created by this process */
+    fn cleanup_created_handle_caps(
+        &mut self,
+        cspace: &mut CSpace,
+        frame_table: &mut FrameTable,
+        handle_cap_table: &mut HandleCapabilityTable<RootServerResource>,
+    ) {
+        for idx in &self.created_handle_caps {
+            let handle_ref = handle_cap_table
+                .get_handle_cap_mut(*idx)
+                .expect("Invalid handle cap index");
+
+            match handle_ref.as_ref().map(|x| x.inner()) {
+                Some(RootServerResource::Window(win)) => {
                     handle_window_destroy_internal(cspace, win.clone(), false);
                 }
-                RootServerResource::Object(obj) => {
+                Some(RootServerResource::Object(obj)) => {
                     handle_obj_destroy_internal(cspace, frame_table, obj.clone(), true);
                 }
-                RootServerResource::ConnRegistration(_) => {
-                    todo!()
-                }
-                RootServerResource::WindowRegistration(_) => {
-                    todo!()
-                }
-                RootServerResource::View(view) => {
-                    handle_unview_internal(cspace, view.clone());
-                }
-                RootServerResource::Connection(_) => {
-                    todo!()
-                }
-                RootServerResource::Server(_) => {
-                    todo!()
-                }
-                RootServerResource::Process(_) => {
-                    todo!()
-                }
-                RootServerResource::Reply(_) => {
-                    todo!()
-                }
-                RootServerResource::HandleCap(_) => {
-                    todo!()
-                }
-                RootServerResource::IRQRegistration(_) => {
-                    todo!()
-                }
-                RootServerResource::ChannelAuthority(_) => {
-                    todo!()
+                Some(RootServerResource::ChannelAuthority(_)) => {
+                    /* The badged notification belongs to the ntfn dispatch of the server that
+                    created the channel, so it is cleaned up along with the server */
                 }
+                Some(_) => panic!("Unexpected resource type in handle cap table"),
+                // @alwin: The handle cap was already destroyed. This is fine as long as nobody
+                // else has allocated it since.
+                None => continue,
             }
+
+            handle_cap_table
+                .cleanup_handle_cap(*idx)
+                .expect("Failed to clean up handle cap");
+        }
+
+        self.created_handle_caps.clear();
+    }
+
+    /* Keeps the core file of a child until it is collected. Only the MAX_CORE_DUMPS most recent
+    core files are kept, so a parent that never collects them doesn't hold on to all of them. */
+    pub fn add_core_dump(
+        &mut self,
+        cspace: &mut CSpace,
+        frame_table: &mut FrameTable,
+        child: Rc<RefCell<ProcessType>>,
+        core_dump: AnonymousMemoryObject,
+        size: usize,
+    ) {
+        if self.core_dumps.len() == MAX_CORE_DUMPS {
+            let (_, oldest, _) = self.core_dumps.remove(0);
+            handle_obj_destroy_internal(cspace, frame_table, oldest, true);
         }
 
-        self.handle_table.fill(None);
+        self.core_dumps
+            .push((child, Rc::new(RefCell::new(core_dump)), size));
+    }
+
+    pub fn destroy(
+        &mut self,
+        cspace: &mut CSpace,
+        ut_table: &mut UTTable,
+        frame_table: &mut FrameTable,
+        handle_cap_table: &mut HandleCapabilityTable<RootServerResource>,
+    ) {
+        /* Stop the other threads before anything they might be using goes away */
+        self.destroy_threads(cspace, ut_table, frame_table);
+
+        self.cleanup_handles(cspace, ut_table, frame_table, &[]);
+
+        self.cleanup_created_handle_caps(cspace, frame_table, handle_cap_table);
 
-        /* @alwin: Do the same thing for the handle cap table */
+        remove_process_mounts(self.pid);
+
+        /* The process may not have finished loading */
+        self.cleanup_initial_windows(cspace, ut_table, frame_table);
+
+        for (_, core_dump, _) in self.core_dumps.drain(..) {
+            handle_obj_destroy_internal(cspace, frame_table, core_dump, true);
+        }
 
         dealloc_retyped(cspace, ut_table, self.sched_context);
 
         dealloc_retyped(cspace, ut_table, self.tcb);
 
+        self.page_tables.borrow_mut().free_all(cspace, ut_table);
+
         dealloc_retyped(cspace, ut_table, self.vspace);
 
         /* @alwin: Should this just be a window/view/obj */
@@ -220,6 +667,13 @@ impl UserProcess {
             .delete_cap(self.fault_ep)
             .expect("Failed to delete fault endpoint");
 
+        if let Some((handler, _)) = self.fault_handler.take() {
+            cspace
+                .delete_cap(handler)
+                .expect("Failed to delete fault handler endpoint");
+            cspace.free_cap(handler);
+        }
+
         self.cspace.destroy(cspace, ut_table);
     }
 
@@ -239,6 +693,28 @@ impl UserProcess {
         return None;
     }
 
+    /* The amount of memory that is mapped into the address space of the process */
+    pub fn mem_usage(&self) -> usize {
+        let mut pages = 0;
+        for window in self.initial_windows.iter().chain(self.windows.iter()) {
+            if let Some(view) = &window.borrow().bound_view {
+                pages += view.borrow().mapped_pages();
+            }
+        }
+
+        return pages * PAGE_SIZE_4K;
+    }
+
+    /* Creates the zombie that replaces this process once it has exited with status */
+    pub fn zombie(&self, status: ExitStatus) -> Zombie {
+        return Zombie {
+            pid: self.pid,
+            status: status,
+            name: self.name.clone(),
+            sched: self.sched,
+        };
+    }
+
     pub fn find_window_containing(&self, vaddr: usize) -> Option<Rc<RefCell<Window>>> {
         /* Do we need to check initial windows? */
         /* @ I think in most cases no but for the boot file server to forward vm faults, yes */
@@ -265,6 +741,52 @@ impl UserProcess {
         self.windows.push(window);
     }
 
+    /* Frees the page tables that only translate addresses in [start, start + size). The IPC buffer
+    and the shared buffer aren't in a window, so the page tables they are mapped through are kept
+    around. */
+    pub fn free_page_tables(
+        &self,
+        cspace: &mut CSpace,
+        ut_table: &mut UTTable,
+        start: usize,
+        size: usize,
+    ) {
+        self.page_tables.borrow_mut().free_range(
+            cspace,
+            ut_table,
+            start,
+            start.saturating_add(size),
+            &[
+                vmem_layout::PROCESS_IPC_BUFFER,
+                vmem_layout::PROCESS_RS_DATA_TRANSFER_PAGE,
+            ],
+        );
+    }
+
+    /* Frees the windows the loader and the stack of the process were set up in. They aren't
+    needed anymore once the program has been loaded. */
+    fn cleanup_initial_windows(
+        &mut self,
+        cspace: &mut CSpace,
+        ut_table: &mut UTTable,
+        frame_table: &mut FrameTable,
+    ) {
+        free_loaded_windows(cspace, frame_table, &self.initial_windows);
+
+        for window in self.initial_windows.clone() {
+            self.free_page_tables(
+                cspace,
+                ut_table,
+                window.borrow().start,
+                window.borrow().size,
+            );
+        }
+
+        // The objects and the views should be cleaned up by doing this since they are RCs that should
+        // not be referenced anywhere else.
+        self.initial_windows.clear();
+    }
+
     pub fn remove_window(&mut self, window: Rc<RefCell<Window>>) {
         let pos = self.windows.iter().position(|x| Rc::ptr_eq(x, &window));
         match pos {
@@ -275,27 +797,82 @@ impl UserProcess {
         }
     }
 
+    /* Loads the loader into the address space of the process and starts the main thread in it.
+    The loader is given loader_args, and passes exec_args on to the program it loads. */
+    fn load_program(
+        &mut self,
+        cspace: &mut CSpace,
+        ut_table: &mut UTTable,
+        frame_table: &mut FrameTable,
+        loader_args: Vec<&str>,
+        exec_args: Option<Vec<&str>>,
+    ) -> Result<(), sel4::Error> {
+        let elf = ElfBytes::<elf::endian::AnyEndian>::minimal_parse(LOADER_CONTENTS)
+            .or(Err(sel4::Error::InvalidArgument))?;
+
+        let (initial_windows, entry_point) = load_elf(
+            cspace,
+            ut_table,
+            frame_table,
+            self.vspace.0,
+            &mut self.page_tables.borrow_mut(),
+            &elf,
+        )?;
+
+        /* The windows are freed along with the process if the rest of this fails */
+        self.initial_windows = initial_windows;
+
+        let (_, stack_window) = init_process_stack(cspace, ut_table, frame_table)?;
+        self.initial_windows.push(stack_window.clone());
+
+        let sp =
+            self.write_args_to_stack(frame_table, stack_window, Some(loader_args), exec_args)?;
+
+        let mut user_context = sel4::UserContext::default();
+        *user_context.pc_mut() = entry_point;
+        *user_context.sp_mut() = sp.try_into().unwrap();
+
+        /* The runtime takes a non-zero thread pointer to be a TLS block that the loader set up, so
+        the one left behind by the previous program is cleared. TPIDR_EL0 comes after pc, sp, spsr
+        and x0-x30 in the user context. */
+        user_context.inner_mut().tpidr_el0 = 0;
+        return self.tcb.0.tcb_write_registers(true, 35, &mut user_context);
+    }
+
     pub fn write_args_to_stack(
         &self,
         frame_table: &mut FrameTable,
         stack: Rc<RefCell<Window>>,
         loader_args: Option<Vec<&str>>,
         exec_args: Option<Vec<&str>>,
-    ) -> usize {
+    ) -> Result<usize, sel4::Error> {
         let mut argv: Vec<u64> = Vec::new();
         let mut envp: Vec<u64> = Vec::new();
         let mut curr_stack_vaddr = vmem_layout::PROCESS_STACK_TOP;
 
+        /* Copy the environment strings onto the stack */
+        let mut env_ptrs: Vec<u64> = Vec::new();
+        for var in &self.env {
+            curr_stack_vaddr = curr_stack_vaddr
+                .checked_sub(var.as_bytes().len() + 1)
+                .ok_or(sel4::Error::RangeError)?;
+            env_ptrs.push(curr_stack_vaddr as u64);
+            self.write_str_to_stack(frame_table, &stack, curr_stack_vaddr, var)?;
+        }
+
+        /* pad to word alignment */
+        curr_stack_vaddr = curr_stack_vaddr - (curr_stack_vaddr % 8);
+
         let envp_ptr = {
             /* envp looks like the following */
-            /* [STACK_TOP, IPC_BUFFER_ADDR, RS_SHARED_BUF, NULL] */
+            /* [STACK_TOP, IPC_BUFFER_ADDR, RS_SHARED_BUF, ENV_VAR_0, ..., ENV_VAR_N, NULL] */
             curr_stack_vaddr = curr_stack_vaddr - 8;
             self.write_words_to_stack(
                 frame_table,
                 &stack,
                 curr_stack_vaddr,
                 &[crate::vmem_layout::PROCESS_STACK_TOP as u64],
-            );
+            )?;
             envp.push(curr_stack_vaddr as u64);
 
             curr_stack_vaddr = curr_stack_vaddr - 8;
@@ -304,7 +881,7 @@ impl UserProcess {
                 &stack,
                 curr_stack_vaddr,
                 &[crate::vmem_layout::PROCESS_IPC_BUFFER as u64],
-            );
+            )?;
             envp.push(curr_stack_vaddr as u64);
 
             curr_stack_vaddr = curr_stack_vaddr - 8;
@@ -313,63 +890,42 @@ impl UserProcess {
                 &stack,
                 curr_stack_vaddr,
                 &[crate::vmem_layout::PROCESS_RS_DATA_TRANSFER_PAGE as u64],
-            );
+            )?;
             envp.push(curr_stack_vaddr as u64);
 
+            /* The environment strings come after the fixed entries */
+            envp.extend(env_ptrs);
+
             /* Add null terminator to envp */
             envp.push(0);
 
-            curr_stack_vaddr = curr_stack_vaddr - (envp.len() * 8);
-            self.write_words_to_stack(frame_table, &stack, curr_stack_vaddr, &envp);
+            curr_stack_vaddr = curr_stack_vaddr
+                .checked_sub(envp.len() * 8)
+                .ok_or(sel4::Error::RangeError)?;
+            self.write_words_to_stack(frame_table, &stack, curr_stack_vaddr, &envp)?;
 
             curr_stack_vaddr as u64
         };
 
         let argv_ptr = if loader_args.is_some() || exec_args.is_some() {
-            if loader_args.is_some() {
-                for arg in loader_args.as_ref().unwrap().iter() {
-                    if ROUND_UP(
-                        curr_stack_vaddr,
-                        sel4_sys::seL4_PageBits.try_into().unwrap(),
-                    ) != ROUND_UP(
-                        curr_stack_vaddr - (arg.as_bytes().len() + 1),
-                        sel4_sys::seL4_PageBits.try_into().unwrap(),
-                    ) {
-                        /* @alwin: When part of an arg ends up on a different page to another part */
-                        todo!();
-                    }
-
-                    curr_stack_vaddr = curr_stack_vaddr - (arg.as_bytes().len() + 1);
-                    argv.push(curr_stack_vaddr as u64);
-                    self.write_str_to_stack(frame_table, &stack, curr_stack_vaddr, arg);
-                }
-            }
-
-            if exec_args.is_some() {
-                for arg in exec_args.as_ref().unwrap().iter() {
-                    if ROUND_UP(
-                        curr_stack_vaddr,
-                        sel4_sys::seL4_PageBits.try_into().unwrap(),
-                    ) != ROUND_UP(
-                        curr_stack_vaddr - (arg.as_bytes().len() + 1),
-                        sel4_sys::seL4_PageBits.try_into().unwrap(),
-                    ) {
-                        /* @alwin: When part of an arg ends up on a different page to another part */
-                        todo!();
-                    }
-
-                    curr_stack_vaddr = curr_stack_vaddr - (arg.as_bytes().len() + 1);
-                    argv.push(curr_stack_vaddr as u64);
-                    self.write_str_to_stack(frame_table, &stack, curr_stack_vaddr, arg);
-                }
+            let args = loader_args.iter().chain(exec_args.iter()).flatten();
+            for arg in args {
+                /* Args are written as is, so they may straddle a page boundary */
+                curr_stack_vaddr = curr_stack_vaddr
+                    .checked_sub(arg.as_bytes().len() + 1)
+                    .ok_or(sel4::Error::RangeError)?;
+                argv.push(curr_stack_vaddr as u64);
+                self.write_str_to_stack(frame_table, &stack, curr_stack_vaddr, arg)?;
             }
 
             /* pad to word alignment */
             curr_stack_vaddr = curr_stack_vaddr - (curr_stack_vaddr % 8);
 
             /* Write argv array to stack */
-            curr_stack_vaddr = curr_stack_vaddr - (argv.len() * 8);
-            self.write_words_to_stack(frame_table, &stack, curr_stack_vaddr, &argv);
+            curr_stack_vaddr = curr_stack_vaddr
+                .checked_sub(argv.len() * 8)
+                .ok_or(sel4::Error::RangeError)?;
+            self.write_words_to_stack(frame_table, &stack, curr_stack_vaddr, &argv)?;
 
             /* Write argv pointer to stack */
             curr_stack_vaddr as u64
@@ -380,11 +936,11 @@ impl UserProcess {
 
         /* Write ptr to envp to stack */
         curr_stack_vaddr = curr_stack_vaddr - 8;
-        self.write_words_to_stack(frame_table, &stack, curr_stack_vaddr, &[envp_ptr]);
+        self.write_words_to_stack(frame_table, &stack, curr_stack_vaddr, &[envp_ptr])?;
 
         /* Write ptr to argv to stack */
         curr_stack_vaddr = curr_stack_vaddr - 8;
-        self.write_words_to_stack(frame_table, &stack, curr_stack_vaddr, &[argv_ptr]);
+        self.write_words_to_stack(frame_table, &stack, curr_stack_vaddr, &[argv_ptr])?;
 
         /* Write argc to stack */
         curr_stack_vaddr = curr_stack_vaddr - 4;
@@ -393,20 +949,25 @@ impl UserProcess {
             &stack,
             curr_stack_vaddr,
             &[argv.len().try_into().unwrap()],
-        );
+        )?;
 
-        return curr_stack_vaddr;
+        return Ok(curr_stack_vaddr);
     }
 
-    fn write_str_to_stack(
+    /* Writes data to the preallocated stack of the process. The data may span as many pages of
+    the stack as needed, but it is an error for it to go past either end of the stack. */
+    fn write_bytes_to_stack(
         &self,
         frame_table: &FrameTable,
         stack_win: &Rc<RefCell<Window>>,
         vaddr: usize,
-        string: &str,
-    ) {
-        const STACK_BOTTOM: usize = vmem_layout::PROCESS_STACK_TOP - STACK_PAGES * PAGE_SIZE_4K;
-        let offset = vaddr - STACK_BOTTOM;
+        data: &[u8],
+    ) -> Result<(), sel4::Error> {
+        const STACK_BOTTOM: usize =
+            vmem_layout::PROCESS_STACK_TOP - PROCESS_STACK_PAGES * PAGE_SIZE_4K;
+        if vaddr < STACK_BOTTOM || vaddr + data.len() > vmem_layout::PROCESS_STACK_TOP {
+            return Err(sel4::Error::RangeError);
+        }
 
         let obj = stack_win
             .borrow_mut()
@@ -418,55 +979,51 @@ impl UserProcess {
             .as_ref()
             .unwrap()
             .clone();
-        let frame_data = frame_table.frame_data(
-            obj.borrow_mut()
-                .lookup_frame(offset)
-                .expect("Could not get frame")
-                .frame_ref,
-        );
-        let offset_page = vaddr % PAGE_SIZE_4K;
 
-        let string_len_with_null = string.as_bytes().len() + 1;
-        copy_terminated_rust_string_to_buffer(
-            &mut frame_data[offset_page..offset_page + string_len_with_null],
-            string,
-        )
-        .expect("Failed to write string to stack");
+        let mut written = 0;
+        while written < data.len() {
+            let curr_vaddr = vaddr + written;
+            let offset_page = curr_vaddr % PAGE_SIZE_4K;
+            let len = core::cmp::min(PAGE_SIZE_4K - offset_page, data.len() - written);
+
+            let frame_data = frame_table.frame_data(
+                obj.borrow_mut()
+                    .lookup_frame(curr_vaddr - STACK_BOTTOM)
+                    .expect("Could not get frame")
+                    .frame_ref,
+            );
+            frame_data[offset_page..offset_page + len]
+                .copy_from_slice(&data[written..written + len]);
+
+            written += len;
+        }
+
+        return Ok(());
     }
 
-    fn write_words_to_stack(
+    fn write_str_to_stack(
         &self,
         frame_table: &FrameTable,
         stack_win: &Rc<RefCell<Window>>,
         vaddr: usize,
-        words: &[u64],
-    ) {
-        const STACK_BOTTOM: usize = vmem_layout::PROCESS_STACK_TOP - STACK_PAGES * PAGE_SIZE_4K;
-        let offset = vaddr - STACK_BOTTOM;
+        string: &str,
+    ) -> Result<(), sel4::Error> {
+        self.write_bytes_to_stack(frame_table, stack_win, vaddr, string.as_bytes())?;
 
-        let obj = stack_win
-            .borrow_mut()
-            .bound_view
-            .as_ref()
-            .unwrap()
-            .borrow_mut()
-            .bound_object
-            .as_ref()
-            .unwrap()
-            .clone();
-        let frame_data = frame_table.frame_data(
-            obj.borrow_mut()
-                .lookup_frame(offset)
-                .expect("Could not get frame")
-                .frame_ref,
-        );
-        let offset_page = vaddr % PAGE_SIZE_4K;
+        /* Null terminate the string */
+        return self.write_bytes_to_stack(frame_table, stack_win, vaddr + string.len(), &[0]);
+    }
 
-        let bytes_length = words.len() * 8;
-        LittleEndian::write_u64_into(
-            words,
-            &mut frame_data[offset_page..offset_page + bytes_length],
-        );
+    fn write_words_to_stack(
+        &self,
+        frame_table: &FrameTable,
+        stack_win: &Rc<RefCell<Window>>,
+        vaddr: usize,
+        words: &[u64],
+    ) -> Result<(), sel4::Error> {
+        let mut bytes = vec![0; words.len() * 8];
+        LittleEndian::write_u64_into(words, &mut bytes);
+        return self.write_bytes_to_stack(frame_table, stack_win, vaddr, &bytes);
     }
 
     fn write_half_words_to_stack(
@@ -475,34 +1032,40 @@ impl UserProcess {
         stack_win: &Rc<RefCell<Window>>,
         vaddr: usize,
         data: &[u32],
-    ) {
-        const STACK_BOTTOM: usize = vmem_layout::PROCESS_STACK_TOP - STACK_PAGES * PAGE_SIZE_4K;
-        let offset = vaddr - STACK_BOTTOM;
+    ) -> Result<(), sel4::Error> {
+        let mut bytes = vec![0; data.len() * 4];
+        LittleEndian::write_u32_into(data, &mut bytes);
+        return self.write_bytes_to_stack(frame_table, stack_win, vaddr, &bytes);
+    }
+}
 
-        let obj = stack_win
-            .borrow_mut()
-            .bound_view
-            .as_ref()
-            .unwrap()
-            .borrow_mut()
-            .bound_object
-            .as_ref()
-            .unwrap()
-            .clone();
-        let frame_data = frame_table.frame_data(
-            obj.borrow_mut()
-                .lookup_frame(offset)
-                .expect("Could not get frame")
-                .frame_ref,
-        );
-        let offset_page = vaddr % PAGE_SIZE_4K;
+/* The number of bytes write_args_to_stack needs at the top of the stack for the given environment
+and args. This is an upper bound, as it assumes the worst case for the alignment padding. */
+fn args_stack_size(env: &[String], args: &[&str]) -> usize {
+    let strings: usize = env
+        .iter()
+        .map(|x| x.len() + 1)
+        .chain(args.iter().map(|x| x.len() + 1))
+        .sum();
+
+    /* envp has three fixed entries and a null terminator, and is followed by argv, the pointers
+    to envp and argv and argc */
+    return strings + 2 * 7 + (env.len() + 4 + args.len() + 2) * 8 + 4;
+}
 
-        let bytes_length = data.len() * 4;
-        LittleEndian::write_u32_into(
-            data,
-            &mut frame_data[offset_page..offset_page + bytes_length],
-        );
-    }
+/* Args that don't fit are rejected before anything is set up for the process */
+fn args_fit_on_stack(
+    env: &[String],
+    loader_args: &Option<Vec<&str>>,
+    exec_args: &Option<Vec<&str>>,
+) -> bool {
+    let args: Vec<&str> = loader_args
+        .iter()
+        .chain(exec_args.iter())
+        .flatten()
+        .copied()
+        .collect();
+    return args_stack_size(env, &args) <= PROCESS_STACK_PAGES * PAGE_SIZE_4K;
 }
 
 fn init_process_stack(
@@ -511,13 +1074,13 @@ fn init_process_stack(
     frame_table: &mut FrameTable,
 ) -> Result<(usize, Rc<RefCell<Window>>), sel4::Error> {
     let window = Rc::new(RefCell::new(Window {
-        start: vmem_layout::PROCESS_STACK_TOP - vmem_layout::STACK_PAGES * PAGE_SIZE_4K,
-        size: vmem_layout::STACK_PAGES * PAGE_SIZE_4K,
+        start: vmem_layout::PROCESS_STACK_TOP - PROCESS_STACK_PAGES * PAGE_SIZE_4K,
+        size: PROCESS_STACK_PAGES * PAGE_SIZE_4K,
         bound_view: None,
     }));
 
     let object = Rc::new(RefCell::new(AnonymousMemoryObject::new(
-        vmem_layout::STACK_PAGES * PAGE_SIZE_4K,
+        PROCESS_STACK_PAGES * PAGE_SIZE_4K,
         sel4::CapRights::all(),
         ObjAttributes::DEFAULT,
     )));
@@ -536,17 +1099,28 @@ fn init_process_stack(
 
     /* Preallocate the stack */
     // @alwin: This just makes my life a little bit easier, but isn't strictly necessary
-    for i in 0..STACK_PAGES {
-        let frame_ref = frame_table
-            .alloc_frame(cspace, ut_table)
-            .ok_or(sel4::Error::NotEnoughMemory)?;
+    for i in 0..PROCESS_STACK_PAGES {
+        let frame_ref = match frame_table.alloc_frame(cspace, ut_table) {
+            Some(frame_ref) => frame_ref,
+            None => {
+                free_loaded_windows(cspace, frame_table, &[window]);
+                return Err(sel4::Error::NotEnoughMemory);
+            }
+        };
         let orig_frame_cap = frame_table.frame_from_ref(frame_ref).get_cap();
         object
             .borrow_mut()
             .insert_frame_at(i * PAGE_SIZE_4K, (orig_frame_cap, frame_ref))
             .expect("Failed to insert frame into object");
 
-        let loadee_frame = sel4::CPtr::from_bits(cspace.alloc_slot()?.try_into().unwrap())
+        let loadee_slot = match cspace.alloc_slot() {
+            Ok(slot) => slot,
+            Err(e) => {
+                free_loaded_windows(cspace, frame_table, &[window]);
+                return Err(e);
+            }
+        };
+        let loadee_frame = sel4::CPtr::from_bits(loadee_slot.try_into().unwrap())
             .cast::<sel4::cap_type::UnspecifiedPage>();
 
         cspace
@@ -572,18 +1146,28 @@ pub fn start_process(
     cspace: &mut CSpace,
     ut_table: &mut UTTable,
     frame_table: &mut FrameTable,
+    handle_cap_table: &mut HandleCapabilityTable<RootServerResource>,
     sched_control: sel4::cap::SchedControl,
     name: &str,
     ep: sel4::cap::Endpoint,
     elf_data: &[u8],
     loader_args: Option<Vec<&str>>,
     exec_args: Option<Vec<&str>>,
-    prio: u8,
+    env: Vec<String>,
+    caps: &[(&str, sel4::AbsoluteCPtr)],
+    sched: &SchedParams,
+    parent: Option<usize>,
 ) -> Result<Rc<RefCell<ProcessType>>, sel4::Error> {
-    /* We essentially use the position in the table as the pid. Don't think this is the right way to
-    do it properly */
+    if !args_fit_on_stack(&env, &loader_args, &exec_args) {
+        err_rs!("Arguments do not fit on the stack");
+        return Err(sel4::Error::RangeError);
+    }
+
     let pos = find_free_proc().ok_or(sel4::Error::NotEnoughMemory)?;
 
+    let sc_size_bits =
+        sched_context_size_bits(sched.extra_refills).ok_or(sel4::Error::InvalidArgument)?;
+
     /* Create a VSpace */
     let vspace = alloc_retype::<sel4::cap_type::VSpace>(
         cspace,
@@ -755,7 +1339,7 @@ pub fn start_process(
         cspace,
         ut_table,
         sel4::ObjectBlueprint::SchedContext {
-            size_bits: sel4_sys::seL4_MinSchedContextBits.try_into().unwrap(),
+            size_bits: sc_size_bits,
         },
     )
     .map_err(|e| {
@@ -772,9 +1356,16 @@ pub fn start_process(
         e
     })?;
 
-    /* Configure the scheduling context to use the first core with budget equal to period */
+    /* Configure the scheduling context to use the first core */
     sched_control
-        .sched_control_configure_flags(sched_context.0, 1000, 1000, 0, 0, 0)
+        .sched_control_configure_flags(
+            sched_context.0,
+            sched.budget,
+            sched.period,
+            sched.extra_refills.try_into().unwrap(),
+            0,
+            0,
+        )
         .map_err(|e| {
             err_rs!("Failed to configure scheduling context");
             dealloc_retyped(cspace, ut_table, tcb);
@@ -834,8 +1425,8 @@ pub fn start_process(
     tcb.0
         .tcb_set_sched_params(
             sel4::init_thread::slot::TCB.cap(),
-            0,
-            prio.into(),
+            sched.mcp.into(),
+            sched.prio.into(),
             sched_context.0,
             fault_ep,
         )
@@ -878,22 +1469,31 @@ pub fn start_process(
         })?;
 
     /* Load the ELF file into the virtual address space */
-    let mut initial_windows =
-        load_elf(cspace, ut_table, frame_table, vspace.0, &elf).map_err(|e| {
-            err_rs!("Failed to load ELF file");
-            cspace.delete_cap(fault_ep).unwrap();
-            cspace.free_cap(fault_ep);
-            dealloc_retyped(cspace, ut_table, tcb);
-            proc_cspace.delete(proc_self_cspace).unwrap();
-            proc_cspace.free_slot(proc_self_cspace);
-            proc_cspace.delete(proc_ep).unwrap();
-            cspace.free_slot(proc_ep);
-            cspace.delete(ipc_buffer_slot).unwrap();
-            cspace.free_slot(ipc_buffer_slot);
-            frame_table.free_frame(ipc_buffer_ref);
-            dealloc_retyped(cspace, ut_table, vspace);
-            e
-        })?;
+    let mut page_tables = PageTableRegistry::new();
+    let (mut initial_windows, entry_point) = load_elf(
+        cspace,
+        ut_table,
+        frame_table,
+        vspace.0,
+        &mut page_tables,
+        &elf,
+    )
+    .map_err(|e| {
+        err_rs!("Failed to load ELF file");
+        cspace.delete_cap(fault_ep).unwrap();
+        cspace.free_cap(fault_ep);
+        dealloc_retyped(cspace, ut_table, tcb);
+        proc_cspace.delete(proc_self_cspace).unwrap();
+        proc_cspace.free_slot(proc_self_cspace);
+        proc_cspace.delete(proc_ep).unwrap();
+        cspace.free_slot(proc_ep);
+        cspace.delete(ipc_buffer_slot).unwrap();
+        cspace.free_slot(ipc_buffer_slot);
+        frame_table.free_frame(ipc_buffer_ref);
+        page_tables.free_all(cspace, ut_table);
+        dealloc_retyped(cspace, ut_table, vspace);
+        e
+    })?;
 
     /* Map the IPC buffer into the virtual address space */
     map_frame(
@@ -905,6 +1505,7 @@ pub fn start_process(
         sel4::CapRightsBuilder::all().build(),
         sel4::VmAttributes::DEFAULT,
         None,
+        Some(&mut page_tables),
     )
     .map_err(|e| {
         err_rs!("Failed to set IPC buffer");
@@ -918,6 +1519,8 @@ pub fn start_process(
         cspace.delete(ipc_buffer_slot).unwrap();
         cspace.free_slot(ipc_buffer_slot);
         frame_table.free_frame(ipc_buffer_ref);
+        free_loaded_windows(cspace, frame_table, &initial_windows);
+        page_tables.free_all(cspace, ut_table);
         dealloc_retyped(cspace, ut_table, vspace);
         e
     })?;
@@ -942,6 +1545,8 @@ pub fn start_process(
             cspace.delete(ipc_buffer_slot).unwrap();
             cspace.free_slot(ipc_buffer_slot);
             frame_table.free_frame(ipc_buffer_ref);
+            free_loaded_windows(cspace, frame_table, &initial_windows);
+            page_tables.free_all(cspace, ut_table);
             dealloc_retyped(cspace, ut_table, vspace);
             e
         })?;
@@ -960,6 +1565,8 @@ pub fn start_process(
         cspace.delete(ipc_buffer_slot).unwrap();
         cspace.free_slot(ipc_buffer_slot);
         frame_table.free_frame(ipc_buffer_ref);
+        free_loaded_windows(cspace, frame_table, &initial_windows);
+        page_tables.free_all(cspace, ut_table);
         dealloc_retyped(cspace, ut_table, vspace);
         e
     })?;
@@ -990,6 +1597,8 @@ pub fn start_process(
             cspace.delete(ipc_buffer_slot).unwrap();
             cspace.free_slot(ipc_buffer_slot);
             frame_table.free_frame(ipc_buffer_ref);
+            free_loaded_windows(cspace, frame_table, &initial_windows);
+            page_tables.free_all(cspace, ut_table);
             dealloc_retyped(cspace, ut_table, vspace);
             e
         })?;
@@ -1005,6 +1614,7 @@ pub fn start_process(
         sel4::CapRightsBuilder::all().build(),
         sel4::VmAttributes::DEFAULT,
         None,
+        Some(&mut page_tables),
     )
     .map_err(|e| {
         err_rs!("Failed to map shared buffer");
@@ -1021,6 +1631,8 @@ pub fn start_process(
         cspace.delete(ipc_buffer_slot).unwrap();
         cspace.free_slot(ipc_buffer_slot);
         frame_table.free_frame(ipc_buffer_ref);
+        free_loaded_windows(cspace, frame_table, &initial_windows);
+        page_tables.free_all(cspace, ut_table);
         dealloc_retyped(cspace, ut_table, vspace);
         e
     })?;
@@ -1028,6 +1640,9 @@ pub fn start_process(
     /* Set up the process stack */
     let (_, stack_window) = init_process_stack(cspace, ut_table, frame_table).map_err(|e| {
         err_rs!("Failed to initialize stack");
+        cspace.delete(shared_buffer_slot).unwrap();
+        cspace.free_slot(shared_buffer_slot);
+        frame_table.free_frame(shared_buffer_ref);
         cspace.delete_cap(fault_ep).unwrap();
         cspace.free_cap(fault_ep);
         dealloc_retyped(cspace, ut_table, tcb);
@@ -1038,15 +1653,18 @@ pub fn start_process(
         cspace.delete(ipc_buffer_slot).unwrap();
         cspace.free_slot(ipc_buffer_slot);
         frame_table.free_frame(ipc_buffer_ref);
+        free_loaded_windows(cspace, frame_table, &initial_windows);
+        page_tables.free_all(cspace, ut_table);
         dealloc_retyped(cspace, ut_table, vspace);
         e
     })?;
 
     initial_windows.push(stack_window.clone());
 
-    let proc = UserProcess::new(
+    let mut proc = UserProcess::new(
         tcb,
         pos,
+        name,
         vspace,
         ipc_buffer,
         sched_context,
@@ -1054,16 +1672,55 @@ pub fn start_process(
         fault_ep,
         shared_buffer,
         initial_windows,
+        page_tables,
+        parent,
+        *sched,
+        env,
     );
 
-    let sp = proc.write_args_to_stack(frame_table, stack_window, loader_args, exec_args);
-
-    let mut user_context = sel4::UserContext::default();
-    *user_context.pc_mut() = elf.ehdr.e_entry;
-    *user_context.sp_mut() = sp.try_into().unwrap();
+    /* Copy the caps the process is started with into its cspace, and tell it where they are
+    through its environment */
+    for (name, cap) in caps {
+        let ret = proc.cspace.alloc_slot().and_then(|slot| {
+            proc.cspace
+                .root_cnode()
+                .absolute_cptr_from_bits_with_depth(slot.try_into().unwrap(), sel4::WORD_SIZE)
+                .copy(cap, sel4::CapRightsBuilder::all().build())
+                .map_err(|e| {
+                    proc.cspace.free_slot(slot);
+                    e
+                })?;
+            return Ok(slot);
+        });
+
+        match ret {
+            Ok(slot) => proc
+                .env
+                .push(format!("{}{}={}", SMOS_CAP_ENV_PREFIX, name, slot)),
+            Err(e) => {
+                err_rs!("Failed to copy cap {} into the process", name);
+                proc.destroy(cspace, ut_table, frame_table, handle_cap_table);
+                return Err(e);
+            }
+        }
+    }
 
-    // @alwin: Clean up the stack if this fails
-    tcb.0.tcb_write_registers(true, 2, &mut user_context)?;
+    /* Everything the process was set up with belongs to it now, so destroying it cleans up */
+    let ret = proc
+        .write_args_to_stack(frame_table, stack_window, loader_args, exec_args)
+        .and_then(|sp| {
+            let mut user_context = sel4::UserContext::default();
+            *user_context.pc_mut() = entry_point;
+            *user_context.sp_mut() = sp.try_into().unwrap();
+
+            tcb.0.tcb_write_registers(true, 2, &mut user_context)
+        });
+
+    if let Err(e) = ret {
+        err_rs!("Failed to start process {}: {:?}", name, e);
+        proc.destroy(cspace, ut_table, frame_table, handle_cap_table);
+        return Err(e);
+    }
 
     let proc_saved = Rc::new(RefCell::new(ProcessType::ActiveProcess(proc)));
     procs_set(pos, Some(proc_saved.clone()));
@@ -1071,33 +1728,137 @@ pub fn start_process(
     return Ok(proc_saved);
 }
 
+/* Works out the environment of a new program, which is the environment of the caller unless it
+was given one explicitly. The caps and handles of the caller are not passed on, so neither are
+their descriptions. */
+fn program_env(p: &UserProcess, env: &Option<Vec<&str>>) -> Result<Vec<String>, InvocationError> {
+    let env: Vec<String> = match env {
+        Some(x) => x.iter().map(|x| String::from(*x)).collect(),
+        None => p.env.clone(),
+    };
+
+    /* Every entry in the environment should be of the form KEY=VALUE */
+    if env
+        .iter()
+        .any(|x| !x.split_once('=').is_some_and(|(key, _)| !key.is_empty()))
+    {
+        return Err(InvocationError::InvalidArguments);
+    }
+
+    return Ok(env
+        .into_iter()
+        .filter(|x| !x.starts_with(SMOS_CAP_ENV_PREFIX) && !x.starts_with(SMOS_HANDLE_ENV_PREFIX))
+        .collect());
+}
+
+/* Cap and handle names end up as part of an environment variable name */
+fn valid_env_name(name: &str) -> bool {
+    return !name.is_empty() && !name.contains('=');
+}
+
+/* Works out where the executable could be, as (server, file) pairs. Paths that are not absolute
+are looked up in each directory of the search path of the caller, in order. */
+fn program_candidates(p: &UserProcess, path: &str) -> Vec<(String, String)> {
+    if path.starts_with('/') {
+        return resolve_path(path).into_iter().collect();
+    }
+
+    let search_path = p
+        .env
+        .iter()
+        .filter_map(|x| x.split_once('='))
+        .find(|(key, _)| *key == SMOS_PATH_ENV)
+        .map_or(SMOS_DEFAULT_PATH, |(_, value)| value);
+
+    return search_path
+        .split(':')
+        .filter(|dir| !dir.is_empty())
+        .filter_map(|dir| resolve_path(&format!("{}/{}", dir.trim_end_matches('/'), path)))
+        .collect();
+}
+
 pub fn handle_process_spawn(
     cspace: &mut CSpace,
     ut_table: &mut UTTable,
     frame_table: &mut FrameTable,
+    handle_cap_table: &mut HandleCapabilityTable<RootServerResource>,
     sched_control: sel4::cap::SchedControl,
     ep: sel4::cap::Endpoint,
     p: &mut UserProcess,
     args: ProcessSpawn,
 ) -> Result<SMOSReply, InvocationError> {
+    /* A process can't give its child more priority or budget than it holds itself */
+    if !p.sched.can_grant(&args.sched) {
+        return Err(InvocationError::InvalidArguments);
+    }
+
+    /* The child inherits the environment of its parent unless it was given one explicitly */
+    let env = program_env(p, &args.env)?;
+
+    if !args.caps.iter().all(|(name, _)| valid_env_name(name)) {
+        return Err(InvocationError::InvalidArguments);
+    }
+
+    /* The caps are looked up in the cspace of the caller */
+    let caps: Vec<(&str, sel4::AbsoluteCPtr)> = args
+        .caps
+        .iter()
+        .map(|(name, slot)| {
+            (
+                *name,
+                p.cspace
+                    .root_cnode()
+                    .absolute_cptr_from_bits_with_depth(*slot as u64, sel4::WORD_SIZE),
+            )
+        })
+        .collect();
+
+    let candidates = program_candidates(p, args.path);
+    if candidates.is_empty() {
+        return Err(InvocationError::InvalidArguments);
+    }
+
+    /* The loader tries each of the (file, server) pairs until it finds the executable */
+    let num_candidates = candidates.len().to_string();
+    let mut loader_args = vec![num_candidates.as_str()];
+    for (server_name, file) in &candidates {
+        loader_args.push(file.as_str());
+        loader_args.push(server_name.as_str());
+    }
+
+    /* start_process tells the child where its caps are through its environment. The slots they
+    end up in aren't known yet, so the longest possible slot number is assumed. */
+    let mut stack_env = env.clone();
+    for (name, _) in &caps {
+        stack_env.push(format!("{}{}={}", SMOS_CAP_ENV_PREFIX, name, usize::MAX));
+    }
+
+    if !args_fit_on_stack(&stack_env, &Some(loader_args.clone()), &args.args) {
+        return Err(InvocationError::BufferTooLarge);
+    }
+
     let (idx, handle_ref) = p.allocate_handle()?;
 
-    let loader_args = Some(vec![args.exec_name, args.fs_name]);
     // @alwin: This is a lazy way of handling the error;
     let proc = start_process(
         cspace,
         ut_table,
         frame_table,
+        handle_cap_table,
         sched_control,
-        &args.exec_name,
+        &args.path,
         ep,
         LOADER_CONTENTS,
-        loader_args,
+        Some(loader_args),
         args.args,
-        args.prio,
+        env,
+        &caps,
+        &args.sched,
+        Some(p.pid),
     )
     .map_err(|_| InvocationError::InsufficientResources)?;
 
+    p.children.push(proc.clone());
     *handle_ref = Some(ServerHandle::new(RootServerResource::Process(proc)));
 
     return Ok(SMOSReply::ProcessSpawn {
@@ -1105,6 +1866,146 @@ pub fn handle_process_spawn(
     });
 }
 
+pub fn handle_process_args_append(
+    p: &mut UserProcess,
+    tid: usize,
+    args: &ProcessArgsAppend,
+) -> Result<SMOSReply, InvocationError> {
+    p.staged_args.append(tid, args.data)?;
+    return Ok(SMOSReply::ProcessArgsAppend);
+}
+
+/* Replaces the program running in the process with a new one. Returns whether the loader was
+started for the new program. The old program is already gone if it wasn't, so the caller has to
+terminate the process. */
+pub fn handle_process_exec(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_table: &mut FrameTable,
+    handle_cap_table: &mut HandleCapabilityTable<RootServerResource>,
+    sched_control: sel4::cap::SchedControl,
+    p: &mut UserProcess,
+    args: ProcessExec,
+) -> Result<bool, InvocationError> {
+    /* The strings in args point into the shared buffer of the calling thread, which is freed along
+    with the thread if it isn't the main one */
+    let path = String::from(args.path);
+    let owned_args: Option<Vec<String>> = args
+        .args
+        .as_ref()
+        .map(|x| x.iter().map(|y| String::from(*y)).collect());
+
+    let mut env = program_env(p, &args.env)?;
+
+    if !args
+        .caps
+        .iter()
+        .chain(args.handles.iter())
+        .all(|(name, _)| valid_env_name(name))
+    {
+        return Err(InvocationError::InvalidArguments);
+    }
+
+    /* Caps stay in the same slot, so they can't be in the slots every process starts with */
+    if args
+        .caps
+        .iter()
+        .any(|(_, slot)| *slot <= InitCNodeSlots::SMOS_CNodeSelf as usize)
+    {
+        return Err(InvocationError::InvalidArguments);
+    }
+
+    /* Windows and anything in them are torn down, so handles that refer to them can't be kept */
+    for (i, (_, idx)) in args.handles.iter().enumerate() {
+        match p.get_handle(*idx) {
+            Ok(Some(hndl)) => match hndl.inner() {
+                RootServerResource::Window(_)
+                | RootServerResource::View(_)
+                | RootServerResource::WindowRegistration(_) => {
+                    return Err(InvocationError::InvalidArguments)
+                }
+                _ => {}
+            },
+            _ => return Err(InvocationError::InvalidHandle { which_arg: i }),
+        }
+    }
+
+    let candidates = program_candidates(p, &path);
+    if candidates.is_empty() {
+        return Err(InvocationError::InvalidArguments);
+    }
+
+    let num_candidates = candidates.len().to_string();
+    let mut loader_args = vec![num_candidates.as_str()];
+    for (server_name, file) in &candidates {
+        loader_args.push(file.as_str());
+        loader_args.push(server_name.as_str());
+    }
+
+    /* Tell the new program where everything it kept is */
+    for (name, slot) in &args.caps {
+        env.push(format!("{}{}={}", SMOS_CAP_ENV_PREFIX, name, slot));
+    }
+    for (name, idx) in &args.handles {
+        env.push(format!("{}{}={}", SMOS_HANDLE_ENV_PREFIX, name, idx));
+    }
+
+    let exec_args: Option<Vec<&str>> = owned_args
+        .as_ref()
+        .map(|x| x.iter().map(|y| y.as_str()).collect());
+
+    /* The old program can't be told that the new one failed to start once it is gone */
+    if !args_fit_on_stack(&env, &Some(loader_args.clone()), &exec_args) {
+        return Err(InvocationError::BufferTooLarge);
+    }
+
+    let keep_handles: Vec<usize> = args.handles.iter().map(|(_, idx)| *idx).collect();
+    let mut keep_slots: Vec<usize> = args.caps.iter().map(|(_, slot)| *slot).collect();
+    keep_slots.push(InitCNodeSlots::SMOS_RootServerEP as usize);
+    keep_slots.push(InitCNodeSlots::SMOS_CNodeSelf as usize);
+
+    /* Get rid of the old program. Threads go first, as they might be using anything else. */
+    p.destroy_threads(cspace, ut_table, frame_table);
+
+    /* A thread of the old program may have been waiting on a child, and there is nobody left to
+    tell when the child exits */
+    for child in &p.children {
+        if let ProcessType::ActiveProcess(x) = &mut *child.borrow_mut() {
+            if let Some(reply) = x.waiter.take() {
+                dealloc_retyped(cspace, ut_table, reply);
+            }
+        }
+    }
+
+    p.cleanup_handles(cspace, ut_table, frame_table, &keep_handles);
+
+    /* The new program starts with the whole budget of the process on its only thread */
+    if let Err(e) = p.configure_sched(sched_control, &p.sched) {
+        err_rs!("Failed to reconfigure the process for {}: {:?}", path, e);
+        return Ok(false);
+    }
+    p.cleanup_created_handle_caps(cspace, frame_table, handle_cap_table);
+
+    /* All the windows and views had a handle, so they are already gone */
+    p.windows.clear();
+    p.views.clear();
+    p.free_page_tables(cspace, ut_table, 0, usize::MAX);
+
+    p.cspace.clear(&keep_slots);
+    p.env = env;
+
+    p.name = path.clone();
+    p.tcb.0.debug_name(path.as_bytes());
+
+    return match p.load_program(cspace, ut_table, frame_table, loader_args, exec_args) {
+        Ok(()) => Ok(true),
+        Err(e) => {
+            err_rs!("Failed to start the loader for {}: {:?}", path, e);
+            Ok(false)
+        }
+    };
+}
+
 pub fn handle_process_wait(
     p: &mut UserProcess,
     reply: RSReplyWrapper,
@@ -1122,93 +2023,439 @@ pub fn handle_process_wait(
     let wait_proc_type: &mut ProcessType = &mut wait_proc.borrow_mut();
     match wait_proc_type {
         ProcessType::ActiveProcess(x) => {
+            /* Only one thread can wait on a process at a time */
+            if x.waiter.is_some() {
+                return Some(Err(InvocationError::InvalidArguments));
+            }
             x.waiter = Some(reply);
             None
         }
-        ProcessType::ZombieProcess(x) => {
-            procs_set(*x, None);
-            Some(Ok(SMOSReply::ProcessWait))
+        ProcessType::ZombieProcess(zombie) => {
+            /* The process table entry may already be gone (and reused) if someone else waited on
+            this process */
+            if procs_get(zombie.pid)
+                .as_ref()
+                .is_some_and(|y| Rc::ptr_eq(y, &wait_proc))
+            {
+                procs_set(zombie.pid, None);
+            }
+            p.children.retain(|y| !Rc::ptr_eq(y, &wait_proc));
+            Some(Ok(SMOSReply::ProcessWait {
+                status: zombie.status,
+            }))
         }
     }
 }
 
+/* Cleans up the process, orphans its children and wakes up anyone waiting on it. The caller is
+responsible for turning the process into a zombie afterwards. */
 pub fn handle_process_exit(
     cspace: &mut CSpace,
     ut_table: &mut UTTable,
     frame_table: &mut FrameTable,
     handle_cap_table: &mut HandleCapabilityTable<RootServerResource>,
     p: &mut UserProcess,
+    status: ExitStatus,
 ) {
     // @alwin: Clean up the process resources
     p.destroy(cspace, ut_table, frame_table, handle_cap_table);
 
-    match p.waiter {
+    /* The root server adopts any orphans. Nothing else holds a handle to them, so they can't be
+    waited on and are reaped as soon as they exit. */
+    for child in p.children.drain(..) {
+        let child_type: &mut ProcessType = &mut child.borrow_mut();
+        match child_type {
+            ProcessType::ActiveProcess(x) => {
+                x.parent = None;
+
+                /* If the child was being waited on, it must have been by this process */
+                if let Some(reply) = x.waiter.take() {
+                    dealloc_retyped(cspace, ut_table, reply);
+                }
+
+                /* A thread of the child may be blocked on a fault that was delivered to this
+                process and will never be replied to, so a child whose faults were handled by this
+                process is killed along with it. It has no parent, so it is reaped straight away. */
+                if x.fault_handler.is_some() {
+                    handle_process_exit(
+                        cspace,
+                        ut_table,
+                        frame_table,
+                        handle_cap_table,
+                        x,
+                        ExitStatus::Killed,
+                    );
+                    *child_type = ProcessType::ZombieProcess(x.zombie(ExitStatus::Killed));
+                }
+            }
+            ProcessType::ZombieProcess(zombie) => {
+                if procs_get(zombie.pid)
+                    .as_ref()
+                    .is_some_and(|y| Rc::ptr_eq(y, &child))
+                {
+                    procs_set(zombie.pid, None);
+                }
+            }
+        }
+    }
+
+    match p.waiter.take() {
         Some(x) => {
             /* There is a process waiting for this one to terminate */
-            let msginfo =
-                sel4::with_ipc_buffer_mut(|ipc_buf| handle_reply(ipc_buf, SMOSReply::ProcessWait));
+            let msginfo = sel4::with_ipc_buffer_mut(|ipc_buf| {
+                handle_reply(ipc_buf, SMOSReply::ProcessWait { status: status })
+            });
 
             /* Send a message saying that this process terminated */
             x.0.send(msginfo);
 
             /* Destroy the reply object*/
             dealloc_retyped(cspace, ut_table, x);
+
+            /* The waiter is a thread of the parent. Another thread of the parent may be the one
+            that is killing this process, in which case the parent is already borrowed further up
+            the call stack. This process is then left as a zombie child of the parent, which is
+            reaped when the parent exits. */
+            let parent = p.parent.and_then(|x| procs_get(x).clone());
+            let this = procs_get(p.pid).clone();
+            if let (Some(parent), Some(this)) = (parent, this) {
+                let mut parent_ref = match parent.try_borrow_mut() {
+                    Ok(x) => x,
+                    Err(_) => return,
+                };
+                if let ProcessType::ActiveProcess(x) = &mut *parent_ref {
+                    x.children.retain(|y| !Rc::ptr_eq(y, &this));
+                }
+            }
+
+            /* Nobody else can wait on this process, so it can be reaped straight away */
             procs_set(p.pid, None);
             warn_rs!("Sending message to waiter");
         }
+        None if p.parent.is_none() => {
+            /* Nobody can wait on this process, so it can be reaped straight away */
+            procs_set(p.pid, None);
+        }
         None => {
-            // Transition the process to a zombie
-            procs_set(
-                p.pid,
-                Some(Rc::new(RefCell::new(ProcessType::ZombieProcess(p.pid)))),
-            );
             warn_rs!("Setting the process to a zombie");
         }
     }
 }
 
-pub fn handle_load_complete(
+pub fn handle_process_destroy(
     cspace: &mut CSpace,
+    ut_table: &mut UTTable,
     frame_table: &mut FrameTable,
+    handle_cap_table: &mut HandleCapabilityTable<RootServerResource>,
     p: &mut UserProcess,
-    args: LoadComplete,
+    args: &ProcessDestroy,
 ) -> Result<SMOSReply, InvocationError> {
-    for window in &p.initial_windows {
-        /* Clean up the memory object by freeing the frames in it */
-        window
-            .borrow_mut()
-            .bound_view
-            .as_ref()
-            .unwrap()
-            .borrow_mut()
-            .bound_object
-            .as_ref()
-            .unwrap()
-            .borrow_mut()
-            .cleanup_frame_table(cspace, frame_table);
+    let target_ref = p
+        .get_handle_mut(args.hndl.idx)
+        .or(Err(InvocationError::InvalidHandle { which_arg: 0 }))?;
+    let target = match target_ref.as_ref().unwrap().inner() {
+        RootServerResource::Process(proc) => proc.clone(),
+        _ => return Err(InvocationError::InvalidHandle { which_arg: 0 }),
+    };
 
-        /* The above should delete these caps, just need to free the slots */
-        window
-            .borrow_mut()
-            .bound_view
-            .as_ref()
-            .unwrap()
-            .borrow_mut()
-            .cleanup_cap_table(cspace, false);
+    /* A process can't destroy itself, it should use process_exit instead */
+    if procs_get(p.pid)
+        .as_ref()
+        .is_some_and(|x| Rc::ptr_eq(x, &target))
+    {
+        return Err(InvocationError::InvalidArguments);
     }
 
-    // The objects and the views should be cleaned up by doing this since they are RCs that should
-    // not be referenced anywhere else.
-    p.initial_windows.clear();
+    let target_type: &mut ProcessType = &mut target.borrow_mut();
+    let target_proc = match &mut *target_type {
+        ProcessType::ActiveProcess(x) => x,
+        /* The process has already terminated, there is nothing left to destroy */
+        ProcessType::ZombieProcess(..) => return Ok(SMOSReply::ProcessDestroy),
+    };
+
+    handle_process_exit(
+        cspace,
+        ut_table,
+        frame_table,
+        handle_cap_table,
+        target_proc,
+        ExitStatus::Killed,
+    );
+
+    /* Keep the exit status around so that the process can still be waited on */
+    *target_type = ProcessType::ZombieProcess(target_proc.zombie(ExitStatus::Killed));
+
+    return Ok(SMOSReply::ProcessDestroy);
+}
+
+pub fn handle_process_set_fault_handler(
+    cspace: &mut CSpace,
+    p: &mut UserProcess,
+    args: &ProcessSetFaultHandler,
+) -> Result<SMOSReply, InvocationError> {
+    let child_ref = p
+        .get_handle(args.hndl.idx)
+        .or(Err(InvocationError::InvalidHandle { which_arg: 0 }))?;
+    let child = match child_ref.as_ref().unwrap().inner() {
+        RootServerResource::Process(proc) => proc.clone(),
+        _ => return Err(InvocationError::InvalidHandle { which_arg: 0 }),
+    };
+
+    let child_type: &mut ProcessType = &mut child.borrow_mut();
+    let child_proc = match child_type {
+        ProcessType::ActiveProcess(x) => x,
+        ProcessType::ZombieProcess(..) => {
+            return Err(InvocationError::InvalidHandle { which_arg: 0 })
+        }
+    };
+
+    /* Keep a copy of the endpoint, as threads the child creates later need badged copies of it
+    too */
+    let handler_ep = cspace
+        .alloc_cap::<sel4::cap_type::Endpoint>()
+        .or(Err(InvocationError::InsufficientResources))?;
+
+    cspace
+        .root_cnode()
+        .absolute_cptr(handler_ep)
+        .copy(&args.fault_ep, sel4::CapRightsBuilder::all().build())
+        .map_err(|_| {
+            cspace.free_cap(handler_ep);
+            InvocationError::InvalidArguments
+        })?;
+
+    /* The badges tell the parent which of its children faulted */
+    child_proc
+        .set_fault_handler(cspace, handler_ep, args.hndl.idx)
+        .or(Err(InvocationError::InvalidArguments))?;
+
+    return Ok(SMOSReply::ProcessSetFaultHandler);
+}
+
+pub fn handle_process_fault_forward(
+    p: &mut UserProcess,
+    args: &ProcessFaultForward,
+) -> Result<SMOSReply, InvocationError> {
+    let child_ref = p
+        .get_handle(args.hndl.idx)
+        .or(Err(InvocationError::InvalidHandle { which_arg: 0 }))?;
+    let child = match child_ref.as_ref().unwrap().inner() {
+        RootServerResource::Process(proc) => proc.clone(),
+        _ => return Err(InvocationError::InvalidHandle { which_arg: 0 }),
+    };
+
+    let child_type: &ProcessType = &child.borrow();
+    let child_proc = match child_type {
+        ProcessType::ActiveProcess(x) => x,
+        ProcessType::ZombieProcess(..) => {
+            return Err(InvocationError::InvalidHandle { which_arg: 0 })
+        }
+    };
+
+    /* The thread faults again into the root server once the handler replies to its fault */
+    child_proc.forward_thread_faults(args.tid)?;
+
+    return Ok(SMOSReply::ProcessFaultForward);
+}
+
+pub fn handle_process_set_sched(
+    sched_control: sel4::cap::SchedControl,
+    p: &mut UserProcess,
+    args: &ProcessSetSched,
+) -> Result<SMOSReply, InvocationError> {
+    let target_ref = p
+        .get_handle(args.hndl.idx)
+        .or(Err(InvocationError::InvalidHandle { which_arg: 0 }))?;
+    let target = match target_ref.as_ref().unwrap().inner() {
+        RootServerResource::Process(proc) => proc.clone(),
+        _ => return Err(InvocationError::InvalidHandle { which_arg: 0 }),
+    };
+
+    let target_type: &mut ProcessType = &mut target.borrow_mut();
+    let target_proc = match target_type {
+        ProcessType::ActiveProcess(x) => x,
+        ProcessType::ZombieProcess(..) => {
+            return Err(InvocationError::InvalidHandle { which_arg: 0 })
+        }
+    };
+
+    let sched = SchedParams {
+        prio: args.prio,
+        budget: args.budget,
+        period: args.period,
+        ..target_proc.sched
+    };
+
+    /* The caller can't give the process more than it could have given it at spawn time */
+    if !p.sched.can_grant(&sched) {
+        return Err(InvocationError::InvalidArguments);
+    }
+
+    /* If the budget is too small to split between the threads, go back to what it was before */
+    if target_proc.configure_sched(sched_control, &sched).is_err() {
+        target_proc
+            .configure_sched(sched_control, &target_proc.sched)
+            .expect("Failed to restore scheduling parameters");
+        return Err(InvocationError::InvalidArguments);
+    }
+
+    target_proc.sched = sched;
+
+    return Ok(SMOSReply::ProcessSetSched);
+}
+
+/* Collects the pids of p and all of its descendants */
+fn subtree_pids(p: &UserProcess, pids: &mut Vec<usize>) {
+    pids.push(p.pid);
+    for child in &p.children {
+        match &*child.borrow() {
+            ProcessType::ActiveProcess(x) => subtree_pids(x, pids),
+            ProcessType::ZombieProcess(zombie) => pids.push(zombie.pid),
+        }
+    }
+}
+
+/* Finds target among the descendants of p and returns the pid of its parent */
+fn find_descendant(p: &UserProcess, target: &Rc<RefCell<ProcessType>>) -> Option<usize> {
+    for child in &p.children {
+        if Rc::ptr_eq(child, target) {
+            return Some(p.pid);
+        }
+
+        if let ProcessType::ActiveProcess(x) = &*child.borrow() {
+            if let Some(parent) = find_descendant(x, target) {
+                return Some(parent);
+            }
+        }
+    }
+
+    return None;
+}
+
+pub fn handle_process_list(
+    frame_table: &mut FrameTable,
+    p: &mut UserProcess,
+) -> Result<SMOSReply, InvocationError> {
+    let shared_buf = frame_table.frame_data(p.shared_buffer.1);
+    let entry_size = core::mem::size_of::<u64>();
+
+    /* A process can only see itself and its descendants */
+    let mut pids = Vec::new();
+    subtree_pids(p, &mut pids);
+
+    /* Write as many pids as fit into the shared buffer, but report how many there are in total */
+    for (i, pid) in pids.iter().enumerate() {
+        let offset = i * entry_size;
+        if offset + entry_size > shared_buf.len() {
+            break;
+        }
+        LittleEndian::write_u64(&mut shared_buf[offset..offset + entry_size], *pid as u64);
+    }
+
+    return Ok(SMOSReply::ProcessList { count: pids.len() });
+}
+
+fn active_process_info(p: &UserProcess) -> ProcessStat {
+    return ProcessStat::new(
+        p.pid,
+        ProcessState::Active,
+        p.sched.prio,
+        p.parent,
+        p.mem_usage(),
+        &p.name,
+    );
+}
+
+pub fn handle_process_info(
+    frame_table: &mut FrameTable,
+    p: &mut UserProcess,
+    args: &ProcessInfo,
+) -> Result<SMOSReply, InvocationError> {
+    let target = procs_get(args.pid)
+        .as_ref()
+        .ok_or(InvocationError::InvalidArguments)?
+        .clone();
+
+    /* The caller is already borrowed, so it has to be accessed through p instead */
+    let caller = procs_get(p.pid).clone();
+    let info = if caller.as_ref().is_some_and(|x| Rc::ptr_eq(x, &target)) {
+        active_process_info(p)
+    } else {
+        /* A process can only look up itself and its descendants */
+        let parent = find_descendant(p, &target).ok_or(InvocationError::InvalidArguments)?;
+
+        match &*target.borrow() {
+            ProcessType::ActiveProcess(x) => active_process_info(x),
+            /* Zombies no longer have any memory, and they don't keep track of their parent. A
+            zombie is only kept around by a live parent, which is the one it was found under. */
+            ProcessType::ZombieProcess(zombie) => ProcessStat::new(
+                zombie.pid,
+                ProcessState::Zombie,
+                zombie.sched.prio,
+                Some(parent),
+                0,
+                &zombie.name,
+            ),
+        }
+    };
+
+    /* The name doesn't fit into the message registers, so it goes into the shared buffer */
+    copy_terminated_rust_string_to_buffer(frame_table.frame_data(p.shared_buffer.1), info.name())?;
+
+    return Ok(SMOSReply::ProcessInfo { data: info });
+}
+
+pub fn handle_process_core_dump(
+    p: &mut UserProcess,
+    args: &ProcessCoreDump,
+) -> Result<SMOSReply, InvocationError> {
+    let proc_ref = p
+        .get_handle(args.hndl.idx)
+        .or(Err(InvocationError::InvalidHandle { which_arg: 0 }))?;
+    let proc = match proc_ref.as_ref().unwrap().inner() {
+        RootServerResource::Process(x) => x.clone(),
+        _ => return Err(InvocationError::InvalidHandle { which_arg: 0 }),
+    };
+
+    /* Core files are only kept for children that faulted, and each can only be collected once */
+    let idx = p
+        .core_dumps
+        .iter()
+        .position(|(x, _, _)| Rc::ptr_eq(x, &proc))
+        .ok_or(InvocationError::InvalidArguments)?;
+
+    let (hndl_idx, handle_ref) = p.allocate_handle()?;
+    let (_, core_dump, size) = p.core_dumps.remove(idx);
+    *handle_ref = Some(ServerHandle::new(RootServerResource::Object(core_dump)));
+
+    return Ok(SMOSReply::ProcessCoreDump {
+        hndl: local_handle::LocalHandle::new(hndl_idx),
+        size: size,
+    });
+}
+
+pub fn handle_load_complete(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_table: &mut FrameTable,
+    p: &mut UserProcess,
+    args: LoadComplete,
+) -> Result<SMOSReply, InvocationError> {
+    p.cleanup_initial_windows(cspace, ut_table, frame_table);
 
     let mut user_context = sel4::UserContext::default();
     *user_context.pc_mut() = args.entry_point as u64;
     *user_context.sp_mut() = args.sp as u64;
 
+    /* The thread pointer of the loader is always replaced, as the runtime of the program uses the
+    TLS block passed in it if it is non-zero. TPIDR_EL0 comes after pc, sp, spsr and x0-x30 in the
+    user context, so writing it means writing everything before it as well. */
+    user_context.inner_mut().tpidr_el0 = args.tls_base.unwrap_or(0) as u64;
+
     // @alwin: deal with error case properly here
     p.tcb
         .0
-        .tcb_write_registers(true, 2, &mut user_context)
+        .tcb_write_registers(true, 35, &mut user_context)
         .expect("@alwin: This shouldn't be an assert");
 
     return Ok(SMOSReply::LoadComplete);
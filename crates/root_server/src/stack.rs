@@ -3,15 +3,23 @@ use sel4::BootInfo;
 
 use crate::cspace::CSpace;
 use crate::dma::DMAPool;
+use crate::large_page::LargePagePool;
 use crate::ut::UTTable;
 
 pub fn utils_run_on_stack(
     stack_top: usize,
-    func: unsafe extern "C" fn(*const BootInfo, *mut CSpace, *mut UTTable, *mut DMAPool) -> !,
+    func: unsafe extern "C" fn(
+        *const BootInfo,
+        *mut CSpace,
+        *mut UTTable,
+        *mut DMAPool,
+        *mut LargePagePool,
+    ) -> !,
     bootinfo: &sel4::BootInfoPtr,
     cspace: &mut CSpace,
     ut_table: &mut UTTable,
     dma_pool: &mut DMAPool,
+    large_page_pool: &mut LargePagePool,
 ) {
     unsafe {
         asm!(
@@ -25,6 +33,7 @@ pub fn utils_run_on_stack(
             in("x1") cspace as *mut CSpace,
             in("x2") ut_table as *mut UTTable,
             in("x3") dma_pool as *mut DMAPool,
+            in("x4") large_page_pool as *mut LargePagePool,
         )
 
         /* @alwin: This doesn't work for some reason - seems like same reg is
@@ -0,0 +1,503 @@
+use crate::connection::Server;
+use crate::cspace::{CSpace, CSpaceTrait};
+use crate::dma::DMAPool;
+use crate::fault::retry_vm_fault;
+use crate::frame_table::{FrameRef, FrameTable};
+use crate::handle::RootServerResource;
+use crate::object::AnonymousMemoryObject;
+use crate::page::PAGE_SIZE_4K;
+use crate::proc::UserProcess;
+use crate::ut::UTTable;
+use crate::RSReplyWrapper;
+use alloc::rc::{Rc, Weak};
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+use smos_common::error::InvocationError;
+use smos_common::local_handle::LocalHandle;
+use smos_common::obj_attributes::ObjAttributes;
+use smos_common::swap::*;
+use smos_common::util::BIT;
+use smos_server::event::NTFN_BIT;
+use smos_server::handle::{HandleAllocater, ServerHandle};
+use smos_server::handle_capability::HandleCapabilityTable;
+use smos_server::reply::SMOSReply;
+use smos_server::syscalls::SwapRegister;
+
+/* The bit of the badge of the root server's notification that the swap server signals when it has
+responded to a request. It is kept out of the bits that are handed out for IRQs. */
+pub const SWAP_NTFN_BADGE: usize = BIT(62);
+
+/* Every process that gets paged out depends on the swap server, so it has to be trusted as much as
+the processes the root server starts itself. Only processes that were given the highest mcp the
+root server hands out can register a swap area. */
+const SWAP_SERVER_MCP: u8 = 254;
+
+/* Keeps track of which blocks of the swap area are in use. A block can be shared between objects
+that were cloned from each other, so it is only free once all of them have let go of it. */
+pub struct SwapBlocks {
+    num_blocks: usize,
+    refs: Vec<u32>,
+    free_blocks: Vec<usize>,
+}
+
+impl SwapBlocks {
+    pub fn new(num_blocks: usize) -> Self {
+        return Self {
+            num_blocks: num_blocks,
+            refs: Vec::new(),
+            free_blocks: Vec::new(),
+        };
+    }
+
+    pub fn alloc(&mut self) -> Option<usize> {
+        let block = match self.free_blocks.pop() {
+            Some(block) => block,
+            None if self.refs.len() < self.num_blocks => {
+                self.refs.push(0);
+                self.refs.len() - 1
+            }
+            None => return None,
+        };
+
+        self.refs[block] = 1;
+        return Some(block);
+    }
+
+    pub fn share(&mut self, block: usize) {
+        assert!(self.refs[block] > 0);
+        self.refs[block] += 1;
+    }
+
+    pub fn free(&mut self, block: usize) {
+        assert!(self.refs[block] > 0);
+        self.refs[block] -= 1;
+        if self.refs[block] == 0 {
+            self.free_blocks.push(block);
+        }
+    }
+
+    pub fn in_use(&self) -> usize {
+        return self.refs.len() - self.free_blocks.len();
+    }
+}
+
+/* The request that the swap server is working on */
+enum Transfer {
+    /* The page has already been freed, its contents are in the data page */
+    Write,
+    /* The page is being read into frame_ref, which goes back into the object at offset */
+    Read {
+        object: Weak<RefCell<AnonymousMemoryObject>>,
+        offset: usize,
+        block: usize,
+        frame_ref: FrameRef,
+    },
+}
+
+/* Blocks of storage provided by a server that pages are swapped out to. The server is handed a
+region containing an sDDF style request and response queue, along with a page that data is
+transferred through. There is only one data page, so the server only ever has one request to work
+on. The root server never waits for it. Faults that need the swap area while it is busy are parked
+and handled again once the server responds. */
+pub struct SwapArea {
+    /* None once the server has gone away. The area sticks around until nothing refers to its
+    blocks anymore, but pages that are still in it can't be read back in. */
+    server: Option<Rc<RefCell<Server>>>,
+    vaddr: usize,
+    /* The badged copy of the notification of the root server that the server signals */
+    ntfn: sel4::cap::Notification,
+    blocks: SwapBlocks,
+    next_id: u32,
+    in_flight: Option<(u32, Transfer)>,
+}
+
+static mut SWAP_AREA: Option<SwapArea> = None;
+
+/* The notification bound to the root server */
+static mut RS_NTFN: Option<sel4::cap::Notification> = None;
+
+/* Faults that are waiting for the swap area to finish a transfer, along with the pid and tid of
+the thread that faulted */
+static mut PARKED_FAULTS: Vec<(usize, usize, RSReplyWrapper, sel4::VmFault)> = Vec::new();
+
+pub fn swap_init(ntfn: sel4::cap::Notification) {
+    unsafe {
+        RS_NTFN = Some(ntfn);
+    }
+}
+
+pub fn swap_area() -> Option<&'static mut SwapArea> {
+    unsafe {
+        return SWAP_AREA.as_mut();
+    }
+}
+
+/* Whether there is a swap area that pages can be written to and read from */
+pub fn swap_active() -> bool {
+    return swap_area().is_some_and(|x| x.server.is_some());
+}
+
+/* A swap area whose server has gone away is removed once nothing refers to its blocks anymore */
+fn remove_dead_swap_area() {
+    if swap_area().is_some_and(|x| x.server.is_none() && x.blocks.in_use() == 0) {
+        unsafe {
+            SWAP_AREA = None;
+        }
+    }
+}
+
+/* Drops a reference to a block of the swap area */
+pub fn free_swap_block(block: usize) {
+    swap_area()
+        .expect("Block freed without a swap area")
+        .blocks
+        .free(block);
+    remove_dead_swap_area();
+}
+
+/* Parks a VM fault until the swap area has finished the transfer it is working on */
+pub fn park_fault(pid: usize, tid: usize, reply: RSReplyWrapper, fault: sel4::VmFault) {
+    unsafe {
+        PARKED_FAULTS.push((pid, tid, reply, fault));
+    }
+}
+
+impl SwapArea {
+    pub fn is_busy(&self) -> bool {
+        return self.in_flight.is_some();
+    }
+
+    pub fn alloc_block(&mut self) -> Option<usize> {
+        if self.server.is_none() {
+            return None;
+        }
+
+        return self.blocks.alloc();
+    }
+
+    pub fn share_block(&mut self, block: usize) {
+        self.blocks.share(block);
+    }
+
+    fn data_page(&self) -> &'static mut [u8; SWAP_BLOCK_SIZE] {
+        return unsafe { &mut *((self.vaddr + SWAP_DATA_OFFSET) as *mut [u8; SWAP_BLOCK_SIZE]) };
+    }
+
+    /* Sends a request to the swap server. The server signals the notification of the root server
+    once it has responded. */
+    fn submit(&mut self, code: SwapRequestCode, block: usize, transfer: Transfer) {
+        assert!(self.server.is_some() && self.in_flight.is_none());
+
+        let req_queue = (self.vaddr + SWAP_REQ_QUEUE_OFFSET) as *mut SwapRequestQueue;
+
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        // Safety: The queues are in the DMA region set up in swap register, which stays mapped
+        // into the root server for as long as the swap area exists
+        unsafe {
+            let tail = ptr::read_volatile(&(*req_queue).tail);
+            ptr::write_volatile(
+                &mut (*req_queue).buffers[tail as usize % SWAP_QUEUE_CAPACITY],
+                SwapRequest {
+                    code: code,
+                    io_or_offset: SWAP_DATA_OFFSET as u64,
+                    block_number: block as u64,
+                    count: 1,
+                    id: id,
+                },
+            );
+            fence(Ordering::Release);
+            ptr::write_volatile(&mut (*req_queue).tail, tail.wrapping_add(1));
+        }
+
+        self.in_flight = Some((id, transfer));
+        self.server
+            .as_ref()
+            .unwrap()
+            .borrow()
+            .ntfn_dispatch
+            .rs_badged_ntfn()
+            .signal();
+    }
+
+    /* Starts writing a page out to block. The page can be freed as soon as this returns. */
+    pub fn start_write(&mut self, block: usize, data: &[u8; PAGE_SIZE_4K]) {
+        self.data_page().copy_from_slice(data);
+        self.submit(SwapRequestCode::Write, block, Transfer::Write);
+    }
+
+    /* Starts reading block into frame_ref, which is put back into the object at offset once the
+    read has finished */
+    pub fn start_read(
+        &mut self,
+        object: &Rc<RefCell<AnonymousMemoryObject>>,
+        offset: usize,
+        block: usize,
+        frame_ref: FrameRef,
+    ) {
+        self.submit(
+            SwapRequestCode::Read,
+            block,
+            Transfer::Read {
+                object: Rc::downgrade(object),
+                offset: offset,
+                block: block,
+                frame_ref: frame_ref,
+            },
+        );
+    }
+
+    /* Picks up the response to the request in flight, if there is one. An error means that the
+    server didn't respond the way it should have, and can't be trusted with the swap area. */
+    fn complete_transfer(&mut self, frame_table: &mut FrameTable) -> Result<(), sel4::Error> {
+        let resp_queue = (self.vaddr + SWAP_RESP_QUEUE_OFFSET) as *mut SwapResponseQueue;
+
+        let resp = unsafe {
+            if ptr::read_volatile(&(*resp_queue).head) == ptr::read_volatile(&(*resp_queue).tail) {
+                return Ok(());
+            }
+
+            fence(Ordering::Acquire);
+            let head = ptr::read_volatile(&(*resp_queue).head);
+            let resp =
+                ptr::read_volatile(&(*resp_queue).buffers[head as usize % SWAP_QUEUE_CAPACITY]);
+            ptr::write_volatile(&mut (*resp_queue).head, head.wrapping_add(1));
+            resp
+        };
+
+        let (id, transfer) = self.in_flight.take().ok_or(sel4::Error::IllegalOperation)?;
+
+        let ok = resp.id == id && resp.status == SwapResponseStatus::Ok && resp.success_count == 1;
+
+        if let Transfer::Read {
+            object,
+            offset,
+            block,
+            frame_ref,
+        } = transfer
+        {
+            /* The object may have been destroyed or written to while the page was being read */
+            let object = object
+                .upgrade()
+                .filter(|x| ok && x.borrow().lookup_swapped(offset) == Some(block));
+
+            match object {
+                Some(object) => {
+                    frame_table
+                        .frame_data(frame_ref)
+                        .copy_from_slice(self.data_page());
+                    object
+                        .borrow_mut()
+                        .swap_in(frame_table, offset, block, frame_ref);
+                }
+                None => frame_table.free_frame(frame_ref),
+            }
+        }
+
+        if !ok {
+            return Err(sel4::Error::IllegalOperation);
+        }
+
+        return Ok(());
+    }
+
+    /* Stops using the server. Pages that are still in the swap area are lost, so processes that
+    access them are terminated. */
+    fn teardown(&mut self, cspace: &mut CSpace, frame_table: &mut FrameTable) {
+        if self.server.take().is_none() {
+            return;
+        }
+
+        if let Some((_, Transfer::Read { frame_ref, .. })) = self.in_flight.take() {
+            frame_table.free_frame(frame_ref);
+        }
+
+        /* Wake ourselves up so that the parked faults are handled without the swap area */
+        self.ntfn.signal();
+
+        /* Take away the copy of the notification that was handed to the server */
+        cspace
+            .root_cnode()
+            .absolute_cptr(self.ntfn)
+            .revoke()
+            .expect("Failed to revoke swap notification");
+        cspace
+            .delete_cap(self.ntfn)
+            .expect("Failed to delete swap notification");
+        cspace.free_cap(self.ntfn);
+
+        // @alwin: The region stays with the server, and DMA allocations are never given back to
+        // the pool
+    }
+}
+
+/* Tears down the swap area if it was provided by server, which is going away */
+pub fn swap_server_gone(
+    cspace: &mut CSpace,
+    frame_table: &mut FrameTable,
+    server: &Rc<RefCell<Server>>,
+) {
+    if let Some(swap) = swap_area() {
+        if swap.server.as_ref().is_some_and(|x| Rc::ptr_eq(x, server)) {
+            swap.teardown(cspace, frame_table);
+        }
+    }
+
+    remove_dead_swap_area();
+}
+
+/* Handles a response from the swap server and gives the faults that were waiting on it another
+go */
+pub fn handle_swap_notification(
+    cspace: &mut CSpace,
+    frame_table: &mut FrameTable,
+    ut_table: &mut UTTable,
+    handle_cap_table: &mut HandleCapabilityTable<RootServerResource>,
+) {
+    if let Some(swap) = swap_area() {
+        if swap.server.is_some() {
+            if let Err(e) = swap.complete_transfer(frame_table) {
+                warn_rs!(
+                    "Swap server misbehaved, tearing down the swap area: {:?}",
+                    e
+                );
+                swap.teardown(cspace, frame_table);
+            }
+        }
+    }
+
+    remove_dead_swap_area();
+
+    if swap_area().is_some_and(|x| x.is_busy()) {
+        return;
+    }
+
+    /* Faults that still can't be handled are parked again */
+    let parked = unsafe { core::mem::take(&mut PARKED_FAULTS) };
+    for (pid, tid, reply, fault) in parked {
+        retry_vm_fault(
+            cspace,
+            frame_table,
+            ut_table,
+            handle_cap_table,
+            reply,
+            fault,
+            pid,
+            tid,
+        );
+    }
+}
+
+pub fn handle_swap_register(
+    cspace: &mut CSpace,
+    dma_pool: &mut DMAPool,
+    p: &mut UserProcess,
+    args: &SwapRegister,
+) -> Result<SMOSReply, InvocationError> {
+    if p.sched.mcp < SWAP_SERVER_MCP {
+        return Err(InvocationError::InvalidArguments);
+    }
+
+    let publish_hndl_ref = p
+        .get_handle(args.publish_hndl.idx)
+        .or(Err(InvocationError::InvalidHandle { which_arg: 0 }))?;
+
+    let server = match publish_hndl_ref.as_ref().unwrap().inner() {
+        RootServerResource::Server(x) => Ok(x.clone()),
+        _ => Err(InvocationError::InvalidHandle { which_arg: 0 }),
+    }?;
+
+    /* Only a single swap area is supported for now */
+    if swap_area().is_some() || args.num_blocks == 0 {
+        return Err(InvocationError::InvalidArguments);
+    }
+
+    /* The swap server needs the physical address of the data page, so the region comes from the
+    DMA pool */
+    let n_pages = SWAP_REGION_SIZE / PAGE_SIZE_4K;
+    let (allocation, frames) = dma_pool.allocate_contig_pages(n_pages.try_into().unwrap())?;
+    let vaddr = dma_pool.allocation_vaddr(&allocation);
+
+    /* The server is given a copy of the notification of the root server, so that responses are
+    picked up by the syscall loop */
+    let ntfn = match cspace.alloc_cap::<sel4::cap_type::Notification>() {
+        Ok(x) => x,
+        Err(_) => {
+            dma_pool.free_contig_pages(allocation);
+            return Err(InvocationError::InsufficientResources);
+        }
+    };
+
+    let rs_ntfn = unsafe { RS_NTFN.expect("Swap area registered before swap_init") };
+    if cspace
+        .root_cnode()
+        .absolute_cptr(ntfn)
+        .mint(
+            &cspace.root_cnode().absolute_cptr(rs_ntfn),
+            sel4::CapRights::write_only(),
+            (NTFN_BIT | SWAP_NTFN_BADGE) as u64,
+        )
+        .is_err()
+    {
+        cspace.free_cap(ntfn);
+        dma_pool.free_contig_pages(allocation);
+        return Err(InvocationError::InsufficientResources);
+    }
+
+    let (idx, handle_ref) = match p.allocate_handle() {
+        Ok(x) => x,
+        Err(e) => {
+            cspace
+                .delete_cap(ntfn)
+                .expect("Failed to delete swap notification");
+            cspace.free_cap(ntfn);
+            dma_pool.free_contig_pages(allocation);
+            return Err(e);
+        }
+    };
+
+    // Safety: The allocation is mapped into the root server and nothing else has access to it yet
+    unsafe {
+        ptr::write_bytes(vaddr as *mut u8, 0, SWAP_REGION_SIZE);
+    }
+
+    /* The frames come from the DMA pool, so destroying the object doesn't give them back to the
+    frame table */
+    let region = Rc::new(RefCell::new(AnonymousMemoryObject::new(
+        SWAP_REGION_SIZE,
+        sel4::CapRightsBuilder::none()
+            .read(true)
+            .write(true)
+            .build(),
+        ObjAttributes::CONTIGUOUS,
+    )));
+    region.borrow_mut().dma_allocation = Some(allocation);
+
+    for (i, frame) in frames.iter().enumerate() {
+        region
+            .borrow_mut()
+            .insert_frame_at(i * PAGE_SIZE_4K, (frame.cast(), 0))
+            .expect("Failed to insert frame into object");
+    }
+
+    *handle_ref = Some(ServerHandle::new(RootServerResource::Object(region)));
+
+    unsafe {
+        SWAP_AREA = Some(SwapArea {
+            server: Some(server),
+            vaddr: vaddr,
+            ntfn: ntfn,
+            blocks: SwapBlocks::new(args.num_blocks),
+            next_id: 0,
+            in_flight: None,
+        });
+    }
+
+    return Ok(SMOSReply::SwapRegister {
+        hndl: LocalHandle::new(idx),
+        ntfn: ntfn,
+    });
+}
@@ -4,20 +4,28 @@ use crate::dma::DMAPool;
 use crate::frame_table::FrameTable;
 use crate::handle::RootServerResource;
 use crate::irq::handle_irq_register;
+use crate::mount::{handle_mount, handle_unmount};
 use crate::object::*;
 use crate::proc::{
-    handle_load_complete, handle_process_exit, handle_process_spawn, handle_process_wait,
-    procs_get_mut, ProcessType, UserProcess,
+    handle_load_complete, handle_process_args_append, handle_process_core_dump,
+    handle_process_destroy, handle_process_exec, handle_process_exit, handle_process_fault_forward,
+    handle_process_info, handle_process_list, handle_process_set_fault_handler,
+    handle_process_set_sched, handle_process_spawn, handle_process_wait, procs_get, ProcessType,
+    UserProcess,
 };
+use crate::swap::handle_swap_register;
+use crate::thread::{active_thread, handle_thread_create, handle_thread_exit, handle_thread_join};
 use crate::ut::UTTable;
-use crate::util::alloc_retype;
+use crate::util::{alloc_retype, dealloc_retyped};
 use crate::view::*;
-use crate::vm::handle_page_map;
+use crate::vm::{handle_page_map, handle_page_unmap};
 use crate::window::*;
 use crate::RSReplyWrapper;
 use smos_common::connection::RootServerConnection;
 use smos_common::error::InvocationError;
+use smos_common::invocations::SMOSInvocation;
 use smos_common::local_handle::LocalHandle;
+use smos_common::returns::ExitStatus;
 use smos_server::handle::{HandleAllocater, ServerHandle};
 use smos_server::handle_capability::HandleCapabilityTable;
 use smos_server::reply::SMOSReply;
@@ -42,9 +50,17 @@ pub fn handle_reply_create(
     });
 }
 
+/* Replies to an invocation from a process that no longer exists. Any cap that came with the
+invocation is dropped. */
+fn invalid_caller(recv_slot: sel4::AbsoluteCPtr) -> sel4::MessageInfo {
+    recv_slot.delete().expect("Failed to delete consumed cap");
+    return sel4::with_ipc_buffer_mut(|buf| handle_error(buf, InvocationError::InvalidInvocation));
+}
+
 pub fn handle_syscall(
     msg: sel4::MessageInfo,
     pid: usize,
+    tid: usize,
     cspace: &mut CSpace,
     frame_table: &mut FrameTable,
     ut_table: &mut UTTable,
@@ -55,13 +71,28 @@ pub fn handle_syscall(
     recv_slot: sel4::AbsoluteCPtr,
     reply: RSReplyWrapper,
 ) -> Option<sel4::MessageInfo> {
-    let proc_type: &mut ProcessType = &mut procs_get_mut(pid)
-        .as_mut()
-        .expect("Was called with an invalid badge")
-        .borrow_mut();
+    /* Hold on to a reference of our own, as the process may remove itself from the process table
+    while it is borrowed */
+    /* The badge may belong to a process that has since been destroyed, in which case the
+    generation of the pid no longer matches */
+    let proc_rc = match procs_get(pid).as_ref() {
+        Some(x) => x.clone(),
+        None => return Some(invalid_caller(recv_slot)),
+    };
+    let proc_type: &mut ProcessType = &mut proc_rc.borrow_mut();
     let mut p = match proc_type {
         ProcessType::ActiveProcess(x) => x,
-        ProcessType::ZombieProcess(_) => panic!("Zombie process invoked root server?!"),
+        ProcessType::ZombieProcess(..) => return Some(invalid_caller(recv_slot)),
+    };
+
+    /* Each thread has its own buffer for passing data to the root server */
+    let shared_buffer_ref = if tid == 0 {
+        p.shared_buffer.1
+    } else {
+        match active_thread(p, tid) {
+            Some(x) => x.shared_buffer.1,
+            None => return Some(invalid_caller(recv_slot)),
+        }
     };
 
     /* Safety: It is necessary to construct this from a raw pointer because otherwise there is
@@ -69,10 +100,26 @@ pub fn handle_syscall(
        still safe, because frame_data is static after it has been initialized and will never be
        changed by an access to the frame table
     */
-    let shared_buf = unsafe { &(*frame_table.frame_data_raw(p.shared_buffer.1)) };
+    let shared_buf = unsafe { &(*frame_table.frame_data_raw(shared_buffer_ref)) };
+
+    /* The shared buffer only holds the last part of arguments that were sent ahead in parts */
+    let staged_args;
+    let data_buf = if msg.label() == SMOSInvocation::ProcSpawn as u64
+        || msg.label() == SMOSInvocation::ProcExec as u64
+    {
+        match p.staged_args.take(tid, shared_buf) {
+            Some(x) => {
+                staged_args = x;
+                &staged_args[..]
+            }
+            None => shared_buf,
+        }
+    } else {
+        shared_buf
+    };
 
     let (invocation, consumed_cap) = sel4::with_ipc_buffer(|buf| {
-        SMOS_Invocation::new::<RootServerConnection>(buf, &msg, Some(shared_buf), recv_slot)
+        SMOS_Invocation::new::<RootServerConnection>(buf, &msg, Some(data_buf), recv_slot)
     });
 
     // The user provided an invalid argument
@@ -88,10 +135,11 @@ pub fn handle_syscall(
     let ret = match invocation.unwrap() {
         SMOS_Invocation::WindowCreate(t) => handle_window_create(&mut p, handle_cap_table, &t),
         SMOS_Invocation::WindowDestroy(t) => {
-            handle_window_destroy(cspace, &mut p, handle_cap_table, &t)
+            handle_window_destroy(cspace, ut_table, &mut p, handle_cap_table, &t)
         }
         SMOS_Invocation::WindowRegister(t) => handle_window_register(&mut p, handle_cap_table, &t),
         SMOS_Invocation::IRQRegister(t) => handle_irq_register(cspace, &mut p, &t),
+        SMOS_Invocation::SwapRegister(t) => handle_swap_register(cspace, dma_pool, &mut p, &t),
         SMOS_Invocation::ConnCreate(t) => handle_conn_create(cspace, &mut p, &t),
         SMOS_Invocation::ConnDestroy(t) => handle_conn_destroy(cspace, &mut p, &t),
         SMOS_Invocation::ObjCreate(t) => handle_obj_create(
@@ -104,6 +152,9 @@ pub fn handle_syscall(
             &t,
         ),
         SMOS_Invocation::ObjStat(t) => handle_obj_stat(dma_pool, &mut p, handle_cap_table, &t),
+        SMOS_Invocation::ObjClone(t) => {
+            handle_obj_clone(cspace, ut_table, frame_table, &mut p, handle_cap_table, &t)
+        }
         SMOS_Invocation::ObjDestroy(t) => {
             handle_obj_destroy(cspace, frame_table, &mut p, handle_cap_table, &t)
         }
@@ -120,14 +171,28 @@ pub fn handle_syscall(
             handle_server_create_channel(cspace, handle_cap_table, &mut p, &t)
         }
         SMOS_Invocation::ChannelOpen(t) => handle_channel_open(&mut p, handle_cap_table, &t),
-        SMOS_Invocation::ProcessSpawn(t) => {
-            handle_process_spawn(cspace, ut_table, frame_table, sched_control, ep, &mut p, t)
+        SMOS_Invocation::ProcessSpawn(t) => handle_process_spawn(
+            cspace,
+            ut_table,
+            frame_table,
+            handle_cap_table,
+            sched_control,
+            ep,
+            &mut p,
+            t,
+        ),
+        SMOS_Invocation::LoadComplete(t) => {
+            handle_load_complete(cspace, ut_table, frame_table, &mut p, t)
         }
-        SMOS_Invocation::LoadComplete(t) => handle_load_complete(cspace, frame_table, &mut p, t),
         SMOS_Invocation::ConnRegister(t) => handle_conn_register(&mut p, &t),
         SMOS_Invocation::PageMap(t) => handle_page_map(cspace, ut_table, frame_table, &mut p, &t),
-        SMOS_Invocation::WindowDeregister(t) => handle_window_deregister(cspace, &mut p, &t),
+        SMOS_Invocation::PageUnmap(t) => handle_page_unmap(cspace, frame_table, &mut p, &t),
+        SMOS_Invocation::WindowDeregister(t) => {
+            handle_window_deregister(cspace, ut_table, frame_table, &mut p, &t)
+        }
         SMOS_Invocation::ConnDeregister(t) => handle_conn_deregister(&mut p, &t),
+        SMOS_Invocation::Mount(t) => handle_mount(&mut p, &t),
+        SMOS_Invocation::Unmount(t) => handle_unmount(&mut p, &t),
         SMOS_Invocation::ProcessWait(t) => {
             match handle_process_wait(&mut p, reply, &t) {
                 Some(x) => x,
@@ -142,8 +207,109 @@ pub fn handle_syscall(
                 }
             }
         }
-        SMOS_Invocation::ProcessExit => {
-            handle_process_exit(cspace, ut_table, frame_table, handle_cap_table, &mut p);
+        SMOS_Invocation::ProcessDestroy(t) => {
+            handle_process_destroy(cspace, ut_table, frame_table, handle_cap_table, &mut p, &t)
+        }
+        SMOS_Invocation::ProcessSetFaultHandler(t) => {
+            handle_process_set_fault_handler(cspace, &mut p, &t)
+        }
+        SMOS_Invocation::ProcessFaultForward(t) => handle_process_fault_forward(&mut p, &t),
+        SMOS_Invocation::ProcessSetSched(t) => handle_process_set_sched(sched_control, &mut p, &t),
+        SMOS_Invocation::ProcessList => handle_process_list(frame_table, &mut p),
+        SMOS_Invocation::ProcessInfo(t) => handle_process_info(frame_table, &mut p, &t),
+        SMOS_Invocation::ProcessCoreDump(t) => handle_process_core_dump(&mut p, &t),
+        SMOS_Invocation::ThreadCreate(t) => {
+            handle_thread_create(cspace, ut_table, frame_table, sched_control, ep, &mut p, &t)
+        }
+        SMOS_Invocation::ThreadExit(t) => {
+            match handle_thread_exit(
+                cspace,
+                ut_table,
+                frame_table,
+                sched_control,
+                &mut p,
+                tid,
+                reply,
+                &t,
+            ) {
+                Some(x) => x,
+                None => {
+                    /* @alwin: how can this be done more cleanly? */
+                    if consumed_cap {
+                        recv_slot
+                            .delete()
+                            .expect("Failed to delete consumed capability");
+                    }
+                    return None;
+                }
+            }
+        }
+        SMOS_Invocation::ThreadJoin(t) => match handle_thread_join(&mut p, tid, reply, &t) {
+            Some(x) => x,
+            None => {
+                /* @alwin: how can this be done more cleanly? */
+                if consumed_cap {
+                    recv_slot
+                        .delete()
+                        .expect("Failed to delete consumed capability");
+                }
+                return None;
+            }
+        },
+        SMOS_Invocation::ProcessArgsAppend(t) => handle_process_args_append(&mut p, tid, &t),
+        SMOS_Invocation::ProcessExec(t) => {
+            match handle_process_exec(
+                cspace,
+                ut_table,
+                frame_table,
+                handle_cap_table,
+                sched_control,
+                &mut p,
+                t,
+            ) {
+                Err(e) => Err(e),
+                Ok(loaded) => {
+                    /* The old program is gone, so there is nobody to reply to. If the new one
+                    couldn't be started, there is nothing left to run in the process. */
+                    if !loaded {
+                        handle_process_exit(
+                            cspace,
+                            ut_table,
+                            frame_table,
+                            handle_cap_table,
+                            &mut p,
+                            ExitStatus::Killed,
+                        );
+                        *proc_type = ProcessType::ZombieProcess(p.zombie(ExitStatus::Killed));
+                    }
+
+                    dealloc_retyped(cspace, ut_table, reply);
+
+                    /* @alwin: how can this be done more cleanly? */
+                    if consumed_cap {
+                        recv_slot
+                            .delete()
+                            .expect("Failed to delete consumed capability");
+                    }
+
+                    return None;
+                }
+            }
+        }
+        SMOS_Invocation::ProcessExit(t) => {
+            let status = ExitStatus::Exited(t.exit_code);
+            handle_process_exit(
+                cspace,
+                ut_table,
+                frame_table,
+                handle_cap_table,
+                &mut p,
+                status,
+            );
+
+            /* Transition the process to a zombie. This is done in place so that anyone holding a
+            handle to the process sees the exit status. */
+            *proc_type = ProcessType::ZombieProcess(p.zombie(status));
 
             /* @alwin: how can this be done more cleanly? */
             if consumed_cap {
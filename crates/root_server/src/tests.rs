@@ -3,9 +3,25 @@
 use crate::bootstrap::INITIAL_TASK_CNODE_SIZE_BITS;
 use crate::cspace::{CSpace, CSpaceTrait, BOT_LVL_PER_NODE, CNODE_SIZE_BITS, CNODE_SLOTS};
 use crate::frame_table::{FrameRef, FrameTable};
+use crate::object::AnonymousMemoryObject;
+use crate::page::PAGE_SIZE_4K;
+use crate::pager::pin_frame;
+use crate::proc::StagedArgs;
+use crate::swap::SwapBlocks;
 use crate::ut::UTTable;
+use crate::view::View;
+use crate::vm::{fault_is_forwarded, unmap_page_from_view};
+use crate::window::Window;
 use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
 use bitfield::{bf_clr_bit, bf_first_free, bf_get_bit, bf_set_bit, bitfield_init, bitfield_type};
+use core::cell::RefCell;
+use smos_common::obj_attributes::ObjAttributes;
+use smos_common::sched::SchedParams;
+use smos_common::string::rust_str_from_buffer;
 use smos_common::util::BIT;
 
 fn test_bf_bit(bit: usize) {
@@ -138,6 +154,230 @@ fn test_frame_table(cspace: &mut CSpace, ut_table: &mut UTTable, frame_table: &m
     }
 }
 
+fn test_frame_sharing(cspace: &mut CSpace, ut_table: &mut UTTable, frame_table: &mut FrameTable) {
+    let frame = frame_table.alloc_frame(cspace, ut_table).unwrap();
+    frame_table.share_frame(frame);
+
+    /* A shared frame only goes back on the free list once every reference is freed */
+    frame_table.free_frame(frame);
+    let other = frame_table.alloc_frame(cspace, ut_table).unwrap();
+    assert!(other != frame);
+
+    frame_table.free_frame(frame);
+    let reused = frame_table.alloc_frame(cspace, ut_table).unwrap();
+    assert!(reused == frame);
+
+    frame_table.free_frame(other);
+    frame_table.free_frame(reused);
+}
+
+fn test_frame_clock(cspace: &mut CSpace, ut_table: &mut UTTable, frame_table: &mut FrameTable) {
+    let object = Rc::new(RefCell::new(AnonymousMemoryObject::new(
+        2 * PAGE_SIZE_4K,
+        sel4::CapRights::all(),
+        ObjAttributes::DEFAULT,
+    )));
+
+    let first = frame_table.alloc_frame(cspace, ut_table).unwrap();
+    let second = frame_table.alloc_frame(cspace, ut_table).unwrap();
+    let pageable = frame_table.num_pageable();
+
+    frame_table.make_pageable(first, &object, 0);
+    frame_table.make_pageable(second, &object, PAGE_SIZE_4K);
+    assert!(frame_table.num_pageable() == pageable + 2);
+    assert!(frame_table.is_referenced(first));
+
+    /* The clock hand moves through the frames in the order they were made pageable */
+    frame_table.set_referenced(first, false);
+    let mut seen = [false; 2];
+    for _ in 0..frame_table.num_pageable() {
+        let (frame_ref, _, offset) = frame_table.clock_advance().unwrap();
+        if frame_ref == first {
+            assert!(offset == 0 && !frame_table.is_referenced(first));
+            seen[0] = true;
+        } else if frame_ref == second {
+            assert!(offset == PAGE_SIZE_4K && frame_table.is_referenced(second));
+            seen[1] = true;
+        }
+    }
+    assert!(seen[0] && seen[1]);
+
+    /* Pinned frames are no longer looked at by the clock */
+    frame_table.pin_frame(first);
+    assert!(frame_table.num_pageable() == pageable + 1);
+
+    frame_table.free_frame(first);
+    frame_table.free_frame(second);
+    assert!(frame_table.num_pageable() == pageable);
+}
+
+fn test_swap_blocks() {
+    let mut blocks = SwapBlocks::new(2);
+    let first = blocks.alloc().unwrap();
+    let second = blocks.alloc().unwrap();
+    assert!(first != second && blocks.alloc().is_none());
+
+    /* A shared block is only free once every reference to it has been dropped */
+    blocks.share(first);
+    blocks.free(first);
+    assert!(blocks.alloc().is_none() && blocks.in_use() == 2);
+    blocks.free(first);
+    assert!(blocks.in_use() == 1);
+    assert!(blocks.alloc().unwrap() == first);
+
+    blocks.free(first);
+    blocks.free(second);
+    assert!(blocks.in_use() == 0);
+}
+
+/* A process can't hand out more of the core than it holds itself */
+fn test_sched_grant() {
+    let parent = SchedParams {
+        mcp: 254,
+        budget: 500,
+        period: 1000,
+        ..SchedParams::new(254)
+    };
+    let child = SchedParams {
+        budget: 100,
+        period: 1000,
+        ..SchedParams::new(253)
+    };
+    assert!(parent.can_grant(&child));
+
+    /* More budget than the parent has */
+    assert!(!parent.can_grant(&SchedParams {
+        budget: 600,
+        period: 2000,
+        ..child
+    }));
+
+    /* Less budget than the parent has, but a bigger share of the core */
+    assert!(!parent.can_grant(&SchedParams {
+        budget: 400,
+        period: 500,
+        ..child
+    }));
+
+    /* A budget that doesn't fit into the period */
+    assert!(!parent.can_grant(&SchedParams {
+        budget: 200,
+        period: 100,
+        ..child
+    }));
+
+    /* Priorities above the mcp of the parent */
+    assert!(!parent.can_grant(&SchedParams { prio: 255, ..child }));
+}
+
+/* A page that page_map put into an externally managed view pins its frame until it is unmapped,
+after which the next access to it faults back to the server managing the view */
+fn test_page_unmap(cspace: &mut CSpace, ut_table: &mut UTTable, frame_table: &mut FrameTable) {
+    let object = Rc::new(RefCell::new(AnonymousMemoryObject::new(
+        PAGE_SIZE_4K,
+        sel4::CapRights::all(),
+        ObjAttributes::DEFAULT,
+    )));
+    let window = Rc::new(RefCell::new(Window {
+        start: 0,
+        size: 2 * PAGE_SIZE_4K,
+        bound_view: None,
+    }));
+    let mut view = View::new(window, None, None, sel4::CapRights::all(), 0, 0);
+
+    /* Map the same frame into both pages of the view, the way page_map does it */
+    for offset in [0, PAGE_SIZE_4K] {
+        pin_frame(cspace, ut_table, frame_table, &object, 0).unwrap();
+        let frame_cap = object.borrow().lookup_frame(0).unwrap().cap;
+        let view_cap = cspace
+            .alloc_cap::<sel4::cap_type::UnspecifiedPage>()
+            .unwrap();
+        cspace
+            .root_cnode()
+            .absolute_cptr(view_cap)
+            .copy(
+                &cspace.root_cnode().absolute_cptr(frame_cap),
+                sel4::CapRights::all(),
+            )
+            .unwrap();
+        view.insert_cap_at(offset, view_cap).unwrap();
+        view.page_map_pins
+            .insert(offset, (Rc::downgrade(&object), 0));
+    }
+    assert!(object.borrow().lookup_frame(0).unwrap().pins == 2);
+    assert!(!fault_is_forwarded(&view, 0));
+
+    /* Each page holds its own pin */
+    assert!(unmap_page_from_view(cspace, frame_table, &mut view, 0));
+    assert!(object.borrow().lookup_frame(0).unwrap().pins == 1);
+    assert!(fault_is_forwarded(&view, 0) && !fault_is_forwarded(&view, PAGE_SIZE_4K));
+    assert!(!unmap_page_from_view(cspace, frame_table, &mut view, 0));
+
+    assert!(unmap_page_from_view(
+        cspace,
+        frame_table,
+        &mut view,
+        PAGE_SIZE_4K
+    ));
+    assert!(object.borrow().lookup_frame(0).unwrap().pins == 0);
+    assert!(fault_is_forwarded(&view, PAGE_SIZE_4K));
+
+    object.borrow_mut().cleanup_frame_table(cspace, frame_table);
+}
+
+/* Spawns with more args than fit into the shared buffer are sent in parts the way a client does
+it, and have to come back out of the root server as they went in */
+fn test_staged_args() {
+    let args: Vec<String> = (0..3)
+        .map(|i| {
+            core::iter::repeat(char::from(b'a' + i))
+                .take(2000)
+                .collect()
+        })
+        .collect();
+    let mut stream: Vec<u8> = Vec::new();
+    for arg in ["test_app"]
+        .into_iter()
+        .chain(args.iter().map(|x| x.as_str()))
+    {
+        stream.extend_from_slice(arg.as_bytes());
+        stream.push(0);
+    }
+    assert!(stream.len() > PAGE_SIZE_4K);
+
+    /* Every part but the last is sent ahead, and the last one is in a shared buffer full of junk */
+    let parts: Vec<&[u8]> = stream.chunks(PAGE_SIZE_4K).collect();
+    let (last, ahead) = parts.split_last().unwrap();
+    let mut shared_buf = [0xff; PAGE_SIZE_4K];
+    shared_buf[..last.len()].copy_from_slice(last);
+
+    /* The parts only go to the thread that sent them */
+    let mut staged = StagedArgs::default();
+    for part in ahead {
+        staged.append(1, part).unwrap();
+    }
+    assert!(staged.take(2, &shared_buf).is_none());
+
+    for part in ahead {
+        staged.append(1, part).unwrap();
+    }
+    let data = staged.take(1, &shared_buf).unwrap();
+    assert!(staged.take(1, &shared_buf).is_none());
+
+    let (path, mut rest) = rust_str_from_buffer(&data).unwrap();
+    assert!(path == "test_app");
+    for arg in args.iter() {
+        let (decoded, tmp) = rust_str_from_buffer(rest).unwrap();
+        assert!(decoded == arg);
+        rest = tmp;
+    }
+
+    /* Args that can't fit on the stack of the process are turned away */
+    let too_large = vec![0; StagedArgs::MAX_SIZE + 1];
+    assert!(staged.append(1, &too_large).is_err());
+    assert!(staged.take(1, &shared_buf).is_none());
+}
+
 fn test_heap() {
     // Test simple heap allocation and free
     let t = Box::new(5);
@@ -150,5 +390,11 @@ pub fn run_tests(cspace: &mut CSpace, ut_table: &mut UTTable, frame_table: &mut
 
     // @alwin: C also has some tests for children cspaces
     test_frame_table(cspace, ut_table, frame_table);
+    test_frame_sharing(cspace, ut_table, frame_table);
+    test_frame_clock(cspace, ut_table, frame_table);
+    test_swap_blocks();
+    test_sched_grant();
+    test_page_unmap(cspace, ut_table, frame_table);
+    test_staged_args();
     test_heap();
 }
@@ -0,0 +1,554 @@
+use crate::cspace::{CSpace, CSpaceTrait};
+use crate::frame_table::{FrameRef, FrameTable};
+use crate::mapping::{map_frame, PageTableRegistry};
+use crate::object::AnonymousMemoryObject;
+use crate::page::PAGE_SIZE_4K;
+use crate::proc::{sched_context_size_bits, UserProcess};
+use crate::ut::{UTTable, UTWrapper};
+use crate::util::{alloc_retype, dealloc_retyped};
+use crate::view::View;
+use crate::vmem_layout;
+use crate::window::Window;
+use crate::RSReplyWrapper;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use smos_common::error::InvocationError;
+use smos_common::obj_attributes::ObjAttributes;
+use smos_server::event::{thread_ident, FAULT_EP_BITS, INVOCATION_EP_BITS};
+use smos_server::reply::{handle_reply, SMOSReply};
+use smos_server::syscalls::{ThreadCreate, ThreadExit, ThreadJoin};
+
+/* A thread of a process other than the one it was started with. It shares the vspace and cspace of
+the process, but has its own TCB, scheduling context, IPC buffer and stack. It also has its own
+endpoints to the root server, which are badged with its tid, and its own buffer for passing data to
+the root server. */
+#[derive(Debug, Clone)]
+pub struct Thread {
+    pub tcb: (sel4::cap::Tcb, UTWrapper),
+    pub sched_context: (sel4::cap::SchedContext, UTWrapper),
+    pub ipc_buffer: Rc<RefCell<Window>>,
+    pub shared_buffer: (Rc<RefCell<Window>>, FrameRef),
+    pub stack: Rc<RefCell<Window>>,
+    /* The slot in the cspace of the process holding the endpoint the thread invokes the root
+    server with. The process allocated the slot, so it is never freed by the root server. */
+    pub rs_ep: usize,
+    pub fault_ep: sel4::cap::Endpoint,
+    pub joiner: Option<RSReplyWrapper>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ThreadType {
+    ActiveThread(Thread),
+    /* A thread that has exited but has not been joined yet */
+    ZombieThread(usize),
+}
+
+/* The main thread of a process has tid 0, so the thread in slot i of the thread table has
+tid i + 1 */
+fn tid_to_idx(tid: usize) -> Option<usize> {
+    return tid.checked_sub(1);
+}
+
+pub fn idx_to_tid(idx: usize) -> usize {
+    return idx + 1;
+}
+
+/* Returns the thread with the given tid if it hasn't exited. The main thread isn't in the thread
+table, so this is always None for tid 0. */
+pub fn active_thread(p: &UserProcess, tid: usize) -> Option<&Thread> {
+    return match tid_to_idx(tid).and_then(|idx| p.threads.get(idx)) {
+        Some(Some(ThreadType::ActiveThread(x))) => Some(x),
+        _ => None,
+    };
+}
+
+/* Returns the TCB of the thread with the given tid if it hasn't exited */
+pub fn thread_tcb(p: &UserProcess, tid: usize) -> Option<sel4::cap::Tcb> {
+    if tid == 0 {
+        return Some(p.tcb.0);
+    }
+
+    return active_thread(p, tid).map(|x| x.tcb.0);
+}
+
+fn thread_region(idx: usize) -> usize {
+    return vmem_layout::PROCESS_THREADS + idx * vmem_layout::PROCESS_THREAD_REGION_SIZE;
+}
+
+/* Creates a window that is backed by a fresh anonymous memory object. Frames are only allocated
+when the process faults on the window. */
+fn create_thread_window(start: usize, size: usize) -> Rc<RefCell<Window>> {
+    let window = Rc::new(RefCell::new(Window {
+        start: start,
+        size: size,
+        bound_view: None,
+    }));
+
+    let object = Rc::new(RefCell::new(AnonymousMemoryObject::new(
+        size,
+        sel4::CapRights::all(),
+        ObjAttributes::DEFAULT,
+    )));
+
+    let view = Rc::new(RefCell::new(View::new(
+        window.clone(),
+        Some(object.clone()),
+        None,
+        sel4::CapRights::all(),
+        0,
+        0,
+    )));
+
+    window.borrow_mut().bound_view = Some(view.clone());
+    object.borrow_mut().associated_views.push(view);
+
+    return window;
+}
+
+fn cleanup_thread_window(
+    cspace: &mut CSpace,
+    frame_table: &mut FrameTable,
+    window: &Rc<RefCell<Window>>,
+) {
+    let view = window.borrow().bound_view.as_ref().unwrap().clone();
+
+    view.borrow()
+        .bound_object
+        .as_ref()
+        .unwrap()
+        .borrow_mut()
+        .cleanup_frame_table(cspace, frame_table);
+
+    /* The above should delete these caps, just need to free the slots */
+    view.borrow_mut().cleanup_cap_table(cspace, false);
+}
+
+/* Back a single page window with a frame and map it in. The kernel needs a frame cap to set up the
+IPC buffer of a TCB and the root server reads the shared buffer straight out of the frame, so these
+can't wait until the thread faults on them. The frame isn't tracked by the pager, so it is never
+paged out. */
+fn map_thread_page(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_table: &mut FrameTable,
+    vspace: sel4::cap::VSpace,
+    page_tables: &mut PageTableRegistry,
+    window: &Rc<RefCell<Window>>,
+) -> Result<(sel4::cap::SmallPage, FrameRef), sel4::Error> {
+    let view = window.borrow().bound_view.as_ref().unwrap().clone();
+    let object = view.borrow().bound_object.as_ref().unwrap().clone();
+
+    let frame_ref = frame_table
+        .alloc_frame(cspace, ut_table)
+        .ok_or(sel4::Error::NotEnoughMemory)?;
+    frame_table.frame_data(frame_ref).fill(0);
+
+    let orig_frame_cap = frame_table.frame_from_ref(frame_ref).get_cap();
+    object
+        .borrow_mut()
+        .insert_frame_at(0, (orig_frame_cap, frame_ref))
+        .expect("Failed to insert frame into object");
+
+    let view_frame_slot = cspace.alloc_slot()?;
+    let view_frame_cap = sel4::CPtr::from_bits(view_frame_slot.try_into().unwrap())
+        .cast::<sel4::cap_type::UnspecifiedPage>();
+    cspace
+        .root_cnode()
+        .absolute_cptr(view_frame_cap)
+        .copy(
+            &cspace.root_cnode().absolute_cptr(orig_frame_cap),
+            sel4::CapRightsBuilder::all().build(),
+        )
+        .map_err(|e| {
+            cspace.free_slot(view_frame_slot);
+            e
+        })?;
+
+    view.borrow_mut()
+        .insert_cap_at(0, view_frame_cap)
+        .expect("Failed to insert frame into view");
+
+    map_frame(
+        cspace,
+        ut_table,
+        view_frame_cap,
+        vspace,
+        window.borrow().start,
+        sel4::CapRightsBuilder::all().build(),
+        sel4::VmAttributes::DEFAULT,
+        None,
+        Some(page_tables),
+    )?;
+
+    return Ok((view_frame_cap.cast(), frame_ref));
+}
+
+/* Tears down the thread and removes its windows from the process. Whoever is joining the thread
+needs to be dealt with by the caller. */
+pub fn destroy_thread_internal(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_table: &mut FrameTable,
+    p: &mut UserProcess,
+    thread: &Thread,
+) {
+    dealloc_retyped(cspace, ut_table, thread.tcb);
+    dealloc_retyped(cspace, ut_table, thread.sched_context);
+
+    /* The slot belongs to the process, which frees it once the thread has been joined */
+    p.cspace
+        .delete(thread.rs_ep)
+        .expect("Failed to delete thread endpoint");
+    cspace
+        .delete_cap(thread.fault_ep)
+        .expect("Failed to delete thread fault endpoint");
+    cspace.free_cap(thread.fault_ep);
+
+    for window in [&thread.ipc_buffer, &thread.shared_buffer.0, &thread.stack] {
+        cleanup_thread_window(cspace, frame_table, window);
+        p.remove_window(window.clone());
+        p.free_page_tables(
+            cspace,
+            ut_table,
+            window.borrow().start,
+            window.borrow().size,
+        );
+    }
+}
+
+/* Undoes the windows of a thread that couldn't be created */
+fn cleanup_thread_windows(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_table: &mut FrameTable,
+    p: &mut UserProcess,
+    windows: &[&Rc<RefCell<Window>>],
+) {
+    for window in windows {
+        cleanup_thread_window(cspace, frame_table, window);
+        p.remove_window((*window).clone());
+        p.free_page_tables(
+            cspace,
+            ut_table,
+            window.borrow().start,
+            window.borrow().size,
+        );
+    }
+}
+
+pub fn handle_thread_create(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_table: &mut FrameTable,
+    sched_control: sel4::cap::SchedControl,
+    ep: sel4::cap::Endpoint,
+    p: &mut UserProcess,
+    args: &ThreadCreate,
+) -> Result<SMOSReply, InvocationError> {
+    let idx = match p.threads.iter().position(|x| x.is_none()) {
+        Some(x) => x,
+        None if p.threads.len() < vmem_layout::PROCESS_MAX_THREADS => {
+            p.threads.push(None);
+            p.threads.len() - 1
+        }
+        None => return Err(InvocationError::InsufficientResources),
+    };
+    let tid = idx_to_tid(idx);
+
+    /* The IPC buffer and the shared buffer are at the bottom of the region and the stack is at the
+    top */
+    let ipc_buffer_vaddr = thread_region(idx);
+    let shared_buffer_vaddr = ipc_buffer_vaddr + PAGE_SIZE_4K;
+    let stack_top = thread_region(idx) + vmem_layout::PROCESS_THREAD_REGION_SIZE;
+    let stack_size = vmem_layout::THREAD_STACK_PAGES * PAGE_SIZE_4K;
+    let stack_start = stack_top - stack_size;
+
+    /* The process may have already created windows of its own in the thread region */
+    if p.overlapping_window(ipc_buffer_vaddr, 2 * PAGE_SIZE_4K)
+        .is_some()
+        || p.overlapping_window(stack_start, stack_size).is_some()
+    {
+        return Err(InvocationError::InsufficientResources);
+    }
+
+    let sc_size_bits = sched_context_size_bits(p.sched.extra_refills)
+        .ok_or(InvocationError::InsufficientResources)?;
+
+    let ipc_buffer = create_thread_window(ipc_buffer_vaddr, PAGE_SIZE_4K);
+    let shared_buffer = create_thread_window(shared_buffer_vaddr, PAGE_SIZE_4K);
+    let stack = create_thread_window(stack_start, stack_size);
+    p.add_window_unchecked(ipc_buffer.clone());
+    p.add_window_unchecked(shared_buffer.clone());
+    p.add_window_unchecked(stack.clone());
+    let windows = [&ipc_buffer, &shared_buffer, &stack];
+
+    let vspace = p.vspace.0;
+    let page_tables = p.page_tables.clone();
+    let mut mapped = Vec::new();
+    for window in [&ipc_buffer, &shared_buffer] {
+        let ret = map_thread_page(
+            cspace,
+            ut_table,
+            frame_table,
+            vspace,
+            &mut page_tables.borrow_mut(),
+            window,
+        );
+        match ret {
+            Ok(x) => mapped.push(x),
+            Err(_) => {
+                cleanup_thread_windows(cspace, ut_table, frame_table, p, &windows);
+                return Err(InvocationError::InsufficientResources);
+            }
+        }
+    }
+    let (ipc_buffer_cap, _) = mapped[0];
+    let (_, shared_buffer_ref) = mapped[1];
+
+    /* The endpoints of the thread are badged with its tid, so the root server knows which thread
+    invoked it or faulted. The process manages the slots of its own cspace, so it chooses where
+    the endpoint goes. */
+    let rs_ep = args.rs_ep_slot;
+    let ret = p
+        .cspace
+        .root_cnode()
+        .absolute_cptr_from_bits_with_depth(rs_ep.try_into().unwrap(), sel4::WORD_SIZE)
+        .mint(
+            &cspace.root_cnode().absolute_cptr(ep),
+            sel4::CapRightsBuilder::all().build(),
+            (thread_ident(p.pid, tid) | INVOCATION_EP_BITS)
+                .try_into()
+                .unwrap(),
+        );
+    if ret.is_err() {
+        /* The slot is occupied or isn't in the cspace of the process */
+        cleanup_thread_windows(cspace, ut_table, frame_table, p, &windows);
+        return Err(InvocationError::InvalidArguments);
+    }
+
+    let fault_ep = match cspace.alloc_cap::<sel4::cap_type::Endpoint>() {
+        Ok(x) => x,
+        Err(_) => {
+            p.cspace.delete(rs_ep).unwrap();
+            cleanup_thread_windows(cspace, ut_table, frame_table, p, &windows);
+            return Err(InvocationError::InsufficientResources);
+        }
+    };
+    let ret = cspace.root_cnode().absolute_cptr(fault_ep).mint(
+        &cspace.root_cnode().absolute_cptr(ep),
+        sel4::CapRightsBuilder::all().build(),
+        (thread_ident(p.pid, tid) | FAULT_EP_BITS)
+            .try_into()
+            .unwrap(),
+    );
+    if ret.is_err() {
+        cspace.free_cap(fault_ep);
+        p.cspace.delete(rs_ep).unwrap();
+        cleanup_thread_windows(cspace, ut_table, frame_table, p, &windows);
+        return Err(InvocationError::InsufficientResources);
+    }
+
+    let tcb = alloc_retype::<sel4::cap_type::Tcb>(cspace, ut_table, sel4::ObjectBlueprint::Tcb)
+        .map_err(|_| {
+            cspace.delete_cap(fault_ep).unwrap();
+            cspace.free_cap(fault_ep);
+            p.cspace.delete(rs_ep).unwrap();
+            cleanup_thread_windows(cspace, ut_table, frame_table, p, &windows);
+            InvocationError::InsufficientResources
+        })?;
+
+    let sched_context = alloc_retype::<sel4::cap_type::SchedContext>(
+        cspace,
+        ut_table,
+        sel4::ObjectBlueprint::SchedContext {
+            size_bits: sc_size_bits,
+        },
+    )
+    .map_err(|_| {
+        dealloc_retyped(cspace, ut_table, tcb);
+        cspace.delete_cap(fault_ep).unwrap();
+        cspace.free_cap(fault_ep);
+        p.cspace.delete(rs_ep).unwrap();
+        cleanup_thread_windows(cspace, ut_table, frame_table, p, &windows);
+        InvocationError::InsufficientResources
+    })?;
+
+    let thread = Thread {
+        tcb: tcb,
+        sched_context: sched_context,
+        ipc_buffer: ipc_buffer,
+        shared_buffer: (shared_buffer, shared_buffer_ref),
+        stack: stack,
+        rs_ep: rs_ep,
+        fault_ep: fault_ep,
+        joiner: None,
+    };
+
+    /* The scheduling context is configured once the thread is in the thread table, as the budget
+    of the process is split between all of its threads */
+    let ret = thread
+        .tcb
+        .0
+        .tcb_configure(
+            p.cspace.root_cnode(),
+            sel4::CNodeCapData::new(0, 0),
+            p.vspace.0,
+            ipc_buffer_vaddr.try_into().unwrap(),
+            ipc_buffer_cap,
+        )
+        .and_then(|_| {
+            thread.tcb.0.tcb_set_sched_params(
+                sel4::init_thread::slot::TCB.cap(),
+                p.sched.mcp.into(),
+                p.sched.prio.into(),
+                thread.sched_context.0,
+                thread.fault_ep,
+            )
+        })
+        .and_then(|_| p.delegate_thread_faults(cspace, thread.tcb.0, tid));
+
+    if ret.is_err() {
+        destroy_thread_internal(cspace, ut_table, frame_table, p, &thread);
+        return Err(InvocationError::InsufficientResources);
+    }
+
+    /* The runtime sets up thread local storage for the new thread on its stack, but until then it
+    runs with the thread pointer of the main thread, like the main thread did before it set up its
+    own */
+    let tls_base = p
+        .tcb
+        .0
+        .tcb_read_registers(false, 35)
+        .map_or(0, |ctx| ctx.inner().tpidr_el0);
+
+    let mut user_context = sel4::UserContext::default();
+    *user_context.pc_mut() = args.entry as u64;
+    *user_context.sp_mut() = stack_top as u64;
+    *user_context.c_param_mut(0) = args.arg as u64;
+    *user_context.c_param_mut(1) = tid as u64;
+    *user_context.c_param_mut(2) = ipc_buffer_vaddr as u64;
+    *user_context.c_param_mut(3) = shared_buffer_vaddr as u64;
+    *user_context.c_param_mut(4) = rs_ep as u64;
+    user_context.inner_mut().tpidr_el0 = tls_base;
+
+    /* TPIDR_EL0 comes after pc, sp, spsr and x0-x30 in the user context */
+    if thread
+        .tcb
+        .0
+        .tcb_write_registers(false, 35, &mut user_context)
+        .is_err()
+    {
+        destroy_thread_internal(cspace, ut_table, frame_table, p, &thread);
+        return Err(InvocationError::InvalidArguments);
+    }
+
+    let tcb = thread.tcb.0;
+    p.threads[idx] = Some(ThreadType::ActiveThread(thread));
+
+    /* The budget may be too small to be split between this many threads */
+    if p.configure_sched(sched_control, &p.sched).is_err() {
+        if let Some(ThreadType::ActiveThread(thread)) = p.threads[idx].take() {
+            destroy_thread_internal(cspace, ut_table, frame_table, p, &thread);
+        }
+        p.configure_sched(sched_control, &p.sched)
+            .expect("Failed to restore scheduling parameters");
+        return Err(InvocationError::InsufficientResources);
+    }
+
+    tcb.tcb_resume().expect("Failed to start thread");
+
+    return Ok(SMOSReply::ThreadCreate { tid: tid });
+}
+
+/* Exits the thread that invoked the root server, which is identified by tid. Returns None if the
+thread exited, in which case the caller must not be replied to. */
+pub fn handle_thread_exit(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_table: &mut FrameTable,
+    sched_control: sel4::cap::SchedControl,
+    p: &mut UserProcess,
+    tid: usize,
+    reply: RSReplyWrapper,
+    args: &ThreadExit,
+) -> Option<Result<SMOSReply, InvocationError>> {
+    /* The main thread should use process_exit instead */
+    let idx = match tid_to_idx(tid) {
+        Some(x) if x < p.threads.len() => x,
+        _ => return Some(Err(InvocationError::InvalidArguments)),
+    };
+
+    let mut thread = match &p.threads[idx] {
+        Some(ThreadType::ActiveThread(x)) => x.clone(),
+        _ => return Some(Err(InvocationError::InvalidArguments)),
+    };
+    p.threads[idx] = None;
+
+    destroy_thread_internal(cspace, ut_table, frame_table, p, &thread);
+
+    /* The remaining threads get the budget of the one that exited */
+    if p.configure_sched(sched_control, &p.sched).is_err() {
+        warn_rs!("Failed to reconfigure the threads of process {}", p.pid);
+    }
+
+    /* The reply object was bound to the thread that just got destroyed */
+    dealloc_retyped(cspace, ut_table, reply);
+
+    match thread.joiner.take() {
+        Some(x) => {
+            let msginfo = sel4::with_ipc_buffer_mut(|ipc_buf| {
+                handle_reply(
+                    ipc_buf,
+                    SMOSReply::ThreadJoin {
+                        exit_code: args.exit_code,
+                    },
+                )
+            });
+
+            x.0.send(msginfo);
+            dealloc_retyped(cspace, ut_table, x);
+
+            /* The thread has been joined, so its slot can be reused */
+        }
+        None => {
+            p.threads[idx] = Some(ThreadType::ZombieThread(args.exit_code));
+        }
+    }
+
+    return None;
+}
+
+/* Returns None if the caller is blocked until the thread exits. The caller, which has the given
+tid, can only join the other threads of its process, and nobody can join the main thread. */
+pub fn handle_thread_join(
+    p: &mut UserProcess,
+    tid: usize,
+    reply: RSReplyWrapper,
+    args: &ThreadJoin,
+) -> Option<Result<SMOSReply, InvocationError>> {
+    let idx = match tid_to_idx(args.tid) {
+        Some(x) if x < p.threads.len() && args.tid != tid => x,
+        _ => return Some(Err(InvocationError::InvalidArguments)),
+    };
+
+    match &mut p.threads[idx] {
+        Some(ThreadType::ActiveThread(x)) => {
+            /* Only one thread can join a thread at a time */
+            if x.joiner.is_some() {
+                return Some(Err(InvocationError::InvalidArguments));
+            }
+
+            x.joiner = Some(reply);
+            None
+        }
+        Some(ThreadType::ZombieThread(exit_code)) => {
+            let exit_code = *exit_code;
+            p.threads[idx] = None;
+            Some(Ok(SMOSReply::ThreadJoin {
+                exit_code: exit_code,
+            }))
+        }
+        None => Some(Err(InvocationError::InvalidArguments)),
+    }
+}
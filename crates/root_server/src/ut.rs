@@ -109,6 +109,7 @@ impl UTTable {
             sel4::CapRightsBuilder::all().build(),
             sel4::VmAttributes::DEFAULT,
             None,
+            None,
         )?;
         let prev = self.next_free_vaddr;
         self.next_free_vaddr += PAGE_SIZE_4K;
@@ -1,12 +1,17 @@
 use crate::connection::Server;
 use crate::cspace::{CSpace, CSpaceTrait};
+use crate::frame_table::FrameTable;
 use crate::handle::RootServerResource;
+use crate::mapping::PageTableRegistry;
 use crate::object::{AnonymousMemoryObject, OBJ_LVL_MAX};
+use crate::page::PAGE_BITS_2M;
+use crate::pager::unpin_frame;
 use crate::proc::UserProcess;
 use crate::window::Window;
 use crate::RSReplyWrapper;
 use crate::PAGE_SIZE_4K;
-use alloc::rc::Rc;
+use alloc::collections::btree_map::BTreeMap;
+use alloc::rc::{Rc, Weak};
 use alloc::vec;
 use alloc::vec::Vec;
 use core::cell::RefCell;
@@ -23,6 +28,8 @@ use smos_server::reply::SMOSReply;
 #[derive(Clone, Debug)]
 pub struct ViewCap {
     pub cap: sel4::cap::UnspecifiedPage,
+    /* The size of the page the cap refers to */
+    pub size_bits: usize,
 }
 
 #[derive(Clone, Debug)]
@@ -45,7 +52,15 @@ pub struct View {
     pub rights: sel4::CapRights,
     pub win_offset: usize,
     pub obj_offset: usize,
-    pub pending_fault: Option<(RSReplyWrapper, sel4::VmFault, sel4::cap::VSpace)>,
+    pub pending_fault: Option<(
+        RSReplyWrapper,
+        sel4::VmFault,
+        sel4::cap::VSpace,
+        Rc<RefCell<PageTableRegistry>>,
+    )>,
+    /* The object and offset of the frame behind each page that page_map put into the view, by
+    offset into the window. Each of these holds a pin on the frame. */
+    pub page_map_pins: BTreeMap<usize, (Weak<RefCell<AnonymousMemoryObject>>, usize)>,
 }
 
 impl View {
@@ -66,6 +81,7 @@ impl View {
             win_offset: win_off,
             obj_offset: obj_off,
             pending_fault: None,
+            page_map_pins: BTreeMap::new(),
         }
     }
 
@@ -122,7 +138,10 @@ impl View {
 
         let idx = (offset >> shift) & (BIT(9) - 1);
         if curr_table[idx].is_none() {
-            curr_table[idx] = Some(ViewCapTableEntry::Cap(ViewCap { cap: cap }));
+            curr_table[idx] = Some(ViewCapTableEntry::Cap(ViewCap {
+                cap: cap,
+                size_bits: sel4_sys::seL4_PageBits.try_into().unwrap(),
+            }));
             return Ok(());
         }
 
@@ -137,6 +156,80 @@ impl View {
         }
     }
 
+    /* Large pages take the place of a table of 4K pages, so this fails if any 4K pages are already
+    present in the large page containing offset */
+    pub fn insert_large_cap_at(
+        &mut self,
+        offset: usize,
+        cap: sel4::cap::UnspecifiedPage,
+    ) -> Result<(), sel4::Error> {
+        let mut shift = 39;
+        let mut curr_table_lvl = 0;
+        let mut curr_table = &mut self.caps;
+        while curr_table_lvl < 2 {
+            let idx = (offset >> shift) & (BIT(9) - 1);
+
+            if curr_table[idx].is_none() {
+                curr_table[idx] = Some(ViewCapTableEntry::CapTable(ViewCapTable {
+                    table: vec![None; OBJ_LVL_MAX],
+                }));
+            }
+
+            curr_table = match &mut curr_table[idx] {
+                None => return Err(sel4::Error::InvalidArgument),
+                Some(ref mut x) => match x {
+                    ViewCapTableEntry::Cap(_) => return Err(sel4::Error::DeleteFirst),
+                    ViewCapTableEntry::CapTable(ref mut y) => &mut y.table,
+                },
+            };
+
+            curr_table_lvl += 1;
+            shift -= 9;
+        }
+
+        let idx = (offset >> shift) & (BIT(9) - 1);
+        if curr_table[idx].is_some() {
+            return Err(sel4::Error::DeleteFirst);
+        }
+
+        curr_table[idx] = Some(ViewCapTableEntry::Cap(ViewCap {
+            cap: cap,
+            size_bits: PAGE_BITS_2M,
+        }));
+        return Ok(());
+    }
+
+    /* Removes the 4K cap at offset from the view and returns it. The caller is responsible for
+    deleting the cap. */
+    pub fn remove_cap_at(&mut self, offset: usize) -> Option<ViewCap> {
+        let mut shift = 39;
+        let mut curr_table_lvl = 0;
+        let mut curr_table = &mut self.caps;
+        while curr_table_lvl < 3 {
+            let idx = (offset >> shift) & (BIT(9) - 1);
+
+            curr_table = match &mut curr_table[idx] {
+                None => return None,
+                Some(x) => match x {
+                    ViewCapTableEntry::Cap(_) => return None,
+                    ViewCapTableEntry::CapTable(ref mut y) => &mut y.table,
+                },
+            };
+
+            curr_table_lvl += 1;
+            shift -= 9;
+        }
+
+        let idx = (offset >> shift) & (BIT(9) - 1);
+        return match curr_table[idx].take() {
+            Some(ViewCapTableEntry::Cap(x)) => Some(x),
+            x => {
+                curr_table[idx] = x;
+                None
+            }
+        };
+    }
+
     fn cleanup_cap_table_inner(
         vec: &Vec<Option<ViewCapTableEntry>>,
         cspace: &mut CSpace,
@@ -165,11 +258,49 @@ impl View {
         }
     }
 
+    fn mapped_pages_inner(vec: &Vec<Option<ViewCapTableEntry>>) -> usize {
+        let mut count = 0;
+        for node in vec {
+            match node {
+                None => continue,
+                Some(ViewCapTableEntry::CapTable(ref y)) => {
+                    count += Self::mapped_pages_inner(&y.table)
+                }
+                Some(ViewCapTableEntry::Cap(ref y)) => count += BIT(y.size_bits) / PAGE_SIZE_4K,
+            }
+        }
+
+        return count;
+    }
+
+    /* Returns the number of pages that are present in the view */
+    pub fn mapped_pages(&self) -> usize {
+        return Self::mapped_pages_inner(&self.caps);
+    }
+
     /* Cleans up cap table. Should use delete == false when the object frame table was cleaned
     before with revoke == true, as the caps would have already been deleted by this. */
     pub fn cleanup_cap_table(&mut self, cspace: &mut CSpace, delete: bool) {
         Self::cleanup_cap_table_inner(&self.caps, cspace, delete)
     }
+
+    /* Releases the pin held by the page that page_map put into the view at offset, if there is one */
+    pub fn release_page_map_pin(&mut self, frame_table: &mut FrameTable, offset: usize) {
+        if let Some((object, obj_offset)) = self.page_map_pins.remove(&offset) {
+            if let Some(object) = object.upgrade() {
+                unpin_frame(frame_table, &object, obj_offset);
+            }
+        }
+    }
+
+    /* Releases the pins held by all the pages that page_map put into the view */
+    pub fn release_page_map_pins(&mut self, frame_table: &mut FrameTable) {
+        for (_, (object, obj_offset)) in core::mem::take(&mut self.page_map_pins) {
+            if let Some(object) = object.upgrade() {
+                unpin_frame(frame_table, &object, obj_offset);
+            }
+        }
+    }
 }
 
 pub fn handle_view(
@@ -240,6 +371,7 @@ pub fn handle_view(
         managing_server_info: None,
         rights: args.rights.clone(), // @alwin: The rights of the view should be &'d with the rights of the object
         pending_fault: None,
+        page_map_pins: BTreeMap::new(),
     }));
 
     window.borrow_mut().bound_view = Some(view.clone());
@@ -1,12 +1,16 @@
 use crate::cspace::{CSpace, CSpaceTrait};
 use crate::frame_table::FrameTable;
 use crate::handle::RootServerResource;
-use crate::mapping::map_frame;
-use crate::page::PAGE_SIZE_4K;
+use crate::mapping::{map_frame, map_large_frame};
+use crate::object::AnonymousMemoryObject;
+use crate::page::{PAGE_BITS_2M, PAGE_SIZE_2M, PAGE_SIZE_4K};
+use crate::pager::{get_or_alloc_frame, pin_frame, track_frame, PagerError};
 use crate::proc::UserProcess;
+use crate::swap::park_fault;
 use crate::ut::UTTable;
 use crate::util::dealloc_retyped;
 use crate::view::View;
+use crate::window::Window;
 use crate::RSReplyWrapper;
 use alloc::rc::Rc;
 use core::cell::RefCell;
@@ -15,7 +19,10 @@ use smos_common::util::ROUND_DOWN;
 use smos_server::handle::HandleAllocater;
 use smos_server::ntfn_buffer::{enqueue_ntfn_buffer_msg, NotificationType, VMFaultNotification};
 use smos_server::reply::{FaultReply, SMOSReply};
-use smos_server::syscalls::PageMap;
+use smos_server::syscalls::{PageMap, PageUnmap};
+
+/* The WnR bit of the fault status register is set for faults caused by writes */
+const FSR_WNR: u64 = 1 << 6;
 
 fn forward_vm_fault(
     p: &mut UserProcess,
@@ -43,7 +50,68 @@ fn forward_vm_fault(
     };
 
     server.borrow().ntfn_dispatch.rs_badged_ntfn().signal();
-    borrowed_view.pending_fault = Some((reply, info, p.vspace.0))
+    borrowed_view.pending_fault = Some((reply, info, p.vspace.0, p.page_tables.clone()))
+}
+
+/* A large frame can only be mapped if the view puts the whole large page of the object that
+fault_offset falls in at a large page aligned address in the window. Returns the offset into the
+window that the large page starts at if this is the case. */
+fn large_page_window_offset(
+    window: &Window,
+    view: &View,
+    object: &AnonymousMemoryObject,
+    fault_offset: usize,
+) -> Option<usize> {
+    let obj_offset = view.obj_offset + (fault_offset - view.win_offset);
+    if !object.large_page_possible(obj_offset) {
+        return None;
+    }
+
+    let obj_start = ROUND_DOWN(obj_offset, PAGE_BITS_2M);
+    if obj_start < view.obj_offset {
+        return None;
+    }
+
+    let win_offset = view.win_offset + (obj_start - view.obj_offset);
+    if (window.start + win_offset) % PAGE_SIZE_2M != 0 || win_offset + PAGE_SIZE_2M > window.size {
+        return None;
+    }
+
+    return Some(win_offset);
+}
+
+/* Whether the page at offset into the window of a view is backed by a frame that is shared with a
+clone of the object */
+fn view_page_is_shared(view: &View, offset: usize) -> bool {
+    if view.bound_object.is_none() || offset < view.win_offset {
+        return false;
+    }
+
+    let obj_offset = view.obj_offset + (offset - view.win_offset);
+    return view
+        .bound_object
+        .as_ref()
+        .unwrap()
+        .borrow()
+        .lookup_frame(obj_offset)
+        .is_some_and(|x| x.cow);
+}
+
+/* Whether the page at offset into the window of a view is backed by a frame that has an up to date
+copy in the swap area */
+fn view_page_is_clean(view: &View, offset: usize) -> bool {
+    if view.bound_object.is_none() || offset < view.win_offset {
+        return false;
+    }
+
+    let obj_offset = view.obj_offset + (offset - view.win_offset);
+    return view
+        .bound_object
+        .as_ref()
+        .unwrap()
+        .borrow()
+        .lookup_frame(obj_offset)
+        .is_some_and(|x| x.swap_block.is_some());
 }
 
 pub fn handle_page_map(
@@ -90,6 +158,42 @@ pub fn handle_page_map(
         .ok_or(InvocationError::InvalidArguments)?
         .clone();
 
+    /* Pages are handed over one 4K frame at a time, which can't be done with part of a large page */
+    if src_view
+        .borrow()
+        .lookup_cap(src_window_offset)
+        .is_some_and(|x| x.size_bits != sel4_sys::seL4_PageBits as usize)
+    {
+        return Err(InvocationError::InvalidArguments);
+    }
+
+    /* The page is handed over with the rights of the destination view, so it can't stay shared
+    with a clone of the object */
+    if view_page_is_shared(&src_view.borrow(), src_window_offset) {
+        let object = src_view.borrow().bound_object.clone().unwrap();
+        let obj_offset =
+            src_view.borrow().obj_offset + (src_window_offset - src_view.borrow().win_offset);
+        object
+            .borrow_mut()
+            .unshare_frame(cspace, ut_table, frame_table, obj_offset)
+            .or(Err(InvocationError::InsufficientResources))?;
+    }
+
+    /* The destination view holds on to the frame, so it can't be paged out until the page is
+    unmapped again.
+    @alwin: This fails while the swap area is busy, the invocation could be parked like faults are */
+    let src_object = src_view.borrow().bound_object.clone();
+    let pin = match src_object {
+        Some(object) => {
+            let obj_offset =
+                src_view.borrow().obj_offset + (src_window_offset - src_view.borrow().win_offset);
+            pin_frame(cspace, ut_table, frame_table, &object, obj_offset)
+                .or(Err(InvocationError::InsufficientResources))?;
+            Some((Rc::downgrade(&object), obj_offset))
+        }
+        None => None,
+    };
+
     /* Check that the view has a mapping associated with offset */
 
     if src_view.borrow().lookup_cap(src_window_offset).is_none() {
@@ -114,25 +218,12 @@ pub fn handle_page_map(
         let obj_offset =
             src_view.borrow().obj_offset + (src_window_offset - src_view.borrow().win_offset);
 
-        let obj_frame_cap = if object.borrow().lookup_frame(obj_offset).is_none() {
-            /* Allocate a frame */
-            let frame_ref = frame_table
-                .alloc_frame(cspace, ut_table)
-                .expect("@alwin: Should probs not be an assert");
-            let orig_frame_cap = frame_table.frame_from_ref(frame_ref).get_cap();
-            object
-                .borrow_mut()
-                .insert_frame_at(obj_offset, (orig_frame_cap, frame_ref))
-                .expect("Failed to insert frame cap into object");
-
-            /* Zero-out the frame */
-            let frame_data = frame_table.frame_data(frame_ref);
-            frame_data[0..4096].fill(0);
-            orig_frame_cap
-        } else {
-            /* The object already has a frame at that location */
-            object.borrow().lookup_frame(obj_offset).unwrap().cap
-        };
+        if object.borrow().lookup_large_frame(obj_offset).is_some() {
+            return Err(InvocationError::InvalidArguments);
+        }
+
+        let obj_frame_cap = get_or_alloc_frame(cspace, ut_table, frame_table, &object, obj_offset)
+            .or(Err(InvocationError::InsufficientResources))?;
 
         /* Copy the frame into the view*/
         let view_frame_slot = cspace
@@ -181,6 +272,13 @@ pub fn handle_page_map(
         .insert_cap_at(args.view_offset, dst_view_frame_cap)
         .expect("Failed to add frame to view");
 
+    if let Some(pin) = pin {
+        dst_view
+            .borrow_mut()
+            .page_map_pins
+            .insert(args.view_offset, pin);
+    }
+
     if dst_view.borrow().pending_fault.is_some() {
         /* Map the page into the faulting process */
         // warn_rs!("Mapping frame");
@@ -196,6 +294,15 @@ pub fn handle_page_map(
             dst_view.borrow().rights.clone(),
             sel4::VmAttributes::DEFAULT,
             None,
+            Some(
+                &mut dst_view
+                    .borrow()
+                    .pending_fault
+                    .as_ref()
+                    .unwrap()
+                    .3
+                    .borrow_mut(),
+            ),
         )
         .expect("Failed to map frame");
 
@@ -225,21 +332,86 @@ pub fn handle_page_map(
     return Ok(SMOSReply::PageMap);
 }
 
+/* Faults on pages that are missing from an externally managed view are forwarded to the server
+managing it */
+pub fn fault_is_forwarded(view: &View, offset: usize) -> bool {
+    return view.bound_object.is_none() && view.lookup_cap(offset).is_none();
+}
+
+/* Takes the page at offset back from a view that page_map put it into, releasing the pin it held
+on its frame. Returns false if there is no page at offset. */
+pub fn unmap_page_from_view(
+    cspace: &mut CSpace,
+    frame_table: &mut FrameTable,
+    view: &mut View,
+    offset: usize,
+) -> bool {
+    let view_cap = match view.remove_cap_at(offset) {
+        Some(x) => x,
+        None => return false,
+    };
+
+    /* Deleting the cap unmaps the page from the process that the view belongs to */
+    cspace
+        .delete_cap(view_cap.cap)
+        .expect("Failed to delete capability");
+    cspace.free_cap(view_cap.cap);
+
+    view.release_page_map_pin(frame_table, offset);
+
+    return true;
+}
+
+pub fn handle_page_unmap(
+    cspace: &mut CSpace,
+    frame_table: &mut FrameTable,
+    p: &mut UserProcess,
+    args: &PageUnmap,
+) -> Result<SMOSReply, InvocationError> {
+    if args.view_offset % PAGE_SIZE_4K != 0 {
+        return Err(InvocationError::AlignmentError { which_arg: 1 });
+    }
+
+    let window_reg_ref = p
+        .get_handle(args.window_registration_hndl.idx)
+        .or(Err(InvocationError::InvalidHandle { which_arg: 0 }))?;
+
+    let view = match window_reg_ref.as_ref().unwrap().inner() {
+        RootServerResource::WindowRegistration(view) => Ok(view.clone()),
+        _ => Err(InvocationError::InvalidHandle { which_arg: 0 }),
+    }?;
+
+    /* There has to be a page mapped into the view at the offset. Once it is gone, the next access
+    to it faults and is forwarded to the server again. */
+    if !unmap_page_from_view(
+        cspace,
+        frame_table,
+        &mut view.borrow_mut(),
+        args.view_offset,
+    ) {
+        return Err(InvocationError::InvalidArguments);
+    }
+
+    return Ok(SMOSReply::PageUnmap);
+}
+
 pub fn handle_vm_fault(
     cspace: &mut CSpace,
     frame_table: &mut FrameTable,
     ut_table: &mut UTTable,
     reply: RSReplyWrapper,
     proc: &mut UserProcess,
+    tid: usize,
     fault_info: sel4::VmFault,
 ) -> Option<FaultReply> {
     let window = proc.find_window_containing(fault_info.addr() as usize);
 
-    /* Maybe should return None here and possibly clean up the process? */
+    /* Faults that can't be resolved are reported without resuming, which terminates the process */
     if window.is_none() {
         warn_rs!(
-            "Process {} faulted at vaddr: 0x{:x}, which is not inside a window",
+            "Process {} faulted at pc: 0x{:x}, vaddr: 0x{:x}, which is not inside a window",
             proc.pid,
+            fault_info.ip(),
             fault_info.addr()
         );
         return Some(FaultReply::VMFault { resume: false });
@@ -247,10 +419,11 @@ pub fn handle_vm_fault(
 
     let window_unwrapped = window.unwrap();
 
-    /* Maybe should return None here and possibly clean up the process? */
     if window_unwrapped.borrow().bound_view.is_none() {
         warn_rs!(
-            "Process faulted at vaddr: 0x{:x}, which does not have a bound view",
+            "Process {} faulted at pc: 0x{:x}, vaddr: 0x{:x}, which does not have a bound view",
+            proc.pid,
+            fault_info.ip(),
             fault_info.addr()
         );
         return Some(FaultReply::VMFault { resume: false });
@@ -266,80 +439,207 @@ pub fn handle_vm_fault(
         .unwrap()
         .clone();
 
-    if view.borrow().lookup_cap(fault_offset).is_none() {
-        let object_option = view.borrow().bound_object.clone();
-
-        if object_option.as_ref().is_none() {
-            forward_vm_fault(
-                proc,
-                view.clone(),
-                ROUND_DOWN(fault_offset, sel4_sys::seL4_PageBits.try_into().unwrap()),
-                reply,
-                fault_info,
+    /* Writes to a frame that is shared with a clone of the object get their own copy of it */
+    if fault_info.fsr() & FSR_WNR != 0
+        && view.borrow().rights.write()
+        && view_page_is_shared(&view.borrow(), fault_offset)
+    {
+        let object = view.borrow().bound_object.clone().unwrap();
+        let obj_offset = view.borrow().obj_offset + (fault_offset - view.borrow().win_offset);
+        if let Err(e) = object
+            .borrow_mut()
+            .unshare_frame(cspace, ut_table, frame_table, obj_offset)
+        {
+            warn_rs!(
+                "Failed to copy shared frame at vaddr: 0x{:x} for process {}: {:?}",
+                fault_info.addr(),
+                proc.pid,
+                e
             );
-
-            return None;
+            return Some(FaultReply::VMFault { resume: false });
         }
+    }
 
-        let object = object_option.unwrap().clone();
+    /* Writes to a frame that has a copy in swap make the copy out of date */
+    if fault_info.fsr() & FSR_WNR != 0
+        && view.borrow().rights.write()
+        && view_page_is_clean(&view.borrow(), fault_offset)
+    {
+        let object = view.borrow().bound_object.clone().unwrap();
+        let obj_offset = view.borrow().obj_offset + (fault_offset - view.borrow().win_offset);
+        object.borrow_mut().mark_dirty(cspace, obj_offset);
+    }
+
+    if fault_is_forwarded(&view.borrow(), fault_offset) {
+        forward_vm_fault(
+            proc,
+            view.clone(),
+            ROUND_DOWN(fault_offset, sel4_sys::seL4_PageBits.try_into().unwrap()),
+            reply,
+            fault_info,
+        );
+
+        return None;
+    }
+
+    if view.borrow().lookup_cap(fault_offset).is_none() {
+        let object = view.borrow().bound_object.clone().unwrap();
 
         let obj_offset = view.borrow().obj_offset + (fault_offset - view.borrow().win_offset);
 
-        let obj_frame_cap = if object.borrow().lookup_frame(obj_offset).is_none() {
-            /* Allocate a frame */
-            let frame_ref = frame_table
-                .alloc_frame(cspace, ut_table)
-                .expect("@alwin: Should probs not be an assert");
-            let orig_frame_cap = frame_table.frame_from_ref(frame_ref).get_cap();
-
-            object
-                .borrow_mut()
-                .insert_frame_at(obj_offset, (orig_frame_cap, frame_ref))
-                .expect("Failed to insert frame into object");
-
-            /* Zero-out the frame */
-            let frame_data = frame_table.frame_data(frame_ref);
-            frame_data[0..4096].fill(0);
-            orig_frame_cap
-        } else {
-            object.borrow().lookup_frame(obj_offset).unwrap().cap
-        };
+        let large_win_offset = large_page_window_offset(
+            &window_unwrapped.borrow(),
+            &view.borrow(),
+            &object.borrow(),
+            fault_offset,
+        );
 
-        let view_frame_cap = cspace
-            .alloc_cap::<sel4::cap_type::UnspecifiedPage>()
-            .expect("@alwin: This should not be an assert");
-        cspace
-            .root_cnode()
-            .absolute_cptr(view_frame_cap)
-            .copy(
-                &cspace.root_cnode().absolute_cptr(obj_frame_cap),
-                // @alwin: Be careful about rights here
-                sel4::CapRightsBuilder::all().build(),
-            )
-            .expect("Failed to copy frame cap into slot");
+        /* Back the large page with a large frame, unless parts of it are already backed by 4K
+        frames or there are no large frames left */
+        if large_win_offset.is_some() && object.borrow().lookup_large_frame(obj_offset).is_none() {
+            if let Some(frame) = frame_table.alloc_large_frame(cspace) {
+                if object
+                    .borrow_mut()
+                    .insert_large_frame_at(obj_offset, frame)
+                    .is_err()
+                {
+                    frame_table.free_large_frame(cspace, frame.0, frame.1);
+                }
+            }
+        }
 
-        view.borrow_mut()
-            .insert_cap_at(fault_offset, view_frame_cap)
-            .expect("Failed to insert mapping into view");
+        let large_frame_cap = object
+            .borrow()
+            .lookup_large_frame(obj_offset)
+            .map(|x| x.cap);
+
+        if let Some(large_frame_cap) = large_frame_cap {
+            /* Another view that lines up differently can't use part of a large frame */
+            if large_win_offset.is_none() {
+                warn_rs!(
+                    "Process {} faulted at vaddr: 0x{:x}, which is in a misaligned large frame",
+                    proc.pid,
+                    fault_info.addr()
+                );
+                return Some(FaultReply::VMFault { resume: false });
+            }
+
+            let view_frame_cap = cspace
+                .alloc_cap::<sel4::cap_type::UnspecifiedPage>()
+                .expect("@alwin: This should not be an assert");
+            cspace
+                .root_cnode()
+                .absolute_cptr(view_frame_cap)
+                .copy(
+                    &cspace.root_cnode().absolute_cptr(large_frame_cap),
+                    // @alwin: Be careful about rights here
+                    sel4::CapRightsBuilder::all().build(),
+                )
+                .expect("Failed to copy frame cap into slot");
+
+            view.borrow_mut()
+                .insert_large_cap_at(large_win_offset.unwrap(), view_frame_cap)
+                .expect("Failed to insert mapping into view");
+        } else {
+            let obj_frame_cap =
+                match get_or_alloc_frame(cspace, ut_table, frame_table, &object, obj_offset) {
+                    Ok(cap) => cap,
+                    /* The fault is handled again once the swap area has finished its transfer */
+                    Err(PagerError::Busy) => {
+                        park_fault(proc.pid, tid, reply, fault_info);
+                        return None;
+                    }
+                    Err(e) => {
+                        warn_rs!(
+                            "Failed to get frame for vaddr: 0x{:x} for process {}: {:?}",
+                            fault_info.addr(),
+                            proc.pid,
+                            e
+                        );
+                        return Some(FaultReply::VMFault { resume: false });
+                    }
+                };
+
+            let view_frame_cap = cspace
+                .alloc_cap::<sel4::cap_type::UnspecifiedPage>()
+                .expect("@alwin: This should not be an assert");
+            cspace
+                .root_cnode()
+                .absolute_cptr(view_frame_cap)
+                .copy(
+                    &cspace.root_cnode().absolute_cptr(obj_frame_cap),
+                    // @alwin: Be careful about rights here
+                    sel4::CapRightsBuilder::all().build(),
+                )
+                .expect("Failed to copy frame cap into slot");
+
+            view.borrow_mut()
+                .insert_cap_at(fault_offset, view_frame_cap)
+                .expect("Failed to insert mapping into view");
+
+            track_frame(frame_table, &object, obj_offset);
+        }
     }
 
     /* Map views[idx] into virtual address space */
-    // @alwin: This leaks page table caps!! How can this be resolved? Do we need a proper page
-    // table after all?
-    map_frame(
-        cspace,
-        ut_table,
-        view.borrow().lookup_cap(fault_offset).unwrap().cap,
-        proc.vspace.0,
-        ROUND_DOWN(
-            fault_info.addr() as usize,
-            sel4_sys::seL4_PageBits.try_into().unwrap(),
-        ),
-        view.borrow().rights.clone(),
-        sel4::VmAttributes::DEFAULT,
-        None,
-    )
-    .expect("@alwin: Can this be an assert?");
+    let view_cap = view.borrow().lookup_cap(fault_offset).unwrap().clone();
+    if view_cap.size_bits == PAGE_BITS_2M {
+        /* @alwin: This fails if a page table for 4K pages from an earlier view is still around */
+        if let Err(e) = map_large_frame(
+            cspace,
+            ut_table,
+            view_cap.cap,
+            proc.vspace.0,
+            ROUND_DOWN(fault_info.addr() as usize, PAGE_BITS_2M),
+            view.borrow().rights.clone(),
+            sel4::VmAttributes::DEFAULT,
+            Some(&mut proc.page_tables.borrow_mut()),
+        ) {
+            warn_rs!(
+                "Failed to map large page at vaddr: 0x{:x} into process {}: {:?}",
+                fault_info.addr(),
+                proc.pid,
+                e
+            );
+            return Some(FaultReply::VMFault { resume: false });
+        }
+    } else {
+        /* Shared frames and frames with a copy in swap are mapped read-only until they are written
+        to */
+        let rights = if view_page_is_shared(&view.borrow(), fault_offset)
+            || view_page_is_clean(&view.borrow(), fault_offset)
+        {
+            sel4::CapRightsBuilder::none()
+                .read(view.borrow().rights.read())
+                .build()
+        } else {
+            view.borrow().rights.clone()
+        };
+
+        if let Err(e) = map_frame(
+            cspace,
+            ut_table,
+            view_cap.cap,
+            proc.vspace.0,
+            ROUND_DOWN(
+                fault_info.addr() as usize,
+                sel4_sys::seL4_PageBits.try_into().unwrap(),
+            ),
+            rights,
+            sel4::VmAttributes::DEFAULT,
+            None,
+            Some(&mut proc.page_tables.borrow_mut()),
+        ) {
+            warn_rs!(
+                "Process {} faulted at pc: 0x{:x}, vaddr: 0x{:x}, which could not be mapped: {:?}",
+                proc.pid,
+                fault_info.ip(),
+                fault_info.addr(),
+                e
+            );
+            return Some(FaultReply::VMFault { resume: false });
+        }
+    }
 
     return Some(FaultReply::VMFault { resume: true });
 }
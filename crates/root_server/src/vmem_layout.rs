@@ -1,6 +1,9 @@
+use smos_common::init::SMOS_STACK_PAGES;
+
 /* Constants for the layout of the root server's address space */
 pub const _DMA_SIZE_BITS: usize = sel4_sys::seL4_LargePageBits as usize;
-pub const _SCRATCH: usize = 0xA0000000;
+/* Large frames are mapped here while the root server needs to look at them */
+pub const SCRATCH: usize = 0xA0000000;
 pub const _DEVICE_START: usize = 0xB0000000;
 pub const STACK: usize = 0xC0000000;
 pub const STACK_PAGES: usize = 128;
@@ -13,7 +16,16 @@ pub const FRAME_DATA: usize = 0x8200000000;
 
 /* Constants for how SOS will layout the address space of any processes it loads up */
 pub const PROCESS_STACK_TOP: usize = 0x90000000;
+pub const PROCESS_STACK_PAGES: usize = SMOS_STACK_PAGES;
 pub const PROCESS_IPC_BUFFER: usize = 0xA0000000;
 pub const PROCESS_RS_DATA_TRANSFER_PAGE: usize = 0xA0001000;
+/* Position-independent executables are loaded with their lowest address here */
+pub const PROCESS_PIE_BASE: usize = 0x10000000;
+/* Each additional thread of a process gets its own region, which has the IPC buffer of the thread at
+the bottom and its stack at the top, with a guard between them */
+pub const PROCESS_THREADS: usize = 0xB0000000;
+pub const PROCESS_THREAD_REGION_SIZE: usize = 0x100000;
+pub const PROCESS_MAX_THREADS: usize = 64;
+pub const THREAD_STACK_PAGES: usize = 64;
 // pub const USER_DEFAULT_STACK_PAGES: usize = 10;
 pub const _PROCESS_VMEM_START: usize = 0xC0000000;
@@ -1,6 +1,9 @@
 use crate::cspace::CSpace;
+use crate::frame_table::FrameTable;
 use crate::handle::RootServerResource;
 use crate::proc::UserProcess;
+use crate::ut::UTTable;
+use crate::util::dealloc_retyped;
 use crate::view::View;
 use crate::PAGE_SIZE_4K;
 use alloc::rc::Rc;
@@ -138,6 +141,8 @@ pub fn handle_window_register(
 
 pub fn handle_window_deregister(
     cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_table: &mut FrameTable,
     p: &mut UserProcess,
     args: &WindowDeregister,
 ) -> Result<SMOSReply, InvocationError> {
@@ -149,12 +154,7 @@ pub fn handle_window_deregister(
         _ => Err(InvocationError::InvalidHandle { which_arg: 0 }),
     }?;
 
-    // @alwin: This fn is kinda the same as handle_unview, what to do abt this?
-    view.borrow_mut().cleanup_cap_table(cspace, true);
-
-    // @alwin: There should probs be some checks about pending fault or something here?
-    view.borrow_mut().bound_window.borrow_mut().bound_view = None;
-    assert!(view.borrow_mut().bound_object.is_none());
+    handle_window_deregister_internal(cspace, ut_table, frame_table, view);
 
     p.cleanup_handle(args.hndl.idx)
         .expect("Failed to clean up handle");
@@ -162,6 +162,26 @@ pub fn handle_window_deregister(
     return Ok(SMOSReply::WindowDeregister);
 }
 
+pub fn handle_window_deregister_internal(
+    cspace: &mut CSpace,
+    ut_table: &mut UTTable,
+    frame_table: &mut FrameTable,
+    view: Rc<RefCell<View>>,
+) {
+    // @alwin: This fn is kinda the same as handle_unview, what to do abt this?
+    view.borrow_mut().cleanup_cap_table(cspace, true);
+    view.borrow_mut().release_page_map_pins(frame_table);
+
+    /* Nothing is going to resolve a fault that is still pending on this view, so get rid of the
+    reply object that was kept around for it */
+    if let Some((reply, _, _, _)) = view.borrow_mut().pending_fault.take() {
+        dealloc_retyped(cspace, ut_table, reply);
+    }
+
+    view.borrow_mut().bound_window.borrow_mut().bound_view = None;
+    assert!(view.borrow_mut().bound_object.is_none());
+}
+
 pub fn handle_window_destroy_internal(
     cspace: &mut CSpace,
     window: Rc<RefCell<Window>>,
@@ -210,6 +230,7 @@ pub fn handle_window_destroy_internal(
 
 pub fn handle_window_destroy(
     cspace: &mut CSpace,
+    ut_table: &mut UTTable,
     p: &mut UserProcess,
     handle_cap_table: &mut HandleCapabilityTable<RootServerResource>,
     args: &WindowDestroy,
@@ -239,7 +260,13 @@ pub fn handle_window_destroy(
     }?;
 
     handle_window_destroy_internal(cspace, window.clone(), true);
-    p.remove_window(window);
+    p.remove_window(window.clone());
+    p.free_page_tables(
+        cspace,
+        ut_table,
+        window.borrow().start,
+        window.borrow().size,
+    );
 
     generic_cleanup_handle(
         p,
@@ -27,6 +27,13 @@ pub enum ObjCreateArgs {
     Length,
 }
 
+#[repr(usize)]
+pub enum ObjCloneArgs {
+    Handle = 0,
+    ReturnCap,
+    Length,
+}
+
 #[repr(usize)]
 pub enum ViewArgs {
     Window = 0,
@@ -37,3 +44,64 @@ pub enum ViewArgs {
     Rights,
     Length,
 }
+
+#[repr(usize)]
+pub enum ProcessSpawnArgs {
+    NumArgs = 0,
+    Prio,
+    Mcp,
+    Budget,
+    Period,
+    ExtraRefills,
+    NumEnv,
+    InheritEnv,
+    NumCaps,
+    Length,
+}
+
+#[repr(usize)]
+pub enum ProcessExecArgs {
+    NumArgs = 0,
+    NumEnv,
+    InheritEnv,
+    NumCaps,
+    NumHandles,
+    Length,
+}
+
+#[repr(usize)]
+pub enum ProcessSetSchedArgs {
+    Handle = 0,
+    Prio,
+    Budget,
+    Period,
+    Length,
+}
+
+#[repr(usize)]
+pub enum ProcessFaultForwardArgs {
+    Handle = 0,
+    Tid,
+    Length,
+}
+
+#[repr(usize)]
+pub enum ThreadCreateArgs {
+    Entry = 0,
+    Arg,
+    RSEPSlot,
+    Length,
+}
+
+#[repr(usize)]
+pub enum ThreadExitArgs {
+    ExitCode = 0,
+    Length,
+}
+
+#[repr(usize)]
+pub enum SwapRegisterArgs {
+    PublishHandle = 0,
+    NumBlocks,
+    Length,
+}
@@ -19,6 +19,28 @@ pub mod InitCNodeSlots {
     pub const SMOS_CNodeSelf: u32          = 2; // @alwin: add this
 }
 
+/* Capabilities that a process is given when it is spawned are described by environment variables
+of the form SMOS_CAP_<NAME>=<SLOT> */
+pub const SMOS_CAP_ENV_PREFIX: &str = "SMOS_CAP_";
+
+/* Handles that a process keeps across an exec are described by environment variables of the form
+SMOS_HANDLE_<NAME>=<INDEX> */
+pub const SMOS_HANDLE_ENV_PREFIX: &str = "SMOS_HANDLE_";
+
+/* The number of pages of stack that programs start with. The root server sets up the stack of the
+loader, which sets up a stack of the same size for the program it loads, so an environment and
+args that fit on one fit on the other. */
+pub const SMOS_STACK_PAGES: usize = 128;
+
+/* Executables that are not spawned with an absolute path are looked up in each of the
+colon-separated directories in the PATH environment variable of the caller */
+pub const SMOS_PATH_ENV: &str = "PATH";
+pub const SMOS_DEFAULT_PATH: &str = "/boot";
+
+/* The name of the object server that crash handlers should write the core files of faulted
+children to */
+pub const SMOS_CORE_SERVER_ENV: &str = "CORE_SERVER";
+
 /// The index of a slot in the initial thread's root CNode.
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
 pub struct Slot<T: CapType = cap_type::Unspecified> {
@@ -16,6 +16,7 @@ pub enum SMOSInvocation {
     View,
     Unview,
     ObjStat,
+    ObjClone,
     ConnCreate,
     ConnDestroy,
     ConnOpen,
@@ -24,12 +25,23 @@ pub enum SMOSInvocation {
     ConnUnpublish,
     ConnRegister,
     ConnDeregister,
+    Mount,
+    Unmount,
     TestSimple,
     Authorise,
     ProcSpawn,
+    ProcExec,
     ProcWait,
     ProcExit,
     ProcDestroy,
+    ProcSetFaultHandler,
+    ProcSetSched,
+    ProcList,
+    ProcInfo,
+    ProcCoreDump,
+    ThreadCreate,
+    ThreadExit,
+    ThreadJoin,
     ProcCreateComplete, // @alwin: needed? You can probably just jump to the application from the loader
     ReplyCreate, // @alwin: This is used for making reply objects, but I think this should be a general function kinda like untyped retype
     ReplyDestroy, // @alwin: as previous
@@ -43,6 +55,7 @@ pub enum SMOSInvocation {
     PageUnmap,
     LoadComplete,
     IRQRegister,
+    SwapRegister,
     sDDFChannelRegisterBidirectional,
     sDDFChannelRegisterReceiveOnly,
     sDDFQueueRegister,
@@ -50,6 +63,8 @@ pub enum SMOSInvocation {
     sDDFProvideDataRegion,
     ServerCreateChannel,
     ChannelOpen,
+    ProcFaultForward,
+    ProcArgsAppend,
 }
 
 impl SMOSInvocation {
@@ -60,7 +75,8 @@ impl SMOSInvocation {
             | SMOSInvocation::sDDFQueueRegister
             | SMOSInvocation::sDDFProvideDataRegion
             | SMOSInvocation::View
-            | SMOSInvocation::ConnOpen => return true,
+            | SMOSInvocation::ConnOpen
+            | SMOSInvocation::ProcSetFaultHandler => return true,
             _ => return false,
         }
     }
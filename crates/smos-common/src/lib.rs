@@ -9,9 +9,12 @@ pub mod init;
 pub mod invocations;
 pub mod local_handle;
 pub mod obj_attributes;
+pub mod reloc;
 pub mod returns;
+pub mod sched;
 pub mod sddf;
 pub mod server_connection;
 pub mod string;
+pub mod swap;
 pub mod syscall;
 pub mod util;
@@ -28,6 +28,10 @@ impl ObjAttributes {
     pub const CONTIGUOUS: Self = Self::from_inner(1);
     pub const DEVICE: Self = Self::from_inner(2);
     pub const EAGER: Self = Self::from_inner(4);
+    /* Back the object with 2MiB frames wherever a whole one fits. They are only mapped as large
+    pages into windows where the virtual address is aligned the same way as the object offset.
+    @alwin: 1GiB frames could be supported too if memory was reserved for them */
+    pub const LARGE_PAGES: Self = Self::from_inner(8);
 }
 
 impl BitOr for ObjAttributes {
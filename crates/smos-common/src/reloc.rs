@@ -0,0 +1,116 @@
+use byteorder::{ByteOrder, LittleEndian};
+use elf::endian::AnyEndian;
+use elf::ElfBytes;
+
+const DYN_ENTRY_SIZE: usize = 16;
+const RELA_ENTRY_SIZE: usize = 24;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RelocationError {
+    /* The segments or the dynamic section could not be read */
+    InvalidElf,
+    /* The relocation table has entries of a size other than that of an Elf64_Rela */
+    InvalidEntrySize(u64),
+    /* The relocation table is not inside a loaded segment */
+    TableNotLoaded,
+    /* Only relative relocations are supported, as there is no dynamic linker to resolve symbols */
+    UnsupportedType(u32),
+}
+
+/* The relative relocations of an ELF file, as (offset, addend) pairs */
+pub struct RelativeRelocations<'a> {
+    entries: core::slice::ChunksExact<'a, u8>,
+}
+
+impl Iterator for RelativeRelocations<'_> {
+    type Item = (u64, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for entry in self.entries.by_ref() {
+            let info = LittleEndian::read_u64(&entry[8..16]);
+            if (info & 0xffffffff) as u32 == elf::abi::R_AARCH64_RELATIVE {
+                return Some((
+                    LittleEndian::read_u64(&entry[0..8]),
+                    LittleEndian::read_u64(&entry[16..24]),
+                ));
+            }
+        }
+
+        return None;
+    }
+}
+
+/* Finds the relocations in the dynamic section of a position-independent ELF file. Every relocation
+is checked before this returns, so applying them can't fail halfway through. Files without a
+dynamic section or relocation table have no relocations. */
+pub fn relative_relocations<'a>(
+    elf: &ElfBytes<'a, AnyEndian>,
+) -> Result<RelativeRelocations<'a>, RelocationError> {
+    let empty: &'a [u8] = &[];
+    let no_relocations = RelativeRelocations {
+        entries: empty.chunks_exact(RELA_ENTRY_SIZE),
+    };
+    let segments = elf.segments().ok_or(RelocationError::InvalidElf)?;
+
+    let dynamic = match segments.iter().find(|x| x.p_type == elf::abi::PT_DYNAMIC) {
+        Some(x) => x,
+        None => return Ok(no_relocations),
+    };
+
+    /* Find the relocation table in the dynamic section */
+    let mut rela: Option<u64> = None;
+    let mut rela_size: u64 = 0;
+    let dynamic_data = elf
+        .segment_data(&dynamic)
+        .or(Err(RelocationError::InvalidElf))?;
+    for entry in dynamic_data.chunks_exact(DYN_ENTRY_SIZE) {
+        let tag = LittleEndian::read_i64(&entry[0..8]);
+        let val = LittleEndian::read_u64(&entry[8..16]);
+        match tag {
+            elf::abi::DT_NULL => break,
+            elf::abi::DT_RELA => rela = Some(val),
+            elf::abi::DT_RELASZ => rela_size = val,
+            elf::abi::DT_RELAENT => {
+                if val as usize != RELA_ENTRY_SIZE {
+                    return Err(RelocationError::InvalidEntrySize(val));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let rela = match rela {
+        Some(x) => x,
+        None => return Ok(no_relocations),
+    };
+
+    /* The table is referred to by its virtual address, so find where it is in the file */
+    let rela_segment = segments
+        .iter()
+        .find(|x| {
+            x.p_type == elf::abi::PT_LOAD
+                && rela >= x.p_vaddr
+                && rela
+                    .checked_add(rela_size)
+                    .is_some_and(|end| end <= x.p_vaddr + x.p_filesz)
+        })
+        .ok_or(RelocationError::TableNotLoaded)?;
+    let rela_start = (rela - rela_segment.p_vaddr) as usize;
+    let rela_data = elf
+        .segment_data(&rela_segment)
+        .or(Err(RelocationError::InvalidElf))?
+        .get(rela_start..rela_start + rela_size as usize)
+        .ok_or(RelocationError::TableNotLoaded)?;
+
+    for entry in rela_data.chunks_exact(RELA_ENTRY_SIZE) {
+        let info = LittleEndian::read_u64(&entry[8..16]);
+        match (info & 0xffffffff) as u32 {
+            elf::abi::R_AARCH64_NONE | elf::abi::R_AARCH64_RELATIVE => {}
+            x => return Err(RelocationError::UnsupportedType(x)),
+        }
+    }
+
+    return Ok(RelativeRelocations {
+        entries: rela_data.chunks_exact(RELA_ENTRY_SIZE),
+    });
+}
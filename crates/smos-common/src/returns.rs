@@ -1,3 +1,5 @@
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+
 pub enum ConnectionCreateReturn {
     ConnectionHandle = 0,
 }
@@ -14,3 +16,158 @@ pub struct ObjStat {
     pub size: usize,
     pub paddr: Option<usize>,
 }
+
+#[repr(usize)]
+pub enum ProcessWaitReturn {
+    Reason = 0,
+    Code,
+    Addr,
+    Length,
+}
+
+/* The kind of fault that terminated a process */
+#[derive(Debug, Copy, Clone, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u64)]
+pub enum FaultKind {
+    VmFault = 0,
+    CapFault,
+    UnknownSyscall,
+    UserException,
+    Timeout,
+    Other,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExitStatus {
+    /* The process called process_exit() with this exit code */
+    Exited(usize),
+    /* The process was terminated by the root server because of a fault. For VM faults, addr is
+    the address that was accessed, otherwise it is the pc of the faulting instruction. */
+    Faulted { kind: FaultKind, addr: usize },
+    /* The process was destroyed by another process */
+    Killed,
+}
+
+#[derive(Debug, TryFromPrimitive, IntoPrimitive)]
+#[repr(u64)]
+pub enum ExitStatusLabel {
+    Exited = 0,
+    Faulted,
+    Killed,
+}
+
+impl ExitStatus {
+    pub fn label(&self) -> ExitStatusLabel {
+        match self {
+            ExitStatus::Exited(_) => ExitStatusLabel::Exited,
+            ExitStatus::Faulted { .. } => ExitStatusLabel::Faulted,
+            ExitStatus::Killed => ExitStatusLabel::Killed,
+        }
+    }
+
+    /* The exit code, or the kind of fault for processes that faulted */
+    pub fn code(&self) -> usize {
+        match self {
+            ExitStatus::Exited(code) => *code,
+            ExitStatus::Faulted { kind, .. } => u64::from(*kind) as usize,
+            ExitStatus::Killed => 0,
+        }
+    }
+
+    pub fn addr(&self) -> usize {
+        match self {
+            ExitStatus::Faulted { addr, .. } => *addr,
+            ExitStatus::Exited(_) | ExitStatus::Killed => 0,
+        }
+    }
+
+    pub fn from_raw(label: u64, code: u64, addr: u64) -> Option<Self> {
+        match ExitStatusLabel::try_from(label).ok()? {
+            ExitStatusLabel::Exited => Some(ExitStatus::Exited(code as usize)),
+            ExitStatusLabel::Faulted => Some(ExitStatus::Faulted {
+                kind: FaultKind::try_from(code).ok()?,
+                addr: addr as usize,
+            }),
+            ExitStatusLabel::Killed => Some(ExitStatus::Killed),
+        }
+    }
+}
+
+#[repr(usize)]
+pub enum ProcessListReturn {
+    Count = 0,
+    Length,
+}
+
+#[repr(usize)]
+pub enum ProcessInfoReturn {
+    State = 0,
+    Prio,
+    HasParent,
+    Parent,
+    MemUsage,
+    Length,
+}
+
+#[repr(usize)]
+pub enum ProcessCoreDumpReturn {
+    Handle = 0,
+    Size,
+    Length,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u64)]
+pub enum ProcessState {
+    Active = 0,
+    Zombie,
+}
+
+/* Longer process names are truncated */
+pub const PROCESS_NAME_MAX: usize = 32;
+
+#[derive(Debug, Copy, Clone)]
+pub struct ProcessStat {
+    pub pid: usize,
+    pub state: ProcessState,
+    pub prio: u8,
+    /* The parent of a zombie is only reported to the parent itself */
+    pub parent: Option<usize>,
+    /* The number of bytes that are mapped into the address space of the process */
+    pub mem_usage: usize,
+    name: [u8; PROCESS_NAME_MAX],
+    name_len: usize,
+}
+
+impl ProcessStat {
+    pub fn new(
+        pid: usize,
+        state: ProcessState,
+        prio: u8,
+        parent: Option<usize>,
+        mem_usage: usize,
+        name: &str,
+    ) -> Self {
+        let mut name_len = core::cmp::min(name.len(), PROCESS_NAME_MAX);
+        while !name.is_char_boundary(name_len) {
+            name_len -= 1;
+        }
+
+        let mut name_buf = [0; PROCESS_NAME_MAX];
+        name_buf[..name_len].copy_from_slice(&name.as_bytes()[..name_len]);
+
+        return Self {
+            pid: pid,
+            state: state,
+            prio: prio,
+            parent: parent,
+            mem_usage: mem_usage,
+            name: name_buf,
+            name_len: name_len,
+        };
+    }
+
+    pub fn name(&self) -> &str {
+        return core::str::from_utf8(&self.name[..self.name_len]).unwrap_or("");
+    }
+}
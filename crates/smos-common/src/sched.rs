@@ -0,0 +1,42 @@
+/* Scheduling parameters for a process. Budget and period are in microseconds. */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SchedParams {
+    pub prio: u8,
+    /* The maximum controlled priority, which bounds the priority the process can give to others */
+    pub mcp: u8,
+    pub budget: u64,
+    pub period: u64,
+    pub extra_refills: usize,
+}
+
+impl SchedParams {
+    pub const DEFAULT_BUDGET: u64 = 1000;
+    pub const DEFAULT_PERIOD: u64 = 1000;
+
+    /* A process at the given priority that is allowed to use the whole core and can't give any
+    priority to processes it spawns */
+    pub const fn new(prio: u8) -> Self {
+        return Self {
+            prio: prio,
+            mcp: 0,
+            budget: Self::DEFAULT_BUDGET,
+            period: Self::DEFAULT_PERIOD,
+            extra_refills: 0,
+        };
+    }
+
+    /* Checks that a process holding self can give the requested parameters to another process.
+    The priorities are bounded by our mcp, and the budget and share of the core can't exceed ours */
+    pub fn can_grant(&self, requested: &SchedParams) -> bool {
+        if requested.prio > self.mcp || requested.mcp > self.mcp {
+            return false;
+        }
+
+        if requested.budget > requested.period || requested.budget > self.budget {
+            return false;
+        }
+
+        return (requested.budget as u128) * (self.period as u128)
+            <= (self.budget as u128) * (requested.period as u128);
+    }
+}
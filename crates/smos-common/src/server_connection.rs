@@ -3,12 +3,14 @@ use crate::connection::*;
 use crate::invocations::SMOSInvocation;
 
 /* @alwin: Figure out how to autogenerate these */
-const ROOT_SERVER_INVOCATIONS: [SMOSInvocation; 20] = [
+const ROOT_SERVER_INVOCATIONS: [SMOSInvocation; 35] = [
     SMOSInvocation::ConnCreate,
     SMOSInvocation::ConnDestroy,
     SMOSInvocation::ConnPublish,
     SMOSInvocation::ConnRegister,
     SMOSInvocation::ConnDeregister,
+    SMOSInvocation::Mount,
+    SMOSInvocation::Unmount,
     SMOSInvocation::TestSimple,
     SMOSInvocation::WindowCreate,
     SMOSInvocation::WindowDestroy,
@@ -17,15 +19,28 @@ const ROOT_SERVER_INVOCATIONS: [SMOSInvocation; 20] = [
     SMOSInvocation::ReplyCreate,
     SMOSInvocation::ServerHandleCapCreate,
     SMOSInvocation::ProcSpawn,
+    SMOSInvocation::ProcExec,
     SMOSInvocation::ProcWait,
     SMOSInvocation::ProcExit,
+    SMOSInvocation::ProcDestroy,
+    SMOSInvocation::ProcSetFaultHandler,
+    SMOSInvocation::ProcFaultForward,
+    SMOSInvocation::ProcSetSched,
+    SMOSInvocation::ProcList,
+    SMOSInvocation::ProcInfo,
+    SMOSInvocation::ProcCoreDump,
+    SMOSInvocation::ThreadCreate,
+    SMOSInvocation::ThreadExit,
+    SMOSInvocation::ThreadJoin,
     SMOSInvocation::PageMap,
+    SMOSInvocation::PageUnmap,
     SMOSInvocation::LoadComplete,
     SMOSInvocation::IRQRegister,
+    SMOSInvocation::SwapRegister,
     SMOSInvocation::ServerCreateChannel,
     SMOSInvocation::ChannelOpen,
 ];
-const OBJECT_SERVER_INVOCATIONS: [SMOSInvocation; 7] = [
+const OBJECT_SERVER_INVOCATIONS: [SMOSInvocation; 8] = [
     SMOSInvocation::ObjCreate,
     SMOSInvocation::View,
     SMOSInvocation::Unview,
@@ -33,6 +48,7 @@ const OBJECT_SERVER_INVOCATIONS: [SMOSInvocation; 7] = [
     SMOSInvocation::ObjClose,
     SMOSInvocation::ObjDestroy,
     SMOSInvocation::ObjStat,
+    SMOSInvocation::ObjClone,
 ];
 const NON_ROOT_SERVER_INVOCATIONS: [SMOSInvocation; 2] =
     [SMOSInvocation::ConnOpen, SMOSInvocation::ConnClose];
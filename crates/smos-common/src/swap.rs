@@ -0,0 +1,63 @@
+// @alwin: This should probably be in a different crate
+
+/* Layout of the region that the root server shares with the server providing its swap area. The
+queues have the same format as sDDF block queues, and each request transfers a single block from
+or to the data page. */
+
+pub const SWAP_BLOCK_SIZE: usize = 4096;
+pub const SWAP_QUEUE_CAPACITY: usize = 16;
+
+pub const SWAP_REQ_QUEUE_OFFSET: usize = 0;
+pub const SWAP_RESP_QUEUE_OFFSET: usize = 0x1000;
+pub const SWAP_DATA_OFFSET: usize = 0x2000;
+pub const SWAP_REGION_SIZE: usize = 0x3000;
+
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SwapRequestCode {
+    Read = 0,
+    Write,
+    Flush,
+    Barrier,
+}
+
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SwapResponseStatus {
+    Ok = 0,
+    SeekError,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SwapRequest {
+    pub code: SwapRequestCode,
+    /* Offset of the data into the swap region */
+    pub io_or_offset: u64,
+    pub block_number: u64,
+    pub count: u16,
+    pub id: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SwapResponse {
+    pub status: SwapResponseStatus,
+    pub success_count: u16,
+    pub id: u32,
+}
+
+#[repr(C)]
+pub struct SwapRequestQueue {
+    pub head: u32,
+    pub tail: u32,
+    pub plugged: bool,
+    pub buffers: [SwapRequest; SWAP_QUEUE_CAPACITY],
+}
+
+#[repr(C)]
+pub struct SwapResponseQueue {
+    pub head: u32,
+    pub tail: u32,
+    pub buffers: [SwapResponse; SWAP_QUEUE_CAPACITY],
+}
@@ -10,9 +10,10 @@ use crate::local_handle::{
 };
 use crate::obj_attributes::ObjAttributes;
 use crate::returns::*;
+use crate::sched::SchedParams;
 use crate::sddf::{QueueType, VirtType};
 use crate::server_connection::*;
-use crate::string::copy_terminated_rust_string_to_buffer;
+use crate::string::{copy_terminated_rust_string_to_buffer, rust_str_from_buffer};
 use core::slice;
 use sel4::AbsoluteCPtr;
 use smos_cspace::SMOSUserCSpace;
@@ -35,6 +36,61 @@ use smos_cspace::SMOSUserCSpace;
 // connecting to. In a real dynamic system, it would be better if the client didn't have to
 // know this at compile time but idk if this is really THAT useful or even feasible.
 
+/* Writes the arguments of process_spawn and process_exec into the shared buffer. Whenever the
+buffer fills up, its contents are sent ahead to the root server, which puts the parts back together
+when the invocation itself is made with the last part in the shared buffer. */
+struct ArgsWriter<'a> {
+    ep: sel4::cap::Endpoint,
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> ArgsWriter<'a> {
+    fn new(ep: sel4::cap::Endpoint, buf: &'a mut [u8]) -> Self {
+        return Self {
+            ep: ep,
+            buf: buf,
+            pos: 0,
+        };
+    }
+
+    fn write(&mut self, mut bytes: &[u8]) -> Result<(), InvocationError> {
+        while !bytes.is_empty() {
+            if self.pos == self.buf.len() {
+                self.flush()?;
+            }
+
+            let len = usize::min(bytes.len(), self.buf.len() - self.pos);
+            self.buf[self.pos..self.pos + len].copy_from_slice(&bytes[..len]);
+            self.pos += len;
+            bytes = &bytes[len..];
+        }
+
+        return Ok(());
+    }
+
+    fn write_str(&mut self, string: &str) -> Result<(), InvocationError> {
+        self.write(string.as_bytes())?;
+        return self.write(&[0]);
+    }
+
+    fn flush(&mut self) -> Result<(), InvocationError> {
+        let msginfo = sel4::MessageInfoBuilder::default()
+            .label(SMOSInvocation::ProcArgsAppend as u64)
+            .length(1)
+            .build();
+
+        sel4::with_ipc_buffer_mut(|ipc_buf| {
+            ipc_buf.msg_regs_mut()[0] = self.pos as u64;
+            let msginfo = self.ep.call(msginfo);
+            try_unpack_error(msginfo.label(), ipc_buf.msg_regs())
+        })?;
+
+        self.pos = 0;
+        return Ok(());
+    }
+}
+
 pub trait RootServerInterface: ClientConnection {
     fn conn_create<T: ClientConnection>(
         &self,
@@ -130,6 +186,42 @@ pub trait RootServerInterface: ClientConnection {
         });
     }
 
+    /* Offers num_blocks blocks of storage to the root server to swap pages out to. Returns a
+    handle to the region containing the request and response queues along with a notification
+    to signal when responses are ready. Requests are signalled through the notification buffer
+    of the server. */
+    fn swap_register(
+        &self,
+        publish_hndl: &LocalHandle<ConnectionHandle>,
+        num_blocks: usize,
+        ntfn_slot: &AbsoluteCPtr,
+    ) -> Result<(LocalHandle<ObjectHandle>, sel4::cap::Notification), InvocationError> {
+        let mut msginfo = sel4::MessageInfoBuilder::default()
+            .label(SMOSInvocation::SwapRegister as u64)
+            .length(SwapRegisterArgs::Length as usize)
+            .build();
+
+        return sel4::with_ipc_buffer_mut(|ipc_buf| {
+            ipc_buf.msg_regs_mut()[SwapRegisterArgs::PublishHandle as usize] =
+                publish_hndl.idx as u64;
+            ipc_buf.msg_regs_mut()[SwapRegisterArgs::NumBlocks as usize] = num_blocks as u64;
+            ipc_buf.set_recv_slot(ntfn_slot);
+
+            msginfo = self.ep().call(msginfo);
+            try_unpack_error(msginfo.label(), ipc_buf.msg_regs())?;
+
+            if msginfo.length() != 1 || msginfo.extra_caps() != 1 || msginfo.caps_unwrapped() != 0 {
+                return Err(InvocationError::ServerError);
+            }
+
+            return Ok((
+                LocalHandle::new(ipc_buf.msg_regs()[0] as usize),
+                sel4::CPtr::from_bits(ntfn_slot.path().bits())
+                    .cast::<sel4::cap_type::Notification>(),
+            ));
+        });
+    }
+
     fn conn_deregister(
         &self,
         reg_hndl: &LocalHandle<ConnRegistrationHandle>,
@@ -183,6 +275,48 @@ pub trait RootServerInterface: ClientConnection {
         ));
     }
 
+    /* Makes the published server called server_name responsible for all paths under path. Only the
+    process that published the server can mount it. */
+    fn mount(&self, path: &str, server_name: &str) -> Result<(), InvocationError> {
+        let shared_buf_raw = self
+            .get_buf_mut()
+            .ok_or(InvocationError::DataBufferNotSet)?;
+        let mut shared_buf =
+            unsafe { slice::from_raw_parts_mut(shared_buf_raw.0, shared_buf_raw.1) };
+        shared_buf = copy_terminated_rust_string_to_buffer(shared_buf, path)?;
+        copy_terminated_rust_string_to_buffer(shared_buf, server_name)?;
+
+        let mut msginfo = sel4::MessageInfoBuilder::default()
+            .label(SMOSInvocation::Mount as u64)
+            .length(0)
+            .build();
+
+        return sel4::with_ipc_buffer_mut(|ipc_buf| {
+            msginfo = self.ep().call(msginfo);
+            try_unpack_error(msginfo.label(), ipc_buf.msg_regs())?;
+            Ok(())
+        });
+    }
+
+    fn unmount(&self, path: &str) -> Result<(), InvocationError> {
+        let shared_buf_raw = self
+            .get_buf_mut()
+            .ok_or(InvocationError::DataBufferNotSet)?;
+        let shared_buf = unsafe { slice::from_raw_parts_mut(shared_buf_raw.0, shared_buf_raw.1) };
+        copy_terminated_rust_string_to_buffer(shared_buf, path)?;
+
+        let mut msginfo = sel4::MessageInfoBuilder::default()
+            .label(SMOSInvocation::Unmount as u64)
+            .length(0)
+            .build();
+
+        return sel4::with_ipc_buffer_mut(|ipc_buf| {
+            msginfo = self.ep().call(msginfo);
+            try_unpack_error(msginfo.label(), ipc_buf.msg_regs())?;
+            Ok(())
+        });
+    }
+
     fn conn_destroy<T: ClientConnection>(
         &self,
         conn: T,
@@ -331,6 +465,27 @@ pub trait RootServerInterface: ClientConnection {
         });
     }
 
+    fn page_unmap(
+        &self,
+        win_reg_hndl: &LocalHandle<WindowRegistrationHandle>,
+        view_offset: usize,
+    ) -> Result<(), InvocationError> {
+        let mut msginfo = sel4::MessageInfoBuilder::default()
+            .label(SMOSInvocation::PageUnmap as u64)
+            .length(2)
+            .build();
+
+        return sel4::with_ipc_buffer_mut(|ipc_buf| {
+            ipc_buf.msg_regs_mut()[0] = win_reg_hndl.idx.try_into().unwrap();
+            ipc_buf.msg_regs_mut()[1] = view_offset as u64;
+
+            msginfo = self.ep().call(msginfo);
+            try_unpack_error(msginfo.label(), ipc_buf.msg_regs())?;
+
+            Ok(())
+        });
+    }
+
     fn window_destroy(
         &self,
         hndl: HandleOrHandleCap<WindowHandle>,
@@ -424,43 +579,77 @@ pub trait RootServerInterface: ClientConnection {
         });
     }
 
+    /* path is resolved through the mount table. If it is not absolute, it is looked up in each
+    directory of the search path of the caller. env is a list of KEY=VALUE strings. If it is None,
+    the child inherits the environment of the caller. Each of the caps is copied into the cspace of
+    the child, which can look it up by name through its environment. The path, args, env and
+    cap names are passed through the shared buffer, and are sent to the root server in several
+    parts if they don't fit into it. Together they can't be larger than the stack of the child,
+    BufferTooLarge is returned if they are. */
     fn process_spawn(
         &self,
-        executable_name: &str,
-        fs_name: &str,
-        prio: u8,
+        path: &str,
+        sched: &SchedParams,
         argv: Option<&[&str]>,
+        env: Option<&[&str]>,
+        caps: Option<&[(&str, AbsoluteCPtr)]>,
     ) -> Result<LocalHandle<ProcessHandle>, InvocationError> {
         let mut msginfo = sel4::MessageInfoBuilder::default()
             .label(SMOSInvocation::ProcSpawn as u64)
-            .length(2)
+            .length(ProcessSpawnArgs::Length as usize)
             .build();
 
         let shared_buf_raw = self
             .get_buf_mut()
             .ok_or(InvocationError::DataBufferNotSet)?;
-        let mut shared_buf =
-            unsafe { slice::from_raw_parts_mut(shared_buf_raw.0, shared_buf_raw.1) };
-
-        /* Copy the executable name into the shared buffer */
-        shared_buf = copy_terminated_rust_string_to_buffer(shared_buf, executable_name)?;
+        let shared_buf = unsafe { slice::from_raw_parts_mut(shared_buf_raw.0, shared_buf_raw.1) };
+        let mut writer = ArgsWriter::new(self.ep(), shared_buf);
 
-        /* Copy the file server name into the shared buffer */
-        shared_buf = copy_terminated_rust_string_to_buffer(shared_buf, fs_name)?;
+        /* Copy the path of the executable into the shared buffer */
+        writer.write_str(path)?;
 
         /* Copy the args for the application into the shared buffer */
         if argv.is_some() {
             for arg in argv.unwrap() {
-                shared_buf = copy_terminated_rust_string_to_buffer(shared_buf, arg)?;
+                writer.write_str(arg)?;
+            }
+        }
+
+        /* Copy the environment for the application into the shared buffer */
+        if env.is_some() {
+            for var in env.unwrap() {
+                writer.write_str(var)?;
+            }
+        }
+
+        /* Each cap is described by its name followed by its slot in our cspace */
+        if caps.is_some() {
+            for (name, cap) in caps.unwrap() {
+                writer.write_str(name)?;
+                writer.write(&cap.path().bits().to_le_bytes())?;
             }
         }
 
         return sel4::with_ipc_buffer_mut(|ipc_buf| {
-            ipc_buf.msg_regs_mut()[0] = match argv {
+            ipc_buf.msg_regs_mut()[ProcessSpawnArgs::NumArgs as usize] = match argv {
+                None => 0,
+                Some(v) => v.len() as u64,
+            };
+            ipc_buf.msg_regs_mut()[ProcessSpawnArgs::Prio as usize] = sched.prio as u64;
+            ipc_buf.msg_regs_mut()[ProcessSpawnArgs::Mcp as usize] = sched.mcp as u64;
+            ipc_buf.msg_regs_mut()[ProcessSpawnArgs::Budget as usize] = sched.budget;
+            ipc_buf.msg_regs_mut()[ProcessSpawnArgs::Period as usize] = sched.period;
+            ipc_buf.msg_regs_mut()[ProcessSpawnArgs::ExtraRefills as usize] =
+                sched.extra_refills as u64;
+            ipc_buf.msg_regs_mut()[ProcessSpawnArgs::NumEnv as usize] = match env {
+                None => 0,
+                Some(v) => v.len() as u64,
+            };
+            ipc_buf.msg_regs_mut()[ProcessSpawnArgs::InheritEnv as usize] = env.is_none() as u64;
+            ipc_buf.msg_regs_mut()[ProcessSpawnArgs::NumCaps as usize] = match caps {
                 None => 0,
                 Some(v) => v.len() as u64,
             };
-            ipc_buf.msg_regs_mut()[1] = prio as u64;
             msginfo = self.ep().call(msginfo);
             try_unpack_error(msginfo.label(), ipc_buf.msg_regs())?;
 
@@ -468,7 +657,90 @@ pub trait RootServerInterface: ClientConnection {
         });
     }
 
-    fn process_wait(&self, hndl: LocalHandle<ProcessHandle>) -> Result<(), InvocationError> {
+    /* Replaces the program running in the calling process with the one at path, which is found
+    in the same way as for process_spawn. The process keeps its pid, its parent and its children,
+    but its windows, views, threads and other handles are destroyed. Each of the caps stays in the
+    same slot of the cspace and each of the handles keeps its index, and the new program can look
+    them up by name through its environment. env works the same way as for process_spawn, and
+    the same limit on the size of the path, args, env and names applies. This only returns if the
+    program could not be replaced. */
+    fn process_exec(
+        &self,
+        path: &str,
+        argv: Option<&[&str]>,
+        env: Option<&[&str]>,
+        caps: Option<&[(&str, AbsoluteCPtr)]>,
+        handles: Option<&[(&str, usize)]>,
+    ) -> Result<(), InvocationError> {
+        let mut msginfo = sel4::MessageInfoBuilder::default()
+            .label(SMOSInvocation::ProcExec as u64)
+            .length(ProcessExecArgs::Length as usize)
+            .build();
+
+        let shared_buf_raw = self
+            .get_buf_mut()
+            .ok_or(InvocationError::DataBufferNotSet)?;
+        let shared_buf = unsafe { slice::from_raw_parts_mut(shared_buf_raw.0, shared_buf_raw.1) };
+        let mut writer = ArgsWriter::new(self.ep(), shared_buf);
+
+        writer.write_str(path)?;
+
+        if argv.is_some() {
+            for arg in argv.unwrap() {
+                writer.write_str(arg)?;
+            }
+        }
+
+        if env.is_some() {
+            for var in env.unwrap() {
+                writer.write_str(var)?;
+            }
+        }
+
+        /* Caps and handles are described by their name followed by their slot or index */
+        let caps_iter = caps
+            .unwrap_or(&[])
+            .iter()
+            .map(|(name, cap)| (*name, cap.path().bits()));
+        let handles_iter = handles
+            .unwrap_or(&[])
+            .iter()
+            .map(|(name, idx)| (*name, *idx as u64));
+        for (name, val) in caps_iter.chain(handles_iter) {
+            writer.write_str(name)?;
+            writer.write(&val.to_le_bytes())?;
+        }
+
+        return sel4::with_ipc_buffer_mut(|ipc_buf| {
+            ipc_buf.msg_regs_mut()[ProcessExecArgs::NumArgs as usize] = match argv {
+                None => 0,
+                Some(v) => v.len() as u64,
+            };
+            ipc_buf.msg_regs_mut()[ProcessExecArgs::NumEnv as usize] = match env {
+                None => 0,
+                Some(v) => v.len() as u64,
+            };
+            ipc_buf.msg_regs_mut()[ProcessExecArgs::InheritEnv as usize] = env.is_none() as u64;
+            ipc_buf.msg_regs_mut()[ProcessExecArgs::NumCaps as usize] = match caps {
+                None => 0,
+                Some(v) => v.len() as u64,
+            };
+            ipc_buf.msg_regs_mut()[ProcessExecArgs::NumHandles as usize] = match handles {
+                None => 0,
+                Some(v) => v.len() as u64,
+            };
+            msginfo = self.ep().call(msginfo);
+            try_unpack_error(msginfo.label(), ipc_buf.msg_regs())?;
+
+            /* The root server never replies if the program was replaced */
+            Err(InvocationError::ServerError)
+        });
+    }
+
+    fn process_wait(
+        &self,
+        hndl: LocalHandle<ProcessHandle>,
+    ) -> Result<ExitStatus, InvocationError> {
         let mut msginfo = sel4::MessageInfoBuilder::default()
             .label(SMOSInvocation::ProcWait as u64)
             .length(1)
@@ -478,23 +750,24 @@ pub trait RootServerInterface: ClientConnection {
             ipc_buf.msg_regs_mut()[0] = hndl.idx as u64;
             msginfo = self.ep().call(msginfo);
             try_unpack_error(msginfo.label(), ipc_buf.msg_regs())?;
-            // @alwin: error code?
-            return Ok(());
-            // return Ok(try_unpack_error(ipc_buf.msg_regs()[0], ipc_buf.msg_regs[1..]));
+
+            return ExitStatus::from_raw(
+                ipc_buf.msg_regs()[ProcessWaitReturn::Reason as usize],
+                ipc_buf.msg_regs()[ProcessWaitReturn::Code as usize],
+                ipc_buf.msg_regs()[ProcessWaitReturn::Addr as usize],
+            )
+            .ok_or(InvocationError::ServerError);
         });
     }
 
-    fn process_exit(&self) -> Result<(), InvocationError> {
+    fn process_exit(&self, exit_code: usize) -> Result<(), InvocationError> {
         let msginfo = sel4::MessageInfoBuilder::default()
             .label(SMOSInvocation::ProcExit as u64)
+            .length(1)
             .build();
 
-        sel4::with_ipc_buffer_mut(|_ipc_buf| {
-            // @alwin: error code?
-            // ipc_buf.msg_regs_mut[0] = match err {
-            // Some(x) => x as u64,
-            // None => InvocationError::NoError as u64,
-            // };
+        sel4::with_ipc_buffer_mut(|ipc_buf| {
+            ipc_buf.msg_regs_mut()[0] = exit_code as u64;
             self.ep().call(msginfo)
         });
 
@@ -502,15 +775,274 @@ pub trait RootServerInterface: ClientConnection {
         unreachable!()
     }
 
-    fn load_complete(&self, entry_point: usize, sp: usize) -> Result<(), InvocationError> {
+    fn process_destroy(&self, hndl: LocalHandle<ProcessHandle>) -> Result<(), InvocationError> {
+        let mut msginfo = sel4::MessageInfoBuilder::default()
+            .label(SMOSInvocation::ProcDestroy as u64)
+            .length(1)
+            .build();
+
+        return sel4::with_ipc_buffer_mut(|ipc_buf| {
+            ipc_buf.msg_regs_mut()[0] = hndl.idx as u64;
+            msginfo = self.ep().call(msginfo);
+            try_unpack_error(msginfo.label(), ipc_buf.msg_regs())?;
+
+            Ok(())
+        });
+    }
+
+    /* Faults of the child are delivered to fault_ep instead of the root server. The fault
+    message is badged with the index of the process handle and the tid of the thread that
+    faulted, so decoding the badge as an SMOS entry type gives EntryType::Fault(ident), where
+    split_thread_ident(ident) is (hndl.idx, tid). Faults the handler can't resolve, such as page
+    faults, are passed back with process_fault_forward. The child is killed if the caller exits
+    while it is still handling the faults of the child. */
+    fn process_set_fault_handler(
+        &self,
+        hndl: LocalHandle<ProcessHandle>,
+        fault_ep: sel4::cap::Endpoint,
+    ) -> Result<(), InvocationError> {
+        let mut msginfo = sel4::MessageInfoBuilder::default()
+            .label(SMOSInvocation::ProcSetFaultHandler as u64)
+            .length(1)
+            .extra_caps(1)
+            .build();
+
+        return sel4::with_ipc_buffer_mut(|ipc_buf| {
+            ipc_buf.msg_regs_mut()[0] = hndl.idx as u64;
+            ipc_buf.caps_or_badges_mut()[0] = fault_ep.bits();
+            msginfo = self.ep().call(msginfo);
+            try_unpack_error(msginfo.label(), ipc_buf.msg_regs())?;
+
+            Ok(())
+        });
+    }
+
+    /* Hands a fault that the thread with the given tid of the child delivered to the fault handler
+    over to the root server, for faults such as page faults that only the root server can resolve.
+    The fault handler then replies to the fault, which makes the thread fault again, this time
+    into the root server. Later faults of the thread go to the fault handler again. */
+    fn process_fault_forward(
+        &self,
+        hndl: LocalHandle<ProcessHandle>,
+        tid: usize,
+    ) -> Result<(), InvocationError> {
+        let mut msginfo = sel4::MessageInfoBuilder::default()
+            .label(SMOSInvocation::ProcFaultForward as u64)
+            .length(ProcessFaultForwardArgs::Length as usize)
+            .build();
+
+        return sel4::with_ipc_buffer_mut(|ipc_buf| {
+            ipc_buf.msg_regs_mut()[ProcessFaultForwardArgs::Handle as usize] = hndl.idx as u64;
+            ipc_buf.msg_regs_mut()[ProcessFaultForwardArgs::Tid as usize] = tid as u64;
+            msginfo = self.ep().call(msginfo);
+            try_unpack_error(msginfo.label(), ipc_buf.msg_regs())?;
+
+            Ok(())
+        });
+    }
+
+    /* Budget and period are in microseconds */
+    fn process_set_sched(
+        &self,
+        hndl: LocalHandle<ProcessHandle>,
+        prio: u8,
+        budget: u64,
+        period: u64,
+    ) -> Result<(), InvocationError> {
+        let mut msginfo = sel4::MessageInfoBuilder::default()
+            .label(SMOSInvocation::ProcSetSched as u64)
+            .length(ProcessSetSchedArgs::Length as usize)
+            .build();
+
+        return sel4::with_ipc_buffer_mut(|ipc_buf| {
+            ipc_buf.msg_regs_mut()[ProcessSetSchedArgs::Handle as usize] = hndl.idx as u64;
+            ipc_buf.msg_regs_mut()[ProcessSetSchedArgs::Prio as usize] = prio as u64;
+            ipc_buf.msg_regs_mut()[ProcessSetSchedArgs::Budget as usize] = budget;
+            ipc_buf.msg_regs_mut()[ProcessSetSchedArgs::Period as usize] = period;
+            msginfo = self.ep().call(msginfo);
+            try_unpack_error(msginfo.label(), ipc_buf.msg_regs())?;
+
+            Ok(())
+        });
+    }
+
+    /* Fills pids with the pids of the caller and its descendants. Returns the total number of
+    them, which may be more than what fit in pids */
+    fn process_list(&self, pids: &mut [usize]) -> Result<usize, InvocationError> {
+        let mut msginfo = sel4::MessageInfoBuilder::default()
+            .label(SMOSInvocation::ProcList as u64)
+            .length(0)
+            .build();
+
+        let shared_buf_raw = self.get_buf().ok_or(InvocationError::DataBufferNotSet)?;
+        let shared_buf = unsafe { slice::from_raw_parts(shared_buf_raw.0, shared_buf_raw.1) };
+
+        return sel4::with_ipc_buffer_mut(|ipc_buf| {
+            msginfo = self.ep().call(msginfo);
+            try_unpack_error(msginfo.label(), ipc_buf.msg_regs())?;
+
+            let count = ipc_buf.msg_regs()[ProcessListReturn::Count as usize] as usize;
+            let entry_size = core::mem::size_of::<u64>();
+            for (i, pid) in pids.iter_mut().take(count).enumerate() {
+                let offset = i * entry_size;
+                if offset + entry_size > shared_buf.len() {
+                    break;
+                }
+                *pid =
+                    u64::from_le_bytes(shared_buf[offset..offset + entry_size].try_into().unwrap())
+                        as usize;
+            }
+
+            Ok(count)
+        });
+    }
+
+    /* Returns information about the caller or one of its descendants */
+    fn process_info(&self, pid: usize) -> Result<ProcessStat, InvocationError> {
+        let mut msginfo = sel4::MessageInfoBuilder::default()
+            .label(SMOSInvocation::ProcInfo as u64)
+            .length(1)
+            .build();
+
+        let shared_buf_raw = self.get_buf().ok_or(InvocationError::DataBufferNotSet)?;
+        let shared_buf = unsafe { slice::from_raw_parts(shared_buf_raw.0, shared_buf_raw.1) };
+
+        return sel4::with_ipc_buffer_mut(|ipc_buf| {
+            ipc_buf.msg_regs_mut()[0] = pid as u64;
+            msginfo = self.ep().call(msginfo);
+            try_unpack_error(msginfo.label(), ipc_buf.msg_regs())?;
+
+            /* The name of the process is written into the shared buffer */
+            let (name, _) =
+                rust_str_from_buffer(shared_buf).or(Err(InvocationError::ServerError))?;
+
+            Ok(ProcessStat::new(
+                pid,
+                ipc_buf.msg_regs()[ProcessInfoReturn::State as usize]
+                    .try_into()
+                    .or(Err(InvocationError::ServerError))?,
+                ipc_buf.msg_regs()[ProcessInfoReturn::Prio as usize] as u8,
+                if ipc_buf.msg_regs()[ProcessInfoReturn::HasParent as usize] != 0 {
+                    Some(ipc_buf.msg_regs()[ProcessInfoReturn::Parent as usize] as usize)
+                } else {
+                    None
+                },
+                ipc_buf.msg_regs()[ProcessInfoReturn::MemUsage as usize] as usize,
+                name,
+            ))
+        });
+    }
+
+    /* Collects the core file of a child that was killed by a fault. The core file is an ELF file
+    held in a read-only object of the root server. Returns a handle to the object along with the
+    size of the file, which may be smaller than the object. The core file can be collected before
+    or after waiting on the child. Each core file can only be collected once, and only the core
+    files of the last few children that faulted are kept. */
+    fn process_core_dump(
+        &self,
+        hndl: &LocalHandle<ProcessHandle>,
+    ) -> Result<(HandleOrHandleCap<ObjectHandle>, usize), InvocationError> {
+        let mut msginfo = sel4::MessageInfoBuilder::default()
+            .label(SMOSInvocation::ProcCoreDump as u64)
+            .length(1)
+            .build();
+
+        return sel4::with_ipc_buffer_mut(|ipc_buf| {
+            ipc_buf.msg_regs_mut()[0] = hndl.idx as u64;
+            msginfo = self.ep().call(msginfo);
+            try_unpack_error(msginfo.label(), ipc_buf.msg_regs())?;
+
+            if msginfo.length() != ProcessCoreDumpReturn::Length as usize {
+                return Err(InvocationError::ServerError);
+            }
+
+            Ok((
+                HandleOrHandleCap::new_handle(
+                    ipc_buf.msg_regs()[ProcessCoreDumpReturn::Handle as usize] as usize,
+                ),
+                ipc_buf.msg_regs()[ProcessCoreDumpReturn::Size as usize] as usize,
+            ))
+        });
+    }
+
+    /* Creates a new thread in the calling process that starts executing at entry on a fresh
+    stack. The root server endpoint of the thread is placed in rs_ep_slot, which must be a free
+    slot the caller has allocated in its own cspace. The slot can be freed once the thread has been
+    joined. The thread is started with arg, its tid, the address of its IPC buffer, the address of
+    its buffer for passing data to the root server and rs_ep_slot as its first five arguments.
+    Returns the tid of the new thread */
+    fn thread_create(
+        &self,
+        entry: usize,
+        arg: usize,
+        rs_ep_slot: usize,
+    ) -> Result<usize, InvocationError> {
+        let mut msginfo = sel4::MessageInfoBuilder::default()
+            .label(SMOSInvocation::ThreadCreate as u64)
+            .length(ThreadCreateArgs::Length as usize)
+            .build();
+
+        return sel4::with_ipc_buffer_mut(|ipc_buf| {
+            ipc_buf.msg_regs_mut()[ThreadCreateArgs::Entry as usize] = entry as u64;
+            ipc_buf.msg_regs_mut()[ThreadCreateArgs::Arg as usize] = arg as u64;
+            ipc_buf.msg_regs_mut()[ThreadCreateArgs::RSEPSlot as usize] = rs_ep_slot as u64;
+            msginfo = self.ep().call(msginfo);
+            try_unpack_error(msginfo.label(), ipc_buf.msg_regs())?;
+
+            Ok(ipc_buf.msg_regs()[0] as usize)
+        });
+    }
+
+    /* Exits the calling thread, which must not be the main thread. The root server works out which
+    thread is exiting from the endpoint it is invoked on, so this has to be called on the endpoint
+    the thread was started with. */
+    fn thread_exit(&self, exit_code: usize) -> Result<(), InvocationError> {
+        let mut msginfo = sel4::MessageInfoBuilder::default()
+            .label(SMOSInvocation::ThreadExit as u64)
+            .length(ThreadExitArgs::Length as usize)
+            .build();
+
+        return sel4::with_ipc_buffer_mut(|ipc_buf| {
+            ipc_buf.msg_regs_mut()[ThreadExitArgs::ExitCode as usize] = exit_code as u64;
+            msginfo = self.ep().call(msginfo);
+
+            /* The root server only replies if it couldn't make the thread exit */
+            try_unpack_error(msginfo.label(), ipc_buf.msg_regs())?;
+            unreachable!()
+        });
+    }
+
+    fn thread_join(&self, tid: usize) -> Result<usize, InvocationError> {
+        let mut msginfo = sel4::MessageInfoBuilder::default()
+            .label(SMOSInvocation::ThreadJoin as u64)
+            .length(1)
+            .build();
+
+        return sel4::with_ipc_buffer_mut(|ipc_buf| {
+            ipc_buf.msg_regs_mut()[0] = tid as u64;
+            msginfo = self.ep().call(msginfo);
+            try_unpack_error(msginfo.label(), ipc_buf.msg_regs())?;
+
+            Ok(ipc_buf.msg_regs()[0] as usize)
+        });
+    }
+
+    /* tls_base is the value the thread pointer of the process is started with */
+    fn load_complete(
+        &self,
+        entry_point: usize,
+        sp: usize,
+        tls_base: Option<usize>,
+    ) -> Result<(), InvocationError> {
         let mut msginfo = sel4::MessageInfoBuilder::default()
             .label(SMOSInvocation::LoadComplete as u64)
-            .length(2)
+            .length(3)
             .build();
 
         return sel4::with_ipc_buffer_mut(|ipc_buf| {
             ipc_buf.msg_regs_mut()[0] = entry_point as u64;
             ipc_buf.msg_regs_mut()[1] = sp as u64;
+            ipc_buf.msg_regs_mut()[2] = tls_base.unwrap_or(0) as u64;
             msginfo = self.ep().call(msginfo);
             try_unpack_error(msginfo.label(), ipc_buf.msg_regs())?;
 
@@ -813,6 +1345,53 @@ pub trait ObjectServerInterface: ClientConnection {
         });
     }
 
+    /* Creates a copy of an object. The copy shares frames with the original until one of them
+    is written to. */
+    fn obj_clone(
+        &self,
+        hndl: &HandleOrHandleCap<ObjectHandle>,
+        return_cap: Option<AbsoluteCPtr>,
+    ) -> Result<HandleOrHandleCap<ObjectHandle>, InvocationError> {
+        let mut msginfo_builder = sel4::MessageInfoBuilder::default()
+            .label(SMOSInvocation::ObjClone as u64)
+            .length(ObjCloneArgs::Length as usize);
+
+        return sel4::with_ipc_buffer_mut(|ipc_buf| {
+            match hndl {
+                HandleOrHandleCap::Handle(LocalHandle { idx, .. }) => {
+                    ipc_buf.msg_regs_mut()[ObjCloneArgs::Handle as usize] = *idx as u64;
+                }
+                HandleOrHandleCap::HandleCap(HandleCap { cptr, .. }) => {
+                    ipc_buf.caps_or_badges_mut()[0] = cptr.path().bits();
+                    ipc_buf.msg_regs_mut()[ObjCloneArgs::Handle as usize] = u64::MAX;
+                    msginfo_builder = msginfo_builder.extra_caps(1);
+                }
+            };
+
+            ipc_buf.msg_regs_mut()[ObjCloneArgs::ReturnCap as usize] = return_cap.is_some() as u64;
+            if return_cap.is_some() {
+                ipc_buf.set_recv_slot(&return_cap.unwrap());
+            }
+
+            let msginfo = self.ep().call(msginfo_builder.build());
+            try_unpack_error(msginfo.label(), ipc_buf.msg_regs())?;
+
+            if return_cap.is_some() {
+                if msginfo.extra_caps() != 1 || msginfo.caps_unwrapped() != 0 {
+                    return Err(InvocationError::ServerError);
+                }
+                return Ok(HandleOrHandleCap::new_handle_cap(return_cap.unwrap()));
+            } else {
+                if msginfo.length() != 1 {
+                    return Err(InvocationError::ServerError);
+                }
+                return Ok(HandleOrHandleCap::new_handle(
+                    ipc_buf.msg_regs()[0] as usize,
+                ));
+            }
+        });
+    }
+
     fn view(
         &self,
         win: &HandleOrHandleCap<WindowHandle>,
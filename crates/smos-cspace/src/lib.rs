@@ -24,6 +24,15 @@ impl SMOSUserCSpace {
         return Ok(index);
     }
 
+    /* Marks a slot that was populated by someone else as used */
+    pub fn reserve_slot(&mut self, slot: usize) -> Result<(), ()> {
+        if slot >= CSPACE_SIZE {
+            return Err(());
+        }
+        bf_set_bit(&mut self.bf, slot);
+        return Ok(());
+    }
+
     pub fn free_slot(&mut self, slot: usize) {
         if slot > CSPACE_SIZE {
             // @alwin: How are we doing logging in user processes?
@@ -2,13 +2,17 @@
 #![no_main]
 
 extern crate alloc;
+use alloc::format;
+use alloc::string::String;
 use alloc::vec::Vec;
 use byteorder::ByteOrder;
 use smos_common::connection::{ObjectServerConnection, RootServerConnection};
+use smos_common::init::SMOS_STACK_PAGES;
 use smos_common::local_handle::{
     HandleOrHandleCap, LocalHandle, ObjectHandle, ViewHandle, WindowHandle,
 };
 use smos_common::obj_attributes::ObjAttributes;
+use smos_common::reloc::relative_relocations;
 use smos_common::string::copy_terminated_rust_string_to_buffer;
 use smos_common::syscall::{NonRootServerInterface, ObjectServerInterface, RootServerInterface};
 use smos_common::util::{ROUND_DOWN, ROUND_UP};
@@ -19,6 +23,15 @@ use elf::ElfBytes;
 
 const SHARED_BUFFER_REGION: *mut u8 = 0xA0002000 as *mut u8;
 const ELF_BASE: *const u8 = 0xB000000 as *const u8;
+const TLS_REGION: *mut u8 = 0xA0003000 as *mut u8;
+
+/* On aarch64, the thread pointer points to a 16 byte control block which is immediately followed
+by the TLS block (suitably aligned) */
+const TLS_TCB_SIZE: usize = 16;
+
+/* Position-independent executables are loaded with their lowest address here. This is away from
+where the root server places a position-independent loader. */
+const PIE_BASE: u64 = 0x40000000;
 
 const PAGE_SIZE_4K: u64 = 4096;
 
@@ -50,26 +63,19 @@ struct SegmentData {
 // @alwin: How the heck am I adding argc and argv
 fn main(rs_conn: RootServerConnection, mut cspace: SMOSUserCSpace) -> sel4::Result<Never> {
     /* Expected arguments
-            0. File name to run
-            1. The name of the file server where this file is expected to be
+            0. The number of places the executable could be
+            1..2n. Pairs of the name of a file and the name of the file server it could be on
+            2n+1... The arguments of the executable
     */
 
-    /* Check that argc == 2 */
     let args: Vec<&str> = smos_runtime::args::args().collect();
-    assert!(args.len() >= 2);
-    sel4::debug_println!(
-        "Hello world! I am loading the executable {} from {}",
-        args[0],
-        args[1]
-    );
-
-    /* Set up connection to file server */
-    let fs_ep_slot = cspace
-        .alloc_slot()
-        .expect("Failed to allocate slot for FS connection");
-    let mut fs_conn = rs_conn
-        .conn_create::<ObjectServerConnection>(&cspace.to_absolute_cptr(fs_ep_slot), args[1])
-        .expect("Failed to connect to specified server"); // @alwin: This should come from args
+    assert!(args.len() >= 1);
+    let num_candidates: usize = args[0]
+        .parse()
+        .expect("Invalid number of executable locations");
+    assert!(args.len() >= 1 + 2 * num_candidates);
+    let candidates = &args[1..1 + 2 * num_candidates];
+    let exec_args = &args[1 + 2 * num_candidates..];
 
     /* Set up shared buffer with FS */
     let shared_buffer_win_hndl = rs_conn
@@ -98,15 +104,52 @@ fn main(rs_conn: RootServerConnection, mut cspace: SMOSUserCSpace) -> sel4::Resu
         )
         .expect("Failed to map shared buffer object");
 
-    /* Open connection to file server */
-    fs_conn
-        .conn_open(Some((shared_buf_obj.clone(), (SHARED_BUFFER_REGION, 4096))))
-        .expect("Failed to open connection to file server");
+    /* Try each of the places the executable could be until one of them has it */
+    let mut opened = None;
+    for candidate in candidates.chunks(2) {
+        let (file_name, server_name) = (candidate[0], candidate[1]);
+
+        /* Set up connection to file server */
+        let fs_ep_slot = cspace
+            .alloc_slot()
+            .expect("Failed to allocate slot for FS connection");
+        let mut fs_conn = match rs_conn.conn_create::<ObjectServerConnection>(
+            &cspace.to_absolute_cptr(fs_ep_slot),
+            server_name,
+        ) {
+            Ok(conn) => conn,
+            Err(_) => {
+                cspace.free_slot(fs_ep_slot);
+                continue;
+            }
+        };
+
+        /* Open connection to file server */
+        fs_conn
+            .conn_open(Some((shared_buf_obj.clone(), (SHARED_BUFFER_REGION, 4096))))
+            .expect("Failed to open connection to file server");
+
+        /* Open the ELF file*/
+        match fs_conn.obj_open(file_name, sel4::CapRights::read_only(), None) {
+            Ok(file_hndl) => {
+                sel4::debug_println!(
+                    "Hello world! I am loading the executable {} from {}",
+                    file_name,
+                    server_name
+                );
+                opened = Some((fs_conn, file_hndl));
+                break;
+            }
+            Err(_) => {
+                fs_conn.conn_close().expect("Failed to close connection");
+                rs_conn
+                    .conn_destroy(fs_conn, &mut cspace)
+                    .expect("Failed to destroy connection");
+            }
+        }
+    }
 
-    /* Open the ELF file*/
-    let file_hndl = fs_conn
-        .obj_open(args[0], sel4::CapRights::read_only(), None)
-        .expect("Failed to open the file");
+    let (mut fs_conn, file_hndl) = opened.expect("Could not find the executable");
     let file_size = fs_conn
         .obj_stat(&file_hndl)
         .expect("Failed to get stat")
@@ -141,13 +184,25 @@ fn main(rs_conn: RootServerConnection, mut cspace: SMOSUserCSpace) -> sel4::Resu
     let elf =
         ElfBytes::<elf::endian::AnyEndian>::minimal_parse(elf_bytes).expect("Invalid elf file");
 
+    // @alwin: The base should be randomised once we have a source of entropy
+    let bias: u64 = match elf.ehdr.e_type {
+        elf::abi::ET_EXEC => 0,
+        elf::abi::ET_DYN => PIE_BASE,
+        _ => panic!("Unsupported ELF file type"),
+    };
+
     let mut segment_mappings: Vec<SegmentData> = Vec::new();
     /* @alwin: The C impl does some syscall table stuff */
     for segment in elf.segments().expect("Couldn't get segments").iter() {
-        if segment.p_type != elf::abi::PT_LOAD && segment.p_type != elf::abi::PT_TLS {
+        /* The initial TLS image lives inside a PT_LOAD segment, so there is nothing to map for
+        PT_TLS segments here */
+        if segment.p_type != elf::abi::PT_LOAD {
             continue;
         }
 
+        let mut segment = segment;
+        segment.p_vaddr += bias;
+
         if segment.p_filesz > segment.p_memsz {
             panic!("Invalid ELF file");
         }
@@ -230,6 +285,20 @@ fn main(rs_conn: RootServerConnection, mut cspace: SMOSUserCSpace) -> sel4::Resu
         );
     }
 
+    /* Relocate position-independent executables. This has to happen before the segments are
+    remapped with their real permissions, as they might not be writable. */
+    if elf.ehdr.e_type == elf::abi::ET_DYN {
+        let relocations = relative_relocations(&elf).expect("Failed to relocate executable");
+        for (offset, addend) in relocations {
+            let vaddr = (bias + offset) as usize;
+            assert!(vaddr % 8 == 0);
+            byteorder::LittleEndian::write_u64(
+                unsafe { core::slice::from_raw_parts_mut(vaddr as *mut u8, 8) },
+                bias + addend,
+            );
+        }
+    }
+
     /* Remap all the views the correct permissions */
     for segment in segment_mappings {
         if segment.rights == sel4::CapRights::all() {
@@ -253,10 +322,82 @@ fn main(rs_conn: RootServerConnection, mut cspace: SMOSUserCSpace) -> sel4::Resu
             .expect("Failed to remap ELF region with correct permissions");
     }
 
+    /* Set up the TLS block for the initial thread, which the runtime of the program keeps using */
+    let mut tls_base = None;
+    if let Some(tls) = elf
+        .segments()
+        .expect("Couldn't get segments")
+        .iter()
+        .find(|x| x.p_type == elf::abi::PT_TLS)
+    {
+        if tls.p_filesz > tls.p_memsz || tls.p_align > PAGE_SIZE_4K {
+            panic!("Invalid TLS segment");
+        }
+
+        let align = core::cmp::max(tls.p_align as usize, 1);
+        let tls_offset = ROUND_UP(TLS_TCB_SIZE, align.trailing_zeros() as usize);
+        let tls_size = ROUND_UP(
+            tls_offset + tls.p_memsz as usize,
+            sel4_sys::seL4_PageBits as usize,
+        );
+
+        let tls_win_hndl = rs_conn
+            .window_create(TLS_REGION as usize, tls_size, None)
+            .expect("Could not make TLS window");
+        let tls_obj_hndl = rs_conn
+            .obj_create(
+                None,
+                tls_size,
+                sel4::CapRights::all(),
+                ObjAttributes::DEFAULT,
+                None,
+            )
+            .expect("Could not make TLS object");
+        rs_conn
+            .view(
+                &tls_win_hndl,
+                &tls_obj_hndl,
+                0,
+                0,
+                tls_size,
+                sel4::CapRights::all(),
+            )
+            .expect("Could not make TLS view");
+
+        /* Copy in .tdata and zero out .tbss */
+        let tls_block = unsafe {
+            core::slice::from_raw_parts_mut(TLS_REGION.add(tls_offset), tls.p_memsz as usize)
+        };
+        tls_block[..(tls.p_filesz as usize)].copy_from_slice(
+            &elf.segment_data(&tls).expect("Could not get TLS data")[..(tls.p_filesz as usize)],
+        );
+        tls_block[(tls.p_filesz as usize)..].fill(0);
+
+        tls_base = Some(TLS_REGION as usize);
+    }
+
+    /* Pass our own environment, which the root server set up for the application, on to it */
+    let vars: Vec<String> = smos_runtime::env::vars()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+
+    /* The root server only starts the loader if the environment and args fit on a stack of the
+    same size as the one created here, but make sure nothing is written below it anyway */
+    let strings: usize = vars
+        .iter()
+        .map(|x| x.len() + 1)
+        .chain(exec_args.iter().map(|x| x.len() + 1))
+        .sum();
+    if strings + 2 * 7 + (vars.len() + 4 + exec_args.len() + 2) * 8 + 4
+        > SMOS_STACK_PAGES * PAGE_SIZE_4K as usize
+    {
+        panic!("The environment and args do not fit on the stack");
+    }
+
     /* Create a stack */
     // @alwin: Put this stuff somewhere else
     const STACK_TOP: usize = 0xA0000000;
-    const STACK_PAGES: usize = 100;
+    const STACK_PAGES: usize = SMOS_STACK_PAGES;
     let stack_win_hndl = rs_conn
         .window_create(
             STACK_TOP - STACK_PAGES * PAGE_SIZE_4K as usize,
@@ -288,6 +429,20 @@ fn main(rs_conn: RootServerConnection, mut cspace: SMOSUserCSpace) -> sel4::Resu
     let mut argv: Vec<u64> = Vec::new();
     let mut envp: Vec<u64> = Vec::new();
 
+    let mut env_ptrs: Vec<u64> = Vec::new();
+    for var in &vars {
+        curr_sp = unsafe { curr_sp.sub(var.as_bytes().len() + 1) };
+        env_ptrs.push(curr_sp as u64);
+        copy_terminated_rust_string_to_buffer(
+            unsafe { core::slice::from_raw_parts_mut(curr_sp, var.as_bytes().len() + 1) },
+            var,
+        )
+        .expect("Failed to copy environment variable to stack");
+    }
+
+    /* Pad to word alignment */
+    curr_sp = unsafe { curr_sp.sub(curr_sp as usize % 8) };
+
     let envp_ptr = {
         /* Write STACK_TOP */
         curr_sp = unsafe { curr_sp.sub(8) };
@@ -312,6 +467,9 @@ fn main(rs_conn: RootServerConnection, mut cspace: SMOSUserCSpace) -> sel4::Resu
         );
         envp.push(curr_sp as u64);
 
+        /* The environment strings come after the fixed entries */
+        envp.extend(env_ptrs);
+
         /* Add null terminator to envp */
         envp.push(0);
 
@@ -324,9 +482,9 @@ fn main(rs_conn: RootServerConnection, mut cspace: SMOSUserCSpace) -> sel4::Resu
         curr_sp
     };
 
-    let argv_ptr = if args[2..].len() > 0 {
+    let argv_ptr = if exec_args.len() > 0 {
         /* Copy args onto the stack */
-        for arg in &args[2..] {
+        for arg in exec_args {
             curr_sp = unsafe { curr_sp.sub(arg.as_bytes().len() + 1) };
             argv.push(curr_sp as u64);
             copy_terminated_rust_string_to_buffer(
@@ -369,7 +527,7 @@ fn main(rs_conn: RootServerConnection, mut cspace: SMOSUserCSpace) -> sel4::Resu
     });
 
     /* Get the ELF entrypoint */
-    let start_vaddr = elf.ehdr.e_entry;
+    let start_vaddr = elf.ehdr.e_entry + bias;
 
     /* Clean up the ELF file */
     // @alwin: Closing the object might gc any views that are associated with it?
@@ -411,7 +569,7 @@ fn main(rs_conn: RootServerConnection, mut cspace: SMOSUserCSpace) -> sel4::Resu
 
     /* Jump to the real executable */
     rs_conn
-        .load_complete(start_vaddr as usize, curr_sp as usize)
+        .load_complete(start_vaddr as usize, curr_sp as usize, tls_base)
         .expect("Failed to complete load");
 
     unreachable!()
@@ -5,18 +5,13 @@ use smos_common::string::rust_str_from_buffer;
 static mut args_inner: Option<ArgsInner> = None;
 
 pub unsafe fn init_args(argc: usize, argv: *const u8) {
-    let mut args_inner_tmp = ArgsInner {
-        inner: [0; 32], // @alwin: HACK
+    /* argv points into the stack, which stays around for the lifetime of the process, so the
+    pointers to the args can be read out of it lazily */
+    args_inner = Some(ArgsInner {
+        argv: argv,
         size: argc,
         index: 0,
-    };
-    if argc > 0 {
-        LittleEndian::read_u64_into(
-            core::slice::from_raw_parts(argv, argc * 8),
-            &mut args_inner_tmp.inner[0..argc],
-        );
-    }
-    args_inner = Some(args_inner_tmp);
+    });
 }
 
 pub fn args() -> Args {
@@ -46,10 +41,13 @@ impl<'a> Iterator for ArgsInner {
 
     fn next(&mut self) -> Option<&'static str> {
         if self.index < self.size {
+            let arg_ptr = LittleEndian::read_u64(unsafe {
+                core::slice::from_raw_parts(self.argv.add(self.index * 8), 8)
+            });
             let slice = unsafe {
                 core::slice::from_raw_parts(
-                    self.inner[self.index] as *const u8,
-                    crate::env::stack_top() - self.inner[self.index] as usize,
+                    arg_ptr as *const u8,
+                    crate::env::stack_top() - arg_ptr as usize,
                 )
             };
             let result = Some(rust_str_from_buffer(slice).ok()?.0);
@@ -67,7 +65,7 @@ impl<'a> Iterator for ArgsInner {
 
 #[derive(Debug, Copy, Clone)]
 struct ArgsInner {
-    inner: [u64; 32],
+    argv: *const u8,
     size: usize,
     index: usize,
 }
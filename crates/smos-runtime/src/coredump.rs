@@ -0,0 +1,172 @@
+use crate::env;
+use smos_common::connection::{ObjectServerConnection, RootServerConnection};
+use smos_common::error::InvocationError;
+use smos_common::init::SMOS_CORE_SERVER_ENV;
+use smos_common::local_handle::{
+    HandleOrHandleCap, LocalHandle, ObjectHandle, ProcessHandle, WindowHandle,
+};
+use smos_common::obj_attributes::ObjAttributes;
+use smos_common::syscall::{ObjectServerInterface, RootServerInterface};
+use smos_common::util::ROUND_UP;
+use smos_cspace::SMOSUserCSpace;
+
+/* The core file is copied to the object server this many bytes at a time */
+pub const CHUNK_SIZE: usize = 0x10000;
+
+/* The name of the object server that core files should be written to, if one was configured */
+pub fn server() -> Option<&'static str> {
+    return env::var(SMOS_CORE_SERVER_ENV);
+}
+
+fn copy_chunks(
+    rs_conn: &RootServerConnection,
+    fs_conn: &ObjectServerConnection,
+    core_hndl: &HandleOrHandleCap<ObjectHandle>,
+    file_hndl: &HandleOrHandleCap<ObjectHandle>,
+    core_win: &HandleOrHandleCap<WindowHandle>,
+    file_win: &HandleOrHandleCap<WindowHandle>,
+    scratch: usize,
+    size: usize,
+) -> Result<(), InvocationError> {
+    for offset in (0..size).step_by(CHUNK_SIZE) {
+        let len = usize::min(CHUNK_SIZE, size - offset);
+
+        let core_view = rs_conn.view(
+            core_win,
+            core_hndl,
+            0,
+            offset,
+            len,
+            sel4::CapRights::read_only(),
+        )?;
+        let file_view = match fs_conn.view(
+            file_win,
+            file_hndl,
+            0,
+            offset,
+            len,
+            sel4::CapRights::read_write(),
+        ) {
+            Ok(x) => x,
+            Err(e) => {
+                let _ = rs_conn.unview(core_view);
+                return Err(e);
+            }
+        };
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                scratch as *const u8,
+                (scratch + CHUNK_SIZE) as *mut u8,
+                len,
+            );
+        }
+
+        let file_ret = fs_conn.unview(file_view);
+        let core_ret = rs_conn.unview(core_view);
+        file_ret.and(core_ret)?;
+    }
+
+    return Ok(());
+}
+
+/* Sets up the windows the core file is copied through and copies it into the file */
+fn copy_to_file(
+    rs_conn: &RootServerConnection,
+    fs_conn: &ObjectServerConnection,
+    cspace: &mut SMOSUserCSpace,
+    core_hndl: &HandleOrHandleCap<ObjectHandle>,
+    file_hndl: &HandleOrHandleCap<ObjectHandle>,
+    scratch: usize,
+    size: usize,
+) -> Result<(), InvocationError> {
+    let core_win = rs_conn.window_create(scratch, CHUNK_SIZE, None)?;
+
+    /* Views of objects on other servers need a handle cap to the window */
+    let file_win_slot = match cspace.alloc_slot() {
+        Ok(x) => x,
+        Err(_) => {
+            let _ = rs_conn.window_destroy(core_win, cspace);
+            return Err(InvocationError::InsufficientResources);
+        }
+    };
+    let file_win = match rs_conn.window_create(
+        scratch + CHUNK_SIZE,
+        CHUNK_SIZE,
+        Some(cspace.to_absolute_cptr(file_win_slot)),
+    ) {
+        Ok(x) => x,
+        Err(e) => {
+            cspace.free_slot(file_win_slot);
+            let _ = rs_conn.window_destroy(core_win, cspace);
+            return Err(e);
+        }
+    };
+
+    let ret = copy_chunks(
+        rs_conn, fs_conn, core_hndl, file_hndl, &core_win, &file_win, scratch, size,
+    );
+
+    /* Everything is torn down even if something fails, and the first error is the one returned */
+    let file_win_ret = rs_conn.window_destroy(file_win, cspace);
+    let core_win_ret = rs_conn.window_destroy(core_win, cspace);
+    return ret.and(file_win_ret).and(core_win_ret);
+}
+
+/* Writes the core file of child, which must have been killed by a fault and not collected yet, to
+a new object called name on the object server behind fs_conn. See write() for how it is copied.
+Returns the size of the core file. The core file can't be collected again afterwards, even if this
+fails. */
+pub fn save(
+    rs_conn: &RootServerConnection,
+    fs_conn: &ObjectServerConnection,
+    cspace: &mut SMOSUserCSpace,
+    child: &LocalHandle<ProcessHandle>,
+    name: &str,
+    scratch: usize,
+) -> Result<usize, InvocationError> {
+    let (core_hndl, size) = rs_conn.process_core_dump(child)?;
+    return write(rs_conn, fs_conn, cspace, core_hndl, size, name, scratch).map(|_| size);
+}
+
+/* Writes a core file that was already collected with process_core_dump to a new object called name
+on the object server behind fs_conn, and destroys the object holding the core file, even if this
+fails. The contents are copied through two windows starting at scratch, so the 2 * CHUNK_SIZE bytes
+from there must not be in use.
+@alwin: The object is a multiple of the page size, so the server doesn't know where the file ends */
+pub fn write(
+    rs_conn: &RootServerConnection,
+    fs_conn: &ObjectServerConnection,
+    cspace: &mut SMOSUserCSpace,
+    core_hndl: HandleOrHandleCap<ObjectHandle>,
+    size: usize,
+    name: &str,
+    scratch: usize,
+) -> Result<(), InvocationError> {
+    let obj_size = ROUND_UP(size, sel4_sys::seL4_PageBits as usize);
+
+    let ret = match fs_conn.obj_create(
+        Some(name),
+        obj_size,
+        sel4::CapRights::read_write(),
+        ObjAttributes::DEFAULT,
+        None,
+    ) {
+        Ok(file_hndl) => {
+            /* A partially written file is of no use, so it is destroyed */
+            match copy_to_file(
+                rs_conn, fs_conn, cspace, &core_hndl, &file_hndl, scratch, obj_size,
+            ) {
+                Ok(()) => fs_conn.obj_close(file_hndl),
+                Err(e) => {
+                    let _ = fs_conn.obj_destroy(file_hndl, cspace);
+                    Err(e)
+                }
+            }
+        }
+        Err(e) => Err(e),
+    };
+
+    let core_ret = rs_conn.obj_destroy(core_hndl, cspace);
+    return ret.and(core_ret);
+}
@@ -1,6 +1,6 @@
 use crate::args::init_args;
 use crate::env;
-use core::arch::global_asm;
+use core::arch::{asm, global_asm};
 use core::panic::UnwindSafe;
 use core::ptr;
 use linked_list_allocator::LockedHeap;
@@ -44,6 +44,15 @@ extern "Rust" {
 
 sel4_panicking_env::register_debug_put_char!(sel4::debug_put_char);
 
+/* The loader sets up the TLS block of the initial thread of programs with a PT_TLS segment and passes
+it in TPIDR_EL0, which is zero otherwise. The block is laid out the way sel4_runtime_common lays out
+the one it makes on the stack. */
+fn has_loader_tls_block() -> bool {
+    let tp: usize;
+    unsafe { asm!("mrs {}, tpidr_el0", out(reg) tp) };
+    return tp != 0;
+}
+
 #[no_mangle]
 unsafe extern "C" fn sel4_runtime_rust_entry(argc: u32, argv: *const u8, envp: *const u8) -> ! {
     fn cont_fn(_cont_arg: *mut sel4_runtime_common::ContArg) -> ! {
@@ -52,6 +61,13 @@ unsafe extern "C" fn sel4_runtime_rust_entry(argc: u32, argv: *const u8, envp: *
 
     unsafe { init_args(argc as usize, argv) };
     unsafe { env::init_env(envp) };
+
+    /* Programs started by the loader keep the TLS block it set up for them, so that it isn't
+    wasted. Everything else, including the loader itself, gets one on the stack. */
+    if has_loader_tls_block() {
+        cont_fn(ptr::null_mut())
+    }
+
     sel4_runtime_common::initialize_tls_on_stack_and_continue(cont_fn, ptr::null_mut())
 }
 
@@ -90,6 +106,13 @@ pub fn run_main<T>(f: impl FnOnce(RootServerConnection, SMOSUserCSpace) -> T + U
     slot = cspace.alloc_slot().expect("Failed to allocate RS ep slot");
     assert!(slot == SMOS_CNodeSelf as usize);
 
+    // Reserve the slots of any caps the process was started with
+    for (name, cap) in env::caps() {
+        cspace
+            .reserve_slot(cap.bits().try_into().unwrap())
+            .unwrap_or_else(|_| panic!("Failed to reserve slot for cap {}", name));
+    }
+
     unsafe {
         ALLOCATOR.lock().init(HEAP.as_mut_ptr(), HEAP.len());
     }
@@ -1,96 +1,132 @@
 use byteorder::{ByteOrder, LittleEndian};
+use smos_common::init::{SMOS_CAP_ENV_PREFIX, SMOS_HANDLE_ENV_PREFIX};
+use smos_common::string::rust_str_from_buffer;
 
+/* The first entries of envp point to words describing the address space of the process. Any
+entries after these point to KEY=VALUE strings. */
 const ENVP_STACK_TOP: usize = 0;
 const ENVP_IPC_BUFFER: usize = 1;
 const ENVP_RS_SHARED_BUF: usize = 2;
 const ENVP_MIN_SIZE: usize = 3;
 
 #[derive(Debug, Copy, Clone)]
-struct EnvVarsInner {
-    inner: [u64; 32],
+struct EnvInner {
+    envp: *const u8,
     size: usize,
-    index: usize,
-}
-
-pub struct EnvVars {
-    inner: EnvVarsInner,
 }
 
 #[allow(non_upper_case_globals)]
-static mut env_inner: Option<EnvVarsInner> = None;
+static mut env_inner: Option<EnvInner> = None;
+
+unsafe fn envp_entry(envp: *const u8, index: usize) -> u64 {
+    return LittleEndian::read_u64(core::slice::from_raw_parts(envp.add(index * 8), 8));
+}
 
 pub unsafe fn init_env(envp: *const u8) {
-    let mut env_inner_tmp = EnvVarsInner {
-        inner: [0; 32], // @alwin: HACK,
-        size: 0,
-        index: 0,
-    };
-
-    // @alwin: HACK
-    for i in 0..32 {
-        LittleEndian::read_u64_into(
-            core::slice::from_raw_parts(envp.add(i * 8), 8),
-            &mut env_inner_tmp.inner[i..i + 1],
-        );
-        if env_inner_tmp.inner[i] == 0 {
-            env_inner_tmp.size = i;
-            break;
-        }
+    /* envp lives on the stack for the lifetime of the process, so it can be read lazily */
+    let mut size = 0;
+    while envp_entry(envp, size) != 0 {
+        size += 1;
     }
 
-    if env_inner_tmp.size < ENVP_MIN_SIZE {
+    if size < ENVP_MIN_SIZE {
         panic!("Recieved a corrupted envp");
     }
 
-    env_inner = Some(env_inner_tmp);
+    env_inner = Some(EnvInner {
+        envp: envp,
+        size: size,
+    });
 }
 
-pub fn env_vars() -> EnvVars {
-    EnvVars {
-        inner: unsafe { env_inner.unwrap() },
+fn aux_word(index: usize) -> usize {
+    unsafe {
+        let inner = env_inner.unwrap();
+        return *(envp_entry(inner.envp, index) as *const u64) as usize;
     }
 }
 
 pub fn stack_top() -> usize {
-    unsafe { env_inner.unwrap().nth(ENVP_STACK_TOP).unwrap() as usize }
+    aux_word(ENVP_STACK_TOP)
 }
 
 pub fn rs_shared_buf() -> usize {
-    unsafe { env_inner.unwrap().nth(ENVP_RS_SHARED_BUF).unwrap() as usize }
+    aux_word(ENVP_RS_SHARED_BUF)
 }
 
 pub fn ipc_buffer() -> usize {
-    unsafe { env_inner.unwrap().nth(ENVP_IPC_BUFFER).unwrap() as usize }
+    aux_word(ENVP_IPC_BUFFER)
 }
 
-impl Iterator for EnvVars {
-    // @alwin: This should maybe eventually be strings
-    type Item = u64;
-
-    fn next(&mut self) -> Option<u64> {
-        self.inner.next()
+/* Returns an iterator over the (KEY, VALUE) pairs in the environment of the process */
+pub fn vars() -> Vars {
+    Vars {
+        inner: unsafe { env_inner.unwrap() },
+        index: ENVP_MIN_SIZE,
     }
+}
 
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        self.inner.size_hint()
-    }
+/* Looks up the value of the environment variable called key */
+pub fn var(key: &str) -> Option<&'static str> {
+    vars().find(|(k, _)| *k == key).map(|(_, v)| v)
+}
+
+/* Returns an iterator over the (NAME, CAP) pairs of the caps the process was started with */
+pub fn caps() -> impl Iterator<Item = (&'static str, sel4::CPtr)> {
+    vars().filter_map(|(k, v)| {
+        let name = k.strip_prefix(SMOS_CAP_ENV_PREFIX)?;
+        let slot = v.parse::<u64>().ok()?;
+        Some((name, sel4::CPtr::from_bits(slot)))
+    })
+}
+
+/* Looks up the cap the process was started with called name */
+pub fn cap(name: &str) -> Option<sel4::CPtr> {
+    caps().find(|(n, _)| *n == name).map(|(_, c)| c)
 }
 
-impl Iterator for EnvVarsInner {
-    // @alwin: This should maybe eventually be strings
-    type Item = u64;
+/* Returns an iterator over the (NAME, INDEX) pairs of the handles that were kept across an exec */
+pub fn handles() -> impl Iterator<Item = (&'static str, usize)> {
+    vars().filter_map(|(k, v)| {
+        let name = k.strip_prefix(SMOS_HANDLE_ENV_PREFIX)?;
+        let idx = v.parse::<usize>().ok()?;
+        Some((name, idx))
+    })
+}
 
-    fn next(&mut self) -> Option<u64> {
-        if self.index < self.size {
-            let res = unsafe { *(self.inner[self.index] as *const u64) };
+/* Looks up the index of the handle called name that was kept across an exec */
+pub fn handle(name: &str) -> Option<usize> {
+    handles().find(|(n, _)| *n == name).map(|(_, i)| i)
+}
+
+pub struct Vars {
+    inner: EnvInner,
+    index: usize,
+}
+
+impl Iterator for Vars {
+    type Item = (&'static str, &'static str);
+
+    fn next(&mut self) -> Option<(&'static str, &'static str)> {
+        while self.index < self.inner.size {
+            let var_ptr = unsafe { envp_entry(self.inner.envp, self.index) };
             self.index += 1;
-            Some(res)
-        } else {
-            None
+
+            let slice = unsafe {
+                core::slice::from_raw_parts(var_ptr as *const u8, stack_top() - var_ptr as usize)
+            };
+
+            /* Skip over anything that doesn't look like KEY=VALUE */
+            match rust_str_from_buffer(slice).map(|(x, _)| x.split_once('=')) {
+                Ok(Some(x)) => return Some(x),
+                _ => continue,
+            }
         }
+
+        None
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        (self.size - self.index, Some(self.size - self.index))
+        (0, Some(self.inner.size - self.index))
     }
 }
@@ -1,10 +1,14 @@
 #![no_std]
 
+extern crate alloc;
+
 use core::fmt;
 
 pub mod args;
+pub mod coredump;
 mod entry;
 pub mod env;
+pub mod thread;
 
 pub use entry::run_main;
 pub use smos_macros::smos_declare_main;
@@ -0,0 +1,115 @@
+use alloc::boxed::Box;
+use smos_common::client_connection::ClientConnection;
+use smos_common::connection::RootServerConnection;
+use smos_common::error::InvocationError;
+use smos_common::local_handle::{ConnectionHandle, LocalHandle};
+use smos_common::syscall::RootServerInterface;
+use smos_cspace::SMOSUserCSpace;
+
+type ThreadFn = Box<dyn FnOnce(&RootServerConnection) -> usize>;
+
+/* Arguments the root server starts a new thread with */
+struct ThreadStart {
+    f: *mut ThreadFn,
+    ipc_buffer: usize,
+    rs_shared_buf: usize,
+    rs_ep: usize,
+}
+
+pub struct JoinHandle {
+    tid: usize,
+    rs_ep: usize,
+}
+
+impl JoinHandle {
+    pub fn tid(&self) -> usize {
+        return self.tid;
+    }
+
+    /* Blocks until the thread exits and returns the value returned by its closure. The slot of the
+    root server endpoint of the thread is freed once the thread is gone. */
+    pub fn join(
+        self,
+        rs_conn: &RootServerConnection,
+        cspace: &mut SMOSUserCSpace,
+    ) -> Result<usize, InvocationError> {
+        let exit_code = rs_conn.thread_join(self.tid)?;
+        cspace.free_slot(self.rs_ep);
+        return Ok(exit_code);
+    }
+}
+
+/* Runs f in a new thread of this process. The thread exits with the value returned by f. f is
+given a connection to the root server of its own, which is the one the thread should use, as the
+root server tells threads apart by the endpoint they invoke it on. */
+pub fn spawn<F>(
+    rs_conn: &RootServerConnection,
+    cspace: &mut SMOSUserCSpace,
+    f: F,
+) -> Result<JoinHandle, InvocationError>
+where
+    F: FnOnce(&RootServerConnection) -> usize + Send + 'static,
+{
+    let rs_ep = cspace
+        .alloc_slot()
+        .or(Err(InvocationError::InsufficientResources))?;
+    let thread_fn: *mut ThreadFn = Box::into_raw(Box::new(Box::new(f)));
+
+    match rs_conn.thread_create(thread_entry as usize, thread_fn as usize, rs_ep) {
+        Ok(tid) => {
+            return Ok(JoinHandle {
+                tid: tid,
+                rs_ep: rs_ep,
+            })
+        }
+        Err(e) => {
+            drop(unsafe { Box::from_raw(thread_fn) });
+            cspace.free_slot(rs_ep);
+            return Err(e);
+        }
+    }
+}
+
+extern "C" fn thread_entry(
+    arg: usize,
+    _tid: usize,
+    ipc_buffer: usize,
+    rs_shared_buf: usize,
+    rs_ep: usize,
+) -> ! {
+    let mut start = ThreadStart {
+        f: arg as *mut ThreadFn,
+        ipc_buffer: ipc_buffer,
+        rs_shared_buf: rs_shared_buf,
+        rs_ep: rs_ep,
+    };
+
+    unsafe {
+        sel4_runtime_common::initialize_tls_on_stack_and_continue(
+            thread_cont,
+            &mut start as *mut ThreadStart as *mut sel4_runtime_common::ContArg,
+        )
+    }
+}
+
+fn thread_cont(cont_arg: *mut sel4_runtime_common::ContArg) -> ! {
+    let start = unsafe { &*(cont_arg as *const ThreadStart) };
+
+    unsafe {
+        ::sel4::set_ipc_buffer((start.ipc_buffer as *mut sel4::IpcBuffer).as_mut().unwrap());
+    }
+
+    let rs_conn = RootServerConnection::new(
+        sel4::CPtr::from_bits(start.rs_ep as u64).cast::<sel4::cap_type::Endpoint>(),
+        LocalHandle::<ConnectionHandle>::new(0),
+        Some((start.rs_shared_buf as *mut u8, 4096)),
+    );
+
+    let f = unsafe { Box::from_raw(start.f) };
+    let exit_code = f(&rs_conn);
+
+    rs_conn
+        .thread_exit(exit_code)
+        .expect("Failed to exit thread");
+    unreachable!()
+}
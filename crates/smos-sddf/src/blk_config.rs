@@ -11,6 +11,71 @@ pub const SDDF_BLK_MAGIC: [c_char; SDDF_BLK_MAGIC_LEN] = [
     0x2,
 ];
 
+pub const BLK_QUEUE_SIZE: usize = 0x1000;
+/* A request takes up 32 bytes, so this many fit into a queue along with its head and tail */
+pub const BLK_QUEUE_CAPACITY: u16 = 64;
+/* Requests and storage info count in blocks of this many bytes */
+pub const BLK_TRANSFER_SIZE: usize = 4096;
+pub const BLK_MAX_SERIAL_NUMBER: usize = 63;
+
+pub const BLK_REQ_READ: u32 = 0;
+pub const BLK_REQ_WRITE: u32 = 1;
+pub const BLK_REQ_FLUSH: u32 = 2;
+pub const BLK_REQ_BARRIER: u32 = 3;
+
+pub const BLK_RESP_OK: u32 = 0;
+
+/* The layouts below mirror those in the sDDF blk headers */
+
+#[repr(C)]
+pub struct BlkStorageInfo {
+    pub serial_number: [c_char; BLK_MAX_SERIAL_NUMBER + 1],
+    pub read_only: bool,
+    pub ready: bool,
+    pub sector_size: u16,
+    pub block_size: u16,
+    pub queue_depth: u16,
+    pub cylinders: u16,
+    pub heads: u16,
+    pub blocks: u16,
+    /* The size of the device in units of BLK_TRANSFER_SIZE */
+    pub capacity: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct BlkRequest {
+    pub code: u32,
+    /* The physical address of the data when talking to the driver */
+    pub io_or_offset: u64,
+    pub block_number: u64,
+    pub count: u16,
+    pub id: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct BlkResponse {
+    pub status: u32,
+    pub success_count: u16,
+    pub id: u32,
+}
+
+#[repr(C)]
+pub struct BlkRequestQueue {
+    pub head: u32,
+    pub tail: u32,
+    pub plugged: bool,
+    pub buffers: [BlkRequest; BLK_QUEUE_CAPACITY as usize],
+}
+
+#[repr(C)]
+pub struct BlkResponseQueue {
+    pub head: u32,
+    pub tail: u32,
+    pub buffers: [BlkResponse; BLK_QUEUE_CAPACITY as usize],
+}
+
 #[repr(C)]
 pub struct BlkConnectionResource {
     pub storage_info: RegionResource,
@@ -73,7 +73,7 @@ impl<T: sDDFQueueType> Queue<T> {
             None,
             size,
             sel4::CapRights::all(),
-            ObjAttributes::DEFAULT,
+            ObjAttributes::LARGE_PAGES,
             Some(cspace.to_absolute_cptr(obj_slot)),
         )?;
 
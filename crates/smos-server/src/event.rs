@@ -24,6 +24,22 @@ const FAULT_VALUE: usize = 0x1;
 pub const INVOCATION_EP_BITS: usize = EP_BIT | INVOCATION_VALUE << EP_TYPE_SHIFT;
 pub const FAULT_EP_BITS: usize = EP_BIT | FAULT_VALUE << EP_TYPE_SHIFT;
 
+/* The root server tells the threads of a process apart by putting the thread id in the top bits of
+ * the identifier that comes with an invocation or fault. The first thread of a process has tid 0,
+ * so an identifier without a thread id refers to it. */
+pub const TID_BITS: usize = 7;
+pub const TID_SHIFT: usize = EP_TYPE_SHIFT - TID_BITS;
+
+pub fn thread_ident(ident: usize, tid: usize) -> usize {
+    assert!(ident < BIT(TID_SHIFT) && tid < BIT(TID_BITS));
+    return ident | tid << TID_SHIFT;
+}
+
+/* Splits an identifier into the identifier of the process and the thread id */
+pub fn split_thread_ident(ident: usize) -> (usize, usize) {
+    return (ident & (BIT(TID_SHIFT) - 1), ident >> TID_SHIFT);
+}
+
 pub enum EntryType {
     Invocation(usize),
     Fault(usize),
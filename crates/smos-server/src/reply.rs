@@ -14,15 +14,41 @@ pub enum SMOSReply {
     },
     WindowDestroy,
     PageMap,
+    PageUnmap,
     Unview,
     WindowDeregister,
     ObjClose,
     ObjDestroy,
     ConnDestroy,
     ConnDeregister,
+    Mount,
+    Unmount,
     LoadComplete,
     sDDFProvideDataRegion,
-    ProcessWait, // @alwin: This should probably have some kind of return code
+    ProcessWait {
+        status: ExitStatus,
+    },
+    ProcessDestroy,
+    ProcessSetFaultHandler,
+    ProcessFaultForward,
+    ProcessSetSched,
+    ProcessArgsAppend,
+    ProcessList {
+        count: usize,
+    },
+    ProcessInfo {
+        data: ProcessStat,
+    },
+    ProcessCoreDump {
+        hndl: LocalHandle<ObjectHandle>,
+        size: usize,
+    },
+    ThreadCreate {
+        tid: usize,
+    },
+    ThreadJoin {
+        exit_code: usize,
+    },
     ConnRegister {
         hndl: LocalHandle<ConnRegistrationHandle>,
     },
@@ -34,6 +60,10 @@ pub enum SMOSReply {
         irq_handler: sel4::cap::IrqHandler,
         badge_bit: u8,
     },
+    SwapRegister {
+        hndl: LocalHandle<ObjectHandle>,
+        ntfn: sel4::cap::Notification,
+    },
     ConnOpen,
     ConnClose,
     ConnCreate {
@@ -67,6 +97,9 @@ pub enum SMOSReply {
     ObjStat {
         data: ObjStat,
     },
+    ObjClone {
+        hndl: HandleOrHandleCap<ObjectHandle>,
+    },
     sDDFChannelRegisterBidirectional {
         hndl_cap: HandleCap<ChannelAuthorityHandle>,
     },
@@ -119,6 +152,9 @@ pub fn handle_reply(ipc_buf: &mut sel4::IpcBuffer, reply_type: SMOSReply) -> sel
         SMOSReply::ObjOpen { hndl } => {
             msginfo = match_hndl_or_hndl_cap(hndl, ipc_buf, msginfo);
         }
+        SMOSReply::ObjClone { hndl } => {
+            msginfo = match_hndl_or_hndl_cap(hndl, ipc_buf, msginfo);
+        }
         SMOSReply::ConnCreate { hndl, ep } => {
             msginfo = msginfo.length(1).extra_caps(1);
             ipc_buf.msg_regs_mut()[0] = hndl.idx as u64;
@@ -174,6 +210,11 @@ pub fn handle_reply(ipc_buf: &mut sel4::IpcBuffer, reply_type: SMOSReply) -> sel
             ipc_buf.msg_regs_mut()[1] = badge_bit as u64;
             ipc_buf.caps_or_badges_mut()[0] = irq_handler.bits();
         }
+        SMOSReply::SwapRegister { hndl, ntfn } => {
+            msginfo = msginfo.length(1).extra_caps(1);
+            ipc_buf.msg_regs_mut()[0] = hndl.idx as u64;
+            ipc_buf.caps_or_badges_mut()[0] = ntfn.bits();
+        }
         SMOSReply::ConnRegister { hndl } => {
             msginfo = msginfo.length(1);
             ipc_buf.msg_regs_mut()[0] = hndl.idx as u64;
@@ -182,6 +223,39 @@ pub fn handle_reply(ipc_buf: &mut sel4::IpcBuffer, reply_type: SMOSReply) -> sel
             msginfo = msginfo.length(1);
             ipc_buf.msg_regs_mut()[0] = hndl.idx as u64;
         }
+        SMOSReply::ProcessWait { status } => {
+            msginfo = msginfo.length(ProcessWaitReturn::Length as usize);
+            ipc_buf.msg_regs_mut()[ProcessWaitReturn::Reason as usize] = status.label().into();
+            ipc_buf.msg_regs_mut()[ProcessWaitReturn::Code as usize] = status.code() as u64;
+            ipc_buf.msg_regs_mut()[ProcessWaitReturn::Addr as usize] = status.addr() as u64;
+        }
+        SMOSReply::ProcessList { count } => {
+            msginfo = msginfo.length(ProcessListReturn::Length as usize);
+            ipc_buf.msg_regs_mut()[ProcessListReturn::Count as usize] = count as u64;
+        }
+        SMOSReply::ProcessInfo { data } => {
+            msginfo = msginfo.length(ProcessInfoReturn::Length as usize);
+            ipc_buf.msg_regs_mut()[ProcessInfoReturn::State as usize] = data.state.into();
+            ipc_buf.msg_regs_mut()[ProcessInfoReturn::Prio as usize] = data.prio as u64;
+            ipc_buf.msg_regs_mut()[ProcessInfoReturn::HasParent as usize] =
+                data.parent.is_some() as u64;
+            ipc_buf.msg_regs_mut()[ProcessInfoReturn::Parent as usize] =
+                data.parent.unwrap_or(0) as u64;
+            ipc_buf.msg_regs_mut()[ProcessInfoReturn::MemUsage as usize] = data.mem_usage as u64;
+        }
+        SMOSReply::ProcessCoreDump { hndl, size } => {
+            msginfo = msginfo.length(ProcessCoreDumpReturn::Length as usize);
+            ipc_buf.msg_regs_mut()[ProcessCoreDumpReturn::Handle as usize] = hndl.idx as u64;
+            ipc_buf.msg_regs_mut()[ProcessCoreDumpReturn::Size as usize] = size as u64;
+        }
+        SMOSReply::ThreadCreate { tid } => {
+            msginfo = msginfo.length(1);
+            ipc_buf.msg_regs_mut()[0] = tid as u64;
+        }
+        SMOSReply::ThreadJoin { exit_code } => {
+            msginfo = msginfo.length(1);
+            ipc_buf.msg_regs_mut()[0] = exit_code as u64;
+        }
         SMOSReply::ObjStat { data } => {
             msginfo = msginfo.length(ObjStatReturn::Length as usize);
             ipc_buf.msg_regs_mut()[0] = data.size as u64;
@@ -194,6 +268,7 @@ pub fn handle_reply(ipc_buf: &mut sel4::IpcBuffer, reply_type: SMOSReply) -> sel
         SMOSReply::WindowDestroy
         | SMOSReply::ConnOpen
         | SMOSReply::PageMap
+        | SMOSReply::PageUnmap
         | SMOSReply::Unview
         | SMOSReply::WindowDeregister
         | SMOSReply::ConnClose
@@ -201,10 +276,17 @@ pub fn handle_reply(ipc_buf: &mut sel4::IpcBuffer, reply_type: SMOSReply) -> sel
         | SMOSReply::ObjDestroy
         | SMOSReply::ConnDestroy
         | SMOSReply::LoadComplete
+        | SMOSReply::ProcessDestroy
+        | SMOSReply::ProcessSetFaultHandler
+        | SMOSReply::ProcessFaultForward
+        | SMOSReply::ProcessSetSched
+        | SMOSReply::ProcessArgsAppend
         | SMOSReply::sDDFChannelRegisterRecvOnly
         | SMOSReply::sDDFQueueRegister
         | SMOSReply::sDDFProvideDataRegion
-        | SMOSReply::ConnDeregister => {}
+        | SMOSReply::ConnDeregister
+        | SMOSReply::Mount
+        | SMOSReply::Unmount => {}
         _ => panic!("Not handled yet"),
     }
 
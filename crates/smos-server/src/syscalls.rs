@@ -5,6 +5,7 @@ use alloc::vec::Vec;
 use sel4::AbsoluteCPtr;
 use sel4_bitfield_ops::Bitfield;
 use smos_common::obj_attributes::ObjAttributes;
+use smos_common::sched::SchedParams;
 use smos_common::sddf::{QueueType, VirtType};
 use smos_common::server_connection::ServerConnection;
 use smos_common::string::rust_str_from_buffer;
@@ -38,6 +39,12 @@ pub struct ObjStat {
     pub hndl: ServerReceivedHandleOrHandleCap,
 }
 
+#[derive(Debug)]
+pub struct ObjClone {
+    pub hndl: ServerReceivedHandleOrHandleCap,
+    pub return_cap: bool,
+}
+
 #[derive(Debug)]
 pub struct ObjOpen<'a> {
     pub name: &'a str,
@@ -73,10 +80,31 @@ pub struct ConnPublish<'a> {
 
 #[derive(Debug)]
 pub struct ProcessSpawn<'a> {
-    pub exec_name: &'a str,
-    pub fs_name: &'a str,
-    pub prio: u8,
+    pub path: &'a str,
+    pub sched: SchedParams,
     pub args: Option<Vec<&'a str>>,
+    /* None means that the environment of the caller should be inherited */
+    pub env: Option<Vec<&'a str>>,
+    /* The names of the caps to give to the child and their slots in the cspace of the caller */
+    pub caps: Vec<(&'a str, usize)>,
+}
+
+#[derive(Debug)]
+pub struct ProcessExec<'a> {
+    pub path: &'a str,
+    pub args: Option<Vec<&'a str>>,
+    /* None means that the current environment should be kept */
+    pub env: Option<Vec<&'a str>>,
+    /* The names of the caps to keep and their slots in the cspace of the caller */
+    pub caps: Vec<(&'a str, usize)>,
+    /* The names of the handles to keep and their indices */
+    pub handles: Vec<(&'a str, usize)>,
+}
+
+/* A part of the arguments of a process_spawn or process_exec that didn't fit into the shared buffer */
+#[derive(Debug)]
+pub struct ProcessArgsAppend<'a> {
+    pub data: &'a [u8],
 }
 
 #[derive(Debug)]
@@ -84,11 +112,62 @@ pub struct ProcessWait {
     pub hndl: ReceivedHandle,
 }
 
-// @alwin: error code?
-// #[derive(Debug)]
-// pub struct ProcessExit {
-// pub res: usize
-// }
+#[derive(Debug)]
+pub struct ProcessDestroy {
+    pub hndl: ReceivedHandle,
+}
+
+#[derive(Debug)]
+pub struct ProcessSetFaultHandler {
+    pub hndl: ReceivedHandle,
+    pub fault_ep: AbsoluteCPtr,
+}
+
+#[derive(Debug)]
+pub struct ProcessSetSched {
+    pub hndl: ReceivedHandle,
+    pub prio: u8,
+    pub budget: u64,
+    pub period: u64,
+}
+
+#[derive(Debug)]
+pub struct ProcessFaultForward {
+    pub hndl: ReceivedHandle,
+    pub tid: usize,
+}
+
+#[derive(Debug)]
+pub struct ProcessInfo {
+    pub pid: usize,
+}
+
+#[derive(Debug)]
+pub struct ProcessCoreDump {
+    pub hndl: ReceivedHandle,
+}
+
+#[derive(Debug)]
+pub struct ProcessExit {
+    pub exit_code: usize,
+}
+
+#[derive(Debug)]
+pub struct ThreadCreate {
+    pub entry: usize,
+    pub arg: usize,
+    pub rs_ep_slot: usize,
+}
+
+#[derive(Debug)]
+pub struct ThreadExit {
+    pub exit_code: usize,
+}
+
+#[derive(Debug)]
+pub struct ThreadJoin {
+    pub tid: usize,
+}
 
 #[derive(Debug)]
 pub struct ConnRegister {
@@ -101,6 +180,17 @@ pub struct ConnDeregister {
     pub hndl: ReceivedHandle,
 }
 
+#[derive(Debug)]
+pub struct Mount<'a> {
+    pub path: &'a str,
+    pub server_name: &'a str,
+}
+
+#[derive(Debug)]
+pub struct Unmount<'a> {
+    pub path: &'a str,
+}
+
 #[derive(Debug)]
 pub struct WindowRegister {
     pub publish_hndl: ReceivedHandle,
@@ -136,6 +226,12 @@ pub struct PageMap {
     pub content_vaddr: usize,
 }
 
+#[derive(Debug)]
+pub struct PageUnmap {
+    pub window_registration_hndl: ReceivedHandle,
+    pub view_offset: usize,
+}
+
 #[derive(Debug)]
 pub struct ConnDestroy {
     pub hndl: ReceivedHandle,
@@ -145,6 +241,7 @@ pub struct ConnDestroy {
 pub struct LoadComplete {
     pub entry_point: usize,
     pub sp: usize,
+    pub tls_base: Option<usize>,
 }
 
 #[derive(Debug)]
@@ -160,6 +257,12 @@ pub struct IRQRegister {
     pub edge_triggered: bool,
 }
 
+#[derive(Debug)]
+pub struct SwapRegister {
+    pub publish_hndl: ReceivedHandle,
+    pub num_blocks: usize,
+}
+
 #[derive(Debug)]
 #[allow(non_camel_case_types)]
 pub struct sDDFChannelRegisterBidirectional {
@@ -207,6 +310,7 @@ pub enum SMOS_Invocation<'a> {
     ObjCreate(ObjCreate<'a>),
     ObjOpen(ObjOpen<'a>),
     ObjStat(ObjStat),
+    ObjClone(ObjClone),
     ObjClose(ObjClose),
     ObjDestroy(ObjDestroy),
     View(View),
@@ -218,16 +322,32 @@ pub enum SMOS_Invocation<'a> {
     ConnPublish(ConnPublish<'a>),
     ConnRegister(ConnRegister),
     ConnDeregister(ConnDeregister),
+    Mount(Mount<'a>),
+    Unmount(Unmount<'a>),
     ReplyCreate,
     ServerHandleCapCreate(ServerHandleCapCreate),
     ProcessSpawn(ProcessSpawn<'a>),
+    ProcessExec(ProcessExec<'a>),
+    ProcessArgsAppend(ProcessArgsAppend<'a>),
     ProcessWait(ProcessWait),
-    ProcessExit,
+    ProcessExit(ProcessExit),
+    ProcessDestroy(ProcessDestroy),
+    ProcessSetFaultHandler(ProcessSetFaultHandler),
+    ProcessSetSched(ProcessSetSched),
+    ProcessFaultForward(ProcessFaultForward),
+    ProcessList,
+    ProcessInfo(ProcessInfo),
+    ProcessCoreDump(ProcessCoreDump),
+    ThreadCreate(ThreadCreate),
+    ThreadExit(ThreadExit),
+    ThreadJoin(ThreadJoin),
     WindowRegister(WindowRegister),
     WindowDeregister(WindowDeregister),
     PageMap(PageMap),
+    PageUnmap(PageUnmap),
     LoadComplete(LoadComplete),
     IRQRegister(IRQRegister),
+    SwapRegister(SwapRegister),
     sDDFChannelRegisterBidirectional(sDDFChannelRegisterBidirectional),
     sDDFChannelRegisterRecvOnly(sDDFChannelRegisterRecvOnly),
     sDDFQueueRegister(sDDFQueueRegister),
@@ -259,6 +379,7 @@ impl<'a> SMOS_Invocation<'a> {
             | SMOS_Invocation::ObjCreate(_)
             | SMOS_Invocation::ObjOpen(_)
             | SMOS_Invocation::ObjStat(_)
+            | SMOS_Invocation::ObjClone(_)
             | SMOS_Invocation::ObjClose(_)
             | SMOS_Invocation::ObjDestroy(_)
             | SMOS_Invocation::Unview(_)
@@ -268,16 +389,31 @@ impl<'a> SMOS_Invocation<'a> {
             | SMOS_Invocation::ConnPublish(_)
             | SMOS_Invocation::ConnRegister(_)
             | SMOS_Invocation::ConnDeregister(_)
+            | SMOS_Invocation::Mount(_)
+            | SMOS_Invocation::Unmount(_)
             | SMOS_Invocation::ReplyCreate
             | SMOS_Invocation::ServerHandleCapCreate(_)
             | SMOS_Invocation::ProcessSpawn(_)
+            | SMOS_Invocation::ProcessExec(_)
+            | SMOS_Invocation::ProcessArgsAppend(_)
             | SMOS_Invocation::ProcessWait(_)
-            | SMOS_Invocation::ProcessExit
+            | SMOS_Invocation::ProcessExit(_)
+            | SMOS_Invocation::ProcessDestroy(_)
+            | SMOS_Invocation::ProcessSetSched(_)
+            | SMOS_Invocation::ProcessFaultForward(_)
+            | SMOS_Invocation::ProcessList
+            | SMOS_Invocation::ProcessInfo(_)
+            | SMOS_Invocation::ProcessCoreDump(_)
+            | SMOS_Invocation::ThreadCreate(_)
+            | SMOS_Invocation::ThreadExit(_)
+            | SMOS_Invocation::ThreadJoin(_)
             | SMOS_Invocation::WindowRegister(_)
             | SMOS_Invocation::WindowDeregister(_)
             | SMOS_Invocation::PageMap(_)
+            | SMOS_Invocation::PageUnmap(_)
             | SMOS_Invocation::LoadComplete(_)
             | SMOS_Invocation::IRQRegister(_)
+            | SMOS_Invocation::SwapRegister(_)
             | SMOS_Invocation::sDDFGetDataRegion
             | SMOS_Invocation::ServerCreateChannel(_)
             | SMOS_Invocation::ChannelOpen(_) => {
@@ -286,7 +422,8 @@ impl<'a> SMOS_Invocation<'a> {
             SMOS_Invocation::sDDFChannelRegisterBidirectional(_)
             | SMOS_Invocation::sDDFChannelRegisterRecvOnly(_)
             | SMOS_Invocation::sDDFQueueRegister(_)
-            | SMOS_Invocation::sDDFProvideDataRegion(_) => {
+            | SMOS_Invocation::sDDFProvideDataRegion(_)
+            | SMOS_Invocation::ProcessSetFaultHandler(_) => {
                 return true;
             }
             SMOS_Invocation::View(x) => {
@@ -540,6 +677,16 @@ mod SMOS_Invocation_Raw {
                     content_vaddr: f_msg(2) as usize,
                 }))
             }
+            SMOSInvocation::PageUnmap => {
+                if info.length() != 2 {
+                    return Err(InvocationError::InvalidArguments);
+                }
+
+                Ok(SMOS_Invocation::PageUnmap(PageUnmap {
+                    window_registration_hndl: ReceivedHandle::new(f_msg(0) as usize),
+                    view_offset: f_msg(1) as usize,
+                }))
+            }
             SMOSInvocation::ConnCreate => {
                 if data_buffer.is_none() {
                     return Err(InvocationError::DataBufferNotSet);
@@ -616,6 +763,29 @@ mod SMOS_Invocation_Raw {
 
                 Ok(SMOS_Invocation::ObjStat(ObjStat { hndl: hndl }))
             }
+            SMOSInvocation::ObjClone => {
+                if info.length() != ObjCloneArgs::Length as usize {
+                    return Err(InvocationError::InvalidArguments);
+                }
+
+                let hndl = if info.extra_caps() == 1 {
+                    if info.caps_unwrapped() != (1 << 0) {
+                        /* Only objects provided by the server being called into can be cloned */
+                        return Err(InvocationError::InvalidArguments);
+                    }
+
+                    ServerReceivedHandleOrHandleCap::new_unwrapped_handle_cap(f_cap(0) as usize)
+                } else {
+                    ServerReceivedHandleOrHandleCap::new_handle(
+                        f_msg(ObjCloneArgs::Handle as u64) as usize
+                    )
+                };
+
+                Ok(SMOS_Invocation::ObjClone(ObjClone {
+                    hndl: hndl,
+                    return_cap: f_msg(ObjCloneArgs::ReturnCap as u64) != 0,
+                }))
+            }
             SMOSInvocation::View => {
                 let window: ServerReceivedHandleOrHandleCap;
 
@@ -703,14 +873,40 @@ mod SMOS_Invocation_Raw {
                     hndl: ReceivedHandle::new(f_msg(0) as usize),
                 }))
             }
+            SMOSInvocation::Mount => {
+                if data_buffer.is_none() {
+                    return Err(InvocationError::DataBufferNotSet);
+                }
+
+                let (path, data_buffer_ref) = rust_str_from_buffer(data_buffer.unwrap())?;
+                let (server_name, _) = rust_str_from_buffer(data_buffer_ref)?;
+
+                Ok(SMOS_Invocation::Mount(Mount {
+                    path: path,
+                    server_name: server_name,
+                }))
+            }
+            SMOSInvocation::Unmount => {
+                if data_buffer.is_none() {
+                    return Err(InvocationError::DataBufferNotSet);
+                }
+
+                Ok(SMOS_Invocation::Unmount(Unmount {
+                    path: rust_str_from_buffer(data_buffer.unwrap())?.0,
+                }))
+            }
             SMOSInvocation::LoadComplete => {
-                if info.length() != 2 {
+                if info.length() != 3 {
                     return Err(InvocationError::InvalidArguments);
                 }
 
                 Ok(SMOS_Invocation::LoadComplete(LoadComplete {
                     entry_point: f_msg(0) as usize,
                     sp: f_msg(1) as usize,
+                    tls_base: match f_msg(2) {
+                        0 => None,
+                        x => Some(x as usize),
+                    },
                 }))
             }
             SMOSInvocation::ConnOpen => {
@@ -769,6 +965,18 @@ mod SMOS_Invocation_Raw {
                     edge_triggered: f_msg(2) != 0,
                 }))
             }
+            SMOSInvocation::SwapRegister => {
+                if info.length() != SwapRegisterArgs::Length as usize {
+                    return Err(InvocationError::InvalidArguments);
+                }
+
+                Ok(SMOS_Invocation::SwapRegister(SwapRegister {
+                    publish_hndl: ReceivedHandle::new(
+                        f_msg(SwapRegisterArgs::PublishHandle as u64) as usize,
+                    ),
+                    num_blocks: f_msg(SwapRegisterArgs::NumBlocks as u64) as usize,
+                }))
+            }
             SMOSInvocation::ReplyCreate => Ok(SMOS_Invocation::ReplyCreate),
             SMOSInvocation::ServerHandleCapCreate => {
                 if info.length() != 2 {
@@ -787,20 +995,20 @@ mod SMOS_Invocation_Raw {
                     return Err(InvocationError::DataBufferNotSet);
                 }
 
-                if info.length() != 2 {
+                if info.length() != ProcessSpawnArgs::Length as usize {
                     return Err(InvocationError::InvalidArguments);
                 }
 
                 let data_buffer_ref = data_buffer.unwrap();
 
-                let (exec_name, ref mut data_buffer_ref) = rust_str_from_buffer(data_buffer_ref)?;
-                let (fs_name, ref mut data_buffer_ref) = rust_str_from_buffer(data_buffer_ref)?;
+                let (path, ref mut data_buffer_ref) = rust_str_from_buffer(data_buffer_ref)?;
 
-                let args = if f_msg(0) == 0 {
+                let num_args = f_msg(ProcessSpawnArgs::NumArgs as u64);
+                let args = if num_args == 0 {
                     None
                 } else {
                     let mut args_inner = Vec::new();
-                    for _ in 0..f_msg(0) {
+                    for _ in 0..num_args {
                         let (arg_tmp, buf_tmp) = rust_str_from_buffer(data_buffer_ref)?;
                         args_inner.push(arg_tmp);
                         *data_buffer_ref = buf_tmp;
@@ -808,13 +1016,131 @@ mod SMOS_Invocation_Raw {
                     Some(args_inner)
                 };
 
+                let num_env = f_msg(ProcessSpawnArgs::NumEnv as u64);
+                let env = if f_msg(ProcessSpawnArgs::InheritEnv as u64) != 0 {
+                    None
+                } else {
+                    let mut env_inner = Vec::new();
+                    for _ in 0..num_env {
+                        let (var_tmp, buf_tmp) = rust_str_from_buffer(data_buffer_ref)?;
+                        env_inner.push(var_tmp);
+                        *data_buffer_ref = buf_tmp;
+                    }
+                    Some(env_inner)
+                };
+
+                let num_caps = f_msg(ProcessSpawnArgs::NumCaps as u64);
+                let mut caps = Vec::new();
+                for _ in 0..num_caps {
+                    let (name_tmp, buf_tmp) = rust_str_from_buffer(data_buffer_ref)?;
+                    if buf_tmp.len() < 8 {
+                        return Err(InvocationError::InvalidArguments);
+                    }
+                    let slot = u64::from_le_bytes(buf_tmp[0..8].try_into().unwrap());
+                    caps.push((name_tmp, slot as usize));
+                    *data_buffer_ref = &buf_tmp[8..];
+                }
+
                 Ok(SMOS_Invocation::ProcessSpawn(ProcessSpawn {
-                    exec_name: exec_name,
-                    fs_name: fs_name,
-                    prio: f_msg(1)
-                        .try_into()
-                        .expect("@alwin: This should not be an assert"),
+                    path: path,
+                    sched: SchedParams {
+                        prio: f_msg(ProcessSpawnArgs::Prio as u64)
+                            .try_into()
+                            .or(Err(InvocationError::InvalidArguments))?,
+                        mcp: f_msg(ProcessSpawnArgs::Mcp as u64)
+                            .try_into()
+                            .or(Err(InvocationError::InvalidArguments))?,
+                        budget: f_msg(ProcessSpawnArgs::Budget as u64),
+                        period: f_msg(ProcessSpawnArgs::Period as u64),
+                        extra_refills: f_msg(ProcessSpawnArgs::ExtraRefills as u64) as usize,
+                    },
                     args: args,
+                    env: env,
+                    caps: caps,
+                }))
+            }
+            SMOSInvocation::ProcExec => {
+                if data_buffer.is_none() {
+                    return Err(InvocationError::DataBufferNotSet);
+                }
+
+                if info.length() != ProcessExecArgs::Length as usize {
+                    return Err(InvocationError::InvalidArguments);
+                }
+
+                let data_buffer_ref = data_buffer.unwrap();
+
+                let (path, ref mut data_buffer_ref) = rust_str_from_buffer(data_buffer_ref)?;
+
+                let num_args = f_msg(ProcessExecArgs::NumArgs as u64);
+                let args = if num_args == 0 {
+                    None
+                } else {
+                    let mut args_inner = Vec::new();
+                    for _ in 0..num_args {
+                        let (arg_tmp, buf_tmp) = rust_str_from_buffer(data_buffer_ref)?;
+                        args_inner.push(arg_tmp);
+                        *data_buffer_ref = buf_tmp;
+                    }
+                    Some(args_inner)
+                };
+
+                let num_env = f_msg(ProcessExecArgs::NumEnv as u64);
+                let env = if f_msg(ProcessExecArgs::InheritEnv as u64) != 0 {
+                    None
+                } else {
+                    let mut env_inner = Vec::new();
+                    for _ in 0..num_env {
+                        let (var_tmp, buf_tmp) = rust_str_from_buffer(data_buffer_ref)?;
+                        env_inner.push(var_tmp);
+                        *data_buffer_ref = buf_tmp;
+                    }
+                    Some(env_inner)
+                };
+
+                /* Caps and handles are both described by a name followed by a number */
+                let num_caps = f_msg(ProcessExecArgs::NumCaps as u64);
+                let num_handles = f_msg(ProcessExecArgs::NumHandles as u64);
+                let mut caps = Vec::new();
+                let mut handles = Vec::new();
+                for i in 0..num_caps + num_handles {
+                    let (name_tmp, buf_tmp) = rust_str_from_buffer(data_buffer_ref)?;
+                    if buf_tmp.len() < 8 {
+                        return Err(InvocationError::InvalidArguments);
+                    }
+                    let val = u64::from_le_bytes(buf_tmp[0..8].try_into().unwrap()) as usize;
+                    if i < num_caps {
+                        caps.push((name_tmp, val));
+                    } else {
+                        handles.push((name_tmp, val));
+                    }
+                    *data_buffer_ref = &buf_tmp[8..];
+                }
+
+                Ok(SMOS_Invocation::ProcessExec(ProcessExec {
+                    path: path,
+                    args: args,
+                    env: env,
+                    caps: caps,
+                    handles: handles,
+                }))
+            }
+            SMOSInvocation::ProcArgsAppend => {
+                if data_buffer.is_none() {
+                    return Err(InvocationError::DataBufferNotSet);
+                }
+
+                if info.length() != 1 {
+                    return Err(InvocationError::InvalidArguments);
+                }
+
+                let data = data_buffer
+                    .unwrap()
+                    .get(..f_msg(0) as usize)
+                    .ok_or(InvocationError::InvalidArguments)?;
+
+                Ok(SMOS_Invocation::ProcessArgsAppend(ProcessArgsAppend {
+                    data: data,
                 }))
             }
             SMOSInvocation::ProcWait => {
@@ -826,7 +1152,116 @@ mod SMOS_Invocation_Raw {
                     hndl: ReceivedHandle::new(f_msg(0) as usize),
                 }))
             }
-            SMOSInvocation::ProcExit => Ok(SMOS_Invocation::ProcessExit),
+            SMOSInvocation::ProcExit => {
+                if info.length() != 1 {
+                    return Err(InvocationError::InvalidArguments);
+                }
+
+                Ok(SMOS_Invocation::ProcessExit(ProcessExit {
+                    exit_code: f_msg(0) as usize,
+                }))
+            }
+            SMOSInvocation::ProcDestroy => {
+                if info.length() != 1 {
+                    return Err(InvocationError::InvalidArguments);
+                }
+
+                Ok(SMOS_Invocation::ProcessDestroy(ProcessDestroy {
+                    hndl: ReceivedHandle::new(f_msg(0) as usize),
+                }))
+            }
+            SMOSInvocation::ProcSetFaultHandler => {
+                if info.length() != 1 || info.extra_caps() != 1 || info.caps_unwrapped() != 0 {
+                    return Err(InvocationError::InvalidArguments);
+                }
+
+                Ok(SMOS_Invocation::ProcessSetFaultHandler(
+                    ProcessSetFaultHandler {
+                        hndl: ReceivedHandle::new(f_msg(0) as usize),
+                        fault_ep: recv_slot,
+                    },
+                ))
+            }
+            SMOSInvocation::ProcSetSched => {
+                if info.length() != ProcessSetSchedArgs::Length as usize {
+                    return Err(InvocationError::InvalidArguments);
+                }
+
+                Ok(SMOS_Invocation::ProcessSetSched(ProcessSetSched {
+                    hndl: ReceivedHandle::new(f_msg(ProcessSetSchedArgs::Handle as u64) as usize),
+                    prio: f_msg(ProcessSetSchedArgs::Prio as u64)
+                        .try_into()
+                        .or(Err(InvocationError::InvalidArguments))?,
+                    budget: f_msg(ProcessSetSchedArgs::Budget as u64),
+                    period: f_msg(ProcessSetSchedArgs::Period as u64),
+                }))
+            }
+            SMOSInvocation::ProcFaultForward => {
+                if info.length() != ProcessFaultForwardArgs::Length as usize {
+                    return Err(InvocationError::InvalidArguments);
+                }
+
+                Ok(SMOS_Invocation::ProcessFaultForward(ProcessFaultForward {
+                    hndl: ReceivedHandle::new(
+                        f_msg(ProcessFaultForwardArgs::Handle as u64) as usize
+                    ),
+                    tid: f_msg(ProcessFaultForwardArgs::Tid as u64) as usize,
+                }))
+            }
+            SMOSInvocation::ProcList => {
+                if info.length() != 0 {
+                    return Err(InvocationError::InvalidArguments);
+                }
+
+                Ok(SMOS_Invocation::ProcessList)
+            }
+            SMOSInvocation::ProcInfo => {
+                if info.length() != 1 {
+                    return Err(InvocationError::InvalidArguments);
+                }
+
+                Ok(SMOS_Invocation::ProcessInfo(ProcessInfo {
+                    pid: f_msg(0) as usize,
+                }))
+            }
+            SMOSInvocation::ProcCoreDump => {
+                if info.length() != 1 {
+                    return Err(InvocationError::InvalidArguments);
+                }
+
+                Ok(SMOS_Invocation::ProcessCoreDump(ProcessCoreDump {
+                    hndl: ReceivedHandle::new(f_msg(0) as usize),
+                }))
+            }
+            SMOSInvocation::ThreadCreate => {
+                if info.length() != ThreadCreateArgs::Length as usize {
+                    return Err(InvocationError::InvalidArguments);
+                }
+
+                Ok(SMOS_Invocation::ThreadCreate(ThreadCreate {
+                    entry: f_msg(ThreadCreateArgs::Entry as u64) as usize,
+                    arg: f_msg(ThreadCreateArgs::Arg as u64) as usize,
+                    rs_ep_slot: f_msg(ThreadCreateArgs::RSEPSlot as u64) as usize,
+                }))
+            }
+            SMOSInvocation::ThreadExit => {
+                if info.length() != ThreadExitArgs::Length as usize {
+                    return Err(InvocationError::InvalidArguments);
+                }
+
+                Ok(SMOS_Invocation::ThreadExit(ThreadExit {
+                    exit_code: f_msg(ThreadExitArgs::ExitCode as u64) as usize,
+                }))
+            }
+            SMOSInvocation::ThreadJoin => {
+                if info.length() != 1 {
+                    return Err(InvocationError::InvalidArguments);
+                }
+
+                Ok(SMOS_Invocation::ThreadJoin(ThreadJoin {
+                    tid: f_msg(0) as usize,
+                }))
+            }
             SMOSInvocation::TestSimple => {
                 panic!("Okay got to test simple");
             }
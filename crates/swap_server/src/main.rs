@@ -0,0 +1,351 @@
+#![no_std]
+#![no_main]
+
+/* Provides the root server with a swap area on a block device. Requests from the root server are
+passed on to the driver, which transfers straight to and from the data page of the swap region. */
+
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+
+use smos_common::client_connection::ClientConnection;
+use smos_common::connection::{sDDFConnection, RootServerConnection};
+use smos_common::local_handle::HandleOrHandleCap;
+use smos_common::sddf::QueueType;
+use smos_common::server_connection::ServerConnection;
+use smos_common::swap::{
+    SwapRequestCode, SwapRequestQueue, SwapResponse, SwapResponseQueue, SwapResponseStatus,
+    SWAP_BLOCK_SIZE, SWAP_DATA_OFFSET, SWAP_QUEUE_CAPACITY, SWAP_REGION_SIZE,
+    SWAP_REQ_QUEUE_OFFSET, SWAP_RESP_QUEUE_OFFSET,
+};
+use smos_common::syscall::{sDDFInterface, NonRootServerInterface, RootServerInterface};
+use smos_cspace::SMOSUserCSpace;
+use smos_runtime::{smos_declare_main, Never};
+use smos_sddf::blk_config::{
+    BlkRequest, BlkRequestQueue, BlkResponseQueue, BlkStorageInfo, BLK_QUEUE_CAPACITY,
+    BLK_QUEUE_SIZE, BLK_REQ_BARRIER, BLK_REQ_FLUSH, BLK_REQ_READ, BLK_REQ_WRITE, BLK_RESP_OK,
+    BLK_TRANSFER_SIZE,
+};
+use smos_sddf::dma_region::DMARegion;
+use smos_sddf::notification_channel::{BidirectionalChannel, NotificationChannel, PPCForbidden};
+use smos_sddf::queue::{Queue, SerialQueue};
+use smos_server::event::{decode_entry_type, smos_serv_replyrecv, EntryType};
+use smos_server::ntfn_buffer::dequeue_ntfn_buffer_msg;
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+const NTFN_BUFFER: *mut u8 = 0xB0000 as *mut u8;
+
+const DRV_REQ: usize = 0xC000000;
+const DRV_RESP: usize = 0xC001000;
+const DRV_STORAGE_INFO: usize = 0xC002000;
+
+const SWAP_REGION: usize = 0xD000000;
+
+/* Swap blocks are passed to the driver as they are */
+const _: () = assert!(SWAP_BLOCK_SIZE == BLK_TRANSFER_SIZE);
+
+struct SwapServer {
+    drv_req: *mut BlkRequestQueue,
+    drv_resp: *mut BlkResponseQueue,
+    drv_channel: NotificationChannel<BidirectionalChannel, PPCForbidden>,
+    swap_req: *mut SwapRequestQueue,
+    swap_resp: *mut SwapResponseQueue,
+    /* Signalled once there are responses for the root server */
+    rs_ntfn: sel4::cap::Notification,
+    /* The physical address of the swap region, which the driver transfers to and from */
+    swap_paddr: u64,
+    num_blocks: u64,
+}
+
+impl SwapServer {
+    /* Returns false if the response queue of the root server is full */
+    fn respond(&self, resp: SwapResponse) -> bool {
+        unsafe {
+            let head = ptr::read_volatile(&(*self.swap_resp).head);
+            let tail = ptr::read_volatile(&(*self.swap_resp).tail);
+            if tail.wrapping_sub(head) as usize == SWAP_QUEUE_CAPACITY {
+                return false;
+            }
+
+            ptr::write_volatile(
+                &mut (*self.swap_resp).buffers[tail as usize % SWAP_QUEUE_CAPACITY],
+                resp,
+            );
+            fence(Ordering::Release);
+            ptr::write_volatile(&mut (*self.swap_resp).tail, tail.wrapping_add(1));
+        }
+
+        return true;
+    }
+
+    /* Returns false if the request queue of the driver is full */
+    fn submit(&self, req: BlkRequest) -> bool {
+        let capacity = BLK_QUEUE_CAPACITY as usize;
+
+        unsafe {
+            let head = ptr::read_volatile(&(*self.drv_req).head);
+            let tail = ptr::read_volatile(&(*self.drv_req).tail);
+            if tail.wrapping_sub(head) as usize == capacity {
+                return false;
+            }
+
+            ptr::write_volatile(&mut (*self.drv_req).buffers[tail as usize % capacity], req);
+            fence(Ordering::Release);
+            ptr::write_volatile(&mut (*self.drv_req).tail, tail.wrapping_add(1));
+        }
+
+        return true;
+    }
+
+    /* Passes the requests of the root server on to the driver. Requests that can't be passed on
+    are failed straight away. */
+    fn handle_swap_requests(&self) {
+        let mut notify_drv = false;
+        let mut notify_rs = false;
+
+        loop {
+            let req = unsafe {
+                let head = ptr::read_volatile(&(*self.swap_req).head);
+                if head == ptr::read_volatile(&(*self.swap_req).tail) {
+                    break;
+                }
+
+                fence(Ordering::Acquire);
+                let req = ptr::read_volatile(
+                    &(*self.swap_req).buffers[head as usize % SWAP_QUEUE_CAPACITY],
+                );
+                ptr::write_volatile(&mut (*self.swap_req).head, head.wrapping_add(1));
+                req
+            };
+
+            let code = match req.code {
+                SwapRequestCode::Read => BLK_REQ_READ,
+                SwapRequestCode::Write => BLK_REQ_WRITE,
+                SwapRequestCode::Flush => BLK_REQ_FLUSH,
+                SwapRequestCode::Barrier => BLK_REQ_BARRIER,
+            };
+
+            /* The root server only transfers one block at a time through the data page */
+            let valid = req.count == 1
+                && req.io_or_offset == SWAP_DATA_OFFSET as u64
+                && req.block_number < self.num_blocks;
+
+            if valid
+                && self.submit(BlkRequest {
+                    code: code,
+                    io_or_offset: self.swap_paddr + SWAP_DATA_OFFSET as u64,
+                    block_number: req.block_number,
+                    count: 1,
+                    id: req.id,
+                })
+            {
+                notify_drv = true;
+                continue;
+            }
+
+            if !self.respond(SwapResponse {
+                status: SwapResponseStatus::SeekError,
+                success_count: 0,
+                id: req.id,
+            }) {
+                sel4::debug_println!("Dropped the response to swap request {}", req.id);
+            }
+            notify_rs = true;
+        }
+
+        if notify_drv && !unsafe { ptr::read_volatile(&(*self.drv_req).plugged) } {
+            self.drv_channel.notify();
+        }
+
+        if notify_rs {
+            self.rs_ntfn.signal();
+        }
+    }
+
+    /* Passes the responses of the driver back to the root server */
+    fn handle_drv_responses(&self) {
+        let capacity = BLK_QUEUE_CAPACITY as usize;
+        let mut notify_rs = false;
+
+        loop {
+            let resp = unsafe {
+                let head = ptr::read_volatile(&(*self.drv_resp).head);
+                if head == ptr::read_volatile(&(*self.drv_resp).tail) {
+                    break;
+                }
+
+                fence(Ordering::Acquire);
+                let resp = ptr::read_volatile(&(*self.drv_resp).buffers[head as usize % capacity]);
+                ptr::write_volatile(&mut (*self.drv_resp).head, head.wrapping_add(1));
+                resp
+            };
+
+            /* The swap protocol doesn't distinguish between the ways a transfer can fail */
+            let status = if resp.status == BLK_RESP_OK {
+                SwapResponseStatus::Ok
+            } else {
+                SwapResponseStatus::SeekError
+            };
+
+            if !self.respond(SwapResponse {
+                status: status,
+                success_count: resp.success_count,
+                id: resp.id,
+            }) {
+                sel4::debug_println!("Dropped the response to swap request {}", resp.id);
+            }
+            notify_rs = true;
+        }
+
+        if notify_rs {
+            self.rs_ntfn.signal();
+        }
+    }
+}
+
+#[smos_declare_main]
+fn main(rs_conn: RootServerConnection, mut cspace: SMOSUserCSpace) -> sel4::Result<Never> {
+    sel4::debug_println!("Hello, I am the swap server!!!");
+
+    let args: Vec<&str> = smos_runtime::args::args().collect();
+    assert!(args.len() == 2);
+
+    /* Register as a server, so that the root server and the driver can notify us */
+    let ep_cptr = cspace.alloc_slot().expect("Could not get a slot");
+    let listen_conn = rs_conn
+        .conn_publish::<sDDFConnection>(NTFN_BUFFER, &cspace.to_absolute_cptr(ep_cptr), args[0])
+        .expect("Failed to publish as a server");
+
+    /* Create connection to blk driver */
+    let conn_ep_slot = cspace.alloc_slot().expect("Failed to allocate slot");
+    let mut drv_conn = rs_conn
+        .conn_create::<sDDFConnection>(&cspace.to_absolute_cptr(conn_ep_slot), args[1])
+        .expect("Failed to establish connection to blk driver");
+
+    drv_conn
+        .conn_open(None)
+        .expect("Failed to open connection with driver");
+
+    let drv_channel = NotificationChannel::<BidirectionalChannel, PPCForbidden>::new(
+        &rs_conn,
+        &drv_conn,
+        &mut cspace,
+        &listen_conn.hndl(),
+        None,
+    )
+    .expect("Failed to establish channel with driver");
+
+    let drv_req = Queue::<SerialQueue>::new(&rs_conn, &mut cspace, DRV_REQ, BLK_QUEUE_SIZE)
+        .expect("Failed to create request queue");
+    let drv_resp = Queue::<SerialQueue>::new(&rs_conn, &mut cspace, DRV_RESP, BLK_QUEUE_SIZE)
+        .expect("Failed to create response queue");
+
+    drv_conn
+        .sddf_queue_register(
+            drv_req.obj_hndl_cap.unwrap(),
+            drv_req.size,
+            QueueType::Request,
+        )
+        .expect("Failed to register request queue");
+    drv_conn
+        .sddf_queue_register(
+            drv_resp.obj_hndl_cap.unwrap(),
+            drv_resp.size,
+            QueueType::Response,
+        )
+        .expect("Failed to register response queue");
+
+    /* The driver fills in the storage info once the device is set up */
+    let storage_info_region = DMARegion::new(&rs_conn, &mut cspace, DRV_STORAGE_INFO, 0x1000, true)
+        .expect("Failed to create storage info region");
+    drv_conn
+        .sddf_data_region_provide(storage_info_region.obj_hndl, storage_info_region.size)
+        .expect("Failed to provide storage info region to driver");
+
+    let storage_info = storage_info_region.vaddr as *const BlkStorageInfo;
+    while !unsafe { ptr::read_volatile(&(*storage_info).ready) } {
+        sel4::r#yield();
+    }
+
+    assert!(
+        !unsafe { ptr::read_volatile(&(*storage_info).read_only) },
+        "Can't swap to a read-only device"
+    );
+
+    // @alwin: The whole device is used as the swap area. This should be a partition once there is
+    // a file system on it too.
+    let num_blocks = unsafe { ptr::read_volatile(&(*storage_info).capacity) };
+
+    /* Register the swap area with the root server */
+    let ntfn_slot = cspace.alloc_slot().expect("Failed to allocate slot");
+    let (region_hndl, rs_ntfn) = rs_conn
+        .swap_register(
+            &listen_conn.hndl(),
+            num_blocks as usize,
+            &cspace.to_absolute_cptr(ntfn_slot),
+        )
+        .expect("Failed to register swap area");
+    let region_hndl = HandleOrHandleCap::Handle(region_hndl);
+
+    let swap_win = rs_conn
+        .window_create(SWAP_REGION, SWAP_REGION_SIZE, None)
+        .expect("Failed to create window for swap region");
+    rs_conn
+        .view(
+            &swap_win,
+            &region_hndl,
+            0,
+            0,
+            SWAP_REGION_SIZE,
+            sel4::CapRights::read_write(),
+        )
+        .expect("Failed to map swap region");
+    let swap_paddr = rs_conn
+        .obj_stat(&region_hndl)
+        .expect("Failed to stat swap region")
+        .paddr
+        .expect("Swap region should have a paddr");
+
+    let server = SwapServer {
+        drv_req: drv_req.vaddr as *mut BlkRequestQueue,
+        drv_resp: drv_resp.vaddr as *mut BlkResponseQueue,
+        drv_channel: drv_channel,
+        swap_req: (SWAP_REGION + SWAP_REQ_QUEUE_OFFSET) as *mut SwapRequestQueue,
+        swap_resp: (SWAP_REGION + SWAP_RESP_QUEUE_OFFSET) as *mut SwapResponseQueue,
+        rs_ntfn: rs_ntfn,
+        swap_paddr: swap_paddr as u64,
+        num_blocks: num_blocks,
+    };
+
+    sel4::debug_println!("Swapping to {} blocks of {}", num_blocks, args[1]);
+
+    /* Allocate a reply cap */
+    let reply_cptr = cspace.alloc_slot().expect("Could not allocate a slot");
+    let reply = rs_conn
+        .reply_create(cspace.to_absolute_cptr(reply_cptr))
+        .expect("Could not create reply object");
+
+    let drv_bit = drv_channel.from_bit.unwrap() as usize;
+
+    loop {
+        let (_msg, badge) = smos_serv_replyrecv(&listen_conn, &reply, None);
+
+        match decode_entry_type(badge.try_into().unwrap()) {
+            EntryType::Notification(bits) => {
+                for bit in bits {
+                    if bit == 0 {
+                        /* Nothing connects to us, so there is nothing to clean up */
+                        while unsafe { dequeue_ntfn_buffer_msg(NTFN_BUFFER) }.is_some() {}
+                        server.handle_swap_requests();
+                    } else if bit == drv_bit {
+                        server.handle_drv_responses();
+                    } else {
+                        sel4::debug_println!("Swap server got an unexpected notification {}", bit);
+                    }
+                }
+            }
+            _ => sel4::debug_println!("The swap server cannot handle invocations or faults"),
+        }
+    }
+}
@@ -43,7 +43,7 @@ fn main(rs_conn: RootServerConnection, mut cspace: SMOSUserCSpace) -> sel4::Resu
     // server look the same, so I think they are deleted by the capability revocation. Need to do
     // something so they look different
 
-    rs_conn.process_exit();
+    rs_conn.process_exit(0);
 
     loop {}
     unreachable!()